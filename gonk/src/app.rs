@@ -0,0 +1,1155 @@
+//! Application state and input handling
+//!
+//! Events are handled separately from the terminal so key sequences can be tested.
+//! Keys go to the open screen first, anything it doesn't use is a global binding.
+#[cfg(feature = "discord")]
+use crate::discord;
+#[cfg(feature = "playlists")]
+use crate::playlist::{self, Playlist};
+#[cfg(feature = "search")]
+use crate::search::{self, Search};
+use crate::{
+    browser::{self, Browser, Pending},
+    config::KeyBindings,
+    hitbox::{Hitboxes, Widget},
+    notify::{notify, Kind, Notifier},
+    preview::{self, Preview},
+    queue::{self, Queue},
+    reveal,
+    screen::{Action, Capture, Context, Screen},
+    settings::Settings,
+    skim::{self, Skim},
+    waveform::Waveform,
+    Mode, FADE_OUT,
+};
+use gonk_core::{
+    db::{self, ScanResult},
+    log, plays,
+    vdb::Database,
+    Index, Song,
+};
+use gonk_player::*;
+use std::{
+    ops::ControlFlow,
+    path::Path,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use winter::Event;
+
+pub struct App {
+    pub mode: Mode,
+    ///Used to leave search.
+    #[cfg(feature = "search")]
+    pub prev_mode: Mode,
+    pub help: bool,
+    pub mute: bool,
+    pub old_volume: u8,
+    pub browser: Browser,
+    pub queue: Queue,
+    #[cfg(feature = "playlists")]
+    pub playlist: Playlist,
+    #[cfg(feature = "search")]
+    pub search: Search,
+    pub songs: Index<Song>,
+    pub db: Database,
+    pub settings: Settings,
+    pub persist: gonk_core::settings::Settings,
+    pub preview: Option<Preview>,
+    pub pending: Option<Pending>,
+    pub scan_handle: Option<JoinHandle<ScanResult>>,
+    pub scan_timer: Instant,
+    pub default_device_name: String,
+    #[cfg(feature = "discord")]
+    pub discord: Option<std::sync::mpsc::Sender<Option<discord::Activity>>>,
+    ///Path, paused and duration of the song last sent to status bars.
+    pub last_playing: (Option<String>, bool, Duration),
+    ///The `starts` of the song being listened to, `heard` and `counted` are reset when it changes.
+    pub started: u64,
+    ///How long the song has been heard since it started, seeking doesn't add to it.
+    pub heard: Duration,
+    ///The song was counted as played since it started.
+    pub counted: bool,
+    ///Clickable areas from the last frame.
+    pub hitboxes: Hitboxes,
+    ///Where the mouse was last clicked, scroll events don't say where the mouse is so they go here.
+    pub pointer: Option<(u16, u16)>,
+    ///The bar being dragged and when the mouse last moved it.
+    pub drag: Option<(Widget, Instant)>,
+    pub waveform: Waveform,
+    ///Where to seek once the keys stop, after seeking past the end of a song.
+    pub skim: Option<Skim>,
+    ///Adds up the songs added in quick succession.
+    pub notifier: Notifier,
+    pub keys: KeyBindings,
+}
+
+///The screen that's open.
+fn screen(app: &App) -> &dyn Screen {
+    match app.mode {
+        Mode::Browser => &app.browser,
+        Mode::Queue => &app.queue,
+        #[cfg(feature = "playlists")]
+        Mode::Playlist => &app.playlist,
+        Mode::Settings => &app.settings,
+        #[cfg(feature = "search")]
+        Mode::Search => &app.search,
+    }
+}
+
+///Run `f` on a screen along with the state every screen shares.
+pub fn with_screen<T>(
+    app: &mut App,
+    mode: Mode,
+    f: impl FnOnce(&mut dyn Screen, &mut Context) -> T,
+) -> T {
+    let mut ctx = Context {
+        db: &mut app.db,
+        songs: &mut app.songs,
+        persist: &mut app.persist,
+        hitboxes: &mut app.hitboxes,
+        normalize: app.settings.normalize,
+        queue_layout: app.settings.queue_layout,
+        mute: app.mute,
+        previewing: app.preview.is_some(),
+        elapsed: preview::elapsed(&app.preview),
+        waveform: app.waveform.levels.as_deref(),
+    };
+    let screen: &mut dyn Screen = match mode {
+        Mode::Browser => &mut app.browser,
+        Mode::Queue => &mut app.queue,
+        #[cfg(feature = "playlists")]
+        Mode::Playlist => &mut app.playlist,
+        Mode::Settings => &mut app.settings,
+        #[cfg(feature = "search")]
+        Mode::Search => &mut app.search,
+    };
+    f(screen, &mut ctx)
+}
+
+///The text box that has focus, if any.
+pub fn capture(app: &App) -> Option<Capture> {
+    screen(app).wants_text_input()
+}
+
+///Update every screen, not just the one that's open.
+pub fn tick(app: &mut App) {
+    let mut modes = vec![Mode::Browser, Mode::Queue, Mode::Settings];
+    #[cfg(feature = "playlists")]
+    modes.push(Mode::Playlist);
+    #[cfg(feature = "search")]
+    modes.push(Mode::Search);
+
+    for mode in modes {
+        let actions = with_screen(app, mode, |screen, ctx| screen.on_tick(ctx));
+        apply(app, actions);
+    }
+}
+
+///Do what the screens couldn't do themselves.
+fn apply(app: &mut App, actions: Vec<Action>) {
+    for action in actions {
+        match action {
+            Action::Enqueue(songs) => {
+                notify(&mut app.notifier, Kind::Queued, &songs);
+                app.songs.extend(songs);
+            }
+            //Only one artist can be added at a time.
+            Action::Pending(p) => {
+                if app.pending.is_none() {
+                    app.pending = Some(p);
+                }
+            }
+            #[cfg(feature = "playlists")]
+            Action::AddToPlaylist(songs) => {
+                if playlist::add(&mut app.playlist, songs) {
+                    app.mode = Mode::Playlist;
+                }
+            }
+            #[cfg(feature = "playlists")]
+            Action::Like(songs) => {
+                playlist::like(&mut app.playlist, &mut app.db, &mut app.notifier, songs)
+            }
+            #[cfg(feature = "search")]
+            Action::Mode(mode) => app.mode = mode,
+            #[cfg(feature = "search")]
+            Action::LeaveSearch => app.mode = app.prev_mode.clone(),
+            Action::Message(message) => {
+                log::clear();
+                log!("{message}");
+            }
+            Action::Scan => scan(app),
+            Action::BrowserSort(sort) => browser::set_sort(&mut app.browser, &app.db, sort),
+            Action::BrowserMode(mode) => browser::set_layout(&mut app.browser, &app.db, mode),
+            #[cfg(feature = "discord")]
+            Action::Discord => {
+                if app.settings.discord && app.discord.is_none() {
+                    app.discord = discord::spawn();
+                }
+                //Send the current song again.
+                app.last_playing = Default::default();
+            }
+        }
+    }
+}
+
+fn reset_columns(app: &mut App) {
+    app.queue.constraints_reset();
+    app.persist.reset_columns();
+    log::clear();
+    log!("Columns: reset");
+}
+
+fn scan(app: &mut App) {
+    if app.scan_handle.is_some() {
+        return;
+    }
+    if app.persist.music_folder.is_empty() {
+        gonk_core::log!("Nothing to scan! Add a folder with 'gonk add /path/'");
+    } else {
+        app.scan_handle = Some(db::create(
+            &app.persist.music_folder,
+            app.persist.guess_tags,
+        ));
+        app.scan_timer = Instant::now();
+        #[cfg(feature = "playlists")]
+        {
+            app.playlist.lists = Index::from(gonk_core::playlist::playlists());
+        }
+    }
+}
+
+///Handle a single key press or mouse event. Breaks when the user quits.
+pub fn handle_event(app: &mut App, event: Event, shift: bool, control: bool) -> ControlFlow<()> {
+    //Remapped keys are handled as their default, typing in a text box isn't remapped.
+    let event = match event {
+        Event::Char(c) if !control && capture(app).is_none() => match app.keys.translate(c) {
+            Some(c) => Event::Char(c),
+            None => return ControlFlow::Continue(()),
+        },
+        event => event,
+    };
+    let empty = app.songs.is_empty();
+    let settings = (!app.persist.errors.is_empty()).then(|| app.persist.list());
+    let flow = on_event(app, event, shift, control);
+
+    //Changing a setting that couldn't be read fixes it.
+    if let Some(settings) = settings {
+        app.persist.clear_changed(&settings);
+    }
+
+    play_first(app, empty);
+    flow
+}
+
+///Start playing when songs are added to an empty queue.
+fn play_first(app: &mut App, empty: bool) {
+    if empty && !app.songs.is_empty() {
+        app.queue.set_index(0);
+        app.songs.select(Some(0));
+        if let Some(song) = app.songs.selected() {
+            play_song(song);
+        }
+    }
+}
+
+///Insert songs into the queue, the selection stays on the same songs.
+pub fn insert_songs(app: &mut App, new: Vec<Song>, position: usize) {
+    notify(&mut app.notifier, Kind::Queued, &new);
+    let empty = app.songs.is_empty();
+    let (position, len) = (position.min(app.songs.len()), new.len());
+    add_songs_at(&mut app.songs, new, position);
+    queue::inserted(&mut app.queue, position, len);
+    play_first(app, empty);
+}
+
+///The path of the playing song once it's been heard long enough to count as a play.
+///
+///`started` is `gonk_player::starts`, a song that plays again is counted again.
+pub fn count_play(
+    app: &mut App,
+    started: u64,
+    delta: Duration,
+    duration: Duration,
+) -> Option<String> {
+    if app.started != started {
+        app.started = started;
+        app.heard = Duration::ZERO;
+        app.counted = false;
+    }
+    let (None, Some(song)) = (&app.preview, app.songs.selected()) else {
+        return None;
+    };
+    app.heard += delta;
+    if app.counted || !plays::listened(app.heard, duration) {
+        return None;
+    }
+    app.counted = true;
+    Some(song.path.clone())
+}
+
+///Add the songs of a large artist that are ready to the queue.
+pub fn enqueue_pending(app: &mut App) {
+    let Some(p) = &mut app.pending else {
+        return;
+    };
+
+    let len = app.songs.len();
+    let finished = !browser::enqueue_chunks(p, &mut app.songs);
+    if !finished && app.songs.len() == len {
+        return;
+    }
+    let message = app
+        .notifier
+        .add(Kind::Queued, &app.songs[len..], Instant::now());
+    log::clear();
+    if finished {
+        log!("{message}");
+        app.pending = None;
+    } else {
+        log!("{message} (Esc to cancel)");
+    }
+
+    play_first(app, len == 0);
+}
+
+fn toggle_mute(app: &mut App) {
+    if app.mute {
+        app.mute = false;
+        set_volume(app.old_volume)
+    } else {
+        app.mute = true;
+        app.old_volume = get_volume();
+        set_volume(0);
+    }
+}
+
+///Winter doesn't report the button being released, holding it and moving the mouse sends more clicks.
+///A drag ends when the clicks stop for this long.
+#[cfg(feature = "mouse")]
+const DRAG_GAP: Duration = Duration::from_millis(100);
+
+///Clicks that follow a click on a bar keep moving that bar, even once the mouse leaves it.
+#[cfg(feature = "mouse")]
+fn click(app: &mut App, x: u16, y: u16) {
+    app.pointer = Some((x, y));
+    let dragging = app
+        .drag
+        .take()
+        .filter(|(_, last)| last.elapsed() < DRAG_GAP)
+        .and_then(|(widget, _)| Some((app.hitboxes.find(widget)?, widget)));
+    let Some((rect, widget)) = dragging.or_else(|| app.hitboxes.get(x, y)) else {
+        return;
+    };
+
+    match widget {
+        Widget::SeekBar => {
+            seek_ratio((x.saturating_sub(rect.x) as f32 / rect.width as f32).min(1.0))
+        }
+        Widget::QueueScrollbar => {
+            let ratio =
+                y.saturating_sub(rect.y) as f32 / rect.height.saturating_sub(1).max(1) as f32;
+            queue::scroll_to(&mut app.queue, &app.songs, ratio);
+        }
+        Widget::Volume => toggle_mute(app),
+        _ => handle_mouse(app, widget),
+    }
+
+    if matches!(widget, Widget::SeekBar | Widget::QueueScrollbar) {
+        app.drag = Some((widget, Instant::now()));
+    }
+}
+
+///Let the open screen handle a widget it drew.
+#[cfg(feature = "mouse")]
+fn handle_mouse(app: &mut App, widget: Widget) {
+    let mode = app.mode.clone();
+    let actions = with_screen(app, mode, |screen, ctx| screen.handle_mouse(ctx, widget));
+    apply(app, actions);
+}
+
+///Scroll the widget under the mouse. Returns false if the selection should move instead.
+#[cfg(feature = "mouse")]
+fn scroll(app: &mut App, up: bool) -> bool {
+    let hovered = app.pointer.and_then(|(x, y)| app.hitboxes.get(x, y));
+    let Some((_, widget)) = hovered.filter(|_| !app.help) else {
+        return false;
+    };
+
+    match widget {
+        Widget::Volume => {
+            if up {
+                volume_up();
+            } else {
+                volume_down();
+            }
+            app.persist.volume = get_volume();
+            true
+        }
+        Widget::SeekBar => {
+            if up {
+                seek_foward();
+            } else {
+                seek_backward();
+            }
+            true
+        }
+        //Focus the pane under the mouse so it scrolls instead of the one that had focus.
+        Widget::BrowserGroup
+        | Widget::BrowserArtist
+        | Widget::BrowserAlbum
+        | Widget::BrowserSong => {
+            handle_mouse(app, widget);
+            false
+        }
+        #[cfg(feature = "playlists")]
+        Widget::PlaylistList | Widget::PlaylistSongs => {
+            handle_mouse(app, widget);
+            false
+        }
+        _ => false,
+    }
+}
+
+fn on_event(app: &mut App, event: Event, shift: bool, control: bool) -> ControlFlow<()> {
+    let preview_song = match app.mode {
+        Mode::Browser => browser::selected_song(&app.browser, &app.db),
+        #[cfg(feature = "search")]
+        Mode::Search => search::selected_song(&app.search, &app.db),
+        _ => None,
+    };
+
+    //Esc and quitting end the preview and still do what they normally do.
+    if matches!(event, Event::Escape) || (control && matches!(event, Event::Char('c'))) {
+        preview::stop(&mut app.preview);
+    }
+
+    //Anything but another click ends a drag.
+    #[cfg(feature = "mouse")]
+    if !matches!(event, Event::LeftMouse(..)) {
+        app.drag = None;
+    }
+
+    //These come before the keys of the open screen.
+    match event {
+        Event::Char('p') if preview_song.is_some() && capture(app).is_none() => {
+            if let Some(song) = &preview_song {
+                preview::start(&mut app.preview, song, app.songs.selected());
+            }
+            return ControlFlow::Continue(());
+        }
+        //Any other input ends the preview.
+        _ if app.preview.is_some() => {
+            preview::stop(&mut app.preview);
+            return ControlFlow::Continue(());
+        }
+        #[cfg(feature = "mouse")]
+        Event::LeftMouse(x, y) => {
+            if !app.help {
+                click(app, x, y);
+            }
+            return ControlFlow::Continue(());
+        }
+        Event::Char('c') if control => return ControlFlow::Break(()),
+        Event::Char('?') | Event::Char('/') | Event::Escape if app.help => {
+            app.help = false;
+            return ControlFlow::Continue(());
+        }
+        Event::Char('a') if control => {
+            app.queue.range = Some(0..app.songs.len());
+            return ControlFlow::Continue(());
+        }
+        _ => {}
+    }
+
+    //Scrolling the volume or seek bar changes them, anywhere else it moves the selection like the arrow keys.
+    #[cfg(feature = "mouse")]
+    let event = match event {
+        Event::ScrollUp | Event::ScrollDown => {
+            let up = matches!(event, Event::ScrollUp);
+            if scroll(app, up) {
+                return ControlFlow::Continue(());
+            }
+            if up {
+                Event::Up
+            } else {
+                Event::Down
+            }
+        }
+        event => event,
+    };
+
+    let mode = app.mode.clone();
+    let handled = with_screen(app, mode, |screen, ctx| {
+        screen.handle_key(ctx, &event, shift, control)
+    });
+    if let Some(actions) = handled {
+        apply(app, actions);
+        return ControlFlow::Continue(());
+    }
+
+    match event {
+        Event::Escape if app.pending.is_some() => {
+            if let Some(p) = app.pending.take() {
+                app.notifier.flush();
+                log::clear();
+                log!("Cancelled, added {} songs to the queue.", p.added);
+            }
+        }
+        #[cfg(feature = "search")]
+        Event::Char('?') if app.mode != Mode::Search => app.help = true,
+        #[cfg(not(feature = "search"))]
+        Event::Char('?') => app.help = true,
+        #[cfg(feature = "search")]
+        Event::Char('/') => {
+            app.prev_mode = app.mode.clone();
+            app.mode = Mode::Search;
+            app.search.query_changed = true;
+        }
+        #[cfg(feature = "search")]
+        Event::Tab => {
+            app.prev_mode = app.mode.clone();
+            app.mode = Mode::Search;
+        }
+        Event::Char(' ') => {
+            //Resume on the configured device if it's back, otherwise use the new default.
+            if device_lost() && is_paused() {
+                let device = if app
+                    .settings
+                    .devices
+                    .iter()
+                    .any(|d| d.name == app.persist.output_device)
+                {
+                    app.persist.output_device.clone()
+                } else {
+                    app.default_device_name.clone()
+                };
+                set_output_device(&device);
+                app.settings.current_device = device;
+            }
+            toggle_playback();
+        }
+        Event::Char('C') => {
+            queue::save_snapshot(&app.songs, preview::elapsed(&app.preview));
+            clear_except_playing(&mut app.songs);
+            app.queue.set_index(0);
+        }
+        Event::Char('c') => {
+            queue::save_snapshot(&app.songs, preview::elapsed(&app.preview));
+            fade_out_and_stop(FADE_OUT);
+            app.songs = Index::default();
+        }
+        Event::Char('z') => toggle_mute(app),
+        //Move the target instead of seeking while waiting to open the next song.
+        Event::Char('q') => match &mut app.skim {
+            Some(skim) => skim::step(skim, -SEEK_STEP, Instant::now()),
+            None => seek_backward(),
+        },
+        Event::Char('e') => match &mut app.skim {
+            Some(skim) => skim::step(skim, SEEK_STEP, Instant::now()),
+            None => seek_foward(),
+        },
+        Event::Char('<') => rate_down(),
+        Event::Char('>') => rate_up(),
+        //Set A, then B, then clear the loop.
+        Event::Char('i') if !app.songs.is_empty() => {
+            let pos = elapsed().as_secs_f32();
+            let time = |secs: f32| format!("{:02}:{:02}", secs as u32 / 60, secs as u32 % 60);
+            log::clear();
+            match loop_points() {
+                (None, _) => {
+                    set_loop_a(pos);
+                    log!("Loop from {}.", time(pos));
+                }
+                (Some(a), None) => {
+                    set_loop_b(pos);
+                    log!("Looping {} to {}.", time(a.min(pos)), time(a.max(pos)));
+                }
+                (Some(_), Some(_)) => {
+                    clear_loop();
+                    log!("Loop cleared.");
+                }
+            }
+        }
+        Event::Char('a') => prev_song(&mut app.songs),
+        Event::Char('d') => next_song(&mut app.songs),
+        Event::Char('S') => {
+            let state = if toggle_shuffle(&app.songs) {
+                "on"
+            } else {
+                "off"
+            };
+            log::clear();
+            log!("Shuffle {state}.");
+        }
+        Event::Char('A') | Event::Char('{') => jump_to_prev_album(&mut app.songs),
+        Event::Char('D') | Event::Char('}') => jump_to_next_album(&mut app.songs),
+        Event::Char('w') => {
+            volume_up();
+            app.persist.volume = get_volume();
+        }
+        Event::Char('s') => {
+            volume_down();
+            app.persist.volume = get_volume();
+        }
+        Event::Char('O') => {
+            let song = match app.mode {
+                Mode::Queue => app.queue.index().and_then(|i| app.songs.get(i)).cloned(),
+                _ => preview_song,
+            };
+            if let Some(song) = song {
+                if let Err(err) = reveal::reveal(Path::new(&song.path)) {
+                    log::clear();
+                    log!("{err}");
+                }
+            }
+        }
+        Event::Char('r') => {
+            app.persist.repeat = toggle_repeat();
+            log::clear();
+            match app.persist.repeat {
+                RepeatMode::Off => log!("Repeat off."),
+                RepeatMode::Queue => log!("Repeating the queue."),
+                RepeatMode::One => log!("Repeating the current song."),
+            }
+        }
+        Event::Char('1') => app.mode = Mode::Queue,
+        Event::Char('2') => app.mode = Mode::Browser,
+        #[cfg(feature = "playlists")]
+        Event::Char('3') => app.mode = Mode::Playlist,
+        Event::Char('4') => app.mode = Mode::Settings,
+        Event::Function(n @ 1..=3) => {
+            queue::constraint(&mut app.queue, n as usize - 1, shift);
+            app.persist.column_preset = gonk_core::settings::CUSTOM_PRESET.to_string();
+            app.persist.custom_columns = app.queue.constraint;
+        }
+        //Shift + 0
+        Event::Char(')') => reset_columns(app),
+        Event::Char('0') if shift => reset_columns(app),
+        Event::Function(4) => {
+            let name = app.persist.next_column_preset().to_string();
+            app.queue.constraint = app.persist.columns();
+            log::clear();
+            log!("Columns: {name}");
+        }
+        Event::Function(9) => {
+            let state = if toggle_gain_bypass() { "BYPASS" } else { "ON" };
+            log::clear();
+            log!("Gain: {state}");
+        }
+        _ => {}
+    }
+
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "playlists")]
+    use crate::playlist::Mode as PlaylistMode;
+    #[cfg(feature = "search")]
+    use crate::search::Mode as SearchMode;
+
+    fn app(songs: Vec<Song>) -> App {
+        let db = Database::from_songs(songs.clone());
+        let index = (!songs.is_empty()).then_some(0);
+        App {
+            mode: Mode::Queue,
+            #[cfg(feature = "search")]
+            prev_mode: Mode::Search,
+            help: false,
+            mute: false,
+            old_volume: 0,
+            browser: Browser::new(&db),
+            queue: Queue::new(0),
+            #[cfg(feature = "playlists")]
+            playlist: Playlist::new().unwrap(),
+            #[cfg(feature = "search")]
+            search: Search::new(),
+            songs: Index::new(songs, index),
+            db,
+            settings: Settings::new(Vec::new(), String::new()),
+            persist: gonk_core::settings::Settings::default(),
+            preview: None,
+            pending: None,
+            scan_handle: None,
+            scan_timer: Instant::now(),
+            default_device_name: String::new(),
+            #[cfg(feature = "discord")]
+            discord: None,
+            last_playing: Default::default(),
+            started: 0,
+            heard: Duration::ZERO,
+            counted: false,
+            hitboxes: Hitboxes::default(),
+            pointer: None,
+            drag: None,
+            waveform: Waveform::default(),
+            skim: None,
+            notifier: Notifier::default(),
+            keys: KeyBindings::default(),
+        }
+    }
+
+    ///Press each character in order.
+    fn keys(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let _ = handle_event(app, Event::Char(c), false, false);
+        }
+    }
+
+    fn press(app: &mut App, event: Event) {
+        let _ = handle_event(app, event, false, false);
+    }
+
+    fn titles(app: &App) -> Vec<&str> {
+        app.songs.iter().map(|song| song.title.as_str()).collect()
+    }
+
+    ///Draw the open screen like a frame at this terminal size.
+    #[cfg(feature = "mouse")]
+    fn frame(app: &mut App, width: u16, height: u16) {
+        let area = winter::Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        let mut buf = winter::Buffer::empty(area);
+        app.hitboxes.clear();
+        let mode = app.mode.clone();
+        with_screen(app, mode, |screen, ctx| screen.draw(ctx, area, &mut buf));
+    }
+
+    #[test]
+    fn queue_delete() {
+        let mut app = app(vec![
+            Song::test("a", "x", "1"),
+            Song::test("a", "x", "2"),
+            Song::test("b", "y", "3"),
+            Song::test("b", "y", "4"),
+        ]);
+
+        keys(&mut app, "jjx");
+        assert_eq!(titles(&app), ["1", "2", "4"]);
+        assert_eq!(app.queue.index(), Some(2));
+
+        //Deleting the last song moves the selection up.
+        keys(&mut app, "x");
+        assert_eq!(titles(&app), ["1", "2"]);
+        assert_eq!(app.queue.index(), Some(1));
+
+        //Collapsed albums are deleted together.
+        let mut app = self::app(vec![
+            Song::test("a", "x", "1"),
+            Song::test("a", "x", "2"),
+            Song::test("b", "y", "3"),
+        ]);
+        app.songs.select(None);
+        keys(&mut app, "gx");
+        assert_eq!(titles(&app), ["3"]);
+        assert_eq!(app.queue.index(), Some(0));
+    }
+
+    #[test]
+    fn counted_twice() {
+        let mut app = app(vec![Song::test("a", "x", "1")]);
+        let secs = Duration::from_secs;
+        let length = secs(240);
+
+        assert_eq!(count_play(&mut app, 1, secs(10), length), None);
+        assert_eq!(count_play(&mut app, 1, secs(25), length).unwrap(), "1.flac");
+        assert_eq!(count_play(&mut app, 1, secs(60), length), None);
+
+        //Repeating the song starts it again.
+        assert_eq!(count_play(&mut app, 2, secs(10), length), None);
+        assert_eq!(count_play(&mut app, 2, secs(25), length).unwrap(), "1.flac");
+
+        //The time heard starts again too.
+        assert_eq!(count_play(&mut app, 3, secs(29), length), None);
+    }
+
+    #[test]
+    fn preview_keys() {
+        let mut app = app(vec![Song::test("a", "x", "1"), Song::test("a", "x", "2")]);
+
+        //Other keys only end the preview.
+        preview::start(&mut app.preview, &app.songs[1].clone(), None);
+        keys(&mut app, "x");
+        assert!(app.preview.is_none());
+        assert_eq!(titles(&app), ["1", "2"]);
+
+        //Esc still closes the help.
+        app.help = true;
+        preview::start(&mut app.preview, &app.songs[1].clone(), None);
+        press(&mut app, Event::Escape);
+        assert!(app.preview.is_none());
+        assert!(!app.help);
+
+        //And quitting still quits.
+        preview::start(&mut app.preview, &app.songs[1].clone(), None);
+        assert!(handle_event(&mut app, Event::Char('c'), false, true).is_break());
+        assert!(app.preview.is_none());
+    }
+
+    #[test]
+    fn insert_songs() {
+        let mut app = app(vec![Song::test("a", "x", "1"), Song::test("a", "x", "2")]);
+        keys(&mut app, "j");
+
+        //Before the selection.
+        super::insert_songs(&mut app, vec![Song::test("b", "y", "3")], 0);
+        assert_eq!(titles(&app), ["3", "1", "2"]);
+        assert_eq!(app.queue.index(), Some(2));
+
+        //After it.
+        super::insert_songs(&mut app, vec![Song::test("b", "y", "4")], usize::MAX);
+        assert_eq!(titles(&app), ["3", "1", "2", "4"]);
+        assert_eq!(app.queue.index(), Some(2));
+
+        //Into an empty queue.
+        app.songs = Index::new(Vec::new(), None);
+        super::insert_songs(&mut app, vec![Song::test("b", "y", "5")], 3);
+        assert_eq!(titles(&app), ["5"]);
+        assert_eq!((app.songs.index(), app.queue.index()), (Some(0), Some(0)));
+    }
+
+    #[test]
+    fn enqueue_pending() {
+        let songs: Vec<Song> = (0..browser::CHUNK_THRESHOLD + 1)
+            .map(|i| Song::test("a", "x", &i.to_string()))
+            .collect();
+        let mut app = app(songs);
+        app.songs = Index::new(Vec::new(), None);
+        app.pending = Some(Pending::new(&app.db, &[String::from("a")], None));
+
+        //The first chunk starts playing like any other enqueue.
+        while app.songs.is_empty() {
+            super::enqueue_pending(&mut app);
+        }
+        assert!(!app.songs.is_empty());
+        assert_eq!(app.songs.index(), Some(0));
+        assert_eq!(app.queue.index(), Some(0));
+
+        //Later chunks leave the selection alone.
+        app.songs.select(Some(1));
+        while app.pending.is_some() {
+            super::enqueue_pending(&mut app);
+        }
+        assert_eq!(app.songs.len(), browser::CHUNK_THRESHOLD + 1);
+        assert_eq!(app.songs.index(), Some(1));
+    }
+
+    #[test]
+    #[cfg(feature = "playlists")]
+    fn playlist_popup() {
+        let mut app = app(vec![Song::test("a", "x", "1"), Song::test("a", "x", "2")]);
+
+        keys(&mut app, "j");
+        let _ = handle_event(&mut app, Event::Enter, true, false);
+        assert!(app.mode == Mode::Playlist);
+        assert!(app.playlist.mode == PlaylistMode::Popup);
+
+        //Invalid names keep the popup open.
+        keys(&mut app, "gonk/");
+        press(&mut app, Event::Enter);
+        assert!(app.playlist.mode == PlaylistMode::Popup);
+        assert!(!app.playlist.song_buffer.is_empty());
+        app.playlist.search_query.clear();
+
+        //Keys are typed into the popup instead of running commands.
+        keys(&mut app, "gonk test");
+        assert_eq!(app.playlist.search_query, "gonk test");
+
+        press(&mut app, Event::Enter);
+        assert!(app.playlist.mode == PlaylistMode::Playlist);
+        assert!(app.playlist.search_query.is_empty());
+
+        let playlist = app.playlist.lists.selected().unwrap();
+        assert_eq!(playlist.name(), "gonk test");
+        assert_eq!(playlist.songs.len(), 1);
+        assert_eq!(playlist.songs[0].title, "2");
+        playlist.delete();
+    }
+
+    #[test]
+    #[cfg(feature = "search")]
+    fn search_escape() {
+        let mut app = app(vec![Song::test("a", "x", "1")]);
+
+        keys(&mut app, "/abc");
+        assert!(app.mode == Mode::Search);
+        assert_eq!(app.search.query, "abc");
+
+        press(&mut app, Event::Escape);
+        assert!(app.mode == Mode::Queue);
+        assert!(app.search.query.is_empty());
+        assert_eq!(app.search.mode, SearchMode::Search);
+
+        //An empty search goes back to where it was opened from.
+        keys(&mut app, "2/");
+        assert!(app.mode == Mode::Search);
+        keys(&mut app, "/");
+        assert!(app.mode == Mode::Browser);
+
+        //Tab leaves search without clearing the query.
+        keys(&mut app, "/q");
+        press(&mut app, Event::Tab);
+        assert!(app.mode == Mode::Browser);
+        assert_eq!(app.search.query, "q");
+    }
+
+    #[test]
+    fn capture_path() {
+        let mut app = app(vec![Song::test("a", "x", "1")]);
+        keys(&mut app, "2o");
+        assert_eq!(capture(&app), Some(Capture::Path));
+
+        //Commands are typed instead.
+        keys(&mut app, "xcq");
+        assert_eq!(app.browser.path.as_deref(), Some("xcq"));
+        assert_eq!(titles(&app), ["1"]);
+
+        press(&mut app, Event::Backspace);
+        assert_eq!(app.browser.path.as_deref(), Some("xc"));
+
+        press(&mut app, Event::Escape);
+        assert_eq!(capture(&app), None);
+        assert!(app.mode == Mode::Browser);
+    }
+
+    #[test]
+    #[cfg(feature = "search")]
+    fn capture_search() {
+        let mut app = app(vec![Song::test("a", "x", "1")]);
+        press(&mut app, Event::Tab);
+        assert_eq!(capture(&app), Some(Capture::Search));
+
+        //Help isn't opened while typing a query.
+        keys(&mut app, "c?");
+        assert_eq!(app.search.query, "c?");
+        assert!(!app.help);
+        assert_eq!(titles(&app), ["1"]);
+    }
+
+    #[test]
+    fn empty_library() {
+        let mut app = app(Vec::new());
+        //Only the liked playlist so nothing on disk can be deleted.
+        #[cfg(feature = "playlists")]
+        {
+            app.playlist.lists = Index::from(vec![gonk_core::Playlist::new(
+                gonk_core::playlist::LIKED,
+                Vec::new(),
+            )]);
+        }
+
+        let mut modes = vec![Mode::Queue, Mode::Browser, Mode::Settings];
+        #[cfg(feature = "playlists")]
+        modes.push(Mode::Playlist);
+        #[cfg(feature = "search")]
+        modes.push(Mode::Search);
+
+        let events = || {
+            let mut events = vec![
+                Event::Enter,
+                Event::Escape,
+                Event::Tab,
+                Event::Backspace,
+                Event::Up,
+                Event::Down,
+                Event::Left,
+                Event::Right,
+            ];
+            #[cfg(feature = "mouse")]
+            events.extend([Event::LeftMouse(1, 1), Event::ScrollUp, Event::ScrollDown]);
+            events.extend((1..=12).map(Event::Function));
+            //Restoring a saved queue and discord talk to the rest of the system.
+            //Shuffling is shared with the other tests.
+            events.extend(
+                (' '..='~')
+                    .filter(|c| !matches!(c, 'R' | 'r' | 'S'))
+                    .map(Event::Char),
+            );
+            events
+        };
+
+        for mode in &modes {
+            for (shift, control) in [(false, false), (true, false), (false, true)] {
+                for event in events() {
+                    app.mode = mode.clone();
+                    app.browser.path = None;
+                    app.settings.filtering = false;
+                    let _ = handle_event(&mut app, event, shift, control);
+                    //Again to confirm any popup that opened.
+                    let _ = handle_event(&mut app, Event::Enter, shift, control);
+                    assert!(app.songs.is_empty());
+                }
+            }
+        }
+
+        //The queue and library are still usable afterwards.
+        app.mode = Mode::Queue;
+        keys(&mut app, "cCxadADjkhl");
+        assert!(app.songs.is_empty());
+    }
+
+    #[test]
+    fn skim_keys() {
+        let mut app = app(vec![Song::test("a", "x", "1"), Song::test("a", "x", "2")]);
+        let around = skim::Around {
+            previous: None,
+            current: 0,
+            next: Some(1),
+        };
+        app.skim = skim::overflow(around, 3.0, Instant::now());
+
+        //Seek keys move the target while the next song is waiting to be opened.
+        keys(&mut app, "ee");
+        let skim = app.skim.as_ref().unwrap();
+        assert_eq!((skim.index, skim.offset), (1, 3.0 + SEEK_STEP * 2.0));
+
+        keys(&mut app, "qqq");
+        let skim = app.skim.as_ref().unwrap();
+        assert_eq!((skim.index, skim.offset), (0, 3.0 - SEEK_STEP));
+    }
+
+    #[test]
+    fn capture_filter() {
+        let mut app = app(Vec::new());
+        keys(&mut app, "4");
+        assert!(handle_event(&mut app, Event::Char('f'), false, true).is_continue());
+        assert_eq!(capture(&app), Some(Capture::Filter));
+
+        //Options aren't toggled while typing.
+        keys(&mut app, "vm");
+        assert_eq!(app.settings.filter, "vm");
+        assert!(app.settings.keep_pitch && !app.settings.remember_rate);
+
+        press(&mut app, Event::Backspace);
+        press(&mut app, Event::Enter);
+        assert_eq!(capture(&app), None);
+        assert_eq!(app.settings.filter, "v");
+        keys(&mut app, "v");
+        assert!(!app.settings.keep_pitch);
+
+        press(&mut app, Event::Escape);
+        assert!(app.settings.filter.is_empty());
+        assert!(app.mode == Mode::Settings);
+    }
+
+    #[test]
+    fn remapped_keys() {
+        let mut app = app(Vec::new());
+        (app.keys, _) = KeyBindings::parse("settings = \"9\"\nqueue = \"4\"");
+
+        keys(&mut app, "9");
+        assert!(app.mode == Mode::Settings);
+        keys(&mut app, "4");
+        assert!(app.mode == Mode::Queue);
+
+        //Typing isn't remapped.
+        keys(&mut app, "9");
+        assert!(handle_event(&mut app, Event::Char('f'), false, true).is_continue());
+        keys(&mut app, "94");
+        assert_eq!(app.settings.filter, "94");
+    }
+
+    #[test]
+    fn escape_order() {
+        let mut app = app(vec![Song::test("a", "x", "1")]);
+        app.pending = Some(Pending::new(&app.db, &[String::from("a")], None));
+        keys(&mut app, "2o");
+
+        //The screen closes its own popups first.
+        press(&mut app, Event::Escape);
+        assert_eq!(capture(&app), None);
+        assert!(app.pending.is_some());
+
+        press(&mut app, Event::Escape);
+        assert!(app.pending.is_none());
+
+        //Keys the screen doesn't use are global bindings.
+        keys(&mut app, "4x1");
+        assert!(app.mode == Mode::Queue);
+        assert_eq!(titles(&app), ["1"]);
+    }
+
+    #[test]
+    #[cfg(feature = "mouse")]
+    fn mouse() {
+        let songs = (0..40)
+            .map(|i| Song::test("a", &i.to_string(), &i.to_string()))
+            .collect();
+        let mut app = app(songs);
+        let click = |app: &mut App, x, y| press(app, Event::LeftMouse(x, y));
+
+        //Rows start below the header, the column names and a gap.
+        frame(&mut app, 100, 20);
+        click(&mut app, 10, 6);
+        assert_eq!(app.queue.index(), Some(1));
+        click(&mut app, 10, 5);
+        assert_eq!(app.queue.index(), Some(0));
+
+        //The queue doesn't fit so the right border is a scrollbar.
+        let bar = app.hitboxes.find(Widget::QueueScrollbar).unwrap();
+        assert_eq!((bar.x, bar.y, bar.height), (99, 5, 11));
+        click(&mut app, 99, 15);
+        assert_eq!(app.queue.index(), Some(39));
+
+        //Dragging keeps moving it after the mouse leaves the border.
+        click(&mut app, 50, 10);
+        assert_eq!(app.queue.index(), Some(20));
+
+        //Once the button is let go the same spot is a row again.
+        app.drag = None;
+        click(&mut app, 50, 10);
+        assert_eq!(app.queue.index(), Some(5));
+        click(&mut app, 99, 16);
+        assert_eq!(app.queue.index(), Some(5));
+        click(&mut app, 10, 5);
+        assert_eq!(app.queue.index(), Some(0));
+
+        //The next frame is drawn smaller, clicks use the new layout.
+        frame(&mut app, 80, 12);
+        click(&mut app, 99, 15);
+        assert_eq!(app.queue.index(), Some(0));
+        click(&mut app, 10, 8);
+        assert_eq!(app.queue.index(), Some(0));
+        click(&mut app, 79, 7);
+        assert_eq!(app.queue.index(), Some(39));
+        app.drag = None;
+        click(&mut app, 79, 5);
+        assert_eq!(app.queue.index(), Some(0));
+        app.drag = None;
+        click(&mut app, 10, 7);
+        assert_eq!(app.queue.index(), Some(2));
+
+        //The volume is left of the border corner.
+        let volume = app.hitboxes.find(Widget::Volume).unwrap();
+        assert_eq!((volume.x + volume.width, volume.y), (78, 0));
+        click(&mut app, 78, 0);
+        assert!(!app.mute);
+        click(&mut app, volume.x, 0);
+        assert!(app.mute);
+        click(&mut app, volume.x - 1, 0);
+        assert!(app.mute);
+        click(&mut app, 77, 0);
+        assert!(!app.mute);
+
+        //Scrolling goes to the widget that was clicked last, the volume doesn't move the selection.
+        press(&mut app, Event::ScrollDown);
+        assert_eq!(app.queue.index(), Some(2));
+        click(&mut app, 10, 6);
+        press(&mut app, Event::ScrollDown);
+        assert_eq!(app.queue.index(), Some(2));
+    }
+
+    #[test]
+    fn quit() {
+        let mut app = app(Vec::new());
+        assert!(handle_event(&mut app, Event::Char('c'), false, true).is_break());
+        assert!(handle_event(&mut app, Event::Char('c'), false, false).is_continue());
+    }
+}
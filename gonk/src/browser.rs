@@ -1,58 +1,184 @@
-use gonk_core::{vdb::Database, Album};
-use gonk_core::{Index, Song};
+use crate::{
+    cast::to_u16_clamped,
+    draw_empty,
+    hitbox::{Hitboxes, Widget},
+    path,
+    screen::{amount, Action, Capture, Context, Screen},
+    EMPTY_LIBRARY,
+};
+use gonk_core::vdb::{Database, Group};
+use gonk_core::{
+    db::{self, Album},
+    plays::Plays,
+    settings::{BrowserMode, BrowserSort},
+    Index, Normalize, Song,
+};
+use gonk_player::play_song;
+use std::{
+    cmp::Reverse,
+    fs, mem,
+    path::MAIN_SEPARATOR,
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    thread,
+};
 use winter::*;
 
-#[derive(PartialEq, Eq)]
+///Artists with more songs than this are added to the queue in chunks.
+pub const CHUNK_THRESHOLD: usize = 200;
+const CHUNK_SIZE: usize = 50;
+///The first group, every artist is shown.
+const ALL: &str = "All";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
+    ///The genres, years or decades.
+    Group,
     Artist,
     Album,
     Song,
 }
 
 pub struct Browser {
+    ///`None` comes first and shows every artist.
+    groups: Index<Option<Group>>,
+    ///Artists in the selected group.
     artists: Index<String>,
-    albums: Index<Album>,
-    ///Title, (disc, track)
-    songs: Index<(String, (u8, u8))>,
+    ///Artist and title of the albums by the selected artist,
+    ///or of every album in the group when there's no artist pane.
+    albums: Index<(String, String)>,
+    ///Title, path. Only loaded once the album list is focused.
+    songs: Index<(String, String)>,
     pub mode: Mode,
+    ///Which panes are shown.
+    pub layout: BrowserMode,
+    ///Order of the artists and albums.
+    pub sort: BrowserSort,
+    ///Path typed by the user, the popup is open when this is `Some`.
+    pub path: Option<String>,
 }
 
 impl Browser {
+    ///Only the artists are loaded up front, albums and songs are loaded when they're shown.
     pub fn new(db: &Database) -> Self {
         mini::profile!();
-        let artists = Index::new(db.artists().into_iter().cloned().collect(), Some(0));
-        let mut albums: Index<Album> = Index::default();
-        let mut songs = Index::default();
-
-        if let Some(artist) = artists.selected() {
-            albums = Index::from(db.albums_by_artist(artist));
-            if let Some(album) = albums.selected() {
-                songs = Index::from(
-                    album
-                        .songs
-                        .iter()
-                        .map(|song| {
-                            (
-                                format!("{}. {}", song.track_number, song.title),
-                                (song.disc_number, song.track_number),
-                            )
-                        })
-                        .collect::<Vec<(String, (u8, u8))>>(),
-                );
+        let layout = BrowserMode::default();
+        Self {
+            groups: Index::from(groups(db, layout)),
+            artists: Index::from(db.artists().into_iter().cloned().collect::<Vec<_>>()),
+            albums: Index::default(),
+            songs: Index::default(),
+            mode: first(layout),
+            layout,
+            sort: BrowserSort::default(),
+            path: None,
+        }
+    }
+}
+
+///The panes that are shown, from left to right.
+fn panes(layout: BrowserMode) -> &'static [Mode] {
+    match layout {
+        BrowserMode::ArtistAlbumSong => &[Mode::Artist, Mode::Album, Mode::Song],
+        BrowserMode::GenreArtistAlbum | BrowserMode::YearArtistAlbum => {
+            &[Mode::Group, Mode::Artist, Mode::Album, Mode::Song]
+        }
+        BrowserMode::DecadeAlbum => &[Mode::Group, Mode::Album, Mode::Song],
+    }
+}
+
+///The pane that has focus after the browser is reset.
+fn first(layout: BrowserMode) -> Mode {
+    if panes(layout).contains(&Mode::Artist) {
+        Mode::Artist
+    } else {
+        Mode::Album
+    }
+}
+
+///Songs by an artist or in a group that are being added to the queue a chunk at a time.
+///
+///They're expanded on another thread so a large group doesn't hold up drawing.
+pub struct Pending {
+    songs: Receiver<Vec<Song>>,
+    pub added: usize,
+}
+
+impl Pending {
+    ///Start sending the songs of these artists, only the ones in `group` if there is one.
+    pub fn new(db: &Database, artists: &[String], group: Option<Group>) -> Self {
+        //The database can't be shared with the producer so the albums are copied.
+        let artists: Vec<Vec<Album>> = artists
+            .iter()
+            .map(|artist| db.albums_by_artist(artist).to_vec())
+            .collect();
+        let (sender, songs) = channel();
+        thread::spawn(move || produce(artists, group, sender));
+        Self { songs, added: 0 }
+    }
+}
+
+///Send the songs a chunk at a time. Stops early once the pending songs are dropped.
+fn produce(artists: Vec<Vec<Album>>, group: Option<Group>, sender: Sender<Vec<Song>>) {
+    let year = |album: &Album| album.songs.first().map_or(0, |song| song.year);
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    for mut albums in artists {
+        //Oldest first, albums from the same year by title.
+        albums.sort_by(|a, b| (year(a), &a.title).cmp(&(year(b), &b.title)));
+        let songs = albums
+            .into_iter()
+            .flat_map(|album| album.songs)
+            .filter(|song| group.as_ref().is_none_or(|group| group.contains(song)));
+        for song in songs {
+            chunk.push(song);
+            if chunk.len() == CHUNK_SIZE && sender.send(mem::take(&mut chunk)).is_err() {
+                return;
             }
         }
+    }
+    if !chunk.is_empty() {
+        let _ = sender.send(chunk);
+    }
+}
 
-        Self {
-            artists,
-            albums,
-            songs,
-            mode: Mode::Artist,
+///Returns `Some` if the selected artist or group is too large to add to the queue at once.
+pub fn pending(browser: &Browser, db: &Database) -> Option<Pending> {
+    let (artists, group) = match browser.mode {
+        //Not the whole library.
+        Mode::Group => (
+            browser.artists.to_vec(),
+            Some(selected_group(browser)?.clone()),
+        ),
+        Mode::Artist => (vec![browser.artists.selected()?.clone()], None),
+        _ => return None,
+    };
+
+    let len = artists
+        .iter()
+        .flat_map(|artist| db.albums_by_artist(artist))
+        .flat_map(|album| &album.songs)
+        .filter(|song| group.as_ref().is_none_or(|group| group.contains(song)))
+        .count();
+
+    (len > CHUNK_THRESHOLD).then(|| Pending::new(db, &artists, group))
+}
+
+///Add the chunks that are ready to the queue. Returns false when every song has been added.
+pub fn enqueue_chunks(pending: &mut Pending, songs: &mut Index<Song>) -> bool {
+    loop {
+        match pending.songs.try_recv() {
+            Ok(chunk) => {
+                pending.added += chunk.len();
+                songs.extend(chunk);
+            }
+            Err(TryRecvError::Empty) => return true,
+            Err(TryRecvError::Disconnected) => return false,
         }
     }
 }
 
 pub fn up(browser: &mut Browser, db: &Database, amount: usize) {
     match browser.mode {
+        Mode::Group => browser.groups.up_n(amount),
         Mode::Artist => browser.artists.up_n(amount),
         Mode::Album => browser.albums.up_n(amount),
         Mode::Song => browser.songs.up_n(amount),
@@ -62,6 +188,7 @@ pub fn up(browser: &mut Browser, db: &Database, amount: usize) {
 
 pub fn down(browser: &mut Browser, db: &Database, amount: usize) {
     match browser.mode {
+        Mode::Group => browser.groups.down_n(amount),
         Mode::Artist => browser.artists.down_n(amount),
         Mode::Album => browser.albums.down_n(amount),
         Mode::Song => browser.songs.down_n(amount),
@@ -70,136 +197,815 @@ pub fn down(browser: &mut Browser, db: &Database, amount: usize) {
 }
 
 pub fn left(browser: &mut Browser) {
-    match browser.mode {
-        Mode::Artist => (),
-        Mode::Album => browser.mode = Mode::Artist,
-        Mode::Song => browser.mode = Mode::Album,
+    let panes = panes(browser.layout);
+    if let Some(i) = panes.iter().position(|mode| *mode == browser.mode) {
+        browser.mode = panes[i.saturating_sub(1)];
     }
 }
 
-pub fn right(browser: &mut Browser) {
-    match browser.mode {
-        Mode::Artist => browser.mode = Mode::Album,
-        Mode::Album => browser.mode = Mode::Song,
-        Mode::Song => (),
+pub fn right(browser: &mut Browser, db: &Database) {
+    let panes = panes(browser.layout);
+    let next = panes
+        .iter()
+        .position(|mode| *mode == browser.mode)
+        .and_then(|i| panes.get(i + 1));
+    match next {
+        Some(Mode::Artist) => browser.mode = Mode::Artist,
+        Some(mode) => focus(browser, db, *mode),
+        None => (),
     }
 }
 
+///Focus a list, the songs are loaded the first time they're needed.
+pub fn focus(browser: &mut Browser, db: &Database, mode: Mode) {
+    if browser.albums.is_empty() {
+        update_albums(browser, db);
+    }
+    if mode != Mode::Group && mode != Mode::Artist && browser.songs.is_empty() {
+        update_songs(browser, db);
+    }
+    browser.mode = mode;
+}
+
 pub fn draw(
     browser: &mut Browser,
     area: winter::Rect,
     buf: &mut winter::Buffer,
-    mouse: Option<(u16, u16)>,
-) {
-    let size = area.width / 3;
-    let rem = area.width % 3;
+    db: &Database,
+    normalize: &Normalize,
+    hitboxes: &mut Hitboxes,
+) -> Option<(u16, u16)> {
+    //Albums are loaded on the first draw instead of at startup.
+    if browser.albums.is_empty() {
+        update_albums(browser, db);
+    }
+
+    let panes = panes(browser.layout);
+    let shown = |mode: Mode| panes.contains(&mode);
+    let size = area.width / panes.len() as u16;
+    let rem = area.width % panes.len() as u16;
+    //Hidden panes have no width.
+    let width = |mode: Mode| match mode {
+        Mode::Song => size + rem,
+        mode if shown(mode) => size,
+        _ => 0,
+    };
 
     let chunks = layout(
         area,
         Direction::Horizontal,
         &[
-            Constraint::Length(size),
-            Constraint::Length(size),
-            Constraint::Length(size + rem),
+            Constraint::Length(width(Mode::Group)),
+            Constraint::Length(width(Mode::Artist)),
+            Constraint::Length(width(Mode::Album)),
+            Constraint::Length(width(Mode::Song)),
         ],
     );
 
-    if let Some((x, y)) = mouse {
-        let rect = Rect {
-            x,
-            y,
-            ..Default::default()
-        };
-        if rect.intersects(chunks[2]) {
-            browser.mode = Mode::Song;
-        } else if rect.intersects(chunks[1]) {
-            browser.mode = Mode::Album;
-        } else if rect.intersects(chunks[0]) {
-            browser.mode = Mode::Artist;
-        }
+    if shown(Mode::Group) {
+        hitboxes.push(chunks[0], Widget::BrowserGroup);
+    }
+    if shown(Mode::Artist) {
+        hitboxes.push(chunks[1], Widget::BrowserArtist);
     }
+    hitboxes.push(chunks[2], Widget::BrowserAlbum);
+    hitboxes.push(chunks[3], Widget::BrowserSong);
 
+    let groups: Vec<_> = browser
+        .groups
+        .iter()
+        .map(|group| match group {
+            Some(group) => lines!(group.name()),
+            None => lines!(ALL),
+        })
+        .collect();
     let artists: Vec<_> = browser.artists.iter().map(|a| lines!(a)).collect();
-    let albums: Vec<_> = browser.albums.iter().map(|a| lines!(&a.title)).collect();
-    let songs: Vec<_> = browser.songs.iter().map(|(s, _)| lines!(s)).collect();
+    let albums: Vec<_> = browser
+        .albums
+        .iter()
+        .map(|(artist, album)| {
+            let album = normalize.album(album).into_owned();
+            //Albums from different artists are listed together.
+            if shown(Mode::Artist) {
+                lines!(album)
+            } else {
+                lines!(format!("{album} - {artist}"))
+            }
+        })
+        .collect();
+    let songs: Vec<_> = browser
+        .songs
+        .iter()
+        .map(|(s, path)| {
+            let title = normalize.title(s).into_owned();
+            if db.is_liked(path) {
+                lines!("♥ ", title)
+            } else {
+                lines!(title)
+            }
+        })
+        .collect();
 
-    fn list<'a>(title: &'static str, items: Vec<Line<'a>>, use_symbol: bool) -> List<'a> {
+    fn list<'a>(title: &'a str, items: Vec<Line<'a>>, use_symbol: bool) -> List<'a> {
         let block = block().title(title.bold()).title_margin(1);
         let symbol = if use_symbol { ">" } else { " " };
         winter::list(&items).block(block).symbol(symbol)
     }
 
-    let artists = list("Aritst", artists, browser.mode == Mode::Artist);
-    let albums = list("Album", albums, browser.mode == Mode::Album);
-    let songs = list("Song", songs, browser.mode == Mode::Song);
+    //The songs that are listed, they aren't loaded until the albums are focused.
+    let listed = (!browser.songs.is_empty()).then_some(browser.songs.len());
+    let song_titles = match listed {
+        Some(listed) => vec![
+            format!(
+                "Song ({} total / {} shown)",
+                thousands(db.len),
+                thousands(listed)
+            ),
+            format!("Song ({} shown)", thousands(listed)),
+        ],
+        None => vec![format!("Song ({})", thousands(db.len))],
+    };
+
+    let group = match browser.layout {
+        BrowserMode::YearArtistAlbum => "Year",
+        BrowserMode::DecadeAlbum => "Decade",
+        _ => "Genre",
+    };
+    let titles = [
+        fit(
+            chunks[0].width,
+            //Without "All".
+            &[format!("{group} ({})", thousands(browser.groups.len() - 1))],
+            group,
+        ),
+        fit(
+            chunks[1].width,
+            &[format!("Artist ({})", thousands(db.artist_count))],
+            "Artist",
+        ),
+        fit(
+            chunks[2].width,
+            &[format!("Album ({})", thousands(db.album_count))],
+            "Album",
+        ),
+        fit(chunks[3].width, &song_titles, "Song"),
+    ];
+    let groups = list(&titles[0], groups, browser.mode == Mode::Group);
+    let artists = list(&titles[1], artists, browser.mode == Mode::Artist);
+    let albums = list(&titles[2], albums, browser.mode == Mode::Album);
+    let songs = list(&titles[3], songs, browser.mode == Mode::Song);
+
+    //One message across the panes, they're too narrow on their own.
+    if db.len == 0 {
+        block().title("Browser").title_margin(1).draw(area, buf);
+        draw_empty(area, buf, EMPTY_LIBRARY);
+    } else {
+        if shown(Mode::Group) {
+            groups.draw(chunks[0], buf, browser.groups.index());
+        }
+        if shown(Mode::Artist) {
+            artists.draw(chunks[1], buf, browser.artists.index());
+        }
+        albums.draw(chunks[2], buf, browser.albums.index());
+        songs.draw(chunks[3], buf, browser.songs.index());
+    }
 
-    artists.draw(chunks[0], buf, browser.artists.index());
-    albums.draw(chunks[1], buf, browser.albums.index());
-    songs.draw(chunks[2], buf, browser.songs.index());
+    let Some(path) = &browser.path else {
+        return None;
+    };
+
+    let Ok(area) = area.centered(60, 3) else {
+        return None;
+    };
+
+    buf.clear(area);
+
+    lines!(path.as_str())
+        .block(block().title("Open path").title_margin(1))
+        .scroll()
+        .draw(area, buf);
+
+    //Draw the cursor.
+    let (x, y) = (area.x + 2, area.y + 2);
+    let width = area.width.saturating_sub(3);
+    let len = to_u16_clamped(path.len());
+    if len < width {
+        Some((x + len, y))
+    } else {
+        Some((x + width, y))
+    }
+}
+
+///Format a number with thousands separators.
+pub fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+///The first title that fits in a pane of this width, counts are dropped from narrow panes.
+fn fit(width: u16, titles: &[String], fallback: &str) -> String {
+    //Both borders and the margin either side of the title.
+    let space = width.saturating_sub(4) as usize;
+    titles
+        .iter()
+        .find(|title| title.len() <= space)
+        .cloned()
+        .unwrap_or_else(|| fallback.to_string())
+}
+
+///Complete the last component of a path using the filesystem.
+pub fn complete(path: &mut String) {
+    let Some(i) = path.rfind(['/', '\\']) else {
+        return;
+    };
+    let (dir, prefix) = path.split_at(i + 1);
+    let prefix = prefix.to_lowercase();
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let mut name = entry.file_name().to_str()?.to_string();
+            if !name.to_lowercase().starts_with(&prefix) {
+                return None;
+            }
+            if entry.path().is_dir() {
+                name.push(MAIN_SEPARATOR);
+            }
+            Some(name)
+        })
+        .collect();
+
+    let Some(first) = names.first() else {
+        return;
+    };
+
+    //Complete as much as every match has in common.
+    let len = names.iter().fold(first.len(), |len, name| {
+        let common: usize = first
+            .chars()
+            .zip(name.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+        common.min(len)
+    });
+
+    if len >= prefix.len() {
+        *path = format!("{dir}{}", &first[..len]);
+    }
 }
 
 pub fn refresh(browser: &mut Browser, db: &Database) {
-    browser.mode = Mode::Artist;
+    browser.mode = first(browser.layout);
 
-    browser.artists = Index::new(db.artists().into_iter().cloned().collect(), Some(0));
+    //Keep the group if it's still there.
+    let group = browser.groups.selected().cloned();
+    browser.groups = Index::from(groups(db, browser.layout));
+    if let Some(i) = group.and_then(|group| browser.groups.iter().position(|g| *g == group)) {
+        browser.groups.select(Some(i));
+    }
     browser.albums = Index::default();
     browser.songs = Index::default();
 
-    update_albums(browser, db);
+    update_artists(browser, db);
+}
+
+///Switch the panes that are shown, the selection goes back to the top.
+pub fn set_layout(browser: &mut Browser, db: &Database, layout: BrowserMode) {
+    browser.layout = layout;
+    browser.groups = Index::from(groups(db, layout));
+    refresh(browser, db);
+}
+
+///Every genre, year or decade with `None` first.
+fn groups(db: &Database, layout: BrowserMode) -> Vec<Option<Group>> {
+    let mut groups = vec![None];
+    match layout {
+        BrowserMode::ArtistAlbumSong => (),
+        BrowserMode::GenreArtistAlbum => groups.extend(
+            db.genres()
+                .into_iter()
+                .map(|genre| Some(Group::Genre(genre.clone()))),
+        ),
+        BrowserMode::YearArtistAlbum => {
+            groups.extend(db.years().into_iter().map(|year| Some(Group::Year(year))))
+        }
+        BrowserMode::DecadeAlbum => groups.extend(
+            db.decades()
+                .into_iter()
+                .map(|decade| Some(Group::Decade(decade))),
+        ),
+    }
+    groups
+}
+
+///The group that's selected, `None` if every artist is shown.
+fn selected_group(browser: &Browser) -> Option<&Group> {
+    browser.groups.selected()?.as_ref()
 }
 
 pub fn update(browser: &mut Browser, db: &Database) {
     match browser.mode {
+        Mode::Group => update_artists(browser, db),
         Mode::Artist => update_albums(browser, db),
         Mode::Album => update_songs(browser, db),
         Mode::Song => (),
     }
 }
 
-pub fn update_albums(browser: &mut Browser, db: &Database) {
-    //Update the album based on artist selection
-    if let Some(artist) = browser.artists.selected() {
-        browser.albums = Index::from(db.albums_by_artist(artist));
+///Change the order of the artists and albums, the selection goes back to the top.
+pub fn set_sort(browser: &mut Browser, db: &Database, sort: BrowserSort) {
+    browser.sort = sort;
+    update_artists(browser, db);
+}
+
+///Sort again after a play is counted, the selection stays where it was.
+pub fn resort(browser: &mut Browser, db: &Database) {
+    if browser.sort == BrowserSort::Name {
+        return;
+    }
+    let artist = browser.artists.selected().cloned();
+    let album = browser.albums.selected().cloned();
+    let song = (!browser.songs.is_empty()).then(|| browser.songs.index());
+
+    update_artists(browser, db);
+    if let Some(i) = artist.and_then(|artist| browser.artists.iter().position(|a| *a == artist)) {
+        browser.artists.select(Some(i));
+        update_albums(browser, db);
+    }
+    if let Some(i) = album.and_then(|album| browser.albums.iter().position(|a| *a == album)) {
+        browser.albums.select(Some(i));
+    }
+    //The songs in an album aren't sorted by plays.
+    if let Some(song) = song {
         update_songs(browser, db);
+        browser.songs.select(song);
     }
 }
 
-pub fn update_songs(browser: &mut Browser, db: &Database) {
-    if let Some(artist) = browser.artists.selected() {
-        if let Some(album) = browser.albums.selected() {
-            let songs: Vec<(String, (u8, u8))> = db
-                .album(artist, &album.title)
-                .songs
+///Most played or most recently played first, ties stay in alphabetical order.
+fn sort_by_plays<T>(items: &mut [T], sort: BrowserSort, plays: impl Fn(&T) -> Plays) {
+    match sort {
+        BrowserSort::Name => (),
+        BrowserSort::MostPlayed => items.sort_by_cached_key(|item| Reverse(plays(item).count)),
+        BrowserSort::RecentlyPlayed => {
+            items.sort_by_cached_key(|item| Reverse(plays(item).last_played))
+        }
+    }
+}
+
+pub fn update_artists(browser: &mut Browser, db: &Database) {
+    let mut artists = match selected_group(browser) {
+        Some(group) => db.artists_in(group),
+        None => db.artists(),
+    };
+    sort_by_plays(&mut artists, browser.sort, |artist| db.artist_plays(artist));
+    browser.artists = Index::from(artists.into_iter().cloned().collect::<Vec<_>>());
+    update_albums(browser, db);
+}
+
+pub fn update_albums(browser: &mut Browser, db: &Database) {
+    //Without an artist pane every album is listed.
+    let mut albums = if panes(browser.layout).contains(&Mode::Artist) {
+        match browser.artists.selected() {
+            Some(artist) => db
+                .albums_by_artist(artist)
                 .iter()
-                .map(|song| {
-                    (
-                        format!("{}. {}", song.track_number, song.title),
-                        (song.disc_number, song.track_number),
-                    )
-                })
-                .collect();
-            browser.songs = Index::from(songs);
+                .map(|album| (artist, album))
+                .collect(),
+            None => Vec::new(),
         }
+    } else {
+        db.albums()
+    };
+    if let Some(group) = selected_group(browser) {
+        albums.retain(|(_, album)| album.songs.iter().any(|song| group.contains(song)));
+    }
+    sort_by_plays(&mut albums, browser.sort, |(_, album)| {
+        db.album_plays(album)
+    });
+    let albums: Vec<(String, String)> = albums
+        .into_iter()
+        .map(|(artist, album)| (artist.clone(), album.title.clone()))
+        .collect();
+    browser.albums = Index::from(albums);
+
+    //Songs are loaded again when the album list is focused.
+    browser.songs = Index::default();
+}
+
+pub fn update_songs(browser: &mut Browser, db: &Database) {
+    if let Some((artist, album)) = browser.albums.selected() {
+        let songs: Vec<(String, String)> = db
+            .album(artist, album)
+            .map_or(&[][..], |album| album.songs.as_slice())
+            .iter()
+            .map(|song| {
+                let title = format!("{}. {}", song.track_number, song.title);
+                if song.guessed {
+                    (format!("{title} (guessed)"), song.path.clone())
+                } else {
+                    (title, song.path.clone())
+                }
+            })
+            .collect();
+        browser.songs = Index::from(songs);
+    }
+}
+
+///The selected song when the song list is focused.
+pub fn selected_song(browser: &Browser, db: &Database) -> Option<Song> {
+    if browser.mode != Mode::Song {
+        return None;
     }
+    let (artist, album) = browser.albums.selected()?;
+    let (_, path) = browser.songs.selected()?;
+    db.song(artist, album, path).cloned()
 }
 
 pub fn get_selected(browser: &Browser, db: &Database) -> Vec<Song> {
-    if let Some(artist) = browser.artists.selected() {
-        if let Some(album) = browser.albums.selected() {
-            if let Some((_, (disc, number))) = browser.songs.selected() {
-                return match browser.mode {
-                    Mode::Artist => db
-                        .albums_by_artist(artist)
-                        .iter()
-                        .flat_map(|album| album.songs.iter().map(|song| song.clone().clone()))
-                        .collect(),
-                    Mode::Album => db.album(artist, &album.title).songs.to_vec(),
-                    Mode::Song => {
-                        vec![db.song(artist, &album.title, *disc, *number).clone()]
+    match browser.mode {
+        //Not the whole library.
+        Mode::Group => match selected_group(browser) {
+            Some(group) => db
+                .songs()
+                .filter(|song| group.contains(song))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        },
+        Mode::Artist => match browser.artists.selected() {
+            Some(artist) => db
+                .albums_by_artist(artist)
+                .iter()
+                .flat_map(|album| album.songs.iter().cloned())
+                .collect(),
+            None => Vec::new(),
+        },
+        Mode::Album => browser
+            .albums
+            .selected()
+            .and_then(|(artist, album)| db.album(artist, album))
+            .map(|album| album.songs.to_vec())
+            .unwrap_or_default(),
+        Mode::Song => selected_song(browser, db).into_iter().collect(),
+    }
+}
+
+impl Screen for Browser {
+    fn draw(&mut self, ctx: &mut Context, area: Rect, buf: &mut Buffer) -> Option<(u16, u16)> {
+        draw(self, area, buf, ctx.db, &ctx.normalize, ctx.hitboxes)
+    }
+
+    fn handle_key(
+        &mut self,
+        ctx: &mut Context,
+        event: &Event,
+        shift: bool,
+        _control: bool,
+    ) -> Option<Vec<Action>> {
+        let mut actions = Vec::new();
+        match *event {
+            Event::Char(c) if self.path.is_some() => {
+                if let Some(path) = &mut self.path {
+                    path.push(c);
+                }
+            }
+            Event::Backspace if self.path.is_some() => {
+                if let Some(path) = &mut self.path {
+                    path.pop();
+                }
+            }
+            Event::Tab if self.path.is_some() => {
+                if let Some(path) = &mut self.path {
+                    complete(path);
+                }
+            }
+            Event::Escape if self.path.is_some() => self.path = None,
+            Event::Enter if self.path.is_some() => {
+                let input = self.path.take().unwrap_or_default();
+                match path(input) {
+                    Some(path) if path.is_dir() => {
+                        let new = db::read_dir(&path.to_string_lossy(), ctx.persist.guess_tags)
+                            .into_iter()
+                            .flatten()
+                            .collect();
+                        actions.push(Action::Enqueue(new));
                     }
-                };
+                    //Play the file without adding it to the database.
+                    Some(path) => match Song::try_from(path.as_path()) {
+                        Ok(song) => play_song(&song),
+                        Err(err) => actions.push(Action::Message(err.to_string())),
+                    },
+                    None => actions.push(Action::Message(String::from("Invalid path."))),
+                }
             }
+            #[cfg(feature = "playlists")]
+            Event::Enter if shift => {
+                actions.push(Action::AddToPlaylist(get_selected(self, ctx.db)));
+            }
+            //Songs are always appended, playback only starts if the queue was empty.
+            Event::Enter | Event::Char('+') => {
+                if let Some(p) = pending(self, ctx.db) {
+                    actions.push(Action::Pending(p));
+                } else {
+                    let selected = get_selected(self, ctx.db);
+                    actions.push(match selected.len() {
+                        0 if ctx.db.len == 0 => Action::Message(EMPTY_LIBRARY.to_string()),
+                        0 => Action::Message(String::from("Nothing is selected.")),
+                        _ => Action::Enqueue(selected),
+                    });
+                }
+            }
+            Event::Char('u') => actions.push(Action::Scan),
+            #[cfg(feature = "playlists")]
+            Event::Char('f') if ctx.db.len != 0 => {
+                actions.push(Action::Like(get_selected(self, ctx.db)));
+            }
+            Event::Char('o') => self.path = Some(String::new()),
+            Event::Up | Event::Char('k') | Event::Char('K') => up(self, ctx.db, amount(shift)),
+            Event::Down | Event::Char('j') | Event::Char('J') => down(self, ctx.db, amount(shift)),
+            Event::Left | Event::Char('h') | Event::Char('H') => left(self),
+            Event::Right | Event::Char('l') | Event::Char('L') => right(self, ctx.db),
+            _ => return None,
+        }
+        Some(actions)
+    }
+
+    #[cfg(feature = "mouse")]
+    fn handle_mouse(&mut self, ctx: &mut Context, widget: Widget) -> Vec<Action> {
+        match widget {
+            Widget::BrowserGroup => self.mode = Mode::Group,
+            Widget::BrowserArtist => self.mode = Mode::Artist,
+            Widget::BrowserAlbum => focus(self, ctx.db, Mode::Album),
+            Widget::BrowserSong => focus(self, ctx.db, Mode::Song),
+            _ => {}
         }
+        Vec::new()
+    }
+
+    fn wants_text_input(&self) -> Option<Capture> {
+        self.path.as_ref().map(|_| Capture::Path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(album: &str, title: &str) -> Song {
+        Song::test("artist", album, title)
+    }
+
+    fn group_names(browser: &Browser) -> Vec<String> {
+        browser
+            .groups
+            .iter()
+            .map(|group| group.as_ref().map_or(String::from(ALL), Group::name))
+            .collect()
+    }
+
+    fn album_titles(browser: &Browser) -> Vec<&str> {
+        browser
+            .albums
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1204), "1,204");
+        assert_eq!(thousands(52330), "52,330");
+        assert_eq!(thousands(1234567), "1,234,567");
+
+        let titles = [
+            String::from("Song (52,330 total / 12 shown)"),
+            String::from("Song (12 shown)"),
+        ];
+        assert_eq!(fit(40, &titles, "Song"), titles[0]);
+        assert_eq!(fit(34, &titles, "Song"), titles[0]);
+        assert_eq!(fit(33, &titles, "Song"), titles[1]);
+        assert_eq!(fit(10, &titles, "Song"), "Song");
+        assert_eq!(fit(0, &[], "Song"), "Song");
+    }
+
+    #[test]
+    fn lazy() {
+        let guessed = Song {
+            guessed: true,
+            ..song("x", "2")
+        };
+        let db = Database::from_songs(vec![song("x", "1"), guessed, song("y", "3")]);
+        let mut browser = Browser::new(&db);
+        assert_eq!(browser.artists.len(), 1);
+        assert!(browser.albums.is_empty());
+        assert!(browser.songs.is_empty());
+
+        //Everything by the artist can be added before anything else is loaded.
+        assert_eq!(get_selected(&browser, &db).len(), 3);
+
+        update_albums(&mut browser, &db);
+        assert_eq!(browser.albums.len(), 2);
+        assert!(browser.songs.is_empty());
+
+        right(&mut browser, &db);
+        let mut titles: Vec<_> = browser
+            .songs
+            .iter()
+            .map(|(title, _)| title.as_str())
+            .collect();
+        titles.sort_unstable();
+        assert_eq!(titles, ["1. 1", "1. 2 (guessed)"]);
+        down(&mut browser, &db, 1);
+        assert_eq!(browser.songs.len(), 1);
+        assert_eq!(get_selected(&browser, &db)[0].title, "3");
+
+        right(&mut browser, &db);
+        assert_eq!(selected_song(&browser, &db).unwrap().title, "3");
+    }
+
+    #[test]
+    fn sorting() {
+        let plays = |name: &&str| match *name {
+            "a" => Plays {
+                count: 1,
+                last_played: 30,
+            },
+            "b" => Plays {
+                count: 5,
+                last_played: 10,
+            },
+            "c" => Plays {
+                count: 2,
+                last_played: 20,
+            },
+            _ => Plays::default(),
+        };
+        let sorted = |sort| {
+            let mut names = ["a", "b", "c", "d"];
+            sort_by_plays(&mut names, sort, plays);
+            names
+        };
+        assert_eq!(sorted(BrowserSort::Name), ["a", "b", "c", "d"]);
+        assert_eq!(sorted(BrowserSort::MostPlayed), ["b", "c", "a", "d"]);
+        assert_eq!(sorted(BrowserSort::RecentlyPlayed), ["a", "c", "b", "d"]);
+
+        //Never played songs don't change the order.
+        let db = Database::from_songs(vec![song("x", "1"), song("y", "2")]);
+        let mut browser = Browser::new(&db);
+        set_sort(&mut browser, &db, BrowserSort::MostPlayed);
+        assert_eq!(album_titles(&browser), ["x", "y"]);
+    }
+
+    #[test]
+    fn genres() {
+        let tagged = |artist: &str, genre: &str| Song {
+            artist: artist.to_string(),
+            genre: genre.to_string(),
+            ..song(artist, artist)
+        };
+        let db = Database::from_songs(vec![
+            tagged("a", "Rock"),
+            tagged("b", "Jazz"),
+            tagged("c", "Rock"),
+            tagged("d", ""),
+        ]);
+        let mut browser = Browser::new(&db);
+        assert_eq!(group_names(&browser), ["All", "Jazz", "Rock"]);
+        assert_eq!(browser.artists.len(), 4);
+
+        left(&mut browser);
+        assert!(browser.mode == Mode::Group);
+        //Every artist, nothing is added to the queue.
+        assert!(get_selected(&browser, &db).is_empty());
+
+        down(&mut browser, &db, 2);
+        assert_eq!(*browser.artists, ["a", "c"]);
+        assert_eq!(album_titles(&browser), ["a"]);
+        let titles: Vec<_> = get_selected(&browser, &db)
+            .into_iter()
+            .map(|song| song.title)
+            .collect();
+        assert_eq!(titles, ["a", "c"]);
+
+        //The genre is kept after a rescan.
+        refresh(&mut browser, &db);
+        assert_eq!(*browser.artists, ["a", "c"]);
+
+        down(&mut browser, &db, 1);
+        assert_eq!(album_titles(&browser), ["c"]);
+    }
+
+    #[test]
+    fn chunks() {
+        //Two artists in the genre and one song in another genre.
+        let mut songs: Vec<Song> = (0..=CHUNK_THRESHOLD)
+            .map(|i| Song {
+                artist: if i % 2 == 0 { "a" } else { "b" }.to_string(),
+                genre: String::from("Rock"),
+                ..song("x", &i.to_string())
+            })
+            .collect();
+        songs.push(Song {
+            artist: String::from("a"),
+            genre: String::from("Jazz"),
+            ..song("y", "jazz")
+        });
+        let db = Database::from_songs(songs);
+        let mut browser = Browser::new(&db);
+
+        //Neither artist is large enough on its own.
+        assert!(pending(&browser, &db).is_none());
+
+        left(&mut browser);
+        down(&mut browser, &db, 1);
+        assert!(pending(&browser, &db).is_none());
+
+        //The whole genre is.
+        down(&mut browser, &db, 1);
+        assert_eq!(
+            selected_group(&browser),
+            Some(&Group::Genre(String::from("Rock")))
+        );
+        let mut p = pending(&browser, &db).unwrap();
+        let mut queue = Index::default();
+        while enqueue_chunks(&mut p, &mut queue) {}
+        assert_eq!(queue.len(), CHUNK_THRESHOLD + 1);
+        assert_eq!(p.added, CHUNK_THRESHOLD + 1);
+        assert!(queue.iter().all(|song| song.genre == "Rock"));
+
+        //Each artist's albums are added oldest first.
+        let dated = |album: &str, year: u16| Song {
+            year,
+            ..song(album, album)
+        };
+        let db = Database::from_songs(vec![dated("b", 2001), dated("c", 1994), dated("a", 2001)]);
+        let mut p = Pending::new(&db, &[String::from("artist")], None);
+        let mut queue = Index::default();
+        while enqueue_chunks(&mut p, &mut queue) {}
+        let albums: Vec<&str> = queue.iter().map(|song| song.album.as_str()).collect();
+        assert_eq!(albums, ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn layouts() {
+        let dated = |artist: &str, album: &str, year: u16| Song {
+            artist: artist.to_string(),
+            year,
+            ..song(album, album)
+        };
+        let db = Database::from_songs(vec![
+            dated("a", "first", 1994),
+            dated("b", "second", 2001),
+            dated("b", "third", 1998),
+            dated("c", "untagged", 0),
+        ]);
+        let mut browser = Browser::new(&db);
+
+        set_layout(&mut browser, &db, BrowserMode::YearArtistAlbum);
+        assert_eq!(group_names(&browser), ["All", "1994", "1998", "2001"]);
+        assert!(browser.mode == Mode::Artist);
+        left(&mut browser);
+        down(&mut browser, &db, 2);
+        assert_eq!(*browser.artists, ["b"]);
+        assert_eq!(album_titles(&browser), ["third"]);
+
+        //The albums are listed straight after the decade.
+        set_layout(&mut browser, &db, BrowserMode::DecadeAlbum);
+        assert_eq!(group_names(&browser), ["All", "1990s", "2000s"]);
+        assert!(browser.mode == Mode::Album);
+        left(&mut browser);
+        assert!(browser.mode == Mode::Group);
+        down(&mut browser, &db, 1);
+        assert_eq!(album_titles(&browser), ["first", "third"]);
+        right(&mut browser, &db);
+        assert!(browser.mode == Mode::Album);
+        assert_eq!(browser.songs.len(), 1);
+        let titles: Vec<_> = get_selected(&browser, &db)
+            .into_iter()
+            .map(|song| song.title)
+            .collect();
+        assert_eq!(titles, ["first"]);
+
+        //Without groups the artists are the first pane.
+        set_layout(&mut browser, &db, BrowserMode::ArtistAlbumSong);
+        assert_eq!(group_names(&browser), ["All"]);
+        left(&mut browser);
+        assert!(browser.mode == Mode::Artist);
+        assert_eq!(browser.artists.len(), 3);
     }
-    todo!()
 }
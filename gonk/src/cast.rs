@@ -0,0 +1,21 @@
+//! Conversions for UI math that saturate instead of wrapping.
+//!
+//! Lengths come from user input and the queue so they can be far larger than the terminal.
+
+///Convert a length or index to a terminal coordinate.
+pub fn to_u16_clamped(n: usize) -> u16 {
+    u16::try_from(n).unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamped() {
+        assert_eq!(to_u16_clamped(0), 0);
+        assert_eq!(to_u16_clamped(65_535), u16::MAX);
+        assert_eq!(to_u16_clamped(70_000), u16::MAX);
+        assert_eq!(to_u16_clamped(usize::MAX), u16::MAX);
+    }
+}
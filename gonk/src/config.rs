@@ -0,0 +1,223 @@
+//! Key bindings
+//!
+//! Read from `keybindings.toml` in the gonk folder, one binding per line:
+//!
+//! ```toml
+//! # Seek with the keys under < and >
+//! seek_backward = ","
+//! seek_forward = "."
+//! play_pause = "space"
+//! ```
+//!
+//! A remapped key is turned back into its default before it's handled,
+//! so the rest of the app only knows about the default keys.
+//!
+//! The theme is read the same way.
+use gonk_core::gonk_path;
+use std::fs;
+
+///Action, default key and what it does.
+pub const ACTIONS: [(&str, char, &str); 20] = [
+    ("play_pause", ' ', "Play/Pause"),
+    ("next", 'd', "Next song"),
+    ("previous", 'a', "Previous song"),
+    ("seek_forward", 'e', "Seek forward"),
+    ("seek_backward", 'q', "Seek backward"),
+    ("volume_up", 'w', "Volume up"),
+    ("volume_down", 's', "Volume down"),
+    ("mute", 'z', "Mute"),
+    ("slower", '<', "Slower"),
+    ("faster", '>', "Faster"),
+    ("repeat", 'r', "Repeat mode"),
+    ("shuffle", 'S', "Shuffle"),
+    ("loop", 'i', "A-B loop"),
+    ("clear", 'c', "Clear queue"),
+    ("queue", '1', "Queue"),
+    ("browser", '2', "Browser"),
+    ("playlists", '3', "Playlists"),
+    ("settings", '4', "Settings"),
+    ("search", '/', "Search"),
+    ("help", '?', "Help"),
+];
+
+///Keys that do something but can't be moved, like the vim style h/j/k/l.
+///
+///An action bound to one of these hides what the key did before.
+pub const BUILT_IN: &str = ")+-0=ABCDEHJKLMORTVX[]bfghjklmnoptuvxy{}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    ///The key pressed for each action in `ACTIONS`.
+    keys: [char; ACTIONS.len()],
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: ACTIONS.map(|(_, key, _)| key),
+        }
+    }
+}
+
+impl KeyBindings {
+    ///Missing files use the defaults. Lines that can't be read are skipped and returned as errors.
+    pub fn load() -> (Self, Vec<String>) {
+        match fs::read_to_string(gonk_path().join("keybindings.toml")) {
+            Ok(file) => Self::parse(&file),
+            Err(_) => (Self::default(), Vec::new()),
+        }
+    }
+
+    pub fn parse(file: &str) -> (Self, Vec<String>) {
+        let mut bindings = Self::default();
+        let mut errors = read_lines("keybindings.toml", file, |action, key| {
+            let index = ACTIONS
+                .iter()
+                .position(|(name, _, _)| *name == action)
+                .ok_or_else(|| format!("Unknown action: {action}"))?;
+            bindings.keys[index] = key_from_str(key)?;
+            Ok(())
+        });
+        errors.extend(bindings.shadowed());
+        (bindings, errors)
+    }
+
+    ///Warnings for actions bound to a built-in key.
+    fn shadowed(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .zip(ACTIONS)
+            .filter(|(key, _)| BUILT_IN.contains(**key))
+            .map(|(key, (action, _, _))| {
+                format!("keybindings.toml: {action} = \"{key}\" hides the built-in {key} key.")
+            })
+            .collect()
+    }
+
+    ///The default key for `key`, or `None` if the key was remapped away and does nothing now.
+    pub fn translate(&self, key: char) -> Option<char> {
+        //A key bound to an action is always that action.
+        if let Some(i) = self.keys.iter().position(|k| *k == key) {
+            return Some(ACTIONS[i].1);
+        }
+        //The action's default key was moved somewhere else.
+        if ACTIONS.iter().any(|(_, default, _)| *default == key) {
+            return None;
+        }
+        Some(key)
+    }
+}
+
+///Call `set` with the name and value on each line, skipping blank lines and comments.
+///
+///Returns the lines that couldn't be read.
+pub fn read_lines(
+    file_name: &str,
+    file: &str,
+    mut set: impl FnMut(&str, &str) -> Result<(), String>,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Err(err) = parse_line(line).and_then(|(name, value)| set(name, value)) {
+            errors.push(format!("{file_name} line {}: {err}", i + 1));
+        }
+    }
+    errors
+}
+
+///Split `name = "value"` into the name and the value without quotes.
+fn parse_line(line: &str) -> Result<(&str, &str), String> {
+    let (name, value) = line
+        .split_once('=')
+        .ok_or_else(|| format!("Expected name = \"value\": {line}"))?;
+    //Strip the comment after the value.
+    let value = value.split(" #").next().unwrap_or_default().trim();
+    let unquoted = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .ok_or_else(|| format!("Values need to be in quotes: {value}"))?;
+    Ok((name.trim(), unquoted))
+}
+
+fn key_from_str(key: &str) -> Result<char, String> {
+    if key.eq_ignore_ascii_case("space") {
+        return Ok(' ');
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("Keys must be a single character or space: {key}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let bindings = KeyBindings::default();
+        for (_, key, _) in ACTIONS {
+            assert_eq!(bindings.translate(key), Some(key));
+        }
+        assert_eq!(bindings.translate('x'), Some('x'));
+
+        //Two actions can't share a default key.
+        for (i, (_, a, _)) in ACTIONS.iter().enumerate() {
+            assert!(ACTIONS[i + 1..].iter().all(|(_, b, _)| a != b), "{a}");
+            assert!(!BUILT_IN.contains(*a), "{a}");
+        }
+    }
+
+    #[test]
+    fn remap() {
+        let file = "
+            # Seek with the keys under < and >
+            seek_backward = \",\"
+            seek_forward = '.' # right
+            play_pause = \"space\"
+            next = \"q\"
+        ";
+        let (bindings, errors) = KeyBindings::parse(file);
+        assert!(errors.is_empty(), "{errors:?}");
+
+        assert_eq!(bindings.translate(','), Some('q'));
+        assert_eq!(bindings.translate('.'), Some('e'));
+        assert_eq!(bindings.translate(' '), Some(' '));
+        //The old seek backward key is now next.
+        assert_eq!(bindings.translate('q'), Some('d'));
+        //Moved away.
+        assert_eq!(bindings.translate('e'), None);
+        assert_eq!(bindings.translate('d'), None);
+        //Untouched.
+        assert_eq!(bindings.translate('w'), Some('w'));
+    }
+
+    #[test]
+    fn shadowed() {
+        let (bindings, errors) = KeyBindings::parse("seek_backward = \"h\"\nnext = \";\"");
+        assert_eq!(
+            errors,
+            ["keybindings.toml: seek_backward = \"h\" hides the built-in h key."]
+        );
+        //It's still bound, the warning is so the user knows why h stopped moving left.
+        assert_eq!(bindings.translate('h'), Some('q'));
+    }
+
+    #[test]
+    fn errors() {
+        let file = "next = \";\"\nfly = \"f\"\nprevious = p\nmute = \"ab\"\nvolume_up";
+        let (bindings, errors) = KeyBindings::parse(file);
+        assert_eq!(bindings.translate(';'), Some('d'));
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].starts_with("keybindings.toml line 2: Unknown action"));
+        //Bad lines keep their default.
+        assert_eq!(bindings.translate('a'), Some('a'));
+        assert_eq!(bindings.translate('z'), Some('z'));
+    }
+}
@@ -0,0 +1,117 @@
+//! Show notifications on the desktop.
+//!
+//! The message is passed through an argument or the environment so it never needs quoting.
+use gonk_core::log;
+use std::{
+    process::{Command, Stdio},
+    thread,
+};
+
+///The command that shows a notification.
+#[cfg(windows)]
+pub fn command(message: &str) -> Command {
+    //A balloon tip from a tray icon works without registering an app id.
+    const SCRIPT: &str = "Add-Type -AssemblyName System.Windows.Forms; \
+        $icon = New-Object System.Windows.Forms.NotifyIcon; \
+        $icon.Icon = [System.Drawing.SystemIcons]::Information; \
+        $icon.Visible = $true; \
+        $icon.ShowBalloonTip(5000, 'gonk', $env:GONK_NOTIFICATION, 'None'); \
+        Start-Sleep -Seconds 6; \
+        $icon.Dispose()";
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-WindowStyle", "Hidden", "-Command", SCRIPT])
+        .env("GONK_NOTIFICATION", message);
+    command
+}
+
+///The command that shows a notification.
+#[cfg(target_os = "macos")]
+pub fn command(message: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command
+        .args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 1 of argv) with title \"gonk\"",
+            "-e",
+            "end run",
+        ])
+        .arg(message);
+    command
+}
+
+///The command that shows a notification.
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn command(message: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.arg("gonk").arg(message);
+    command
+}
+
+///Show a notification without waiting for it.
+pub fn notify(message: &str) {
+    let child = command(message)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(err) => log!("Couldn't show a desktop notification: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn args(command: &Command) -> Vec<&OsStr> {
+        command.get_args().collect()
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn windows() {
+        let command = command("Added 2 songs from \"Björk\" & 'Sigur Rós'.");
+        assert_eq!(command.get_program(), "powershell");
+        assert!(args(&command)
+            .last()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("$env:GONK_NOTIFICATION"));
+        let env: Vec<_> = command.get_envs().collect();
+        assert_eq!(
+            env,
+            [(
+                OsStr::new("GONK_NOTIFICATION"),
+                Some(OsStr::new("Added 2 songs from \"Björk\" & 'Sigur Rós'."))
+            )]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn macos() {
+        let command = command("Added 2 songs from \"Björk\".");
+        assert_eq!(command.get_program(), "osascript");
+        assert_eq!(
+            args(&command).last().unwrap(),
+            &"Added 2 songs from \"Björk\"."
+        );
+    }
+
+    #[test]
+    #[cfg(not(any(windows, target_os = "macos")))]
+    fn linux() {
+        let command = command("Added 2 songs from \"Björk\".");
+        assert_eq!(command.get_program(), "notify-send");
+        assert_eq!(args(&command), ["gonk", "Added 2 songs from \"Björk\"."]);
+    }
+}
@@ -0,0 +1,275 @@
+//! Discord rich presence
+//!
+//! Talks to the local Discord client over its IPC socket on a background thread.
+//! The client id of the Discord application is set at build time with `GONK_DISCORD_CLIENT_ID`.
+use crate::nowplaying::escape;
+use gonk_core::{log, Song};
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc::{channel, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+///Discord allows 5 updates every 20 seconds.
+const RATE_LIMIT: Duration = Duration::from_secs(4);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+const HANDSHAKE: u32 = 0;
+const FRAME: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Activity {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub elapsed: Duration,
+    pub duration: Duration,
+    pub paused: bool,
+}
+
+impl Activity {
+    pub fn new(song: &Song, elapsed: Duration, duration: Duration, paused: bool) -> Self {
+        Self {
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            elapsed,
+            duration,
+            paused,
+        }
+    }
+}
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+///Spawn the presence thread. Sending `None` clears the presence.
+///
+///Returns `None` when gonk was built without a client id.
+pub fn spawn() -> Option<Sender<Option<Activity>>> {
+    let Some(client_id) = option_env!("GONK_DISCORD_CLIENT_ID") else {
+        log!("Discord presence is unavailable, GONK_DISCORD_CLIENT_ID was not set.");
+        return None;
+    };
+
+    let (sender, receiver) = channel::<Option<Activity>>();
+    thread::spawn(move || {
+        let mut latest: Option<Activity> = None;
+        let mut backoff = Duration::from_secs(1);
+        let mut logged = false;
+        let mut nonce = 0;
+
+        loop {
+            let mut stream = match connect(client_id) {
+                Ok(stream) => {
+                    backoff = Duration::from_secs(1);
+                    logged = false;
+                    stream
+                }
+                Err(err) => {
+                    if !logged {
+                        log!("Discord presence disconnected: {err}");
+                        logged = true;
+                    }
+
+                    //Keep track of the latest activity while waiting to reconnect.
+                    match receiver.recv_timeout(backoff) {
+                        Ok(activity) => latest = activity,
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            };
+
+            let mut dirty = true;
+            let mut last_update: Option<Instant> = None;
+
+            loop {
+                let wait = match last_update {
+                    Some(last) if dirty => RATE_LIMIT.saturating_sub(last.elapsed()),
+                    Some(_) => Duration::MAX,
+                    None => Duration::ZERO,
+                };
+
+                match receiver.recv_timeout(wait) {
+                    Ok(activity) => {
+                        dirty = dirty || activity != latest;
+                        latest = activity;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        //Discord also clears the activity once the socket closes.
+                        nonce += 1;
+                        let _ = send(&mut stream, &set_activity(None, nonce));
+                        return;
+                    }
+                }
+
+                if dirty {
+                    nonce += 1;
+                    if send(&mut stream, &set_activity(latest.as_ref(), nonce)).is_err() {
+                        break;
+                    }
+                    last_update = Some(Instant::now());
+                    dirty = false;
+                }
+            }
+        }
+    });
+
+    Some(sender)
+}
+
+fn connect(client_id: &str) -> io::Result<Box<dyn Stream>> {
+    let mut stream = open()?;
+    let handshake = format!("{{\"v\":1,\"client_id\":\"{}\"}}", escape(client_id));
+    stream.write_all(&frame(HANDSHAKE, &handshake))?;
+    match read(&mut stream)? {
+        (FRAME, _) => Ok(stream),
+        (_, payload) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, payload)),
+    }
+}
+
+#[cfg(unix)]
+fn open() -> io::Result<Box<dyn Stream>> {
+    use std::{env, os::unix::net::UnixStream, path::PathBuf};
+
+    let dir = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .into_iter()
+        .find_map(env::var_os)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+
+    //Flatpak and snap installs put the socket in a sub directory.
+    for sub in ["", "app/com.discordapp.Discord", "snap.discord"] {
+        for i in 0..10 {
+            if let Ok(stream) = UnixStream::connect(dir.join(sub).join(format!("discord-ipc-{i}")))
+            {
+                return Ok(Box::new(stream));
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Discord is not running",
+    ))
+}
+
+#[cfg(windows)]
+fn open() -> io::Result<Box<dyn Stream>> {
+    use std::fs::OpenOptions;
+
+    for i in 0..10 {
+        let path = format!(r"\\.\pipe\discord-ipc-{i}");
+        if let Ok(pipe) = OpenOptions::new().read(true).write(true).open(path) {
+            return Ok(Box::new(pipe));
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Discord is not running",
+    ))
+}
+
+fn send(stream: &mut Box<dyn Stream>, payload: &str) -> io::Result<()> {
+    stream.write_all(&frame(FRAME, payload))?;
+    //Discord replies to every command, the response isn't needed.
+    read(stream).map(|_| ())
+}
+
+fn frame(opcode: u32, payload: &str) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(8 + payload.len());
+    buffer.extend_from_slice(&opcode.to_le_bytes());
+    buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(payload.as_bytes());
+    buffer
+}
+
+fn read(stream: &mut Box<dyn Stream>) -> io::Result<(u32, String)> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((opcode, String::from_utf8_lossy(&payload).to_string()))
+}
+
+fn set_activity(activity: Option<&Activity>, nonce: usize) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    activity_json(activity, std::process::id(), now, nonce)
+}
+
+fn activity_json(activity: Option<&Activity>, pid: u32, now: Duration, nonce: usize) -> String {
+    //Discord rejects fields longer than 128 characters.
+    fn field(s: &str) -> String {
+        escape(&s.chars().take(128).collect::<String>())
+    }
+
+    let activity = match activity {
+        Some(a) if a.paused => format!(
+            "{{\"type\":2,\"details\":\"{}\",\"state\":\"{}\"}}",
+            field(&a.title),
+            field(&format!("Paused - {}", a.artist))
+        ),
+        Some(a) => {
+            let start = now.saturating_sub(a.elapsed);
+            let end = start + a.duration;
+            format!(
+                "{{\"type\":2,\"details\":\"{}\",\"state\":\"{}\",\"timestamps\":{{\"start\":{},\"end\":{}}}}}",
+                field(&a.title),
+                field(&format!("{} - {}", a.artist, a.album)),
+                start.as_millis(),
+                end.as_millis()
+            )
+        }
+        None => String::from("null"),
+    };
+
+    format!(
+        "{{\"cmd\":\"SET_ACTIVITY\",\"args\":{{\"pid\":{pid},\"activity\":{activity}}},\"nonce\":\"{nonce}\"}}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn activity() {
+        let mut activity = Activity::new(
+            &Song::example(),
+            Duration::from_secs(30),
+            Duration::from_secs(200),
+            false,
+        );
+        let now = Duration::from_secs(1000);
+
+        assert_eq!(
+            activity_json(Some(&activity), 1, now, 1),
+            "{\"cmd\":\"SET_ACTIVITY\",\"args\":{\"pid\":1,\"activity\":{\"type\":2,\"details\":\"title\",\"state\":\"artist - album\",\"timestamps\":{\"start\":970000,\"end\":1170000}}},\"nonce\":\"1\"}"
+        );
+
+        activity.paused = true;
+        assert_eq!(
+            activity_json(Some(&activity), 1, now, 2),
+            "{\"cmd\":\"SET_ACTIVITY\",\"args\":{\"pid\":1,\"activity\":{\"type\":2,\"details\":\"title\",\"state\":\"Paused - artist\"}},\"nonce\":\"2\"}"
+        );
+
+        assert_eq!(
+            activity_json(None, 1, now, 3),
+            "{\"cmd\":\"SET_ACTIVITY\",\"args\":{\"pid\":1,\"activity\":null},\"nonce\":\"3\"}"
+        );
+
+        let frame = frame(FRAME, "{}");
+        assert_eq!(frame, [1, 0, 0, 0, 2, 0, 0, 0, b'{', b'}']);
+    }
+}
@@ -5,7 +5,7 @@ use winter::*;
 //TODO: Add scrolling to the help menu.
 //TODO: Improve visability, it's hard to tell which option matches which command.
 //TODO: Do I have a widget for adding lines?
-pub static HELP: LazyLock<[Row; 32]> = LazyLock::new(|| {
+pub static HELP: LazyLock<[Row; 68]> = LazyLock::new(|| {
     [
         row!["Move Up".fg(Cyan), "K / UP"],
         row!["Move Down".fg(Cyan), "J / Down"],
@@ -22,25 +22,64 @@ pub static HELP: LazyLock<[Row; 32]> = LazyLock::new(|| {
         row!["Play/Pause".fg(Magenta), "Space"],
         row!["Previous".fg(Magenta), "A"],
         row!["Next".fg(Magenta), "D"],
+        row!["Previous album".fg(Magenta), "Shift + A / {"],
+        row!["Next album".fg(Magenta), "Shift + D / }"],
         row!["Seek -10s".fg(Magenta), "Q"],
         row!["Seek 10s".fg(Magenta), "E"],
+        row!["Slower".fg(Magenta), "<"],
+        row!["Faster".fg(Magenta), ">"],
         row!["Queue".fg(Blue), "1"],
         row!["Browser".fg(Blue), "2"],
         row!["Playlists".fg(Blue), "3"],
         row!["Settings".fg(Blue), "4"],
         row!["Search".fg(Blue), "/"],
         row!["Exit Search".fg(Blue), "Escape | Tab"],
+        row!["Sort search results".fg(Blue), "Tab (Select)"],
         row!["Select all".fg(Cyan), "Control + A"],
         row!["Add song to queue".fg(Cyan), "Enter"],
+        row!["Append to queue".fg(Cyan), "+ (Browser)"],
         row!["Add selection to playlist".fg(Cyan), "Shift + Enter"],
+        row!["Open path".fg(Cyan), "O (Browser)"],
+        row!["Show file in file manager".fg(Cyan), "Shift + O"],
+        row!["Group albums".fg(Cyan), "G (Queue)"],
+        row!["Restore previous queue".fg(Cyan), "Shift + R (Queue)"],
+        row!["Repeat off / queue / one".fg(Magenta), "R"],
+        row!["Shuffle".fg(Magenta), "Shift + S"],
+        row!["Loop start / end / clear".fg(Magenta), "I"],
+        row!["Preview song".fg(Cyan), "P (Browser, Search)"],
+        row!["Like song".fg(Cyan), "F (Queue, Browser)"],
         row!["Move song margin".fg(Green), "F1 / Shift + F1"],
         row!["Move album margin".fg(Green), "F2 / Shift + F2"],
         row!["Move artist margin".fg(Green), "F3 / Shift + F3"],
+        row!["Cycle column presets".fg(Green), "F4"],
+        row!["Reset columns".fg(Green), "Shift + 0"],
+        row!["Bypass replay gain".fg(Green), "F9"],
+        row!["Filter settings".fg(Yellow), "Ctrl + F (Settings)"],
+        row!["Toggle pause on device change".fg(Yellow), "P (Settings)"],
+        row!["Toggle featured artists".fg(Yellow), "F (Settings)"],
+        row!["Toggle title qualifiers".fg(Yellow), "B (Settings)"],
+        row!["Maximum column width".fg(Yellow), "+ / - (Settings)"],
+        row!["Startup playback".fg(Yellow), "O (Settings)"],
+        row!["Queue layout".fg(Yellow), "T (Settings)"],
+        row!["Test output device".fg(Yellow), "Shift + T (Settings)"],
+        row!["Guess missing tags".fg(Yellow), "G (Settings)"],
+        row!["Keep pitch when changing speed".fg(Yellow), "V (Settings)"],
+        row!["Remember speed per folder".fg(Yellow), "M (Settings)"],
+        row!["Replay gain mode".fg(Yellow), "Y (Settings)"],
+        row!["Crossfade".fg(Yellow), "[ / ] (Settings)"],
+        row!["Volume curve".fg(Yellow), "Shift + V (Settings)"],
+        row!["Mono output".fg(Yellow), "Shift + M (Settings)"],
+        row!["Browser sort".fg(Yellow), "Shift + B (Settings)"],
+        row!["Browser panes".fg(Yellow), "Shift + G (Settings)"],
         row!["Update database".fg(Yellow), "U"],
         row!["Quit player".fg(Yellow), "Ctrl + C"],
         row!["Clear queue".fg(Red), "C"],
         row!["Clear except playing".fg(Red), "Shift + C"],
         row!["Delete song/playlist".fg(Red), "X"],
         row!["Delete without confirmation".fg(Red), "Shift + X"],
+        row![
+            "Export playlist to M3U8".fg(Yellow),
+            "Shift + E (Playlists)"
+        ],
     ]
 });
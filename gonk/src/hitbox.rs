@@ -0,0 +1,148 @@
+//! Clickable areas
+//!
+//! Widgets register where they were drawn each frame.
+//! Clicks, scrolls and drags are matched against the last frame instead of re-calculating the layout.
+//!
+//! Without the `mouse` feature widgets still register their areas but nothing reads them.
+#![cfg_attr(not(feature = "mouse"), allow(dead_code))]
+use winter::Rect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Widget {
+    ///Index of the song in the queue.
+    QueueRow(usize),
+    ///Clicking mutes, scrolling changes the volume.
+    Volume,
+    ///The right border of the queue when it doesn't fit.
+    QueueScrollbar,
+    SeekBar,
+    BrowserGroup,
+    BrowserArtist,
+    BrowserAlbum,
+    BrowserSong,
+    #[cfg(feature = "playlists")]
+    PlaylistList,
+    #[cfg(feature = "playlists")]
+    PlaylistSongs,
+    #[cfg(feature = "playlists")]
+    PlaylistYes,
+    #[cfg(feature = "playlists")]
+    PlaylistNo,
+    #[cfg(feature = "search")]
+    SearchInput,
+    #[cfg(feature = "search")]
+    SearchResults,
+}
+
+#[derive(Default)]
+pub struct Hitboxes {
+    regions: Vec<(Rect, Widget)>,
+}
+
+impl Hitboxes {
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+    pub fn push(&mut self, rect: Rect, widget: Widget) {
+        self.regions.push((rect, widget));
+    }
+    ///Find the widget under the mouse.
+    ///
+    ///Widgets drawn last are on top so they are checked first.
+    pub fn get(&self, x: u16, y: u16) -> Option<(Rect, Widget)> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|(rect, _)| contains(rect, x, y))
+            .copied()
+    }
+    ///Where a widget was drawn, used to keep dragging it after the mouse leaves.
+    pub fn find(&self, widget: Widget) -> Option<Rect> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|(_, w)| *w == widget)
+            .map(|(rect, _)| *rect)
+    }
+}
+
+fn contains(rect: &Rect, x: u16, y: u16) -> bool {
+    x >= rect.x
+        && y >= rect.y
+        && (x as u32) < rect.x as u32 + rect.width as u32
+        && (y as u32) < rect.y as u32 + rect.height as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::browser::{self, Browser};
+    use gonk_core::{vdb::Database, Normalize, Song};
+    use winter::Buffer;
+
+    fn rect(x: u16, y: u16, width: u16, height: u16) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    ///The hitboxes from drawing the browser at this size.
+    fn browser(width: u16, height: u16) -> Hitboxes {
+        let db = Database::from_songs(vec![Song::example()]);
+        let mut browser = Browser::new(&db);
+        let area = rect(0, 0, width, height);
+        let mut buf = Buffer::empty(area);
+        let mut hitboxes = Hitboxes::default();
+        browser::draw(
+            &mut browser,
+            area,
+            &mut buf,
+            &db,
+            &Normalize::default(),
+            &mut hitboxes,
+        );
+        hitboxes
+    }
+
+    #[test]
+    fn boundaries() {
+        let hitboxes = browser(120, 30);
+        assert_eq!(hitboxes.get(0, 0).unwrap().1, Widget::BrowserGroup);
+        assert_eq!(hitboxes.get(29, 29).unwrap().1, Widget::BrowserGroup);
+        assert_eq!(hitboxes.get(30, 0).unwrap().1, Widget::BrowserArtist);
+        assert_eq!(hitboxes.get(60, 0).unwrap().1, Widget::BrowserAlbum);
+        assert_eq!(hitboxes.get(90, 0).unwrap().1, Widget::BrowserSong);
+        assert_eq!(hitboxes.get(119, 29).unwrap().1, Widget::BrowserSong);
+        assert!(hitboxes.get(120, 0).is_none());
+        assert!(hitboxes.get(0, 30).is_none());
+    }
+
+    #[test]
+    fn resize() {
+        let mut hitboxes = browser(120, 30);
+        assert_eq!(hitboxes.get(65, 10).unwrap().1, Widget::BrowserAlbum);
+
+        //The next frame is drawn at a smaller size.
+        hitboxes = browser(42, 10);
+        assert_eq!(hitboxes.get(35, 5).unwrap().1, Widget::BrowserSong);
+        assert_eq!(hitboxes.get(41, 9).unwrap().1, Widget::BrowserSong);
+        assert_eq!(hitboxes.get(30, 9).unwrap().1, Widget::BrowserSong);
+        assert_eq!(hitboxes.get(29, 9).unwrap().1, Widget::BrowserAlbum);
+        assert!(hitboxes.get(35, 10).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "playlists")]
+    fn popup() {
+        let mut hitboxes = browser(120, 30);
+        hitboxes.push(rect(70, 10, 10, 3), Widget::PlaylistYes);
+        assert_eq!(hitboxes.get(70, 10).unwrap().1, Widget::PlaylistYes);
+        assert_eq!(hitboxes.get(69, 10).unwrap().1, Widget::BrowserAlbum);
+
+        hitboxes.clear();
+        assert!(hitboxes.get(70, 10).is_none());
+    }
+}
@@ -0,0 +1,228 @@
+//! Control a running instance of gonk from the command line.
+//!
+//! Commands are sent as a single line over a named pipe on Windows and a unix socket elsewhere.
+//! Only the user running gonk can send them.
+//! They're read on their own thread so clients can't block the UI.
+//! A client that doesn't finish its line within [`TIMEOUT`] is dropped so the next one can connect.
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+///How long a client has to send its command.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Command {
+    PlayIndex(usize),
+    Skip,
+    ///Insert a file or folder before a position in the queue.
+    Insert(usize, String),
+}
+
+impl Command {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.trim_end_matches(['\r', '\n']).splitn(3, ' ');
+        match parts.next()? {
+            "play" => Some(Command::PlayIndex(parts.next()?.trim().parse().ok()?)),
+            "skip" => Some(Command::Skip),
+            //The path is the rest of the line so it can contain spaces.
+            "insert" => {
+                let position = parts.next()?.parse().ok()?;
+                let path = parts.next().filter(|path| !path.is_empty())?;
+                Some(Command::Insert(position, path.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::PlayIndex(i) => write!(f, "play {i}"),
+            Command::Skip => write!(f, "skip"),
+            Command::Insert(position, path) => write!(f, "insert {position} {path}"),
+        }
+    }
+}
+
+///Start listening for commands. Returns `None` if another instance is already listening.
+pub fn listen() -> Option<Receiver<Command>> {
+    let (sender, receiver) = channel();
+    platform::listen(sender).ok()?;
+    Some(receiver)
+}
+
+///Check for a new command without blocking.
+pub fn poll(receiver: &Receiver<Command>) -> Option<Command> {
+    receiver.try_recv().ok()
+}
+
+///Send a command to the running instance.
+pub fn send(command: Command) -> io::Result<()> {
+    let mut stream = platform::connect()?;
+    writeln!(stream, "{command}")
+}
+
+///Is another instance listening for commands.
+pub fn running() -> bool {
+    platform::connect().is_ok()
+}
+
+///Read one command from a client, anything else it sends is ignored.
+fn read(client: impl io::Read, sender: &Sender<Command>) {
+    let mut line = String::new();
+    if BufReader::new(client).read_line(&mut line).is_ok() {
+        if let Some(command) = Command::parse(&line) {
+            let _ = sender.send(command);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+    use crate::pipe::{self, Direction, Pipe};
+    use std::fs::{File, OpenOptions};
+
+    const NAME: &str = r"\\.\pipe\gonk";
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    pub fn listen(sender: Sender<Command>) -> io::Result<()> {
+        let pipe = Pipe::create(NAME, Direction::Inbound)?;
+        thread::spawn(move || {
+            while pipe.connect().is_ok() {
+                //The pipe is synchronous, a read that takes too long is cancelled from here.
+                let file = pipe.file();
+                let client = sender.clone();
+                let (done, finished) = channel();
+                let reader = thread::spawn(move || {
+                    read(&*file, &client);
+                    let _ = done.send(());
+                });
+                if finished.recv_timeout(TIMEOUT).is_err() {
+                    while !reader.is_finished() {
+                        pipe::cancel(&reader);
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                let _ = reader.join();
+                pipe.disconnect();
+            }
+        });
+        Ok(())
+    }
+
+    pub fn connect() -> io::Result<File> {
+        match OpenOptions::new().write(true).open(NAME) {
+            //Another command is being read.
+            Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                thread::sleep(std::time::Duration::from_millis(50));
+                OpenOptions::new().write(true).open(NAME)
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::*;
+    use gonk_core::gonk_path;
+    use std::{
+        fs,
+        os::unix::net::{UnixListener, UnixStream},
+        path::PathBuf,
+    };
+
+    fn path() -> PathBuf {
+        gonk_path().join("gonk.sock")
+    }
+
+    pub fn listen(sender: Sender<Command>) -> io::Result<()> {
+        //A socket left behind by a crash can be replaced, one that answers can't.
+        if connect().is_ok() {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        let _ = fs::remove_file(path());
+
+        serve(UnixListener::bind(path())?, sender);
+        Ok(())
+    }
+
+    fn serve(listener: UnixListener, sender: Sender<Command>) {
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_read_timeout(Some(TIMEOUT)).is_ok() {
+                    read(stream, &sender);
+                }
+            }
+        });
+    }
+
+    pub fn connect() -> io::Result<UnixStream> {
+        UnixStream::connect(path())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn stalled() {
+            let dir = std::env::temp_dir().join(format!("gonk-ipc-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("gonk.sock");
+            let (sender, receiver) = channel();
+            serve(UnixListener::bind(&path).unwrap(), sender);
+
+            //Never sends a newline.
+            let mut stalled = UnixStream::connect(&path).unwrap();
+            write!(stalled, "play").unwrap();
+            let mut client = UnixStream::connect(&path).unwrap();
+            writeln!(client, "skip").unwrap();
+            assert_eq!(
+                receiver.recv_timeout(TIMEOUT + Duration::from_secs(2)),
+                Ok(Command::Skip)
+            );
+            drop(stalled);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command() {
+        for command in [
+            Command::PlayIndex(5),
+            Command::Skip,
+            Command::Insert(2, String::from("C:\\My Music\\song.flac")),
+        ] {
+            assert_eq!(Command::parse(&command.to_string()), Some(command));
+        }
+        assert_eq!(Command::parse("play 1\n"), Some(Command::PlayIndex(1)));
+        assert_eq!(Command::parse("insert 2"), None);
+        assert_eq!(Command::parse("play"), None);
+        assert_eq!(Command::parse("play five"), None);
+        assert_eq!(Command::parse("stop"), None);
+    }
+
+    #[test]
+    fn read() {
+        let (sender, receiver) = channel();
+        super::read("skip\nplay 1\n".as_bytes(), &sender);
+        super::read("play".as_bytes(), &sender);
+        super::read(&[0xff, b'\n'][..], &sender);
+        //Only the first line of each client.
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [Command::Skip]);
+    }
+}
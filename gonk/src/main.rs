@@ -1,57 +1,76 @@
+use app::{handle_event, App};
 use browser::Browser;
 use gonk_core::{vdb::*, *};
 use gonk_player::*;
+use hitbox::Hitboxes;
 use mini::defer_results;
-use playlist::{Mode as PlaylistMode, Playlist};
+use notify::Notifier;
+#[cfg(feature = "playlists")]
+use playlist::Playlist;
 use queue::Queue;
-use search::{Mode as SearchMode, Search};
+#[cfg(feature = "search")]
+use search::Search;
 use settings::Settings;
 use std::{
     fs,
     time::{Duration, Instant},
 };
+use waveform::Waveform;
 use winter::*;
 
+mod app;
 mod browser;
+mod cast;
+mod config;
+#[cfg(feature = "desktop-notifications")]
+mod desktop;
+#[cfg(feature = "discord")]
+mod discord;
 mod help;
+mod hitbox;
+mod ipc;
+mod notify;
+#[cfg(feature = "media-integrations")]
+mod nowplaying;
+#[cfg(windows)]
+mod pipe;
+#[cfg(feature = "playlists")]
 mod playlist;
+mod preview;
 mod queue;
+mod reveal;
+mod screen;
+#[cfg(feature = "search")]
 mod search;
 mod settings;
+mod skim;
+mod suspend;
+mod theme;
+mod waveform;
 
 const JUMP_AMOUNT: usize = 3;
+const FADE_OUT: Duration = Duration::from_millis(250);
 const FRAME_TIME: f32 = 1000.0 / 300.0;
+///How often the queue and position are saved while playing.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
 
-const NUMBER: Color = Color::Green;
-const TITLE: Color = Color::Cyan;
-const ALBUM: Color = Color::Magenta;
-const ARTIST: Color = Color::Blue;
-const SEEKER: Color = Color::White;
+///Shown in place of an empty list.
+const EMPTY_QUEUE: &str = "Queue is empty, press Enter in the Browser to add songs";
+const EMPTY_LIBRARY: &str = "Library is empty, add a folder with `gonk add <path>`";
 
 #[derive(PartialEq, Eq, Clone)]
 pub enum Mode {
     Browser,
     Queue,
+    #[cfg(feature = "playlists")]
     Playlist,
     Settings,
+    #[cfg(feature = "search")]
     Search,
 }
 
-fn draw(
-    winter: &mut Winter,
-    mode: &Mode,
-    browser: &mut Browser,
-    settings: &Settings,
-    queue: &mut Queue,
-    playlist: &mut Playlist,
-    search: &mut Search,
-    cursor: &mut Option<(u16, u16)>,
-    songs: &mut Index<Song>,
-    db: &Database,
-    mouse: Option<(u16, u16)>,
-    help: bool,
-    mute: bool,
-) {
+fn draw(winter: &mut Winter, app: &mut App, cursor: &mut Option<(u16, u16)>) {
+    app.hitboxes.clear();
     let viewport = winter.viewport;
     let buf = winter.buffer();
     let area = if let Some(msg) = log::last_message() {
@@ -64,21 +83,10 @@ fn draw(
         viewport
     };
 
-    //Hide the cursor when it's not needed.
-    match mode {
-        Mode::Search | Mode::Playlist => {}
-        _ => *cursor = None,
-    }
-
-    match mode {
-        Mode::Browser => browser::draw(browser, area, buf, mouse),
-        Mode::Settings => settings::draw(settings, area, buf),
-        Mode::Queue => queue::draw(queue, area, buf, mouse, songs, mute),
-        Mode::Playlist => *cursor = playlist::draw(playlist, area, buf, mouse),
-        Mode::Search => *cursor = search::draw(search, area, buf, mouse, db),
-    }
+    let mode = app.mode.clone();
+    *cursor = app::with_screen(app, mode, |screen, ctx| screen.draw(ctx, area, buf));
 
-    if help {
+    if app.help {
         if let Ok(area) = area.inner(8, 6) {
             let widths = [Constraint::Percentage(50), Constraint::Percentage(50)];
 
@@ -93,19 +101,40 @@ fn draw(
     }
 }
 
+///Draw a message in the middle of an empty pane.
+fn draw_empty(area: Rect, buf: &mut winter::Buffer, message: &str) {
+    let Ok(inner) = area.inner(1, 1) else {
+        return;
+    };
+    let middle = Rect {
+        y: inner.y + inner.height / 2,
+        height: 1,
+        ..inner
+    };
+    lines!(message.dim()).align(Center).draw(middle, buf);
+}
+
 fn path(mut path: String) -> Option<std::path::PathBuf> {
-    if path.contains("~") {
-        path = path.replace("~", &user_profile_directory().unwrap());
+    if let (true, Some(home)) = (path.contains("~"), user_profile_directory()) {
+        path = path.replace("~", &home);
     }
     fs::canonicalize(path).ok()
 }
 
+///Get the value after a flag e.g. `--field artist`.
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    args.get(i + 1)
+}
+
 fn main() {
     defer_results!();
     let mut persist = gonk_core::settings::Settings::new().unwrap();
     let args: Vec<String> = std::env::args().skip(1).collect();
     let mut scan_timer = Instant::now();
     let mut scan_handle = None;
+    #[cfg(feature = "media-integrations")]
+    let mut nowplaying = None;
 
     if !args.is_empty() {
         match args[0].as_str() {
@@ -117,18 +146,210 @@ fn main() {
                 match path(args[1].clone()) {
                     Some(path) if path.exists() => {
                         persist.music_folder = path.to_string_lossy().to_string();
-                        scan_handle = Some(db::create(&persist.music_folder));
+                        scan_handle = Some(db::create(&persist.music_folder, persist.guess_tags));
                         scan_timer = Instant::now();
                     }
                     _ => return println!("Invalid path."),
                 }
             }
+            "scan-errors" => {
+                return match fs::read_to_string(db::scan_errors_path()) {
+                    Ok(errors) => println!("{errors}"),
+                    Err(_) => println!("The last scan had no errors."),
+                };
+            }
             "reset" => {
                 return match gonk_core::db::reset() {
                     Ok(_) => println!("Database reset!"),
                     Err(e) => println!("Failed to reset database! {e}"),
                 };
             }
+            "fix-tags" => {
+                let (Some(field), Some(pattern), Some(replacement)) = (
+                    flag(&args, "--field"),
+                    flag(&args, "--match"),
+                    flag(&args, "--replace"),
+                ) else {
+                    return println!(
+                        "Usage: gonk fix-tags --field <artist|album|title> --match <regex> --replace <text>"
+                    );
+                };
+
+                let field = match db::SongField::try_from(field.as_str()) {
+                    Ok(field) => field,
+                    Err(err) => return println!("{err}"),
+                };
+
+                return match db::update_field_by_pattern(field, pattern, replacement) {
+                    Ok(count) => println!("Updated {count} songs."),
+                    Err(e) => println!("Failed to update database! {e}"),
+                };
+            }
+            "index" if args.get(1).is_some_and(|arg| arg == "check") => {
+                let check = match db::check() {
+                    Ok(check) => check,
+                    Err(e) => {
+                        println!("Failed to read database! {e}");
+                        std::process::exit(2);
+                    }
+                };
+                println!("{check}");
+
+                let code = if check.is_ok() {
+                    0
+                } else if !args.iter().any(|arg| arg == "--fix") {
+                    2
+                } else {
+                    match db::fix(&check) {
+                        Ok(_) => {
+                            println!("Database fixed.");
+                            1
+                        }
+                        Err(e) => {
+                            println!("Failed to update database! {e}");
+                            2
+                        }
+                    }
+                };
+                std::process::exit(code);
+            }
+            "index" => {
+                let (Some("migrate-paths"), Some(from), Some(to)) = (
+                    args.get(1).map(String::as_str),
+                    flag(&args, "--from"),
+                    flag(&args, "--to"),
+                ) else {
+                    return println!(
+                        "Usage: gonk index <check [--fix] | migrate-paths --from <old> --to <new>>"
+                    );
+                };
+
+                let count = match db::migrate_paths(from, to) {
+                    Ok(count) => count,
+                    Err(e) => return println!("Failed to update database! {e}"),
+                };
+
+                let mut playlist_count = 0;
+                for mut playlist in gonk_core::playlist::playlists() {
+                    let n = db::migrate_songs(&mut playlist.songs, from, to);
+                    if n != 0 {
+                        playlist.save().unwrap();
+                        playlist_count += n;
+                    }
+                }
+
+                let mut plays = gonk_core::plays::load();
+                if db::migrate_plays(&mut plays, from, to) != 0 {
+                    if let Err(e) = gonk_core::plays::save(&plays) {
+                        return println!("Failed to update the play counts! {e}");
+                    }
+                }
+
+                db::migrate_songs(&mut persist.queue, from, to);
+                if let Some(folder) = db::migrate_path(&persist.music_folder, from, to) {
+                    persist.music_folder = folder;
+                }
+                persist.save().unwrap();
+
+                return println!("Updated {count} songs and {playlist_count} playlist songs.");
+            }
+            "import" => {
+                let Some(file) = args.get(1).cloned().and_then(path) else {
+                    return println!("Usage: gonk import <file.m3u8> [name]");
+                };
+                let stem = file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string());
+                let name = match args.get(2).cloned().or(stem) {
+                    Some(name) => name,
+                    None => return println!("Usage: gonk import <file.m3u8> [name]"),
+                };
+                let name = match gonk_core::playlist::validate_name(&name) {
+                    Ok(name) => name,
+                    Err(err) => return println!("{err}"),
+                };
+                if gonk_core::playlist::playlists()
+                    .iter()
+                    .any(|playlist| gonk_core::playlist::same_name(playlist.name(), name))
+                {
+                    return println!("A playlist called {name} already exists.");
+                }
+
+                let songs = match gonk_core::m3u::import(&file, &Database::new()) {
+                    Ok(songs) => songs,
+                    Err(e) => return println!("Failed to read {}! {e}", file.display()),
+                };
+                if songs.is_empty() {
+                    return println!("None of the songs are in the library.");
+                }
+                let len = songs.len();
+                return match gonk_core::Playlist::new(name, songs).save() {
+                    Ok(_) => println!("Imported {len} songs into {name}."),
+                    Err(e) => println!("Failed to save playlist! {e}"),
+                };
+            }
+            "queue" => {
+                return match args.get(1).map(String::as_str) {
+                    Some("list") => {
+                        for (i, song) in persist.queue.iter().enumerate() {
+                            let playing = if i == persist.index { ">>" } else { "  " };
+                            println!(
+                                "{playing} {i}. {} - {} - {}",
+                                song.title, song.album, song.artist
+                            );
+                        }
+                    }
+                    Some("play") => match args.get(2).and_then(|i| i.parse::<usize>().ok()) {
+                        Some(i) => match ipc::send(ipc::Command::PlayIndex(i)) {
+                            Ok(_) => (),
+                            Err(_) => println!("Could not connect to gonk, is it running?"),
+                        },
+                        None => println!("Usage: gonk queue play <index>"),
+                    },
+                    Some("skip") => match ipc::send(ipc::Command::Skip) {
+                        Ok(_) => (),
+                        Err(_) => println!("Could not connect to gonk, is it running?"),
+                    },
+                    Some("insert") => match (
+                        args.get(2).and_then(|i| i.parse::<usize>().ok()),
+                        args.get(3).cloned().and_then(path),
+                    ) {
+                        (Some(i), Some(path)) => {
+                            let path = path.to_string_lossy().to_string();
+                            match ipc::send(ipc::Command::Insert(i, path)) {
+                                Ok(_) => (),
+                                Err(_) => println!("Could not connect to gonk, is it running?"),
+                            }
+                        }
+                        _ => println!("Usage: gonk queue insert <index> <path>"),
+                    },
+                    _ => println!(
+                        "Usage: gonk queue <list | play <index> | skip | insert <index> <path>>"
+                    ),
+                };
+            }
+            "config" => {
+                return match (args.get(1).map(String::as_str), args.get(2), args.get(3)) {
+                    (Some("get"), Some(key), None) => match persist.get(key) {
+                        Ok(value) => println!("{value}"),
+                        Err(err) => println!("{err}"),
+                    },
+                    //The running instance saves its own copy of the settings, which would undo this.
+                    (Some("set"), Some(_), Some(_)) if ipc::running() => {
+                        println!("gonk is running, change the setting there or close it first.")
+                    }
+                    (Some("set"), Some(key), Some(value)) => match persist.set(key, value) {
+                        Ok(_) => persist.save().unwrap(),
+                        Err(err) => println!("{err}"),
+                    },
+                    (Some("list"), None, None) => {
+                        for (key, value) in persist.list() {
+                            println!("{key} = {value}");
+                        }
+                    }
+                    _ => println!("Usage: gonk config <get <key> | set <key> <value> | list>"),
+                };
+            }
             "help" | "--help" => {
                 println!("Usage");
                 println!("   gonk [<command> <args>]");
@@ -136,18 +357,51 @@ fn main() {
                 println!("Options");
                 println!("   add    <path> Add music to the library");
                 println!("   reset         Reset the database");
-                println!("   buffer <size> Set a custom ring buffer size");
+                println!("   scan-errors   List the files the last scan couldn't read");
+                println!("   fix-tags      Find and replace tags with a regex");
+                println!("                 --field <artist|album|title> --match <regex> --replace <text>");
+                println!("   index  migrate-paths --from <old> --to <new>");
+                println!("                 Update paths after moving the music folder");
+                println!("   index  check [--fix]");
+                println!(
+                    "                 Find songs that were deleted or changed since the last scan"
+                );
+                println!("   import <file.m3u8> [name] Create a playlist from an M3U8 file");
+                println!("   queue  list   Print the queue");
+                println!("   queue  play   <index> Play a song in the running queue");
+                println!("   queue  skip   Skip to the next song in the running queue");
+                println!("   queue  insert <index> <path> Insert a file or folder into the running queue");
+                println!("   config list   Print every setting");
+                println!("   config get    <key> Print a setting");
+                println!("   config set    <key> <value> Change a setting");
+                println!(
+                    "   buffer <ms>   How much decoded audio to keep ready, 200 ms by default"
+                );
+                #[cfg(feature = "media-integrations")]
+                println!("   --nowplaying-pipe <path> Write the current song to a named pipe");
                 return;
             }
-            "b" | "buffer" | "--buffer" | "--b" => match args.get(1) {
-                Some(rb_size) => unsafe {
-                    gonk_player::RB_SIZE = rb_size.parse::<usize>().unwrap()
-                },
-                None => {
-                    println!("Please enter a valid ring buffer size `buffer <size>`.");
-                    return;
+            #[cfg(feature = "media-integrations")]
+            "--nowplaying-pipe" => match args.get(1) {
+                Some(path) => {
+                    nowplaying = nowplaying::spawn(std::path::PathBuf::from(path));
+                    if nowplaying.is_none() {
+                        return println!("Could not create a now playing pipe at {path}.");
+                    }
                 }
+                None => return println!("Usage: gonk --nowplaying-pipe <path>"),
             },
+            "b" | "buffer" | "--buffer" | "--b" => {
+                match args.get(1).and_then(|ms| ms.parse::<u64>().ok()) {
+                    Some(ms) if ms > 0 => unsafe {
+                        gonk_player::BUFFER_DURATION = Duration::from_millis(ms)
+                    },
+                    _ => {
+                        println!("Please enter a valid buffer length `buffer <ms>`.");
+                        return;
+                    }
+                }
+            }
             _ if !args.is_empty() => return println!("Invalid command."),
             _ => (),
         }
@@ -156,6 +410,10 @@ fn main() {
     //Prevents panic messages from being hidden.
     let orig_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
+        //A broken file is reported as a scan error instead.
+        if db::is_reading_file() {
+            return;
+        }
         let mut stdout = std::io::stdout();
         let mut stdin = std::io::stdin();
         uninit(&mut stdout, &mut stdin);
@@ -178,438 +436,412 @@ fn main() {
     });
 
     let mut winter = Winter::new();
-    let index = (!persist.queue.is_empty()).then_some(persist.index as usize);
+    let commands = ipc::listen();
+    let (insert_sender, inserted) = std::sync::mpsc::channel();
+
+    if let Some(err) = persist.load_error.take() {
+        log!("{err}");
+    }
+    let (keys, key_errors) = config::KeyBindings::load();
+    let (theme, theme_errors) = theme::Theme::load_or_default();
+    theme::set(theme);
+    for err in key_errors.into_iter().chain(theme_errors) {
+        log!("{err}");
+    }
+    match persist.remove_missing() {
+        0 => {}
+        1 => log!("Removed 1 missing song from the queue."),
+        n => log!("Removed {n} missing songs from the queue."),
+    }
+    let index = (!persist.queue.is_empty()).then_some(persist.index);
 
+    set_volume_curve(persist.volume_curve);
     set_volume(persist.volume);
+    set_keep_pitch(persist.keep_pitch);
+    set_remember_rate(persist.remember_rate);
+    set_seek_across(persist.seek_across);
+    set_repeat(persist.repeat);
+    set_replay_gain(persist.replay_gain, None);
+    set_crossfade(persist.crossfade as f32);
+    set_fade_ms(persist.fade_ms);
+    set_mono(persist.mono);
+    for (band, gain) in persist.equalizer.iter().enumerate() {
+        set_band_gain(band, *gain);
+    }
 
-    let mut songs = Index::new(persist.queue.clone(), index);
+    let songs = Index::new(persist.queue.clone(), index);
     if let Some(song) = songs.selected() {
         play_song(song);
         pause();
         seek(persist.elapsed);
+        if persist.startup.resume(persist.playing) {
+            play_when_ready();
+            log!("Resumed playback");
+        }
     }
 
-    let mut db = Database::new();
+    if let Err(err) = vdb::migrate() {
+        log!("Could not update the database: {err}");
+    }
+    gonk_core::playlist::repair_names();
+    let db = Database::new();
     let mut browser = Browser::new(&db);
+    browser::set_sort(&mut browser, &db, persist.browser_sort);
+    browser::set_layout(&mut browser, &db, persist.browser_mode);
 
     //Everything here initialises quickly.
     let mut queue = Queue::new(index.unwrap_or(0));
-    let mut playlist = Playlist::new().unwrap();
-    let mut search = Search::new();
-    let mut mode = Mode::Browser;
+    queue.constraint = persist.columns();
+    #[cfg(feature = "playlists")]
+    let playlist = Playlist::new().unwrap();
+    #[cfg(feature = "search")]
+    let search = Search::new();
     let mut last_tick = Instant::now();
+    let mut last_save = Instant::now();
     let mut ft = Instant::now();
     let mut dots: usize = 1;
-    let mut help = false;
-    let mut prev_mode = Mode::Search; //Used for search.
-    let mut mute = false;
-    let mut old_volume = 0;
     let mut cursor: Option<(u16, u16)> = None;
-    let mut shift;
-    let mut control;
 
     let mut settings = thread.join().unwrap();
-
-    //If there are songs in the queue and the database isn't scanning, display the queue.
-    if !songs.is_empty() && scan_handle.is_none() {
-        mode = Mode::Queue;
-    }
-
-    macro_rules! up {
-        () => {{
-            let amount = if shift { JUMP_AMOUNT } else { 1 };
-            match mode {
-                Mode::Browser => browser::up(&mut browser, &db, amount),
-                Mode::Queue => queue::up(&mut queue, &mut songs, amount),
-                Mode::Playlist => playlist::up(&mut playlist, amount),
-                Mode::Settings => settings::up(&mut settings, amount),
-                Mode::Search => search.results.up_n(amount),
-            }
-        }};
+    settings.pause_on_device_change = persist.pause_on_device_change;
+    settings.normalize = persist.normalize;
+    settings.discord = persist.discord;
+    settings.startup = persist.startup;
+    settings.queue_layout = persist.queue_layout;
+    settings.guess_tags = persist.guess_tags;
+    settings.keep_pitch = persist.keep_pitch;
+    settings.remember_rate = persist.remember_rate;
+    settings.seek_across = persist.seek_across;
+    settings.replay_gain = persist.replay_gain;
+    settings.crossfade = persist.crossfade;
+    settings.mono = persist.mono;
+    settings.browser_sort = persist.browser_sort;
+    settings.browser_mode = persist.browser_mode;
+    settings.volume_curve = persist.volume_curve;
+    if !persist.errors.is_empty() {
+        log!(
+            "{} settings couldn't be read, see the settings page.",
+            persist.errors.len()
+        );
     }
+    #[cfg(feature = "discord")]
+    let discord = if settings.discord {
+        discord::spawn()
+    } else {
+        None
+    };
 
-    macro_rules! down {
-        () => {{
-            let amount = if shift { JUMP_AMOUNT } else { 1 };
-            match mode {
-                Mode::Browser => browser::down(&mut browser, &db, amount),
-                Mode::Queue => queue::down(&mut queue, &mut songs, amount),
-                Mode::Playlist => playlist::down(&mut playlist, amount),
-                Mode::Settings => settings::down(&mut settings, amount),
-                Mode::Search => search.results.down_n(amount),
-            }
-        }};
-    }
+    //If there are songs in the queue and the database isn't scanning, display the queue.
+    let mode = if !songs.is_empty() && scan_handle.is_none() {
+        Mode::Queue
+    } else {
+        Mode::Browser
+    };
 
-    macro_rules! left {
-        () => {
-            match mode {
-                Mode::Browser => browser::left(&mut browser),
-                Mode::Playlist => playlist::left(&mut playlist),
-                _ => {}
-            }
-        };
-    }
+    let mut app = App {
+        mode,
+        #[cfg(feature = "search")]
+        prev_mode: Mode::Search,
+        help: false,
+        mute: false,
+        old_volume: 0,
+        browser,
+        queue,
+        #[cfg(feature = "playlists")]
+        playlist,
+        #[cfg(feature = "search")]
+        search,
+        songs,
+        db,
+        settings,
+        persist,
+        preview: None,
+        pending: None,
+        scan_handle,
+        scan_timer,
+        default_device_name: default_device().name,
+        #[cfg(feature = "discord")]
+        discord,
+        last_playing: Default::default(),
+        started: 0,
+        heard: Duration::ZERO,
+        counted: false,
+        hitboxes: Hitboxes::default(),
+        pointer: None,
+        drag: None,
+        waveform: Waveform::default(),
+        skim: None,
+        notifier: Notifier::default(),
+        keys,
+    };
 
-    macro_rules! right {
-        () => {
-            match mode {
-                Mode::Browser => browser::right(&mut browser),
-                Mode::Playlist => playlist::right(&mut playlist),
-                _ => {}
-            }
-        };
-    }
+    let mut last_frame = Instant::now();
+    loop {
+        let now = Instant::now();
+        let mut delta = now.saturating_duration_since(last_frame);
+        if let Some(gap) = suspend::gap(last_frame, now) {
+            //Messages from before the computer went to sleep are stale.
+            log::clear();
+            preview::resume(&mut app.preview, gap, now);
+            //Status bars and discord have the wrong position.
+            app.last_playing = Default::default();
+            delta = Duration::ZERO;
+        }
+        last_frame = now;
 
-    'outer: loop {
-        if let Some(handle) = &scan_handle {
+        if let Some(handle) = &app.scan_handle {
             if handle.is_finished() {
-                let handle = scan_handle.take().unwrap();
-                let result = handle.join().unwrap();
+                let handle = app.scan_handle.take().unwrap();
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| db::ScanResult::Failed(String::from("the scan crashed")));
 
-                db = Database::new();
+                app.db = Database::new();
                 log::clear();
 
                 match result {
                     db::ScanResult::Completed => {
                         log!(
                             "Finished adding {} files in {:.2} seconds.",
-                            db.len,
-                            scan_timer.elapsed().as_secs_f32()
+                            app.db.len,
+                            app.scan_timer.elapsed().as_secs_f32()
                         );
                     }
                     db::ScanResult::CompletedWithErrors(errors) => {
-                        let dir = "See %appdata%/gonk/gonk.log for details.";
                         let len = errors.len();
                         let s = if len == 1 { "" } else { "s" };
 
                         log!(
-                            "Added {} files with {len} error{s}. {dir}",
-                            db.len.saturating_sub(len)
+                            "Scanned {} files, {len} error{s}. Run `gonk scan-errors` for details.",
+                            app.db.len + len
                         );
-
-                        let path = gonk_path().join("gonk.log");
-                        let errors = errors.join("\n");
-                        fs::write(path, errors).unwrap();
                     }
                     db::ScanResult::FileInUse => {
                         log!("Could not update database, file in use.")
                     }
+                    db::ScanResult::Failed(err) => {
+                        log!("Could not update database, {err}.")
+                    }
                 }
 
-                browser::refresh(&mut browser, &db);
-                search.results = Index::new(db.search(&search.query), None);
+                browser::refresh(&mut app.browser, &app.db);
+                app.pending = None;
+                #[cfg(feature = "search")]
+                {
+                    app.search.update_search(&app.db);
+                    app.search.results.select(None);
+                }
 
                 //No need to reset scan_timer since it's reset with new scans.
-                scan_handle = None;
+                app.scan_handle = None;
             }
         }
 
         if last_tick.elapsed() >= Duration::from_millis(150) {
-            if scan_handle.is_some() {
-                if dots < 3 {
-                    dots += 1;
+            if app.scan_handle.is_some() {
+                let (done, total) = db::progress();
+                if let Some(msg) = db::progress_message(done, total, app.scan_timer.elapsed()) {
+                    log!("{msg}");
                 } else {
-                    dots = 1;
+                    if dots < 3 {
+                        dots += 1;
+                    } else {
+                        dots = 1;
+                    }
+                    log!(
+                        "Scanning {} for files{}",
+                        //Remove the UNC \\?\ from the path.
+                        &app.persist.music_folder.replace("\\\\?\\", ""),
+                        ".".repeat(dots)
+                    );
                 }
-                log!(
-                    "Scanning {} for files{}",
-                    //Remove the UNC \\?\ from the path.
-                    &persist.music_folder.replace("\\\\?\\", ""),
-                    ".".repeat(dots)
-                );
             }
 
-            //Update the time elapsed.
-            persist.index = songs.index().unwrap_or(0) as u16;
-            persist.elapsed = elapsed().as_secs_f32();
-            persist.queue = songs.to_vec();
-            persist.save().unwrap();
+            //Save the queue straight away when it changes so `gonk queue list` is up to date.
+            let changed = app.persist.queue.len() != app.songs.len()
+                || app.persist.index != app.songs.index().unwrap_or(0);
+            if changed || last_save.elapsed() >= SAVE_INTERVAL {
+                app.persist.index = app.songs.index().unwrap_or(0);
+                app.persist.elapsed = preview::elapsed(&app.preview);
+                app.persist.playing = !app.songs.is_empty() && !is_paused();
+                app.persist.queue = app.songs.to_vec();
+                if let Err(err) = app.persist.save() {
+                    log!("Could not save the queue: {err}");
+                }
+                last_save = Instant::now();
+            }
 
             //Update the list of output devices
-            settings.devices = devices();
-            let mut index = settings.index.unwrap_or(0);
-            if index >= settings.devices.len() {
-                index = settings.devices.len().saturating_sub(1);
-                settings.index = Some(index);
+            app.settings.devices = devices();
+            let mut index = app.settings.index.unwrap_or(0);
+            if index >= app.settings.devices.len() {
+                index = app.settings.devices.len().saturating_sub(1);
+                app.settings.index = Some(index);
+            }
+
+            //Pause when the output device is unplugged or the default device changes.
+            let default = default_device().name;
+            if app.settings.pause_on_device_change && !is_paused() && !app.songs.is_empty() {
+                let names: Vec<&str> = app
+                    .settings
+                    .devices
+                    .iter()
+                    .map(|d| d.name.as_str())
+                    .collect();
+                if settings::device_lost(
+                    &app.settings.current_device,
+                    &names,
+                    &app.default_device_name,
+                    &default,
+                ) {
+                    pause_for_device_change();
+                    log!("Output device changed — paused");
+                }
+            }
+            app.default_device_name = default;
+
+            //Checked after the pause above so unplugging headphones still pauses.
+            if let Some(name) = take_device_switch() {
+                log!("Output device disconnected, switched to {name}");
+                app.settings.current_device = name;
             }
 
             last_tick = Instant::now();
         }
 
-        //Play the next song if the current is finished.
-        if gonk_player::play_next() && !songs.is_empty() {
-            songs.down();
-            if let Some(song) = songs.selected() {
-                play_song(song);
+        app::tick(&mut app);
+
+        //Handle commands sent from `gonk queue`.
+        if let Some(command) = commands.as_ref().and_then(ipc::poll) {
+            match command {
+                ipc::Command::PlayIndex(i) if i < app.songs.len() => {
+                    play_index(&mut app.songs, i);
+                    app.queue.set_index(i);
+                }
+                ipc::Command::PlayIndex(_) => {}
+                ipc::Command::Skip => next_song(&mut app.songs),
+                ipc::Command::Insert(position, path) => {
+                    //Reading a folder can take a while, the songs are inserted once they're ready.
+                    let sender = insert_sender.clone();
+                    let guess_tags = app.persist.guess_tags;
+                    std::thread::spawn(move || {
+                        let path = std::path::Path::new(&path);
+                        let new: Vec<Song> = if path.is_dir() {
+                            db::read_dir(&path.to_string_lossy(), guess_tags)
+                                .into_iter()
+                                .flatten()
+                                .collect()
+                        } else {
+                            Song::try_from(path).into_iter().collect()
+                        };
+                        let _ = sender.send((position, new));
+                    });
+                }
             }
         }
+        while let Ok((position, new)) = inserted.try_recv() {
+            app::insert_songs(&mut app, new, position);
+        }
 
-        let input_playlist = playlist.mode == PlaylistMode::Popup && mode == Mode::Playlist;
-        let empty = songs.is_empty();
-
-        draw(
-            &mut winter,
-            &mode,
-            &mut browser,
-            &settings,
-            &mut queue,
-            &mut playlist,
-            &mut search,
-            &mut cursor,
-            &mut songs,
-            &db,
-            None,
-            help,
-            mute,
-        );
+        //Go back to the queue once the preview is over.
+        if let Some(p) = &app.preview {
+            if p.finished() || gonk_player::play_next() {
+                preview::stop(&mut app.preview);
+            }
+        }
+        preview::show(&app.preview);
 
-        'events: {
-            let Some((event, state)) = winter.poll() else {
-                break 'events;
-            };
-
-            shift = state.shift();
-            control = state.control();
-
-            match event {
-                Event::LeftMouse(x, y) if !help => {
-                    draw(
-                        &mut winter,
-                        &mode,
-                        &mut browser,
-                        &settings,
-                        &mut queue,
-                        &mut playlist,
-                        &mut search,
-                        &mut cursor,
-                        &mut songs,
-                        &db,
-                        Some((x, y)),
-                        help,
-                        mute,
-                    );
-                }
-                Event::ScrollUp => up!(),
-                Event::ScrollDown => down!(),
-                Event::Backspace if mode == Mode::Playlist => {
-                    playlist::on_backspace(&mut playlist, control);
-                }
-                Event::Char('c') if control => break 'outer,
-                Event::Char('?') | Event::Char('/') | Event::Escape if help => help = false,
-                Event::Char('?') if mode != Mode::Search => help = true,
-                Event::Char('/') => {
-                    if mode != Mode::Search {
-                        prev_mode = mode;
-                        mode = Mode::Search;
-                        search.query_changed = true;
-                    } else {
-                        match search.mode {
-                            SearchMode::Search if search.query.is_empty() => {
-                                mode = prev_mode.clone();
-                            }
-                            SearchMode::Search => {
-                                search.query.push('/');
-                                search.query_changed = true;
-                            }
-                            SearchMode::Select => {
-                                search.mode = SearchMode::Search;
-                                search.results.select(None);
-                            }
-                        }
-                    }
-                }
-                Event::Char('a') if control => {
-                    queue.range = Some(0..songs.len());
-                }
-                Event::Backspace if mode == Mode::Search => {
-                    search::on_backspace(&mut search, control, shift);
-                }
-                //Handle ^W as control backspace.
-                Event::Char('w') if control && mode == Mode::Search => {
-                    search::on_backspace(&mut search, control, shift);
-                }
-                Event::Char(c) if search.mode == SearchMode::Search && mode == Mode::Search => {
-                    search.query.push(c);
-                    search.query_changed = true;
-                }
-                Event::Escape if mode == Mode::Search => {
-                    search.query = String::new();
-                    search.query_changed = true;
-                    search.mode = SearchMode::Search;
-                    mode = prev_mode.clone();
-                    search.results.select(None);
-                }
-                Event::Tab if mode == Mode::Search => {
-                    mode = prev_mode.clone();
-                }
-                Event::Char(c) if input_playlist => {
-                    if control && c == 'w' {
-                        playlist::on_backspace(&mut playlist, true);
-                    } else {
-                        playlist.changed = true;
-                        playlist.search_query.push(c);
-                    }
-                }
-                Event::Char(' ') => toggle_playback(),
-                Event::Char('C') => {
-                    clear_except_playing(&mut songs);
-                    queue.set_index(0);
-                }
-                Event::Char('c') => {
-                    gonk_player::clear(&mut songs);
-                }
-                Event::Char('x') => match mode {
-                    Mode::Queue => {
-                        if let Some(i) = queue.index() {
-                            gonk_player::delete(&mut songs, i);
-
-                            //Sync the UI index.
-                            let len = songs.len().saturating_sub(1);
-                            if i > len {
-                                queue.set_index(len);
-                            }
-                        }
-                    }
-                    Mode::Playlist => {
-                        playlist::delete(&mut playlist, false);
-                    }
-                    _ => (),
-                },
-                //Force delete -> Shift + X.
-                Event::Char('X') if mode == Mode::Playlist => playlist::delete(&mut playlist, true),
-                Event::Char('u') if mode == Mode::Browser || mode == Mode::Playlist => {
-                    if scan_handle.is_none() {
-                        if persist.music_folder.is_empty() {
-                            gonk_core::log!("Nothing to scan! Add a folder with 'gonk add /path/'");
-                        } else {
-                            scan_handle = Some(db::create(&persist.music_folder));
-                            scan_timer = Instant::now();
-                            playlist.lists = Index::from(gonk_core::playlist::playlists());
-                        }
-                    }
-                }
-                Event::Char('z') => {
-                    if mute {
-                        mute = false;
-                        set_volume(old_volume)
+        //Play the next song if the current is finished.
+        if app.preview.is_none() && gonk_player::play_next() && !app.songs.is_empty() {
+            gonk_player::finished(&mut app.songs);
+        }
+
+        //Open the next song early so there's no gap between them.
+        let next = match app.preview {
+            None => gonk_player::up_next(&app.songs),
+            Some(_) => None,
+        };
+        gonk_player::prepare_next(app.songs.selected(), next);
+
+        //Seeking ran past the end of the song, carry on in the next one once the keys stop.
+        if let Some(overflow) = gonk_player::take_overflow() {
+            if let (None, Some(current)) = (&app.preview, app.songs.index()) {
+                let (previous, next) = gonk_player::neighbours(&app.songs);
+                let around = skim::Around {
+                    previous,
+                    current,
+                    next,
+                };
+                app.skim = skim::overflow(around, overflow, now);
+                //There's nothing to carry on into, stop at the start or end of the song.
+                if app.skim.is_none() {
+                    gonk_player::seek(if overflow < 0.0 {
+                        0.0
                     } else {
-                        mute = true;
-                        old_volume = get_volume();
-                        set_volume(0);
-                    }
-                }
-                Event::Char('q') => seek_backward(),
-                Event::Char('e') => seek_foward(),
-                Event::Char('a') => {
-                    songs.up();
-                    if let Some(song) = songs.selected() {
-                        play_song(song);
-                    }
-                }
-                Event::Char('d') => {
-                    songs.down();
-                    if let Some(song) = songs.selected() {
-                        play_song(song);
-                    }
-                }
-                Event::Char('w') => {
-                    volume_up();
-                    persist.volume = get_volume();
+                        duration().as_secs_f32()
+                    });
                 }
-                Event::Char('s') => {
-                    volume_down();
-                    persist.volume = get_volume();
-                }
-                Event::Escape if mode == Mode::Playlist => {
-                    if playlist.delete {
-                        playlist.yes = true;
-                        playlist.delete = false;
-                    } else if let playlist::Mode::Popup = playlist.mode {
-                        playlist.mode = playlist::Mode::Playlist;
-                        playlist.search_query = String::new();
-                        playlist.changed = true;
-                    }
-                }
-                Event::Tab if mode != Mode::Search => {
-                    prev_mode = mode.clone();
-                    mode = Mode::Search;
-                }
-                Event::Enter if mode == Mode::Browser && shift => {
-                    playlist::add(&mut playlist, browser::get_selected(&browser, &db));
-                    mode = Mode::Playlist
-                }
-                Event::Enter if mode == Mode::Browser => {
-                    songs.extend(browser::get_selected(&browser, &db));
-                }
-                Event::Enter if mode == Mode::Queue && shift => {
-                    if let Some(range) = &queue.range {
-                        let mut playlist_songs = Vec::new();
+            }
+        }
+        if let Some(target) = skim::due(&mut app.skim, now) {
+            if target.index < app.songs.len() {
+                app.queue.set_index(target.index);
+                gonk_player::skim_into(&mut app.songs, target.index, target.offset);
+            }
+        }
 
-                        for index in range.start..=range.end {
-                            if let Some(song) = songs.get(index) {
-                                playlist_songs.push(song.clone());
-                            }
-                        }
+        //Add large artists to the queue as they are expanded.
+        app::enqueue_pending(&mut app);
 
-                        playlist::add(&mut playlist, playlist_songs);
-                        mode = Mode::Playlist;
-                    }
-                }
-                Event::Enter if mode == Mode::Queue => {
-                    if let Some(i) = queue.index() {
-                        songs.select(Some(i));
-                        play_song(&songs[i]);
-                    }
-                }
-                Event::Enter if mode == Mode::Settings => {
-                    if let Some(device) = settings::selected(&settings) {
-                        let device = device.to_string();
-                        set_output_device(&device);
-                        settings.current_device = device.clone();
-                        persist.output_device = device.clone();
-                    }
-                }
-                Event::Enter if mode == Mode::Playlist => {
-                    playlist::on_enter(&mut playlist, &mut songs, shift);
-                }
-                Event::Enter if mode == Mode::Search && shift => {
-                    if let Some(songs) = search::on_enter(&mut search, &db) {
-                        playlist::add(
-                            &mut playlist,
-                            songs.iter().map(|song| song.clone().clone()).collect(),
-                        );
-                        mode = Mode::Playlist;
-                    }
-                }
-                Event::Enter if mode == Mode::Search => {
-                    if let Some(s) = search::on_enter(&mut search, &db) {
-                        //Swap to the queue so people can see what they added.
-                        mode = Mode::Queue;
-                        songs.extend(s.iter().cloned());
-                    }
+        #[cfg(feature = "desktop-notifications")]
+        for message in app.notifier.settled(Instant::now()) {
+            desktop::notify(&message);
+        }
+
+        //Count a play once enough of the song is heard, skipping or seeking through it doesn't count.
+        if !is_paused() {
+            if let Some(path) = app::count_play(&mut app, starts(), delta, duration()) {
+                if let Err(err) = app.db.increment_play_count(std::path::Path::new(&path)) {
+                    log!("Could not save the play count: {err}");
                 }
-                Event::Char('1') => mode = Mode::Queue,
-                Event::Char('2') => mode = Mode::Browser,
-                Event::Char('3') => mode = Mode::Playlist,
-                Event::Char('4') => mode = Mode::Settings,
-                Event::Function(1) => queue::constraint(&mut queue, 0, shift),
-                Event::Function(2) => queue::constraint(&mut queue, 1, shift),
-                Event::Function(3) => queue::constraint(&mut queue, 2, shift),
-                Event::Up | Event::Char('k') | Event::Char('K') => up!(),
-                Event::Down | Event::Char('j') | Event::Char('J') => down!(),
-                Event::Left | Event::Char('h') | Event::Char('H') => left!(),
-                Event::Right | Event::Char('l') | Event::Char('L') => right!(),
-                _ => {}
+                browser::resort(&mut app.browser, &app.db);
+            }
+        }
+
+        //Tell status bars and discord when the song or playback state changes.
+        let song = app.songs.selected();
+        let paused = is_paused();
+        let length = duration();
+        if song.map(|s| &s.path) != app.last_playing.0.as_ref()
+            || paused != app.last_playing.1
+            || length != app.last_playing.2
+        {
+            #[cfg(feature = "media-integrations")]
+            if let Some(sender) = &nowplaying {
+                let _ = sender.send(nowplaying::json(song, paused));
+            }
+
+            #[cfg(feature = "discord")]
+            if let Some(sender) = &app.discord {
+                let activity = song
+                    .filter(|_| app.settings.discord)
+                    .map(|song| discord::Activity::new(song, elapsed(), length, paused));
+                let _ = sender.send(activity);
             }
+
+            app.last_playing = (song.map(|s| s.path.clone()), paused, length);
         }
 
-        //New songs were added.
-        if empty && !songs.is_empty() {
-            queue.set_index(0);
-            songs.select(Some(0));
-            if let Some(song) = songs.selected() {
-                play_song(song);
+        waveform::update(
+            &mut app.waveform,
+            app.songs.selected().map(|song| song.path.as_str()),
+        );
+
+        draw(&mut winter, &mut app, &mut cursor);
+
+        if let Some((event, state)) = winter.poll() {
+            if handle_event(&mut app, event, state.shift(), state.control()).is_break() {
+                break;
             }
         }
 
@@ -634,8 +866,15 @@ fn main() {
         }
     }
 
-    persist.queue = songs.to_vec();
-    persist.index = songs.index().unwrap_or(0) as u16;
-    persist.elapsed = elapsed().as_secs_f32();
-    persist.save().unwrap();
+    app.persist.queue = app.songs.to_vec();
+    app.persist.index = app.songs.index().unwrap_or(0);
+    app.persist.elapsed = preview::elapsed(&app.preview);
+    app.persist.playing = !app.songs.is_empty() && !is_paused();
+    app.persist.save().unwrap();
+
+    //Let the audio fade out instead of cutting off.
+    if !app.songs.is_empty() && !is_paused() {
+        fade_out_and_stop(FADE_OUT);
+        std::thread::sleep(FADE_OUT);
+    }
 }
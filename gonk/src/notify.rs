@@ -0,0 +1,179 @@
+//! Collapse notifications of the same kind into one message.
+//!
+//! Adding an artist a chunk at a time or pressing enter on album after album
+//! would replace the message every time, instead they add up to a running total.
+//! The status bar shows the total as it grows, desktop notifications get it once it settles.
+use gonk_core::{log, Song};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+///Actions closer together than this are counted together.
+pub const WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Queued,
+    Liked,
+}
+
+#[derive(Debug)]
+struct Batch {
+    kind: Kind,
+    songs: usize,
+    albums: HashSet<(String, String)>,
+    last: Instant,
+}
+
+impl Batch {
+    fn message(&self) -> String {
+        match (self.kind, self.songs, self.albums.len()) {
+            (Kind::Queued, 1, _) => String::from("Added 1 song to the queue."),
+            (Kind::Queued, songs, albums) if albums > 1 => {
+                format!("Added {songs} songs from {albums} albums.")
+            }
+            (Kind::Queued, songs, _) => format!("Added {songs} songs to the queue."),
+            (Kind::Liked, 1, _) => String::from("Liked 1 song."),
+            (Kind::Liked, songs, _) => format!("Liked {songs} songs."),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Notifier {
+    batch: Option<Batch>,
+    ///Finished totals that haven't been sent to the desktop.
+    ready: Vec<String>,
+}
+
+impl Notifier {
+    ///Count the songs and return the message with the running total.
+    pub fn add(&mut self, kind: Kind, songs: &[Song], now: Instant) -> String {
+        let mut batch = match self.batch.take() {
+            Some(batch) if batch.kind == kind && now.duration_since(batch.last) < WINDOW => batch,
+            previous => {
+                self.ready.extend(previous.map(|batch| batch.message()));
+                Batch {
+                    kind,
+                    songs: 0,
+                    albums: HashSet::new(),
+                    last: now,
+                }
+            }
+        };
+
+        batch.songs += songs.len();
+        batch.albums.extend(
+            songs
+                .iter()
+                .map(|song| (song.artist.clone(), song.album.clone())),
+        );
+        batch.last = now;
+
+        let message = batch.message();
+        self.batch = Some(batch);
+        message
+    }
+
+    ///Start a new total, the next notification won't include the earlier ones.
+    pub fn flush(&mut self) {
+        self.ready
+            .extend(self.batch.take().map(|batch| batch.message()));
+    }
+
+    ///Totals that stopped growing, each one is only returned once.
+    pub fn settled(&mut self, now: Instant) -> Vec<String> {
+        if let Some(batch) = &self.batch {
+            if now.duration_since(batch.last) >= WINDOW {
+                self.flush();
+            }
+        }
+        std::mem::take(&mut self.ready)
+    }
+}
+
+///Show the running total in the status bar.
+pub fn notify(notifier: &mut Notifier, kind: Kind, songs: &[Song]) {
+    let message = notifier.add(kind, songs, Instant::now());
+    log::clear();
+    log!("{message}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn songs(album: &str, n: usize) -> Vec<Song> {
+        (0..n)
+            .map(|i| Song::test("artist", album, &format!("{album}{i}")))
+            .collect()
+    }
+
+    #[test]
+    fn collapse() {
+        let now = Instant::now();
+        let mut notifier = Notifier::default();
+
+        let message = notifier.add(Kind::Queued, &songs("a", 1), now);
+        assert_eq!(message, "Added 1 song to the queue.");
+
+        let message = notifier.add(Kind::Queued, &songs("a", 9), now + WINDOW / 2);
+        assert_eq!(message, "Added 10 songs to the queue.");
+
+        //Each action restarts the window.
+        let later = now + WINDOW / 2 + WINDOW / 2 + WINDOW / 4;
+        let message = notifier.add(Kind::Queued, &songs("b", 402), later);
+        assert_eq!(message, "Added 412 songs from 2 albums.");
+
+        //Other kinds start their own total.
+        let message = notifier.add(Kind::Liked, &songs("b", 2), later);
+        assert_eq!(message, "Liked 2 songs.");
+        let message = notifier.add(Kind::Queued, &songs("c", 3), later);
+        assert_eq!(message, "Added 3 songs to the queue.");
+    }
+
+    #[test]
+    fn flush() {
+        let now = Instant::now();
+        let mut notifier = Notifier::default();
+        notifier.add(Kind::Queued, &songs("a", 5), now);
+
+        //The window ran out.
+        let message = notifier.add(Kind::Queued, &songs("a", 5), now + WINDOW);
+        assert_eq!(message, "Added 5 songs to the queue.");
+
+        //Flushed by hand.
+        notifier.flush();
+        let message = notifier.add(Kind::Liked, &songs("a", 1), now + WINDOW);
+        assert_eq!(message, "Liked 1 song.");
+        notifier.flush();
+        let message = notifier.add(Kind::Liked, &songs("a", 1), now + WINDOW);
+        assert_eq!(message, "Liked 1 song.");
+    }
+
+    #[test]
+    fn settled() {
+        let now = Instant::now();
+        let mut notifier = Notifier::default();
+        notifier.add(Kind::Queued, &songs("a", 100), now);
+        notifier.add(Kind::Queued, &songs("b", 100), now + WINDOW / 2);
+
+        //Still growing.
+        assert!(notifier.settled(now + WINDOW).is_empty());
+
+        //The desktop gets the same total as the status bar, once.
+        let later = now + WINDOW / 2 + WINDOW;
+        assert_eq!(notifier.settled(later), ["Added 200 songs from 2 albums."]);
+        assert!(notifier.settled(later + WINDOW).is_empty());
+
+        //A different kind or a flush finishes the total early.
+        notifier.add(Kind::Queued, &songs("a", 3), later);
+        notifier.add(Kind::Liked, &songs("a", 1), later);
+        notifier.flush();
+        assert_eq!(
+            notifier.settled(later),
+            ["Added 3 songs to the queue.", "Liked 1 song."]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Now playing output for status bars like polybar and i3blocks.
+//!
+//! A JSON object is written to a named pipe every time the song or playback state changes.
+//! macOS uses a unix domain socket instead since FIFOs behave differently.
+//! On Windows the path is the name of the pipe, `now-playing` becomes `\\.\pipe\now-playing`.
+use gonk_core::Song;
+use std::{path::PathBuf, sync::mpsc::Sender};
+
+///Escape a string for use in JSON.
+pub fn escape(s: &str) -> String {
+    let mut buffer = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\t' => buffer.push_str("\\t"),
+            c if c.is_control() => buffer.push_str(&format!("\\u{:04x}", c as u32)),
+            c => buffer.push(c),
+        }
+    }
+    buffer
+}
+
+///Format the current song as a single line of JSON.
+pub fn json(song: Option<&Song>, paused: bool) -> String {
+    match song {
+        Some(song) => format!(
+            "{{\"state\":\"{}\",\"title\":\"{}\",\"album\":\"{}\",\"artist\":\"{}\"}}\n",
+            if paused { "paused" } else { "playing" },
+            escape(&song.title),
+            escape(&song.album),
+            escape(&song.artist)
+        ),
+        None => String::from("{\"state\":\"stopped\"}\n"),
+    }
+}
+
+///Spawn the thread that writes to the pipe.
+///
+///Returns `None` on platforms without named pipes.
+pub fn spawn(path: PathBuf) -> Option<Sender<String>> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        use nix::{sys::stat::Mode, unistd::mkfifo};
+        use std::{fs::OpenOptions, io::Write, sync::mpsc::channel, thread};
+
+        if let Err(err) = mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR) {
+            if err != nix::errno::Errno::EEXIST {
+                gonk_core::log!("Failed to create pipe: {err}");
+                return None;
+            }
+        }
+
+        let (sender, receiver) = channel::<String>();
+        thread::spawn(move || {
+            let mut latest = String::from("{\"state\":\"stopped\"}\n");
+            loop {
+                //Blocks until a reader connects.
+                let Ok(mut pipe) = OpenOptions::new().write(true).open(&path) else {
+                    return;
+                };
+
+                //Skip the changes that happened while waiting for a reader.
+                while let Ok(line) = receiver.try_recv() {
+                    latest = line;
+                }
+
+                if pipe.write_all(latest.as_bytes()).is_err() {
+                    continue;
+                }
+
+                //Write until the reader disconnects.
+                while let Ok(line) = receiver.recv() {
+                    latest = line;
+                    if pipe.write_all(latest.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Some(sender)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::{
+            io::Write,
+            os::unix::net::UnixListener,
+            sync::mpsc::{channel, RecvTimeoutError},
+            thread,
+            time::Duration,
+        };
+
+        let _ = std::fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                gonk_core::log!("Failed to create socket: {err}");
+                return None;
+            }
+        };
+        listener.set_nonblocking(true).ok()?;
+
+        let (sender, receiver) = channel::<String>();
+        thread::spawn(move || {
+            let mut latest = String::from("{\"state\":\"stopped\"}\n");
+            let mut clients = Vec::new();
+            loop {
+                //New clients get the current state straight away.
+                while let Ok((mut stream, _)) = listener.accept() {
+                    if stream.write_all(latest.as_bytes()).is_ok() {
+                        clients.push(stream);
+                    }
+                }
+
+                match receiver.recv_timeout(Duration::from_millis(250)) {
+                    Ok(line) => {
+                        latest = line;
+                        clients.retain_mut(|stream| stream.write_all(latest.as_bytes()).is_ok());
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        Some(sender)
+    }
+
+    #[cfg(windows)]
+    {
+        use crate::pipe::{Direction, Pipe};
+        use std::{io::Write, sync::mpsc::channel, thread};
+
+        let name = pipe_name(&path);
+        let pipe = match Pipe::create(&name, Direction::Outbound) {
+            Ok(pipe) => pipe,
+            Err(err) => {
+                gonk_core::log!("Failed to create pipe: {err}");
+                return None;
+            }
+        };
+
+        let (sender, receiver) = channel::<String>();
+        thread::spawn(move || {
+            let mut latest = String::from("{\"state\":\"stopped\"}\n");
+            //Blocks until a reader connects.
+            while pipe.connect().is_ok() {
+                //Skip the changes that happened while waiting for a reader.
+                while let Ok(line) = receiver.try_recv() {
+                    latest = line;
+                }
+
+                //Write until the reader disconnects.
+                let mut file = pipe.file();
+                let mut connected = file.write_all(latest.as_bytes()).is_ok();
+                while connected {
+                    let Ok(line) = receiver.recv() else {
+                        return;
+                    };
+                    latest = line;
+                    connected = file.write_all(latest.as_bytes()).is_ok();
+                }
+                pipe.disconnect();
+            }
+        });
+
+        Some(sender)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+///The full name of a pipe, anything that isn't already one is put in the pipe namespace.
+#[cfg(windows)]
+fn pipe_name(path: &std::path::Path) -> String {
+    const PREFIX: &str = r"\\.\pipe\";
+    let path = path.to_string_lossy();
+    if path.starts_with(PREFIX) {
+        path.into_owned()
+    } else {
+        let name = path.rsplit(['/', '\\']).next().unwrap_or_default();
+        format!("{PREFIX}{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let mut song = Song::example();
+        song.title = String::from("A \"quoted\" \\ title");
+        assert_eq!(
+            super::json(Some(&song), false),
+            "{\"state\":\"playing\",\"title\":\"A \\\"quoted\\\" \\\\ title\",\"album\":\"album\",\"artist\":\"artist\"}\n"
+        );
+        assert_eq!(super::json(None, true), "{\"state\":\"stopped\"}\n");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn pipe_name() {
+        let name = |path: &str| super::pipe_name(std::path::Path::new(path));
+        assert_eq!(name("now-playing"), r"\\.\pipe\now-playing");
+        assert_eq!(name(r"\\.\pipe\now-playing"), r"\\.\pipe\now-playing");
+        assert_eq!(name(r"C:\Users\me\now-playing"), r"\\.\pipe\now-playing");
+    }
+}
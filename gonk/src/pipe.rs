@@ -0,0 +1,116 @@
+//! Windows named pipes.
+//!
+//! The standard library can open a pipe as a file but it can't create one.
+//! Remote clients are rejected and the default permissions only let other users read,
+//! so an inbound pipe can only be written to by the user that created it.
+use std::{
+    ffi::c_void,
+    fs::File,
+    io,
+    iter::once,
+    mem::ManuallyDrop,
+    os::windows::io::{AsRawHandle, FromRawHandle},
+    ptr::{null, null_mut},
+    thread::JoinHandle,
+};
+
+const PIPE_ACCESS_INBOUND: u32 = 0x1;
+const PIPE_ACCESS_OUTBOUND: u32 = 0x2;
+const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x0008_0000;
+//Byte mode and blocking are both zero.
+const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x8;
+const ERROR_PIPE_CONNECTED: i32 = 535;
+const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateNamedPipeW(
+        name: *const u16,
+        open_mode: u32,
+        pipe_mode: u32,
+        max_instances: u32,
+        out_buffer_size: u32,
+        in_buffer_size: u32,
+        default_timeout: u32,
+        security_attributes: *const c_void,
+    ) -> *mut c_void;
+    fn ConnectNamedPipe(pipe: *mut c_void, overlapped: *mut c_void) -> i32;
+    fn DisconnectNamedPipe(pipe: *mut c_void) -> i32;
+    fn CloseHandle(handle: *mut c_void) -> i32;
+    fn CancelSynchronousIo(thread: *mut c_void) -> i32;
+}
+
+///Stop the read or write a thread is blocked on, it fails with `ERROR_OPERATION_ABORTED`.
+pub fn cancel<T>(thread: &JoinHandle<T>) {
+    unsafe { CancelSynchronousIo(thread.as_raw_handle()) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ///Clients write to gonk.
+    Inbound,
+    ///Gonk writes to clients.
+    Outbound,
+}
+
+///The server end of a pipe with a single instance, one client is connected at a time.
+pub struct Pipe {
+    handle: *mut c_void,
+}
+
+unsafe impl Send for Pipe {}
+
+impl Pipe {
+    ///Fails if another process already created a pipe with this name.
+    pub fn create(name: &str, direction: Direction) -> io::Result<Self> {
+        let name: Vec<u16> = name.encode_utf16().chain(once(0)).collect();
+        let access = match direction {
+            Direction::Inbound => PIPE_ACCESS_INBOUND,
+            Direction::Outbound => PIPE_ACCESS_OUTBOUND,
+        };
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                access | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                4096,
+                4096,
+                0,
+                null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { handle })
+    }
+
+    ///Block until a client opens the pipe.
+    pub fn connect(&self) -> io::Result<()> {
+        if unsafe { ConnectNamedPipe(self.handle, null_mut()) } != 0 {
+            return Ok(());
+        }
+        //The client opened the pipe before this was called.
+        match io::Error::last_os_error() {
+            err if err.raw_os_error() == Some(ERROR_PIPE_CONNECTED) => Ok(()),
+            err => Err(err),
+        }
+    }
+
+    ///Read and write the connected client, the handle still belongs to the pipe.
+    pub fn file(&self) -> ManuallyDrop<File> {
+        ManuallyDrop::new(unsafe { File::from_raw_handle(self.handle) })
+    }
+
+    ///Drop the client so the next one can connect.
+    pub fn disconnect(&self) {
+        unsafe { DisconnectNamedPipe(self.handle) };
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle) };
+    }
+}
@@ -1,5 +1,17 @@
-use crate::{ALBUM, ARTIST, TITLE};
-use gonk_core::{Index, Song};
+use crate::{
+    cast::to_u16_clamped,
+    draw_empty,
+    hitbox::{Hitboxes, Widget},
+    notify::{notify, Kind, Notifier},
+    screen::{amount, Action, Capture, Context, Screen},
+    theme::theme,
+};
+use gonk_core::{
+    log,
+    playlist::{same_name, validate_name},
+    vdb::Database,
+    Index, Normalize, Song,
+};
 use std::{error::Error, mem};
 use winter::*;
 
@@ -154,9 +166,17 @@ pub fn on_enter(playlist: &mut Playlist, songs: &mut Index<Song>, shift: bool) {
             }
         }
         Mode::Popup if !playlist.song_buffer.is_empty() => {
+            //Invalid names are shown in the popup as they're typed.
+            let Ok(name) = validate_name(&playlist.search_query) else {
+                return;
+            };
+            let name = name.to_string();
+
             //Find the index of the playlist
-            let name = playlist.search_query.trim().to_string();
-            let pos = playlist.lists.iter().position(|p| p.name() == name);
+            let pos = playlist
+                .lists
+                .iter()
+                .position(|p| same_name(p.name(), &name));
 
             let songs = mem::take(&mut playlist.song_buffer);
 
@@ -165,13 +185,17 @@ pub fn on_enter(playlist: &mut Playlist, songs: &mut Index<Song>, shift: bool) {
                 let pl = &mut playlist.lists[pos];
                 pl.songs.extend(songs);
                 pl.songs.select(Some(0));
-                pl.save().unwrap();
+                if let Err(err) = pl.save() {
+                    log!("Failed to save {}: {err}", pl.name());
+                }
                 playlist.lists.select(Some(pos));
             } else {
                 //If the playlist does not exist create it.
                 let len = playlist.lists.len();
                 playlist.lists.push(gonk_core::Playlist::new(&name, songs));
-                playlist.lists[len].save().unwrap();
+                if let Err(err) = playlist.lists[len].save() {
+                    log!("Failed to save {name}: {err}");
+                }
                 playlist.lists.select(Some(len));
             }
 
@@ -187,7 +211,8 @@ pub fn draw(
     playlist: &mut Playlist,
     area: winter::Rect,
     buf: &mut winter::Buffer,
-    mouse: Option<(u16, u16)>,
+    normalize: &Normalize,
+    hitboxes: &mut Hitboxes,
 ) -> Option<(u16, u16)> {
     let horizontal = layout(
         area,
@@ -195,21 +220,10 @@ pub fn draw(
         &[Constraint::Percentage(30), Constraint::Percentage(70)],
     );
 
-    if let Some((x, y)) = mouse {
-        let rect = Rect {
-            x,
-            y,
-            ..Default::default()
-        };
-
-        //Don't let the user change modes while adding songs.
-        if playlist.mode != Mode::Popup {
-            if rect.intersects(horizontal[1]) {
-                playlist.mode = Mode::Song;
-            } else if rect.intersects(horizontal[0]) {
-                playlist.mode = Mode::Playlist;
-            }
-        }
+    //Don't let the user change modes while adding songs.
+    if playlist.mode != Mode::Popup {
+        hitboxes.push(horizontal[0], Widget::PlaylistList);
+        hitboxes.push(horizontal[1], Widget::PlaylistSongs);
     }
 
     let items: Vec<Line<'_>> = playlist.lists.iter().map(|p| lines!(p.name())).collect();
@@ -231,9 +245,12 @@ pub fn draw(
             .iter()
             .map(|song| {
                 row![
-                    song.title.as_str().fg(TITLE),
-                    song.album.as_str().fg(ALBUM),
-                    song.artist.as_str().fg(ARTIST)
+                    normalize.title(&song.title).into_owned().fg(theme().title),
+                    normalize.album(&song.album).into_owned().fg(theme().album),
+                    normalize
+                        .artist(&song.artist, &song.title)
+                        .into_owned()
+                        .fg(theme().artist)
                 ]
             })
             .collect();
@@ -250,6 +267,11 @@ pub fn draw(
         .symbol(symbol)
         .block(song_block);
         table.draw(horizontal[1], buf, selected.songs.index());
+        if selected.songs.is_empty() && selected.is_liked() {
+            draw_empty(horizontal[1], buf, "Press F on a song to like it");
+        } else if selected.songs.is_empty() {
+            draw_empty(horizontal[1], buf, "No songs");
+        }
     } else {
         song_block.draw(horizontal[1], buf);
     }
@@ -295,6 +317,9 @@ pub fn draw(
                 .block(block().borders(Borders::RIGHT | Borders::BOTTOM))
                 .align(Center)
                 .draw(h[1], buf);
+
+            hitboxes.push(h[0], Widget::PlaylistYes);
+            hitboxes.push(h[1], Widget::PlaylistNo);
         }
     } else if let Mode::Popup = playlist.mode {
         //TODO: I think I want a different popup.
@@ -334,28 +359,21 @@ pub fn draw(
 
         if playlist.changed {
             playlist.changed = false;
-            let target_playlist = playlist.lists.iter().find_map(|p| {
-                if p.name().to_ascii_lowercase() == playlist.search_query.to_ascii_lowercase() {
-                    Some(p.name())
-                } else {
-                    None
-                }
-            });
-
-            let add_line = if let Some(target_playlist) = target_playlist {
-                lines!(
-                    "Add to ",
-                    "existing".underlined(),
-                    format!(" playlist: {}", target_playlist)
-                )
-            } else if playlist.search_query.is_empty() {
-                "Enter a playlist name...".into()
-            } else {
-                lines!(
-                    "Add to ",
-                    "new".underlined(),
-                    format!(" playlist: {}", playlist.search_query)
-                )
+            let add_line = match validate_name(&playlist.search_query) {
+                _ if playlist.search_query.is_empty() => "Enter a playlist name...".into(),
+                Err(err) => lines!(text!("{err}").fg(Red)),
+                Ok(name) => match playlist.lists.iter().find(|p| same_name(p.name(), name)) {
+                    Some(target) => lines!(
+                        "Add to ",
+                        "existing".underlined(),
+                        format!(" playlist: {}", target.name())
+                    ),
+                    None => lines!(
+                        "Add to ",
+                        "new".underlined(),
+                        format!(" playlist: {}", name)
+                    ),
+                },
             };
 
             playlist.search_result = Box::new(add_line);
@@ -371,8 +389,9 @@ pub fn draw(
             return Some((x, y));
         } else {
             let width = v[0].width.saturating_sub(3);
-            if playlist.search_query.len() < width as usize {
-                return Some((x + (playlist.search_query.len() as u16), y));
+            let len = to_u16_clamped(playlist.search_query.len());
+            if len < width {
+                return Some((x + len, y));
             } else {
                 return Some((x + width, y));
             }
@@ -382,9 +401,15 @@ pub fn draw(
     None
 }
 
-pub fn add(playlist: &mut Playlist, songs: Vec<Song>) {
+///Open the popup to add songs to a playlist. Returns false if there's nothing to add.
+pub fn add(playlist: &mut Playlist, songs: Vec<Song>) -> bool {
+    if songs.is_empty() {
+        log!("There are no songs to add.");
+        return false;
+    }
     playlist.song_buffer = songs;
     playlist.mode = Mode::Popup;
+    true
 }
 
 fn delete_song(playlist: &mut Playlist) {
@@ -393,12 +418,19 @@ fn delete_song(playlist: &mut Playlist) {
 
         if let Some(j) = selected.songs.index() {
             selected.songs.remove(j);
-            selected.save().unwrap();
+            if let Err(err) = selected.save() {
+                log!("Failed to save {}: {err}", selected.name());
+            }
 
             //If there are no songs left delete the playlist.
+            //Liked songs are kept around empty.
             if selected.songs.is_empty() {
-                selected.delete();
-                playlist.lists.remove_and_move(i);
+                if selected.is_liked() {
+                    selected.songs.select(None);
+                } else {
+                    selected.delete();
+                    playlist.lists.remove_and_move(i);
+                }
                 playlist.mode = Mode::Playlist;
             }
         }
@@ -408,6 +440,10 @@ fn delete_song(playlist: &mut Playlist) {
 
 fn delete_playlist(playlist: &mut Playlist) {
     if let Some(index) = playlist.lists.index() {
+        if playlist.lists[index].is_liked() {
+            log!("Liked songs can't be deleted.");
+            return playlist.delete = false;
+        }
         playlist.lists[index].delete();
         playlist.lists.remove_and_move(index);
         playlist.delete = false;
@@ -416,6 +452,13 @@ fn delete_playlist(playlist: &mut Playlist) {
 
 pub fn delete(playlist: &mut Playlist, shift: bool) {
     match playlist.mode {
+        //There's nothing to delete.
+        Mode::Playlist if playlist.lists.selected().is_none() => (),
+        Mode::Song
+            if !playlist
+                .lists
+                .selected()
+                .is_some_and(|p| p.songs.selected().is_some()) => {}
         Mode::Playlist if shift => delete_playlist(playlist),
         Mode::Song if shift => delete_song(playlist),
         Mode::Playlist | Mode::Song => {
@@ -424,3 +467,113 @@ pub fn delete(playlist: &mut Playlist, shift: bool) {
         Mode::Popup => (),
     }
 }
+
+///Add songs to the liked playlist.
+pub fn like(playlist: &mut Playlist, db: &mut Database, notifier: &mut Notifier, songs: Vec<Song>) {
+    let Some(liked) = playlist.lists.iter_mut().find(|p| p.is_liked()) else {
+        return;
+    };
+
+    let len = songs.len();
+    let before = liked.songs.len();
+    match gonk_core::playlist::add_to_liked(liked, songs) {
+        Ok(0) if len == 1 => log!("Song is already liked."),
+        Ok(0) => log!("Songs are already liked."),
+        Ok(_) => notify(notifier, Kind::Liked, &liked.songs[before..]),
+        Err(err) => log!("Failed to save liked songs: {err}"),
+    }
+    db.set_liked(liked);
+}
+
+///Export the selected playlist as an M3U8 file.
+pub fn export(playlist: &Playlist) {
+    let Some(selected) = playlist.lists.selected() else {
+        return;
+    };
+    let path = gonk_core::m3u::export_path(selected.name());
+    match gonk_core::m3u::export(&selected.songs, &path) {
+        Ok(()) => log!("Exported to {}", path.display()),
+        Err(err) => log!("Failed to export {}: {err}", selected.name()),
+    }
+}
+
+///Keep the liked markers in sync after the playlist was edited.
+pub fn sync_liked(playlist: &Playlist, db: &mut Database) {
+    if let Some(liked) = playlist.lists.iter().find(|p| p.is_liked()) {
+        db.set_liked(liked);
+    }
+}
+
+impl Screen for Playlist {
+    fn draw(&mut self, ctx: &mut Context, area: Rect, buf: &mut Buffer) -> Option<(u16, u16)> {
+        draw(self, area, buf, &ctx.normalize, ctx.hitboxes)
+    }
+
+    fn handle_key(
+        &mut self,
+        ctx: &mut Context,
+        event: &Event,
+        shift: bool,
+        control: bool,
+    ) -> Option<Vec<Action>> {
+        let mut actions = Vec::new();
+        match *event {
+            Event::Backspace => on_backspace(self, control),
+            //Handle ^W as control backspace.
+            Event::Char('w') if control && self.mode == Mode::Popup => on_backspace(self, true),
+            Event::Char(c) if self.mode == Mode::Popup => {
+                self.changed = true;
+                self.search_query.push(c);
+            }
+            Event::Char('x') => {
+                delete(self, false);
+                sync_liked(self, ctx.db);
+            }
+            //Force delete -> Shift + X.
+            Event::Char('X') => {
+                delete(self, true);
+                sync_liked(self, ctx.db);
+            }
+            Event::Char('E') => export(self),
+            Event::Char('u') => actions.push(Action::Scan),
+            Event::Escape if self.delete => {
+                self.yes = true;
+                self.delete = false;
+            }
+            Event::Escape if self.mode == Mode::Popup => {
+                self.mode = Mode::Playlist;
+                self.search_query = String::new();
+                self.changed = true;
+            }
+            Event::Enter => {
+                on_enter(self, ctx.songs, shift);
+                sync_liked(self, ctx.db);
+            }
+            Event::Up | Event::Char('k') | Event::Char('K') => up(self, amount(shift)),
+            Event::Down | Event::Char('j') | Event::Char('J') => down(self, amount(shift)),
+            Event::Left | Event::Char('h') | Event::Char('H') => left(self),
+            Event::Right | Event::Char('l') | Event::Char('L') => right(self),
+            _ => return None,
+        }
+        Some(actions)
+    }
+
+    #[cfg(feature = "mouse")]
+    fn handle_mouse(&mut self, ctx: &mut Context, widget: Widget) -> Vec<Action> {
+        match widget {
+            Widget::PlaylistList => self.mode = Mode::Playlist,
+            Widget::PlaylistSongs => self.mode = Mode::Song,
+            Widget::PlaylistYes | Widget::PlaylistNo => {
+                self.yes = widget == Widget::PlaylistYes;
+                on_enter(self, ctx.songs, false);
+                sync_liked(self, ctx.db);
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn wants_text_input(&self) -> Option<Capture> {
+        (self.mode == Mode::Popup).then_some(Capture::Playlist)
+    }
+}
@@ -0,0 +1,121 @@
+//! Audition a song without touching the queue.
+//!
+//! The queue's song is paused while the preview plays and resumed afterwards.
+use gonk_core::{log, Song};
+use gonk_player::{is_paused, pause, play_song, seek, seek_ratio};
+use std::time::{Duration, Instant};
+
+///Songs are previewed for this long.
+pub const LENGTH: Duration = Duration::from_secs(15);
+///Previews start a quarter of the way into the song.
+const START: f32 = 0.25;
+
+pub struct Preview {
+    pub title: String,
+    started: Instant,
+    ///The queue's song, position and paused state before the preview.
+    resume: Option<(Song, f32, bool)>,
+}
+
+impl Preview {
+    pub fn finished(&self) -> bool {
+        self.started.elapsed() >= LENGTH
+    }
+
+    ///Shown in the status bar while the preview plays.
+    pub fn message(&self) -> String {
+        format!("Previewing: {}", self.title)
+    }
+}
+
+///Start previewing a song, replacing any preview that is already playing.
+pub fn start(preview: &mut Option<Preview>, song: &Song, playing: Option<&Song>) {
+    //Previewing songs back to back should still resume the original song.
+    let resume = match preview.take() {
+        Some(previous) => {
+            log::remove(&previous.message());
+            previous.resume
+        }
+        None => playing.map(|playing| {
+            let position = gonk_player::elapsed().as_secs_f32();
+            (playing.clone(), position, is_paused())
+        }),
+    };
+
+    play_song(song);
+    seek_ratio(START);
+
+    *preview = Some(Preview {
+        title: song.title.clone(),
+        started: Instant::now(),
+        resume,
+    });
+}
+
+///Stop the preview and go back to the queue.
+pub fn stop(preview: &mut Option<Preview>) {
+    let Some(preview) = preview.take() else {
+        return;
+    };
+    log::remove(&preview.message());
+
+    match preview.resume {
+        Some((song, position, paused)) => {
+            play_song(&song);
+            seek(position);
+            if paused {
+                pause();
+            }
+        }
+        None => gonk_player::stop(),
+    }
+}
+
+///Keep the message up while the preview plays.
+pub fn show(preview: &Option<Preview>) {
+    if let Some(preview) = preview {
+        log::keep(&preview.message());
+    }
+}
+
+///Don't count the time the computer was asleep towards the preview.
+pub fn resume(preview: &mut Option<Preview>, gap: Duration, now: Instant) {
+    if let Some(preview) = preview {
+        preview.started = crate::suspend::reanchor(preview.started, gap, now);
+    }
+}
+
+///Position of the queue's song, ignoring the preview.
+pub fn elapsed(preview: &Option<Preview>) -> f32 {
+    match preview {
+        Some(Preview {
+            resume: Some((_, position, _)),
+            ..
+        }) => *position,
+        Some(_) => 0.0,
+        None => gonk_player::elapsed().as_secs_f32(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume() {
+        let mut preview = Some(Preview {
+            title: String::from("title"),
+            started: Instant::now(),
+            resume: Some((Song::example(), 42.0, true)),
+        });
+        assert_eq!(elapsed(&preview), 42.0);
+        assert!(!preview.as_ref().unwrap().finished());
+
+        //Nothing was playing before the preview.
+        preview.as_mut().unwrap().resume = None;
+        assert_eq!(elapsed(&preview), 0.0);
+
+        preview.as_mut().unwrap().started = Instant::now() - LENGTH;
+        assert!(preview.as_ref().unwrap().finished());
+    }
+}
@@ -1,8 +1,37 @@
-use crate::{ALBUM, ARTIST, NUMBER, SEEKER, TITLE};
-use core::ops::Range;
-use gonk_core::{log, Index, Song};
+use crate::{
+    browser::thousands,
+    cast::to_u16_clamped,
+    draw_empty,
+    hitbox::{Hitboxes, Widget},
+    screen::{amount, Action, Context, Screen},
+    theme::theme,
+    waveform, EMPTY_QUEUE,
+};
+use core::ops::{Range, RangeInclusive};
+use gonk_core::{
+    log,
+    settings::{QueueLayout, RepeatMode, BALANCED},
+    snapshot::{self, Snapshot},
+    vdb::Database,
+    Index, Normalize, Song,
+};
+use gonk_player::{play_song, seek};
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use winter::*;
 
+///Queues narrower than this use two lines per song when the layout is auto.
+const TWO_LINE_WIDTH: u16 = 70;
+
+pub enum QueueEntry {
+    ///Index of the song in the queue.
+    Song(usize),
+    ///Consecutive songs from the same album.
+    AlbumGroup { songs: Range<usize>, expanded: bool },
+}
+
 pub struct Queue {
     pub constraint: [u16; 4],
     //TODO: This doesn't remember the previous index after a selection.
@@ -10,6 +39,12 @@ pub struct Queue {
     //It would selected song 2, not song 6 like it should.
     //Select all should be a temporay operation.
     pub range: Option<Range<usize>>,
+    ///Collapse consecutive songs from the same album into a single row.
+    pub group_albums: bool,
+    ///(Artist, Album) of the groups opened by the user.
+    pub expanded: HashSet<(String, String)>,
+    ///Previous queues, the popup is open when this is `Some`.
+    pub snapshots: Option<Index<Snapshot>>,
 }
 
 impl Queue {
@@ -24,15 +59,27 @@ impl Queue {
     }
     pub fn new(index: usize) -> Self {
         Self {
-            constraint: [6, 37, 31, 26],
+            constraint: BALANCED,
             range: Some(index..index),
+            group_albums: false,
+            expanded: HashSet::new(),
+            snapshots: None,
         }
     }
+    ///Go back to the default column widths.
+    pub fn constraints_reset(&mut self) {
+        self.constraint = BALANCED;
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use gonk_core::*;
+    use super::{
+        constraint, draw, entries, expand, offset, row_hitboxes, rows, two_line, Queue, QueueEntry,
+    };
+    use crate::hitbox::{Hitboxes, Widget};
+    use gonk_core::{settings::QueueLayout, vdb::Database, *};
+    use winter::{Buffer, Rect};
 
     #[test]
     fn test() {
@@ -46,10 +93,384 @@ mod tests {
         assert_eq!(down(8, 7, 5), 4);
 
         assert_eq!(down(8, 1, 5), 6);
+
+        //Jumping further than the length of the list.
+        assert_eq!(up(2, 0, 3), 1);
+        assert_eq!(down(2, 1, 3), 0);
+    }
+
+    #[test]
+    fn tiny_terminal() {
+        let rect = |y, height| Rect {
+            x: 0,
+            y,
+            width: 80,
+            height,
+        };
+        let mut hitboxes = Hitboxes::default();
+        let starts: Vec<usize> = (0..100).collect();
+
+        //A 3 row terminal leaves no room for the table.
+        row_hitboxes(rect(3, 0), 0..=10, &starts, 1, &mut hitboxes);
+        for y in 0..4 {
+            assert!(hitboxes.get(5, y).is_none());
+        }
+
+        //Only the first row fits between the header and the border.
+        row_hitboxes(rect(3, 4), 0..=10, &starts, 1, &mut hitboxes);
+        assert_eq!(hitboxes.get(5, 5).unwrap().1, Widget::QueueRow(0));
+        assert!(hitboxes.get(5, 6).is_none());
+
+        //Rows past the end of the queue can't be clicked.
+        hitboxes.clear();
+        row_hitboxes(rect(0, 20), 0..=10, &starts[..2], 1, &mut hitboxes);
+        assert_eq!(hitboxes.get(5, 3).unwrap().1, Widget::QueueRow(1));
+        assert!(hitboxes.get(5, 4).is_none());
+
+        //Tables at the edge of the coordinate space.
+        hitboxes.clear();
+        row_hitboxes(
+            rect(u16::MAX - 1, u16::MAX),
+            0..=10,
+            &starts,
+            1,
+            &mut hitboxes,
+        );
+        assert!(hitboxes.get(5, u16::MAX).is_none());
+    }
+
+    #[test]
+    fn two_line_rows() {
+        let mut hitboxes = Hitboxes::default();
+        let starts: Vec<usize> = (0..100).collect();
+
+        for width in [50, 60] {
+            assert!(two_line(QueueLayout::Auto, width));
+            assert!(!two_line(QueueLayout::Columns, width));
+
+            //Header, a gap, 8 rows of songs and the border.
+            let table = Rect {
+                x: 0,
+                y: 3,
+                width,
+                height: 19,
+            };
+            let visible = 8;
+
+            //The selected song is scrolled to the bottom.
+            let start = offset(Some(20), visible);
+            assert_eq!(start, 13);
+
+            hitboxes.clear();
+            row_hitboxes(
+                table,
+                start..=start + visible - 1,
+                &starts,
+                2,
+                &mut hitboxes,
+            );
+
+            //Both lines of a row select the same song.
+            assert_eq!(hitboxes.get(5, 5).unwrap().1, Widget::QueueRow(13));
+            assert_eq!(hitboxes.get(width - 1, 6).unwrap().1, Widget::QueueRow(13));
+            assert_eq!(hitboxes.get(5, 7).unwrap().1, Widget::QueueRow(14));
+            assert_eq!(hitboxes.get(5, 19).unwrap().1, Widget::QueueRow(20));
+            assert_eq!(hitboxes.get(5, 20).unwrap().1, Widget::QueueRow(20));
+            assert!(hitboxes.get(5, 21).is_none());
+        }
+        assert!(!two_line(QueueLayout::Auto, 80));
+        assert!(two_line(QueueLayout::TwoLine, 80));
+
+        //An odd number of lines leaves the last one empty.
+        hitboxes.clear();
+        let table = Rect {
+            x: 0,
+            y: 0,
+            width: 50,
+            height: 6,
+        };
+        row_hitboxes(table, 0..=10, &starts, 2, &mut hitboxes);
+        assert_eq!(hitboxes.get(5, 3).unwrap().1, Widget::QueueRow(0));
+        assert!(hitboxes.get(5, 4).is_none());
+
+        assert_eq!(offset(Some(3), 0), 0);
+        assert_eq!(offset(None, 8), 0);
+        assert_eq!(offset(Some(7), 8), 0);
+    }
+
+    #[test]
+    fn two_line_draw() {
+        let songs: Vec<Song> = (0..30)
+            .map(|i| Song::test("artist", "album", &format!("Song {i}")))
+            .collect();
+        let db = Database::from_songs(songs.clone());
+
+        for width in [50, 60] {
+            let area = Rect {
+                x: 0,
+                y: 0,
+                width,
+                height: 25,
+            };
+            let mut buf = Buffer::empty(area);
+            let mut songs = Index::new(songs.clone(), Some(20));
+            let mut queue = Queue::new(20);
+            let mut hitboxes = Hitboxes::default();
+            draw(
+                &mut queue,
+                area,
+                &mut buf,
+                &mut songs,
+                &db,
+                false,
+                &Normalize::default(),
+                QueueLayout::Auto,
+                None,
+                &mut hitboxes,
+            );
+
+            //The table is below the 3 line header, the selected song is scrolled to the bottom.
+            let row = |y| match hitboxes.get(5, y) {
+                Some((_, Widget::QueueRow(i))) => Some(i),
+                _ => None,
+            };
+            let clicked: Vec<Option<usize>> = (5..21).map(row).collect();
+            let expected: Vec<Option<usize>> = (13..=20).flat_map(|i| [Some(i), Some(i)]).collect();
+            assert_eq!(clicked, expected, "{width} columns");
+            assert_eq!(row(4), None);
+            assert_eq!(row(21), None);
+        }
+    }
+
+    #[test]
+    fn group_albums() {
+        let song = |artist: &str, album: &str| Song::test(artist, album, "title");
+        let mut songs = Index::new(
+            vec![
+                song("a", "x"),
+                song("a", "x"),
+                song("a", "y"),
+                song("b", "x"),
+                song("b", "x"),
+                song("b", "x"),
+            ],
+            Some(4),
+        );
+        let mut queue = Queue::new(0);
+        queue.group_albums = true;
+
+        let entries = entries(&queue, &songs);
+        assert!(matches!(
+            entries[..],
+            [
+                QueueEntry::AlbumGroup {
+                    expanded: false,
+                    ..
+                },
+                QueueEntry::Song(2),
+                //The playing song is always visible.
+                QueueEntry::AlbumGroup { expanded: true, .. },
+            ]
+        ));
+        assert_eq!(rows(&entries), [0..2, 2..3, 3..4, 4..5, 5..6]);
+
+        //Moving down skips over the collapsed songs.
+        super::down(&mut queue, &songs, 1);
+        assert_eq!(queue.index(), Some(2));
+        super::up(&mut queue, &mut songs, 1);
+        assert_eq!(queue.index(), Some(0));
+
+        assert!(expand(&mut queue, &songs));
+        assert_eq!(rows(&super::entries(&queue, &songs)).len(), 6);
+        super::down(&mut queue, &songs, 1);
+        assert_eq!(queue.index(), Some(1));
+        assert!(!expand(&mut queue, &songs));
+
+        super::collapse(&mut queue, &songs);
+        assert_eq!(queue.index(), Some(0));
+        assert_eq!(rows(&super::entries(&queue, &songs)).len(), 5);
+
+        //Nothing is grouped when the option is off.
+        queue.group_albums = false;
+        songs.select(None);
+        assert_eq!(rows(&super::entries(&queue, &songs)).len(), 6);
+    }
+
+    #[test]
+    fn ago() {
+        assert_eq!(super::ago(0), "just now");
+        assert_eq!(super::ago(59), "just now");
+        assert_eq!(super::ago(60), "1 min ago");
+        assert_eq!(super::ago(7200), "2 hours ago");
+        assert_eq!(super::ago(86400 * 3), "3 days ago");
+    }
+
+    #[test]
+    fn constraint_extremes() {
+        let mut queue = Queue::new(0);
+        queue.constraint = [100, 0, 0, 0];
+
+        //Push everything to the last column and back again.
+        for (row, shift, expected) in [
+            (0, true, [0, 100, 0, 0]),
+            (1, true, [0, 0, 100, 0]),
+            (2, true, [0, 0, 0, 100]),
+            (2, false, [0, 0, 100, 0]),
+            (1, false, [0, 100, 0, 0]),
+            (0, false, [100, 0, 0, 0]),
+        ] {
+            for _ in 0..201 {
+                constraint(&mut queue, row, shift);
+                assert_eq!(queue.constraint.iter().sum::<u16>(), 100);
+            }
+            assert_eq!(queue.constraint, expected);
+        }
+
+        //Nothing left to move.
+        constraint(&mut queue, 0, false);
+        assert_eq!(queue.constraint, [100, 0, 0, 0]);
+    }
+
+    #[test]
+    fn constraints_reset() {
+        let mut queue = Queue::new(0);
+        for (row, shift) in [(0, false), (1, true), (2, true), (0, true)] {
+            for _ in 0..30 {
+                constraint(&mut queue, row, shift);
+            }
+            queue.constraints_reset();
+            assert_eq!(queue.constraint.iter().sum::<u16>(), 100);
+            assert_eq!(queue.constraint, Queue::new(0).constraint);
+        }
+    }
+}
+
+///Group consecutive songs from the same album.
+///
+///The group with the playing song is always expanded.
+pub fn entries(queue: &Queue, songs: &Index<Song>) -> Vec<QueueEntry> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+
+    while let Some(first) = songs.get(start) {
+        let end = songs[start..]
+            .iter()
+            .position(|song| song.artist != first.artist || song.album != first.album)
+            .map_or(songs.len(), |len| start + len);
+
+        if !queue.group_albums || end - start == 1 {
+            entries.extend((start..end).map(QueueEntry::Song));
+        } else {
+            let playing = songs.index().is_some_and(|i| (start..end).contains(&i));
+            let key = (first.artist.clone(), first.album.clone());
+            entries.push(QueueEntry::AlbumGroup {
+                songs: start..end,
+                expanded: playing || queue.expanded.contains(&key),
+            });
+        }
+
+        start = end;
+    }
+
+    entries
+}
+
+///The songs on each row of the table.
+fn rows(entries: &[QueueEntry]) -> Vec<Range<usize>> {
+    let mut rows = Vec::new();
+    for entry in entries {
+        match entry {
+            QueueEntry::Song(i) => rows.push(*i..*i + 1),
+            QueueEntry::AlbumGroup {
+                songs,
+                expanded: true,
+            } => rows.extend(songs.clone().map(|i| i..i + 1)),
+            QueueEntry::AlbumGroup {
+                songs,
+                expanded: false,
+            } => rows.push(songs.clone()),
+        }
+    }
+    rows
+}
+
+///Find the row that contains a song.
+fn row(rows: &[Range<usize>], index: usize) -> Option<usize> {
+    rows.iter().position(|row| row.contains(&index))
+}
+
+///Songs on the selected row. A collapsed album has more than one.
+pub fn selected_row(queue: &Queue, songs: &Index<Song>) -> Option<Range<usize>> {
+    let index = queue.index()?;
+    rows(&entries(queue, songs))
+        .into_iter()
+        .find(|row| row.contains(&index))
+}
+
+///Open the selected album. Returns false if a single song is selected.
+pub fn expand(queue: &mut Queue, songs: &Index<Song>) -> bool {
+    let Some(row) = selected_row(queue, songs) else {
+        return false;
+    };
+    if row.len() < 2 {
+        return false;
+    }
+
+    let song = &songs[row.start];
+    queue
+        .expanded
+        .insert((song.artist.clone(), song.album.clone()));
+    true
+}
+
+///Close the album that the selected song belongs to.
+pub fn collapse(queue: &mut Queue, songs: &Index<Song>) {
+    let Some(index) = queue.index() else {
+        return;
+    };
+
+    for entry in entries(queue, songs) {
+        if let QueueEntry::AlbumGroup {
+            songs: range,
+            expanded: true,
+        } = entry
+        {
+            if range.contains(&index) {
+                let song = &songs[index];
+                queue
+                    .expanded
+                    .remove(&(song.artist.clone(), song.album.clone()));
+                if !songs.index().is_some_and(|i| range.contains(&i)) {
+                    queue.set_index(range.start);
+                }
+                return;
+            }
+        }
+    }
+}
+
+///Keep the selection on the same songs after `len` songs were inserted at `position`.
+pub fn inserted(queue: &mut Queue, position: usize, len: usize) {
+    if let Some(range) = &mut queue.range {
+        if position <= range.start {
+            range.start += len;
+        }
+        if position <= range.end {
+            range.end += len;
+        }
     }
 }
 
 pub fn up(queue: &mut Queue, songs: &mut Index<Song>, amount: usize) {
+    if let Some(snapshots) = &mut queue.snapshots {
+        return snapshots.up_n(amount);
+    }
+
+    let rows = rows(&entries(queue, songs));
+    if rows.is_empty() {
+        return;
+    }
+
     if let Some(range) = &mut queue.range {
         if range.start != range.end && range.start == 0 {
             //If the user selectes every song.
@@ -60,8 +481,8 @@ pub fn up(queue: &mut Queue, songs: &mut Index<Song>, amount: usize) {
             return;
         };
 
-        let index = range.start;
-        let new_index = gonk_core::up(songs.len(), index, amount);
+        let row = row(&rows, range.start).unwrap_or(0);
+        let new_index = rows[gonk_core::up(rows.len(), row, amount)].start;
 
         //This will override and ranges and just set the position
         //to a single index.
@@ -70,9 +491,18 @@ pub fn up(queue: &mut Queue, songs: &mut Index<Song>, amount: usize) {
 }
 
 pub fn down(queue: &mut Queue, songs: &Index<Song>, amount: usize) {
+    if let Some(snapshots) = &mut queue.snapshots {
+        return snapshots.down_n(amount);
+    }
+
+    let rows = rows(&entries(queue, songs));
+    if rows.is_empty() {
+        return;
+    }
+
     if let Some(range) = &mut queue.range {
-        let index = range.start;
-        let new_index = gonk_core::down(songs.len(), index, amount);
+        let row = row(&rows, range.start).unwrap_or(0);
+        let new_index = rows[gonk_core::down(rows.len(), row, amount)].start;
 
         //This will override and ranges and just set the position
         //to a single index.
@@ -84,12 +514,17 @@ pub fn draw(
     queue: &mut Queue,
     viewport: winter::Rect,
     buf: &mut winter::Buffer,
-    mouse: Option<(u16, u16)>,
     songs: &mut Index<Song>,
+    db: &Database,
     mute: bool,
+    normalize: &Normalize,
+    layout: QueueLayout,
+    waveform: Option<&[u8]>,
+    hitboxes: &mut Hitboxes,
 ) {
     let fill = viewport.height.saturating_sub(3 + 3);
-    let area = layout(
+    let two_line = two_line(layout, viewport.width);
+    let area = winter::layout(
         viewport,
         Direction::Vertical,
         &[
@@ -101,15 +536,30 @@ pub fn draw(
     );
 
     //Header
+    let state = if songs.is_empty() {
+        "Stopped"
+    } else if gonk_player::is_paused() {
+        "Paused"
+    } else {
+        "Playing"
+    };
+    let rate = gonk_player::rate();
+    let mut title = if rate == 1.0 || songs.is_empty() {
+        state.to_string()
+    } else {
+        format!("{state} {rate}x")
+    };
+    if gonk_player::gain_bypassed() {
+        title.push_str(" (Gain bypassed)");
+    }
+    title.push(' ');
+    title.push_str(repeat_symbol(gonk_player::repeat()));
+    if gonk_player::shuffled() {
+        title.push_str(" 🔀");
+    }
     block()
         .borders(Borders::TOP | Borders::LEFT | Borders::RIGHT)
-        .title(if songs.is_empty() {
-            "Stopped"
-        } else if gonk_player::is_paused() {
-            "Paused"
-        } else {
-            "Playing"
-        })
+        .title(title.as_str())
         .title_margin(1)
         .draw(area[0], buf);
 
@@ -147,14 +597,14 @@ pub fn draw(
 
             let top = lines![
                 text!("─│ {}", pad_front),
-                artist.fg(ARTIST),
+                artist.fg(theme().artist),
                 " ─ ",
-                album.fg(ALBUM),
+                album.fg(theme().album),
                 text!("{} │─", pad_back)
             ];
             top.align(Center).draw(area[0], buf);
 
-            let bottom = lines!(title.fg(TITLE));
+            let bottom = lines!(title.fg(theme().title));
             let mut area = area[0];
             if area.height > 1 {
                 area.y += 1;
@@ -163,12 +613,25 @@ pub fn draw(
         }
     }
 
-    let volume: Line<'_> = if mute {
-        "Mute─╮".into()
+    let volume = if mute {
+        String::from("Mute")
     } else {
-        text!("Vol: {}%─╮", gonk_player::get_volume()).into()
+        format!("Vol: {}%", gonk_player::get_volume())
     };
-    volume.align(Right).draw(area[0], buf);
+    lines!(text!("{}─╮", volume))
+        .align(Right)
+        .draw(area[0], buf);
+    //The border corner isn't part of it.
+    let width = to_u16_clamped(volume.width()).min(area[0].width.saturating_sub(2));
+    hitboxes.push(
+        Rect {
+            x: area[0].x + area[0].width.saturating_sub(width + 2),
+            y: area[0].y,
+            width,
+            height: 1,
+        },
+        Widget::Volume,
+    );
 
     let mut row_bounds = None;
 
@@ -180,97 +643,86 @@ pub fn draw(
             block().borders(Borders::LEFT | Borders::RIGHT)
         };
         block.draw(area[1], buf);
+        draw_empty(area[1], buf, EMPTY_QUEUE);
     } else {
-        let mut rows: Vec<Row> = songs
-            .iter()
-            .map(|song| {
-                row![
-                    text!(),
-                    song.track_number.to_string().fg(NUMBER),
-                    song.title.as_str().fg(TITLE),
-                    song.album.as_str().fg(ALBUM),
-                    song.artist.as_str().fg(ARTIST)
-                ]
-            })
-            .collect();
-
-        'selection: {
-            let Some(playing_index) = songs.index() else {
-                break 'selection;
-            };
-
-            let Some(song) = songs.get(playing_index) else {
-                break 'selection;
-            };
+        let playing = songs.index();
+        let selected = queue.range.clone();
+        let rows = rows(&entries(queue, songs));
+        let index = queue.index().and_then(|i| self::row(&rows, i));
+        let starts: Vec<usize> = rows.iter().map(|row| row.start).collect();
 
-            let Some(user_range) = &queue.range else {
-                break 'selection;
-            };
+        if two_line {
+            let (start, end) = draw_two_line(area[1], buf, queue, &rows, songs, db, normalize);
+            row_bounds = Some(((start, end), starts, 2));
+        } else {
+            let table_rows: Vec<Row> = rows
+                .iter()
+                .map(|row| {
+                    //The user range is inclusive.
+                    let is_selected = selected
+                        .as_ref()
+                        .is_some_and(|s| row.start <= s.end && s.start < row.end);
 
-            if playing_index != user_range.start {
-                //Currently playing song and not selected.
-                //Has arrow and standard colors.
-                rows[playing_index] = row![
-                    ">>".fg(White).dim().bold(),
-                    song.track_number.to_string().fg(NUMBER),
-                    song.title.as_str().fg(TITLE),
-                    song.album.as_str().fg(ALBUM),
-                    song.artist.as_str().fg(ARTIST)
-                ];
-            }
+                    if row.len() == 1 {
+                        let song = &songs[row.start];
+                        let marker = if playing == Some(row.start) {
+                            ">>"
+                        } else if db.is_liked(&song.path) {
+                            "♥"
+                        } else {
+                            ""
+                        };
+                        song_row(song, marker, is_selected, normalize)
+                    } else {
+                        album_row(&songs[row.clone()], is_selected, normalize)
+                    }
+                })
+                .collect();
 
-            for index in user_range.start..=user_range.end {
-                let Some(song) = songs.get(index) else {
-                    continue;
-                };
-                if index == playing_index {
-                    //Currently playing and currently selected.
-                    //Has arrow and inverted colors.
-                    rows[index] = row![
-                        ">>".fg(White).dim().bold(),
-                        song.track_number.to_string().bg(NUMBER).fg(Black).dim(),
-                        song.title.as_str().bg(TITLE).fg(Black).dim(),
-                        song.album.as_str().bg(ALBUM).fg(Black).dim(),
-                        song.artist.as_str().bg(ARTIST).fg(Black).dim()
-                    ];
-                } else {
-                    rows[index] = row![
-                        text!(),
-                        song.track_number.to_string().fg(Black).bg(NUMBER).dim(),
-                        song.title.as_str().fg(Black).bg(TITLE).dim(),
-                        song.album.as_str().fg(Black).bg(ALBUM).dim(),
-                        song.artist.as_str().fg(Black).bg(ARTIST).dim()
-                    ];
-                }
-            }
+            let con = [
+                Constraint::Length(2),
+                Constraint::Percentage(queue.constraint[0]),
+                Constraint::Percentage(queue.constraint[1]),
+                Constraint::Percentage(queue.constraint[2]),
+                Constraint::Percentage(queue.constraint[3]),
+            ];
+            let block = block().borders(Borders::LEFT | Borders::RIGHT | Borders::BOTTOM);
+            let header = header![
+                text!(),
+                "#".bold(),
+                "Title".bold(),
+                "Album".bold(),
+                "Artist".bold()
+            ];
+            let table = table(table_rows, &con)
+                .header(header)
+                .block(block)
+                .spacing(1);
+            table.draw(area[1], buf, index);
+            row_bounds = Some((
+                table.get_row_bounds(index, table.get_row_height(area[1])),
+                starts,
+                1,
+            ));
         }
-
-        let con = [
-            Constraint::Length(2),
-            Constraint::Percentage(queue.constraint[0]),
-            Constraint::Percentage(queue.constraint[1]),
-            Constraint::Percentage(queue.constraint[2]),
-            Constraint::Percentage(queue.constraint[3]),
-        ];
-        let block = block().borders(Borders::LEFT | Borders::RIGHT | Borders::BOTTOM);
-        let header = header![
-            text!(),
-            "#".bold(),
-            "Title".bold(),
-            "Album".bold(),
-            "Artist".bold()
-        ];
-        let table = table(rows, &con).header(header).block(block).spacing(1);
-        table.draw(area[1], buf, queue.index());
-        row_bounds = Some(table.get_row_bounds(queue.index(), table.get_row_height(area[1])));
     };
 
     if log::last_message().is_none() {
         //Seeker
         if songs.is_empty() {
-            return block()
+            //Nothing is playing, show the size of the library instead.
+            let total = match db.len {
+                0 => String::new(),
+                1 => String::from("1 song"),
+                n => format!("{} songs", thousands(n)),
+            };
+            block()
                 .borders(Borders::BOTTOM | Borders::LEFT | Borders::RIGHT)
                 .draw(area[2], buf);
+            if let Ok(inner) = area[2].inner(1, 1) {
+                lines!(total.as_str().dim()).align(Center).draw(inner, buf);
+            }
+            return;
         }
 
         let elapsed = gonk_player::elapsed().as_secs_f32();
@@ -292,62 +744,357 @@ pub fn draw(
                 ratio.clamp(0.0, 1.0)
             };
 
-            guage(Some(block()), ratio, seeker.into(), bg(SEEKER), style()).draw(area[2], buf);
+            match (waveform, area[2].inner(1, 1)) {
+                (Some(levels), Ok(inner)) if !levels.is_empty() => {
+                    block()
+                        .title(seeker.as_str())
+                        .title_margin(1)
+                        .draw(area[2], buf);
+                    let bars = waveform::bars(levels, inner.width as usize);
+                    let played = (ratio * inner.width as f32).round() as usize;
+                    let (played, rest) = bars.split_at(
+                        bars.char_indices()
+                            .nth(played)
+                            .map_or(bars.len(), |(i, _)| i),
+                    );
+                    lines!(played.fg(theme().seeker), rest.dim()).draw(inner, buf);
+                }
+                _ => guage(
+                    Some(block()),
+                    ratio,
+                    seeker.into(),
+                    bg(theme().seeker),
+                    style(),
+                )
+                .draw(area[2], buf),
+            }
         } else {
             guage(
                 Some(block()),
                 0.0,
                 "00:00/00:00".into(),
-                bg(SEEKER),
+                bg(theme().seeker),
                 style(),
             )
             .draw(area[2], buf);
         }
+
+        hitboxes.push(area[2], Widget::SeekBar);
     }
 
-    //Don't handle mouse input when the queue is empty.
-    if songs.is_empty() {
-        return;
+    if let Some(((start, end), starts, row_height)) = row_bounds {
+        row_hitboxes(area[1], start..=end, &starts, row_height, hitboxes);
+        scrollbar(area[1], start..=end, starts.len(), buf, hitboxes);
     }
 
-    //Handle mouse input.
-    if let Some((x, y)) = mouse {
-        let header_height = 5;
-        let size = viewport;
+    if let Some(snapshots) = &queue.snapshots {
+        let Ok(area) = viewport.centered(40, 9) else {
+            return;
+        };
 
-        //Mouse support for the seek bar.
-        if (size.height - 3 == y || size.height - 2 == y || size.height - 1 == y)
-            && size.height > 15
-        {
-            let ratio = x as f32 / size.width as f32;
-            let duration = gonk_player::duration().as_secs_f32();
-            gonk_player::seek(duration * ratio);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let items: Vec<Line> = snapshots
+            .iter()
+            .map(|snapshot| {
+                lines!(
+                    text!("{} songs", snapshot.songs.len()),
+                    text!(" - {}", ago(now.saturating_sub(snapshot.time) / 1000)).dim()
+                )
+            })
+            .collect();
+
+        buf.clear(area);
+        list(&items)
+            .block(block().title("Restore queue").title_margin(1))
+            .symbol(">")
+            .draw(area, buf, snapshots.index());
+    }
+}
+
+///How long ago something happened in a short form.
+fn ago(secs: u64) -> String {
+    match secs {
+        0..60 => String::from("just now"),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} hours ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+///The marker is `>>` for the playing song and `♥` for liked songs.
+fn song_row<'a>(
+    song: &Song,
+    marker: &'static str,
+    selected: bool,
+    normalize: &Normalize,
+) -> Row<'a> {
+    let arrow = marker;
+    let number = song.track_number.to_string();
+    let title = normalize.title(&song.title).into_owned();
+    let album = normalize.album(&song.album).into_owned();
+    let artist = normalize.artist(&song.artist, &song.title).into_owned();
+
+    let arrow_color = if marker == ">>" {
+        theme().playing
+    } else {
+        White
+    };
+
+    if selected {
+        //Inverted colors.
+        row![
+            arrow.fg(arrow_color).dim().bold(),
+            number.fg(Black).bg(theme().number).dim(),
+            title.fg(Black).bg(theme().title).dim(),
+            album.fg(Black).bg(theme().album).dim(),
+            artist.fg(Black).bg(theme().artist).dim()
+        ]
+    } else {
+        row![
+            arrow.fg(arrow_color).dim().bold(),
+            number.fg(theme().number),
+            title.fg(theme().title),
+            album.fg(theme().album),
+            artist.fg(theme().artist)
+        ]
+    }
+}
+
+///A collapsed album. It never contains the playing song.
+fn album_row<'a>(songs: &[Song], selected: bool, normalize: &Normalize) -> Row<'a> {
+    let first = &songs[0];
+    let name = format!(
+        "{} - {} ({} songs)",
+        first.artist,
+        normalize.album(&first.album),
+        songs.len()
+    );
+
+    if selected {
+        row![
+            text!(),
+            "+".fg(Black).bg(theme().number).dim(),
+            name.fg(Black).bg(theme().album).dim(),
+            text!(),
+            text!()
+        ]
+    } else {
+        row![
+            text!(),
+            "+".fg(theme().number),
+            name.fg(theme().album),
+            text!(),
+            text!()
+        ]
+    }
+}
+
+///Shown in the header next to the playback state.
+fn repeat_symbol(mode: RepeatMode) -> &'static str {
+    match mode {
+        RepeatMode::Off => "—",
+        RepeatMode::Queue => "🔁",
+        RepeatMode::One => "🔂",
+    }
+}
+
+///Should the queue be drawn with two lines per song.
+fn two_line(layout: QueueLayout, width: u16) -> bool {
+    match layout {
+        QueueLayout::Auto => width < TWO_LINE_WIDTH,
+        QueueLayout::Columns => false,
+        QueueLayout::TwoLine => true,
+    }
+}
+
+///The first row to draw so the selected row stays on screen.
+fn offset(index: Option<usize>, visible: usize) -> usize {
+    match index {
+        Some(index) if visible != 0 && index >= visible => index + 1 - visible,
+        _ => 0,
+    }
+}
+
+///Draw the title on one line and the artist and album on the next.
+///
+///Returns the first and last row that were drawn.
+fn draw_two_line(
+    table: Rect,
+    buf: &mut Buffer,
+    queue: &Queue,
+    rows: &[Range<usize>],
+    songs: &Index<Song>,
+    db: &Database,
+    normalize: &Normalize,
+) -> (usize, usize) {
+    block()
+        .borders(Borders::LEFT | Borders::RIGHT | Borders::BOTTOM)
+        .draw(table, buf);
+
+    let x = table.x.saturating_add(1);
+    let width = table.width.saturating_sub(2);
+    let line = |y| Rect {
+        x,
+        y,
+        width,
+        height: 1,
+    };
+
+    //Same spacing as the table so the rows line up with the hitboxes.
+    let top = table.y.saturating_add(2);
+    let bottom = table.y.saturating_add(table.height).saturating_sub(1);
+    if top > bottom {
+        return (0, 0);
+    }
+    lines!("    # ".bold(), "Title".bold()).draw(line(table.y), buf);
+
+    let visible = usize::from((bottom - top) / 2);
+    let index = queue.index().and_then(|i| self::row(rows, i));
+    let offset = offset(index, visible);
+
+    for (i, row) in rows.iter().enumerate().skip(offset).take(visible) {
+        let y = top.saturating_add(to_u16_clamped(i - offset).saturating_mul(2));
+
+        //The user range is inclusive.
+        let is_selected = queue
+            .range
+            .as_ref()
+            .is_some_and(|s| row.start <= s.end && s.start < row.end);
+
+        let (first, second) = if row.len() == 1 {
+            let song = &songs[row.start];
+            let marker = if songs.index() == Some(row.start) {
+                ">>"
+            } else if db.is_liked(&song.path) {
+                "♥"
+            } else {
+                ""
+            };
+            (
+                format!(
+                    "{marker:2} {:>2} {}",
+                    song.track_number,
+                    normalize.title(&song.title)
+                ),
+                format!(
+                    "      {} — {}",
+                    normalize.artist(&song.artist, &song.title),
+                    normalize.album(&song.album)
+                ),
+            )
+        } else {
+            let first = &songs[row.start];
+            (
+                format!(
+                    "    + {} ({} songs)",
+                    normalize.album(&first.album),
+                    row.len()
+                ),
+                format!("      {}", first.artist),
+            )
+        };
+
+        if is_selected {
+            //Pad the text so the highlight covers both lines.
+            let width = usize::from(width);
+            lines!(format!("{first:width$}").fg(Black).bg(theme().title).dim()).draw(line(y), buf);
+            lines!(format!("{second:width$}").fg(Black).bg(theme().title).dim())
+                .draw(line(y.saturating_add(1)), buf);
+        } else {
+            lines!(first.fg(theme().title)).draw(line(y), buf);
+            lines!(second.dim()).draw(line(y.saturating_add(1)), buf);
         }
+    }
 
-        //Mouse support for the queue.
-        if let Some((start, _)) = row_bounds {
-            //Check if you clicked on the header.
-            if y >= header_height {
-                let index = (y - header_height) as usize + start;
-
-                //Make sure you didn't click on the seek bar
-                //and that the song index exists.
-                if index < songs.len()
-                    && ((size.height < 15 && y < size.height.saturating_sub(1))
-                        || y < size.height.saturating_sub(3))
-                {
-                    queue.range = Some(index..index);
-                }
-            }
+    (offset, offset + visible.saturating_sub(1))
+}
+
+///Register a hitbox for every visible row of the queue table.
+///
+///`starts` is the first song on each row. Each row is `row_height` lines tall.
+fn row_hitboxes(
+    table: Rect,
+    rows: RangeInclusive<usize>,
+    starts: &[usize],
+    row_height: u16,
+    hitboxes: &mut Hitboxes,
+) {
+    //Rows start below the table header and stop above the bottom border.
+    let top = table.y.saturating_add(2);
+    let bottom = table.y.saturating_add(table.height).saturating_sub(1);
+
+    for (i, index) in rows.enumerate() {
+        let y = top.saturating_add(to_u16_clamped(i).saturating_mul(row_height));
+        let Some(&start) = starts.get(index) else {
+            break;
+        };
+        //Rows that are cut off by the border aren't drawn.
+        if y as u32 + row_height as u32 > bottom as u32 {
+            break;
         }
+
+        let mut row = table;
+        row.y = y;
+        row.height = row_height;
+        hitboxes.push(row, Widget::QueueRow(start));
     }
 }
 
+///Draw a thumb on the right border when not every row fits, it can be clicked and dragged.
+fn scrollbar(
+    table: Rect,
+    shown: RangeInclusive<usize>,
+    rows: usize,
+    buf: &mut Buffer,
+    hitboxes: &mut Hitboxes,
+) {
+    //Same space as the rows.
+    let top = table.y.saturating_add(2);
+    let bottom = table.y.saturating_add(table.height).saturating_sub(1);
+    let (first, last) = shown.into_inner();
+    if table.width == 0 || top >= bottom || (first == 0 && last + 1 >= rows) {
+        return;
+    }
+
+    let track = Rect {
+        x: table.x + table.width - 1,
+        y: top,
+        width: 1,
+        height: bottom - top,
+    };
+    let height = track.height as usize;
+    let start = first * height / rows;
+    let len = ((last + 1 - first) * height / rows).max(1);
+    for y in start..(start + len).min(height) {
+        let mut cell = track;
+        cell.y += y as u16;
+        cell.height = 1;
+        lines!("┃".fg(theme().seeker)).draw(cell, buf);
+    }
+    hitboxes.push(track, Widget::QueueScrollbar);
+}
+
+///Select the row `ratio` of the way down the queue, used by the scrollbar.
+#[cfg(feature = "mouse")]
+pub fn scroll_to(queue: &mut Queue, songs: &Index<Song>, ratio: f32) {
+    let rows = rows(&entries(queue, songs));
+    if rows.is_empty() {
+        return;
+    }
+    let row = ((rows.len() - 1) as f32 * ratio.clamp(0.0, 1.0)).round() as usize;
+    queue.set_index(rows[row].start);
+}
+
 pub fn constraint(queue: &mut Queue, row: usize, shift: bool) {
-    if shift && queue.constraint[row] != 0 {
+    if shift {
         //Move row back.
-        queue.constraint[row + 1] += 1;
-        queue.constraint[row] = queue.constraint[row].saturating_sub(1);
+        if queue.constraint[row] != 0 {
+            queue.constraint[row + 1] += 1;
+            queue.constraint[row] -= 1;
+        }
     } else if queue.constraint[row + 1] != 0 {
         //Move row forward.
         queue.constraint[row] += 1;
@@ -360,3 +1107,158 @@ pub fn constraint(queue: &mut Queue, row: usize, shift: bool) {
         queue.constraint
     );
 }
+
+///Keep a copy of the queue before it's cleared or replaced.
+pub fn save_snapshot(songs: &Index<Song>, elapsed: f32) {
+    if let Err(err) = snapshot::save(songs, songs.index(), elapsed) {
+        log!("Failed to save the queue: {err}");
+    }
+}
+
+impl Screen for Queue {
+    fn draw(&mut self, ctx: &mut Context, area: Rect, buf: &mut Buffer) -> Option<(u16, u16)> {
+        draw(
+            self,
+            area,
+            buf,
+            ctx.songs,
+            ctx.db,
+            ctx.mute,
+            &ctx.normalize,
+            ctx.queue_layout,
+            ctx.waveform,
+            ctx.hitboxes,
+        );
+        None
+    }
+
+    fn handle_key(
+        &mut self,
+        ctx: &mut Context,
+        event: &Event,
+        shift: bool,
+        _control: bool,
+    ) -> Option<Vec<Action>> {
+        let mut actions = Vec::new();
+        match *event {
+            Event::Escape if self.snapshots.is_some() => self.snapshots = None,
+            Event::Enter if self.snapshots.is_some() => {
+                let Some(mut snapshots) = self.snapshots.take() else {
+                    return Some(actions);
+                };
+                let Some(i) = snapshots.index() else {
+                    return Some(actions);
+                };
+
+                let (restored, index, elapsed, skipped) = snapshots.remove(i).resolve();
+                if restored.is_empty() {
+                    let message = "None of the songs in that queue could be found.";
+                    return Some(vec![Action::Message(message.to_string())]);
+                }
+
+                //The current queue becomes the newest snapshot.
+                save_snapshot(ctx.songs, ctx.elapsed);
+
+                actions.push(Action::Message(if skipped == 0 {
+                    format!("Restored {} songs.", restored.len())
+                } else {
+                    format!(
+                        "Restored {} songs, skipped {} missing songs.",
+                        restored.len(),
+                        skipped
+                    )
+                }));
+
+                *ctx.songs = Index::new(restored, index);
+                self.set_index(index.unwrap_or(0));
+                if let Some(song) = ctx.songs.selected() {
+                    play_song(song);
+                    seek(elapsed);
+                }
+            }
+            Event::Char('R') => {
+                let snapshots = snapshot::snapshots();
+                if snapshots.is_empty() {
+                    actions.push(Action::Message(String::from(
+                        "There are no previous queues.",
+                    )));
+                } else {
+                    self.snapshots = Some(Index::new(snapshots, Some(0)));
+                }
+            }
+            Event::Char('x') => {
+                //Collapsed albums are deleted together.
+                if let Some(row) = selected_row(self, ctx.songs) {
+                    let i = row.start;
+                    for _ in row {
+                        gonk_player::delete(ctx.songs, i);
+                    }
+
+                    //Sync the UI index.
+                    let len = ctx.songs.len().saturating_sub(1);
+                    if i > len {
+                        self.set_index(len);
+                    } else {
+                        self.set_index(i);
+                    }
+                }
+            }
+            #[cfg(feature = "playlists")]
+            Event::Enter if shift => {
+                if let Some(range) = &self.range {
+                    //A single selection might be a collapsed album.
+                    let range = match selected_row(self, ctx.songs) {
+                        Some(row) if range.start == range.end => row.start..=row.end - 1,
+                        _ => range.start..=range.end,
+                    };
+
+                    let songs = range.filter_map(|i| ctx.songs.get(i)).cloned().collect();
+                    actions.push(Action::AddToPlaylist(songs));
+                }
+            }
+            Event::Enter => {
+                //The selection can be left on an empty queue.
+                if let Some(i) = self.index().filter(|i| *i < ctx.songs.len()) {
+                    if !expand(self, ctx.songs) {
+                        ctx.songs.select(Some(i));
+                        play_song(&ctx.songs[i]);
+                    }
+                }
+            }
+            Event::Char('g') => {
+                self.group_albums = !self.group_albums;
+                //Keep the selection on a visible row.
+                if let Some(row) = selected_row(self, ctx.songs) {
+                    self.set_index(row.start);
+                }
+            }
+            #[cfg(feature = "playlists")]
+            Event::Char('f') => {
+                if let Some(row) = selected_row(self, ctx.songs) {
+                    actions.push(Action::Like(ctx.songs[row].to_vec()));
+                }
+            }
+            Event::Up | Event::Char('k') | Event::Char('K') => up(self, ctx.songs, amount(shift)),
+            Event::Down | Event::Char('j') | Event::Char('J') => {
+                down(self, ctx.songs, amount(shift))
+            }
+            Event::Left | Event::Char('h') | Event::Char('H') => collapse(self, ctx.songs),
+            Event::Right | Event::Char('l') | Event::Char('L') => {
+                expand(self, ctx.songs);
+            }
+            _ => return None,
+        }
+        Some(actions)
+    }
+
+    #[cfg(feature = "mouse")]
+    fn handle_mouse(&mut self, ctx: &mut Context, widget: Widget) -> Vec<Action> {
+        //The queue may have changed since the last frame.
+        if let Widget::QueueRow(index) = widget {
+            if index < ctx.songs.len() {
+                self.set_index(index);
+            }
+        }
+        Vec::new()
+    }
+}
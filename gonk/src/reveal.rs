@@ -0,0 +1,97 @@
+//! Show a song in the system file manager.
+//!
+//! The path is passed as its own argument so spaces and unicode don't need quoting.
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+///The command that opens the file manager at a file.
+#[cfg(windows)]
+pub fn command(path: &Path) -> Command {
+    //Explorer accepts the path after `/select,` as a separate argument.
+    let mut command = Command::new("explorer");
+    command.arg("/select,").arg(path);
+    command
+}
+
+///The command that opens the file manager at a file.
+#[cfg(target_os = "macos")]
+pub fn command(path: &Path) -> Command {
+    let mut command = Command::new("open");
+    command.arg("-R").arg(path);
+    command
+}
+
+///The command that opens the file manager at a file.
+///
+///There's no standard way to select a file, so the folder is opened instead.
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn command(path: &Path) -> Command {
+    let mut command = Command::new("xdg-open");
+    command.arg(path.parent().unwrap_or(path));
+    command
+}
+
+///Open the file manager without waiting for it.
+pub fn reveal(path: &Path) -> Result<(), String> {
+    if !path.exists() {
+        return Err(format!("{} doesn't exist.", path.display()));
+    }
+
+    let mut child = command(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("Couldn't open the file manager: {err}"))?;
+
+    //Explorer exits with an error code even when it works, so it's ignored.
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn args(command: &Command) -> Vec<&OsStr> {
+        command.get_args().collect()
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn windows() {
+        let path = Path::new(r"C:\My Music\Björk, Sigur Rós\01 Jóga.flac");
+        let command = command(path);
+        assert_eq!(command.get_program(), "explorer");
+        assert_eq!(args(&command), ["/select,", path.as_os_str()]);
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn macos() {
+        let path = Path::new("/Users/me/My Music/Björk/01 Jóga.flac");
+        let command = command(path);
+        assert_eq!(command.get_program(), "open");
+        assert_eq!(args(&command), ["-R", path.as_os_str()]);
+    }
+
+    #[test]
+    #[cfg(not(any(windows, target_os = "macos")))]
+    fn linux() {
+        let path = Path::new("/home/me/My Music/Björk/01 Jóga.flac");
+        let command = command(path);
+        assert_eq!(command.get_program(), "xdg-open");
+        assert_eq!(args(&command), ["/home/me/My Music/Björk"]);
+    }
+
+    #[test]
+    fn missing() {
+        assert!(reveal(Path::new("missing/song.flac"))
+            .unwrap_err()
+            .contains("doesn't exist"));
+    }
+}
@@ -0,0 +1,113 @@
+//! The screens that can be switched between.
+//!
+//! Each screen handles its own keys and clicks. Anything that reaches outside of the screen,
+//! like adding to the queue or switching screens, is returned as an action and applied by the app.
+#[cfg(feature = "mouse")]
+use crate::hitbox::Widget;
+#[cfg(feature = "search")]
+use crate::Mode;
+use crate::{browser::Pending, hitbox::Hitboxes, JUMP_AMOUNT};
+use gonk_core::{
+    settings::{BrowserMode, BrowserSort, QueueLayout},
+    vdb::Database,
+    Index, Normalize, Song,
+};
+use winter::{Buffer, Event, Rect};
+
+///A text box that takes typed characters instead of running commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    ///The path to add in the browser.
+    Path,
+    ///The settings filter.
+    Filter,
+    ///The playlist name popup.
+    #[cfg(feature = "playlists")]
+    Playlist,
+    ///The search query.
+    #[cfg(feature = "search")]
+    Search,
+}
+
+///Something a screen can't do by itself.
+pub enum Action {
+    ///Add songs to the end of the queue.
+    Enqueue(Vec<Song>),
+    ///Add a large artist to the queue a chunk at a time.
+    Pending(Pending),
+    ///Open the playlist popup with these songs.
+    #[cfg(feature = "playlists")]
+    AddToPlaylist(Vec<Song>),
+    #[cfg(feature = "playlists")]
+    Like(Vec<Song>),
+    #[cfg(feature = "search")]
+    Mode(Mode),
+    ///Go back to the screen search was opened from.
+    #[cfg(feature = "search")]
+    LeaveSearch,
+    ///Replace the status message.
+    Message(String),
+    ///Rescan the music folder.
+    Scan,
+    BrowserSort(BrowserSort),
+    ///Change the panes shown in the browser.
+    BrowserMode(BrowserMode),
+    ///Turn discord on or off.
+    #[cfg(feature = "discord")]
+    Discord,
+}
+
+///The state every screen shares.
+pub struct Context<'a> {
+    pub db: &'a mut Database,
+    pub songs: &'a mut Index<Song>,
+    pub persist: &'a mut gonk_core::settings::Settings,
+    pub hitboxes: &'a mut Hitboxes,
+    pub normalize: Normalize,
+    pub queue_layout: QueueLayout,
+    pub mute: bool,
+    ///A song is being previewed instead of the queue playing.
+    pub previewing: bool,
+    ///Seconds into the song in the queue, a preview doesn't move it.
+    pub elapsed: f32,
+    pub waveform: Option<&'a [u8]>,
+}
+
+pub trait Screen {
+    ///Returns where to put the cursor.
+    fn draw(&mut self, ctx: &mut Context, area: Rect, buf: &mut Buffer) -> Option<(u16, u16)>;
+
+    ///Returns `None` when the key isn't used, it's then handled as a global binding.
+    fn handle_key(
+        &mut self,
+        ctx: &mut Context,
+        event: &Event,
+        shift: bool,
+        control: bool,
+    ) -> Option<Vec<Action>>;
+
+    ///Called with the widgets this screen drew.
+    #[cfg(feature = "mouse")]
+    fn handle_mouse(&mut self, _ctx: &mut Context, _widget: Widget) -> Vec<Action> {
+        Vec::new()
+    }
+
+    ///Called every frame, even when the screen isn't open.
+    fn on_tick(&mut self, _ctx: &mut Context) -> Vec<Action> {
+        Vec::new()
+    }
+
+    ///The text box that has focus, if any.
+    fn wants_text_input(&self) -> Option<Capture> {
+        None
+    }
+}
+
+///How far to move the selection.
+pub fn amount(shift: bool) -> usize {
+    if shift {
+        JUMP_AMOUNT
+    } else {
+        1
+    }
+}
@@ -1,7 +1,14 @@
-use crate::{ALBUM, ARTIST, TITLE};
+use crate::{
+    cast::to_u16_clamped,
+    draw_empty,
+    hitbox::{Hitboxes, Widget},
+    screen::{amount, Action, Capture, Context, Screen},
+    theme::theme,
+    EMPTY_LIBRARY,
+};
 use gonk_core::{
     vdb::{Database, Item},
-    Index, Song,
+    Index, Normalize, Song,
 };
 use winter::*;
 
@@ -11,11 +18,42 @@ pub enum Mode {
     Select,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SearchSortOrder {
+    #[default]
+    Relevance,
+    Artist,
+    Album,
+    Title,
+}
+
+impl SearchSortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SearchSortOrder::Relevance => SearchSortOrder::Artist,
+            SearchSortOrder::Artist => SearchSortOrder::Album,
+            SearchSortOrder::Album => SearchSortOrder::Title,
+            SearchSortOrder::Title => SearchSortOrder::Relevance,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchSortOrder::Relevance => "relevance",
+            SearchSortOrder::Artist => "artist",
+            SearchSortOrder::Album => "album",
+            SearchSortOrder::Title => "title",
+        }
+    }
+}
+
 pub struct Search {
     pub query: String,
     pub query_changed: bool,
     pub mode: Mode,
     pub results: Index<Item>,
+    pub sort: SearchSortOrder,
+    ///Results in the order returned by the database.
+    relevance: Vec<Item>,
 }
 
 impl Search {
@@ -25,8 +63,56 @@ impl Search {
             query_changed: false,
             mode: Mode::Search,
             results: Index::default(),
+            sort: SearchSortOrder::default(),
+            relevance: Vec::new(),
         }
     }
+    pub fn update_search(&mut self, db: &Database) {
+        self.relevance = db.search(&self.query);
+        *self.results = self.relevance.clone();
+        self.set_sort(self.sort);
+    }
+    ///Sort the results and keep the same item selected.
+    pub fn set_sort(&mut self, order: SearchSortOrder) {
+        self.sort = order;
+        let selected = self.results.selected().cloned();
+
+        let mut results = self.relevance.clone();
+        //Items without the field go last. The sort is stable so ties keep their relevance order.
+        match order {
+            SearchSortOrder::Relevance => {}
+            SearchSortOrder::Artist => results.sort_by_cached_key(|item| key(Some(artist(item)))),
+            SearchSortOrder::Album => results.sort_by_cached_key(|item| match item {
+                Item::Song((_, album, _, _, _, _)) | Item::Album((_, album)) => key(Some(album)),
+                Item::Artist(_) => key(None),
+            }),
+            SearchSortOrder::Title => results.sort_by_cached_key(|item| match item {
+                Item::Song((_, _, title, _, _, _)) => key(Some(title)),
+                Item::Album(_) | Item::Artist(_) => key(None),
+            }),
+        }
+        *self.results = results;
+
+        if let Some(selected) = selected {
+            let index = self.results.iter().position(|item| *item == selected);
+            self.results.select(index);
+        }
+    }
+}
+
+fn artist(item: &Item) -> &str {
+    match item {
+        Item::Song((artist, _, _, _, _, _)) | Item::Album((artist, _)) | Item::Artist(artist) => {
+            artist
+        }
+    }
+}
+
+fn key(field: Option<&str>) -> (bool, String) {
+    match field {
+        Some(field) => (false, field.to_lowercase()),
+        None => (true, String::new()),
+    }
 }
 
 //TODO: Artist and albums colors aren't quite right.
@@ -34,33 +120,23 @@ pub fn draw(
     search: &mut Search,
     area: winter::Rect,
     buf: &mut winter::Buffer,
-    mouse: Option<(u16, u16)>,
     db: &Database,
+    normalize: &Normalize,
+    hitboxes: &mut Hitboxes,
 ) -> Option<(u16, u16)> {
     if search.query_changed {
         search.query_changed = !search.query_changed;
-        *search.results = db.search(&search.query);
+        search.update_search(db);
     }
 
     let v = layout(area, Vertical, &[Length(3), Fill]);
 
-    if let Some((x, y)) = mouse {
-        let rect = Rect {
-            x,
-            y,
-            ..Default::default()
-        };
-        if rect.intersects(v[0]) {
-            search.mode = Mode::Search;
-            search.results.select(None);
-        } else if rect.intersects(v[1]) && !search.results.is_empty() {
-            search.mode = Mode::Select;
-            search.results.select(Some(0));
-        }
-    }
+    hitboxes.push(v[0], Widget::SearchInput);
+    hitboxes.push(v[1], Widget::SearchResults);
 
+    let title = format!("Search [by: {}]", search.sort.name());
     lines!(search.query.as_str())
-        .block(block().title("Search:"))
+        .block(block().title(title.as_str()))
         .scroll()
         .draw(v[0], buf);
 
@@ -70,12 +146,12 @@ pub fn draw(
         .enumerate()
         .map(|(i, item)| {
             let Some(s) = search.results.index() else {
-                return cell(item, false);
+                return cell(item, false, normalize);
             };
             if s == i {
-                cell(item, true)
+                cell(item, true, normalize)
             } else {
-                cell(item, false)
+                cell(item, false, normalize)
             }
         })
         .collect();
@@ -98,50 +174,66 @@ pub fn draw(
     .block(block());
 
     table.draw(v[1], buf, search.results.index());
-
-    let layout_margin = 1;
-    let x = 1 + layout_margin;
-    let y = 1 + layout_margin;
+    if db.len == 0 {
+        draw_empty(v[1], buf, EMPTY_LIBRARY);
+    } else if search.results.is_empty() && !search.query.is_empty() {
+        draw_empty(v[1], buf, "No results");
+    }
 
     if let Mode::Search = search.mode {
         if search.results.index().is_none() && search.query.is_empty() {
-            Some((x, y))
+            Some((2, 2))
         } else {
-            let len = search.query.len() as u16;
-            let max_width = area.width.saturating_sub(3);
-            if len >= max_width {
-                Some((x - 1 + max_width, y))
-            } else {
-                Some((x + len, y))
-            }
+            Some(cursor(&search.query, area.width))
         }
     } else {
         None
     }
 }
 
+///Position of the cursor at the end of the query.
+fn cursor(query: &str, width: u16) -> (u16, u16) {
+    let layout_margin = 1;
+    let x = 1 + layout_margin;
+    let y = 1 + layout_margin;
+
+    let len = to_u16_clamped(query.len());
+    let max_width = width.saturating_sub(3);
+    if len >= max_width {
+        (x - 1 + max_width, y)
+    } else {
+        (x + len, y)
+    }
+}
+
 //Items have a lifetime of 'search because they live in the Search struct.
-fn cell(item: &Item, selected: bool) -> Row<'_> {
+fn cell<'a>(item: &'a Item, selected: bool, normalize: &Normalize) -> Row<'a> {
     let selected_cell = if selected { ">" } else { "" };
 
     match item {
-        Item::Song((artist, album, name, _, _)) => row![
+        Item::Song((artist, album, name, _, _, _)) => row![
             selected_cell,
-            name.as_str().fg(TITLE),
-            album.as_str().fg(ALBUM),
-            artist.as_str().fg(ARTIST)
+            normalize.title(name).into_owned().fg(theme().title),
+            normalize.album(album).into_owned().fg(theme().album),
+            normalize
+                .artist(artist, name)
+                .into_owned()
+                .fg(theme().artist)
         ],
         Item::Album((artist, album)) => row![
             selected_cell,
-            lines!(text!("{album} - ").fg(ALBUM), "Album".fg(ALBUM).italic()),
+            lines!(
+                text!("{album} - ").fg(theme().album),
+                "Album".fg(theme().album).italic()
+            ),
             "-",
-            artist.fg(ARTIST)
+            artist.fg(theme().artist)
         ],
         Item::Artist(artist) => row![
             selected_cell,
             lines!(
-                text!("{artist} - ").fg(ARTIST),
-                "Artist".fg(ARTIST).italic()
+                text!("{artist} - ").fg(theme().artist),
+                "Artist".fg(theme().artist).italic()
             ),
             "-",
             "-"
@@ -176,6 +268,17 @@ pub fn on_backspace(search: &mut Search, control: bool, shift: bool) {
     }
 }
 
+///The selected result if it's a song.
+pub fn selected_song(search: &Search, db: &Database) -> Option<Song> {
+    if search.mode != Mode::Select {
+        return None;
+    }
+    match search.results.selected()? {
+        Item::Song((artist, album, _, _, _, path)) => db.song(artist, album, path).cloned(),
+        Item::Album(_) | Item::Artist(_) => None,
+    }
+}
+
 pub fn on_enter(search: &mut Search, db: &Database) -> Option<Vec<Song>> {
     match search.mode {
         Mode::Search => {
@@ -186,10 +289,13 @@ pub fn on_enter(search: &mut Search, db: &Database) -> Option<Vec<Song>> {
             None
         }
         Mode::Select => search.results.selected().map(|item| match item {
-            Item::Song((artist, album, _, disc, number)) => {
-                vec![db.song(artist, album, *disc, *number).clone()]
+            Item::Song((artist, album, _, _, _, path)) => {
+                db.song(artist, album, path).into_iter().cloned().collect()
             }
-            Item::Album((artist, album)) => db.album(artist, album).songs.clone(),
+            Item::Album((artist, album)) => db
+                .album(artist, album)
+                .map(|album| album.songs.clone())
+                .unwrap_or_default(),
             Item::Artist(artist) => db
                 .albums_by_artist(artist)
                 .iter()
@@ -198,3 +304,156 @@ pub fn on_enter(search: &mut Search, db: &Database) -> Option<Vec<Song>> {
         }),
     }
 }
+
+impl Screen for Search {
+    fn draw(&mut self, ctx: &mut Context, area: Rect, buf: &mut Buffer) -> Option<(u16, u16)> {
+        draw(self, area, buf, ctx.db, &ctx.normalize, ctx.hitboxes)
+    }
+
+    fn handle_key(
+        &mut self,
+        ctx: &mut Context,
+        event: &Event,
+        shift: bool,
+        control: bool,
+    ) -> Option<Vec<Action>> {
+        let mut actions = Vec::new();
+        match *event {
+            Event::Char('/') => match self.mode {
+                Mode::Search if self.query.is_empty() => actions.push(Action::LeaveSearch),
+                Mode::Search => {
+                    self.query.push('/');
+                    self.query_changed = true;
+                }
+                Mode::Select => {
+                    self.mode = Mode::Search;
+                    self.results.select(None);
+                }
+            },
+            Event::Backspace => on_backspace(self, control, shift),
+            //Handle ^W as control backspace.
+            Event::Char('w') if control => on_backspace(self, control, shift),
+            Event::Char(c) if self.mode == Mode::Search => {
+                self.query.push(c);
+                self.query_changed = true;
+            }
+            Event::Escape => {
+                self.query = String::new();
+                self.query_changed = true;
+                self.mode = Mode::Search;
+                self.results.select(None);
+                actions.push(Action::LeaveSearch);
+            }
+            Event::Tab if self.mode == Mode::Select => self.set_sort(self.sort.next()),
+            Event::Tab => actions.push(Action::LeaveSearch),
+            #[cfg(feature = "playlists")]
+            Event::Enter if shift => {
+                if let Some(songs) = on_enter(self, ctx.db) {
+                    actions.push(Action::AddToPlaylist(songs));
+                }
+            }
+            Event::Enter => {
+                if let Some(songs) = on_enter(self, ctx.db) {
+                    //Swap to the queue so people can see what they added.
+                    actions.push(Action::Mode(crate::Mode::Queue));
+                    actions.push(Action::Enqueue(songs));
+                }
+            }
+            Event::Up | Event::Char('k') | Event::Char('K') => self.results.up_n(amount(shift)),
+            Event::Down | Event::Char('j') | Event::Char('J') => self.results.down_n(amount(shift)),
+            _ => return None,
+        }
+        Some(actions)
+    }
+
+    #[cfg(feature = "mouse")]
+    fn handle_mouse(&mut self, _ctx: &mut Context, widget: Widget) -> Vec<Action> {
+        match widget {
+            Widget::SearchInput => {
+                self.mode = Mode::Search;
+                self.results.select(None);
+            }
+            Widget::SearchResults if !self.results.is_empty() => {
+                self.mode = Mode::Select;
+                self.results.select(Some(0));
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn wants_text_input(&self) -> Option<Capture> {
+        (self.mode == Mode::Search).then_some(Capture::Search)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort() {
+        let song = |artist: &str, album: &str, title: &str| {
+            Item::Song((
+                artist.into(),
+                album.into(),
+                title.into(),
+                1,
+                1,
+                title.into(),
+            ))
+        };
+        let mut search = Search::new();
+        search.relevance = vec![
+            song("b", "y", "2"),
+            Item::Artist("c".into()),
+            song("a", "z", "1"),
+            Item::Album(("b".into(), "x".into())),
+            song("B", "w", "1"),
+        ];
+        *search.results = search.relevance.clone();
+        search.results.select(Some(2));
+
+        search.set_sort(SearchSortOrder::Artist);
+        assert_eq!(
+            *search.results,
+            [
+                song("a", "z", "1"),
+                song("b", "y", "2"),
+                Item::Album(("b".into(), "x".into())),
+                song("B", "w", "1"),
+                Item::Artist("c".into()),
+            ]
+        );
+        //The selected song moved to the top.
+        assert_eq!(search.results.index(), Some(0));
+
+        search.set_sort(SearchSortOrder::Album);
+        assert_eq!(search.results[0], song("B", "w", "1"));
+        assert_eq!(search.results[4], Item::Artist("c".into()));
+
+        search.set_sort(SearchSortOrder::Title);
+        assert_eq!(search.results[0], song("a", "z", "1"));
+        assert_eq!(search.results[1], song("B", "w", "1"));
+        assert_eq!(search.results[2], song("b", "y", "2"));
+        assert_eq!(search.results[3], Item::Artist("c".into()));
+        assert_eq!(search.results[4], Item::Album(("b".into(), "x".into())));
+
+        search.set_sort(SearchSortOrder::Relevance);
+        assert_eq!(*search.results, search.relevance);
+        assert_eq!(search.results.index(), Some(2));
+    }
+
+    #[test]
+    fn long_query() {
+        let query = "a".repeat(70_000);
+        assert_eq!(cursor(&query, 80), (78, 2));
+
+        //65,540 would wrap around to 4.
+        let query = "a".repeat(65_540);
+        assert_eq!(cursor(&query, 80), (78, 2));
+
+        assert_eq!(cursor("abc", 80), (5, 2));
+        assert_eq!(cursor("abc", 0), (1, 2));
+    }
+}
@@ -1,10 +1,48 @@
+use crate::{
+    cast::to_u16_clamped,
+    screen::{amount, Action, Capture, Context, Screen},
+};
+use gonk_core::{
+    settings::{
+        BrowserMode, BrowserSort, ConfigError, QueueLayout, ReplayGainMode, Startup, VolumeCurve,
+        MAX_CROSSFADE,
+    },
+    strsim, Normalize,
+};
 use gonk_player::*;
+use std::thread::JoinHandle;
 use winter::*;
 
+///How close a word needs to be to the filter to match.
+const FUZZY_ACCURACY: f64 = 0.85;
+
 pub struct Settings {
     pub devices: Vec<Device>,
     pub index: Option<usize>,
     pub current_device: String,
+    pub pause_on_device_change: bool,
+    pub normalize: Normalize,
+    pub discord: bool,
+    pub startup: Startup,
+    pub queue_layout: QueueLayout,
+    pub guess_tags: bool,
+    pub keep_pitch: bool,
+    pub remember_rate: bool,
+    pub seek_across: bool,
+    pub replay_gain: ReplayGainMode,
+    pub crossfade: u8,
+    pub mono: bool,
+    pub browser_sort: BrowserSort,
+    pub browser_mode: BrowserMode,
+    pub volume_curve: VolumeCurve,
+    ///The device playing a test tone.
+    pub test: Option<(String, JoinHandle<Result<(), String>>)>,
+    ///The last test result, shown next to the device.
+    pub test_result: Option<(String, Result<(), String>)>,
+    ///Only show the options that match.
+    pub filter: String,
+    ///Typing into the filter.
+    pub filtering: bool,
 }
 
 impl Settings {
@@ -15,10 +53,97 @@ impl Settings {
             index: if devices.is_empty() { None } else { Some(0) },
             devices,
             current_device,
+            pause_on_device_change: true,
+            normalize: Normalize::default(),
+            discord: false,
+            startup: Startup::default(),
+            queue_layout: QueueLayout::default(),
+            guess_tags: true,
+            keep_pitch: true,
+            remember_rate: false,
+            seek_across: true,
+            replay_gain: ReplayGainMode::default(),
+            crossfade: 0,
+            mono: false,
+            browser_sort: BrowserSort::default(),
+            browser_mode: BrowserMode::default(),
+            volume_curve: VolumeCurve::default(),
+            test: None,
+            test_result: None,
+            filter: String::new(),
+            filtering: false,
         }
     }
 }
 
+///Returns true when the device we're using was removed
+///or the default device changed away from it.
+pub fn device_lost(current: &str, devices: &[&str], old_default: &str, new_default: &str) -> bool {
+    if !devices.contains(&current) {
+        return true;
+    }
+    old_default == current && new_default != current
+}
+
+///Does the filter match the name or description of a setting.
+///
+///Words that are close to the filter match as well, so typos still find the setting.
+pub fn matches(filter: &str, key: &str, description: &str) -> bool {
+    let filter = filter.trim().to_lowercase();
+    if filter.is_empty() {
+        return true;
+    }
+    let text = format!("{key} {description}").to_lowercase();
+    text.contains(&filter)
+        || text
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| strsim::jaro_winkler(&filter, word) > FUZZY_ACCURACY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let pitch = (
+            "player.keep_pitch",
+            "(V) Keep the pitch when changing speed: ",
+        );
+        let matches = |filter| matches(filter, pitch.0, pitch.1);
+
+        assert!(matches(""));
+        assert!(matches("pitch"));
+        assert!(matches("Keep The"));
+        assert!(matches("player."));
+        assert!(matches("ptich"));
+        assert!(matches("sped"));
+        assert!(!matches("discord"));
+        assert!(!matches("volume"));
+    }
+
+    #[test]
+    fn device_change() {
+        let lost = |current, devices: &[&str], old, new| device_lost(current, devices, old, new);
+        let devices = ["Headphones", "Speakers"];
+
+        //Nothing changed.
+        assert!(!lost("Headphones", &devices, "Headphones", "Headphones"));
+
+        //Headphones were unplugged.
+        assert!(lost("Headphones", &["Speakers"], "Headphones", "Speakers"));
+
+        //Default changed away from the device we were using.
+        assert!(lost("Headphones", &devices, "Headphones", "Speakers"));
+
+        //Default changed but we weren't using it.
+        assert!(!lost("Speakers", &devices, "Headphones", "Monitor"));
+
+        //Default changed to the device we're using.
+        assert!(!lost("Speakers", &devices, "Headphones", "Speakers"));
+    }
+}
+
 pub fn selected(settings: &Settings) -> Option<&str> {
     if let Some(index) = settings.index {
         if let Some(device) = settings.devices.get(index) {
@@ -28,6 +153,34 @@ pub fn selected(settings: &Settings) -> Option<&str> {
     None
 }
 
+///Play a test tone on the selected device. Does nothing while a test is running.
+pub fn test(settings: &mut Settings) {
+    if settings.test.is_some() {
+        return;
+    }
+    let Some(device) = settings.index.and_then(|i| settings.devices.get(i)) else {
+        return;
+    };
+    settings.test_result = None;
+    settings.test = Some((device.name.clone(), test_tone(device.clone())));
+}
+
+///Collect the result once the test tone has finished.
+pub fn poll_test(settings: &mut Settings) {
+    if !settings
+        .test
+        .as_ref()
+        .is_some_and(|(_, handle)| handle.is_finished())
+    {
+        return;
+    }
+    let (name, handle) = settings.test.take().unwrap();
+    let result = handle
+        .join()
+        .unwrap_or_else(|_| Err(String::from("the test crashed")));
+    settings.test_result = Some((name, result));
+}
+
 pub fn up(settings: &mut Settings, amount: usize) {
     if settings.devices.is_empty() {
         return;
@@ -46,13 +199,53 @@ pub fn down(settings: &mut Settings, amount: usize) {
 
 //TODO: I liked the old item menu bold selections instead of white background.
 //It doesn't work on most terminals though :(
-pub fn draw(settings: &Settings, area: winter::Rect, buf: &mut winter::Buffer) {
+pub fn draw(
+    settings: &Settings,
+    errors: &[ConfigError],
+    area: winter::Rect,
+    buf: &mut winter::Buffer,
+) -> Option<(u16, u16)> {
     let mut items = Vec::new();
     for device in &settings.devices {
-        let item = if device.name == settings.current_device {
-            lines!(">> ".dim(), &device.name)
+        let marker = if device.name == settings.current_device {
+            ">> "
         } else {
-            lines!("   ", &device.name)
+            "   "
+        };
+        let testing = settings
+            .test
+            .as_ref()
+            .is_some_and(|(name, _)| *name == device.name);
+        let result = settings
+            .test_result
+            .as_ref()
+            .filter(|(name, _)| *name == device.name);
+        //The device in use shows what playback was opened with instead of the mix format.
+        let format = match device_info() {
+            Some(format) if device.name == settings.current_device => Some(format),
+            _ => device.format,
+        };
+        let format = format.map(|f| format!(" {f}")).unwrap_or_default();
+        let item = match result {
+            _ if testing => lines!(
+                marker.dim(),
+                &device.name,
+                format.as_str().dim(),
+                " Testing...".dim()
+            ),
+            Some((_, Ok(()))) => lines!(
+                marker.dim(),
+                &device.name,
+                format.as_str().dim(),
+                " Test passed".fg(Green)
+            ),
+            Some((_, Err(err))) => lines!(
+                marker.dim(),
+                &device.name,
+                format.as_str().dim(),
+                text!(" Test failed: {err}").fg(Red)
+            ),
+            None => lines!(marker.dim(), &device.name, format.as_str().dim()),
         };
         items.push(item);
     }
@@ -61,6 +254,328 @@ pub fn draw(settings: &Settings, area: winter::Rect, buf: &mut winter::Buffer) {
         items[index].style = Some(fg(Black).bg(White));
     }
 
-    let list = list(&items).block(block().title("Output Device").title_margin(1));
-    list.draw(area, buf, settings.index);
+    fn on_off(value: bool) -> String {
+        String::from(if value { "On" } else { "Off" })
+    }
+
+    let max_width = if settings.normalize.max_width == 0 {
+        String::from("Off")
+    } else {
+        settings.normalize.max_width.to_string()
+    };
+
+    //Shows whether the last change of song was faded, albums that run on aren't.
+    let crossfade = match (settings.crossfade, gonk_player::transition()) {
+        (0, _) => String::from("Off"),
+        (secs, Some(transition)) => format!("{secs}s (last: {})", transition.description()),
+        (secs, None) => format!("{secs}s"),
+    };
+
+    //Setting name, description and value.
+    let mut rows: Vec<(&str, &str, String)> = vec![
+        (
+            "player.pause_on_device_change",
+            "(P) Pause when the output device changes: ",
+            on_off(settings.pause_on_device_change),
+        ),
+        (
+            "normalize.featuring",
+            "(F) Move featured artists out of titles: ",
+            on_off(settings.normalize.featuring),
+        ),
+        (
+            "normalize.strip",
+            "(B) Hide trailing title qualifiers: ",
+            on_off(settings.normalize.strip),
+        ),
+        (
+            "normalize.max_width",
+            "(+/-) Maximum column width: ",
+            max_width,
+        ),
+        (
+            "player.startup",
+            "(O) On startup: ",
+            settings.startup.description().to_string(),
+        ),
+        (
+            "library.guess_tags",
+            "(G) Guess missing tags from file names: ",
+            on_off(settings.guess_tags),
+        ),
+        (
+            "player.keep_pitch",
+            "(V) Keep the pitch when changing speed: ",
+            on_off(settings.keep_pitch),
+        ),
+        (
+            "player.remember_rate",
+            "(M) Remember speed for each folder: ",
+            on_off(settings.remember_rate),
+        ),
+        (
+            "player.seek_across",
+            "(N) Seek into the next and previous song: ",
+            on_off(settings.seek_across),
+        ),
+        (
+            "player.replay_gain",
+            "(Y) Replay gain: ",
+            settings.replay_gain.description().to_string(),
+        ),
+        ("player.crossfade", "([/]) Crossfade: ", crossfade),
+        (
+            "player.volume_curve",
+            "(Shift + V) Volume curve: ",
+            settings.volume_curve.description().to_string(),
+        ),
+        (
+            "player.mono",
+            "(Shift + M) Mono, both channels in both ears: ",
+            on_off(settings.mono),
+        ),
+        (
+            "",
+            "(Shift + T) Play a test tone on the highlighted device",
+            String::new(),
+        ),
+        (
+            "queue.layout",
+            "(T) Queue layout: ",
+            settings.queue_layout.description().to_string(),
+        ),
+        (
+            "browser.sort",
+            "(Shift + B) Sort artists and albums by: ",
+            settings.browser_sort.description().to_string(),
+        ),
+        (
+            "browser.mode",
+            "(Shift + G) Browser panes: ",
+            settings.browser_mode.description().to_string(),
+        ),
+    ];
+
+    if cfg!(feature = "discord") {
+        rows.push((
+            "discord.enabled",
+            "(R) Discord rich presence: ",
+            on_off(settings.discord),
+        ));
+    }
+
+    //Settings without an option are only shown when they're broken.
+    for error in errors {
+        if !rows.iter().any(|(key, _, _)| *key == error.key) {
+            rows.push((error.key, error.key, String::new()));
+        }
+    }
+
+    let options: Vec<_> = rows
+        .iter()
+        .filter(|(key, description, _)| matches(&settings.filter, key, description))
+        .map(
+            |(key, description, value)| match errors.iter().find(|error| error.key == *key) {
+                Some(error) => lines!(
+                    *description,
+                    value.as_str().bold(),
+                    text!("  {}", error.message).fg(Red)
+                ),
+                None => lines!(*description, value.as_str().bold()),
+            },
+        )
+        .collect();
+
+    let show_filter = settings.filtering || !settings.filter.is_empty();
+    let v = layout(
+        area,
+        Vertical,
+        &[
+            Length(if show_filter { 3 } else { 0 }),
+            Fill,
+            Length(options.len() as u16 + 2),
+        ],
+    );
+
+    //Changes with the device and sample rate, so it's read every frame.
+    let latency = latency();
+    let title = if latency.is_zero() {
+        String::from("Output Device")
+    } else {
+        format!(
+            "Output Device (latency: {} ms, buffered: {} ms)",
+            latency.as_millis(),
+            buffered().as_millis()
+        )
+    };
+    let list = list(&items).block(block().title(title.as_str()).title_margin(1));
+    list.draw(v[1], buf, settings.index);
+
+    winter::list(&options)
+        .block(block().title("Options").title_margin(1))
+        .draw(v[2], buf, None);
+
+    if !show_filter {
+        return None;
+    }
+
+    lines!(settings.filter.as_str())
+        .block(block().title("Filter").title_margin(1))
+        .scroll()
+        .draw(v[0], buf);
+
+    if !settings.filtering {
+        return None;
+    }
+
+    //Draw the cursor.
+    let (x, y) = (v[0].x + 2, v[0].y + 2);
+    let width = v[0].width.saturating_sub(3);
+    let len = to_u16_clamped(settings.filter.len());
+    Some((x + len.min(width), y))
+}
+
+impl Screen for Settings {
+    fn draw(&mut self, ctx: &mut Context, area: Rect, buf: &mut Buffer) -> Option<(u16, u16)> {
+        draw(self, &ctx.persist.errors, area, buf)
+    }
+
+    fn handle_key(
+        &mut self,
+        ctx: &mut Context,
+        event: &Event,
+        shift: bool,
+        control: bool,
+    ) -> Option<Vec<Action>> {
+        let mut actions = Vec::new();
+        let persist = &mut ctx.persist;
+        match *event {
+            Event::Char(c) if self.filtering => self.filter.push(c),
+            Event::Backspace if self.filtering => {
+                self.filter.pop();
+            }
+            //Keep the filter while using the options.
+            Event::Enter if self.filtering => self.filtering = false,
+            Event::Escape if self.filtering || !self.filter.is_empty() => {
+                self.filter.clear();
+                self.filtering = false;
+            }
+            Event::Char('f') if control => self.filtering = true,
+            Event::Char('p') => {
+                self.pause_on_device_change = !self.pause_on_device_change;
+                persist.pause_on_device_change = self.pause_on_device_change;
+            }
+            #[cfg(feature = "discord")]
+            Event::Char('r') => {
+                self.discord = !self.discord;
+                persist.discord = self.discord;
+                actions.push(Action::Discord);
+            }
+            Event::Char('o') => {
+                self.startup = self.startup.next();
+                persist.startup = self.startup;
+            }
+            Event::Char('T') => test(self),
+            Event::Char('t') => {
+                self.queue_layout = self.queue_layout.next();
+                persist.queue_layout = self.queue_layout;
+            }
+            Event::Char('g') => {
+                self.guess_tags = !self.guess_tags;
+                persist.guess_tags = self.guess_tags;
+            }
+            Event::Char('v') => {
+                self.keep_pitch = !self.keep_pitch;
+                persist.keep_pitch = self.keep_pitch;
+                set_keep_pitch(self.keep_pitch);
+            }
+            Event::Char('m') => {
+                self.remember_rate = !self.remember_rate;
+                persist.remember_rate = self.remember_rate;
+                set_remember_rate(self.remember_rate);
+            }
+            Event::Char('n') => {
+                self.seek_across = !self.seek_across;
+                persist.seek_across = self.seek_across;
+                set_seek_across(self.seek_across);
+            }
+            Event::Char('y') => {
+                self.replay_gain = self.replay_gain.next();
+                persist.replay_gain = self.replay_gain;
+                let playing = if ctx.previewing {
+                    None
+                } else {
+                    ctx.songs.selected()
+                };
+                set_replay_gain(self.replay_gain, playing);
+            }
+            Event::Char('M') => {
+                self.mono = !self.mono;
+                persist.mono = self.mono;
+                set_mono(self.mono);
+            }
+            Event::Char('V') => {
+                self.volume_curve = self.volume_curve.next();
+                persist.volume_curve = self.volume_curve;
+                set_volume_curve(self.volume_curve);
+            }
+            Event::Char('B') => {
+                self.browser_sort = self.browser_sort.next();
+                persist.browser_sort = self.browser_sort;
+                actions.push(Action::BrowserSort(self.browser_sort));
+            }
+            Event::Char('G') => {
+                self.browser_mode = self.browser_mode.next();
+                persist.browser_mode = self.browser_mode;
+                actions.push(Action::BrowserMode(self.browser_mode));
+            }
+            Event::Char('[') => {
+                self.crossfade = self.crossfade.saturating_sub(1);
+                persist.crossfade = self.crossfade;
+                set_crossfade(self.crossfade as f32);
+            }
+            Event::Char(']') => {
+                self.crossfade = (self.crossfade + 1).min(MAX_CROSSFADE);
+                persist.crossfade = self.crossfade;
+                set_crossfade(self.crossfade as f32);
+            }
+            Event::Char('f') => {
+                self.normalize.featuring = !self.normalize.featuring;
+                persist.normalize = self.normalize;
+            }
+            Event::Char('b') => {
+                self.normalize.strip = !self.normalize.strip;
+                persist.normalize = self.normalize;
+            }
+            Event::Char('+') | Event::Char('=') => {
+                self.normalize.max_width += 5;
+                persist.normalize = self.normalize;
+            }
+            Event::Char('-') => {
+                self.normalize.max_width = self.normalize.max_width.saturating_sub(5);
+                persist.normalize = self.normalize;
+            }
+            Event::Enter => {
+                if let Some(device) = selected(self) {
+                    let device = device.to_string();
+                    set_output_device(&device);
+                    self.current_device = device.clone();
+                    persist.output_device = device;
+                }
+            }
+            Event::Up | Event::Char('k') | Event::Char('K') => up(self, amount(shift)),
+            Event::Down | Event::Char('j') | Event::Char('J') => down(self, amount(shift)),
+            _ => return None,
+        }
+        Some(actions)
+    }
+
+    fn on_tick(&mut self, _ctx: &mut Context) -> Vec<Action> {
+        poll_test(self);
+        Vec::new()
+    }
+
+    fn wants_text_input(&self) -> Option<Capture> {
+        self.filtering.then_some(Capture::Filter)
+    }
 }
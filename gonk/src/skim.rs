@@ -0,0 +1,159 @@
+//! Seeking that carries on into the next or previous song.
+//!
+//! Holding a seek key across several short songs would open each one for a single key press.
+//! Instead the key presses move a target and the song is only opened once they stop.
+use std::time::{Duration, Instant};
+
+///How long after the last key press the song is opened.
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+
+///The song that was playing and the ones seeking can carry on into.
+///
+///These come from the player so repeat and shuffle are followed, `None` is the end of the queue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Around {
+    pub previous: Option<usize>,
+    pub current: usize,
+    pub next: Option<usize>,
+}
+
+///Where to start playing once seeking stops.
+#[derive(Debug, PartialEq)]
+pub struct Skim {
+    ///The song in the queue.
+    pub index: usize,
+    ///Seconds from the start of the song, or from the end when negative.
+    pub offset: f32,
+    pub deadline: Instant,
+    around: Around,
+    side: Side,
+}
+
+///Which of the songs in `Around` is the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Previous,
+    Current,
+    Next,
+}
+
+///The player seeked past the end of the current song, or before the start when negative.
+///
+///Returns `None` if there's no song to carry on into.
+pub fn overflow(around: Around, overflow: f32, now: Instant) -> Option<Skim> {
+    let (index, side) = if overflow < 0.0 {
+        (around.previous?, Side::Previous)
+    } else {
+        (around.next?, Side::Next)
+    };
+    Some(Skim {
+        index,
+        offset: overflow,
+        deadline: now + DEBOUNCE,
+        around,
+        side,
+    })
+}
+
+///Move the target while the keys are still being pressed.
+///
+///Going past the start of the song moves to the end of the previous one and the other way around.
+pub fn step(skim: &mut Skim, amount: f32, now: Instant) {
+    let around = skim.around;
+    let offset = skim.offset + amount;
+
+    //The target only reaches the current song from one side, so the song on the other exists.
+    if skim.offset >= 0.0 && offset < 0.0 {
+        (skim.index, skim.side) = match (skim.side, around.previous) {
+            (Side::Current, Some(previous)) => (previous, Side::Previous),
+            _ => (around.current, Side::Current),
+        };
+    } else if skim.offset < 0.0 && offset >= 0.0 {
+        (skim.index, skim.side) = match (skim.side, around.next) {
+            (Side::Current, Some(next)) => (next, Side::Next),
+            _ => (around.current, Side::Current),
+        };
+    }
+    skim.offset = offset;
+    skim.deadline = now + DEBOUNCE;
+}
+
+///The target once the keys have stopped.
+pub fn due(skim: &mut Option<Skim>, now: Instant) -> Option<Skim> {
+    skim.take_if(|skim| now >= skim.deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn around(previous: Option<usize>, current: usize, next: Option<usize>) -> Around {
+        Around {
+            previous,
+            current,
+            next,
+        }
+    }
+
+    #[test]
+    fn debounce() {
+        let now = Instant::now();
+
+        //Ran off the end of the second song.
+        let mut skim = overflow(around(Some(0), 1, Some(2)), 4.0, now);
+        assert_eq!(skim.as_ref().unwrap().index, 2);
+        assert!(due(&mut skim, now).is_none());
+
+        //Key presses move the target instead of opening songs.
+        let later = now + DEBOUNCE / 2;
+        step(skim.as_mut().unwrap(), 10.0, later);
+        assert!(due(&mut skim, now + DEBOUNCE).is_none());
+
+        let skim = due(&mut skim, later + DEBOUNCE).unwrap();
+        assert_eq!((skim.index, skim.offset), (2, 14.0));
+    }
+
+    #[test]
+    fn across() {
+        let now = Instant::now();
+
+        //Seeking back from the start of the first song goes to the end of the queue when it repeats.
+        let mut skim = overflow(around(Some(2), 0, Some(1)), -2.0, now).unwrap();
+        assert_eq!((skim.index, skim.offset), (2, -2.0));
+
+        //Forward past where the seek started goes back to the first song.
+        step(&mut skim, 10.0, now);
+        assert_eq!((skim.index, skim.offset), (0, 8.0));
+
+        //And back again into the tail of the previous song.
+        step(&mut skim, -10.0, now);
+        assert_eq!((skim.index, skim.offset), (2, -2.0));
+        step(&mut skim, -10.0, now);
+        assert_eq!((skim.index, skim.offset), (2, -12.0));
+
+        //Repeating one song stays on it.
+        let mut skim = overflow(around(Some(0), 0, Some(0)), 3.0, now).unwrap();
+        assert_eq!(skim.index, 0);
+        step(&mut skim, -10.0, now);
+        step(&mut skim, -10.0, now);
+        assert_eq!((skim.index, skim.offset), (0, -17.0));
+    }
+
+    #[test]
+    fn ends() {
+        let now = Instant::now();
+
+        //Nothing to carry on into when the queue doesn't repeat.
+        assert!(overflow(around(None, 0, Some(1)), -2.0, now).is_none());
+        assert!(overflow(around(Some(1), 2, None), 2.0, now).is_none());
+
+        //Going back from the next song stays in the first one.
+        let mut skim = overflow(around(None, 0, Some(3)), 2.0, now).unwrap();
+        step(&mut skim, -10.0, now);
+        assert_eq!((skim.index, skim.offset), (0, -8.0));
+        step(&mut skim, -10.0, now);
+        assert_eq!((skim.index, skim.offset), (0, -18.0));
+        step(&mut skim, 20.0, now);
+        assert_eq!((skim.index, skim.offset), (3, 2.0));
+    }
+}
@@ -0,0 +1,51 @@
+//! Notice when the computer wakes up from sleep
+//!
+//! `Instant` keeps counting while the computer is asleep,
+//! so timers that were running look like they finished all at once.
+use std::time::{Duration, Instant};
+
+///The main loop runs every few milliseconds, a gap this long means the computer was asleep.
+pub const GAP: Duration = Duration::from_secs(5);
+
+///How long the loop was stopped for, if it was long enough to have been asleep.
+pub fn gap(last: Instant, now: Instant) -> Option<Duration> {
+    let elapsed = now.saturating_duration_since(last);
+    (elapsed >= GAP).then_some(elapsed)
+}
+
+///Move a start time forward so the time spent asleep isn't counted.
+pub fn reanchor(start: Instant, gap: Duration, now: Instant) -> Instant {
+    start.checked_add(gap).unwrap_or(now).min(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps() {
+        let now = Instant::now();
+        let last = now - Duration::from_millis(3);
+        assert_eq!(gap(last, now), None);
+
+        let asleep = Duration::from_secs(30);
+        assert_eq!(gap(now - asleep, now), Some(asleep));
+
+        //A clock that goes backwards isn't a gap.
+        assert_eq!(gap(now + asleep, now), None);
+    }
+
+    #[test]
+    fn reanchored() {
+        let now = Instant::now();
+        let asleep = Duration::from_secs(30);
+
+        //Started 10 seconds before going to sleep.
+        let start = now - asleep - Duration::from_secs(10);
+        let start = reanchor(start, asleep, now);
+        assert_eq!(now - start, Duration::from_secs(10));
+
+        //Never moves past now.
+        assert_eq!(reanchor(now, asleep, now), now);
+    }
+}
@@ -0,0 +1,119 @@
+//! Colors
+//!
+//! Read from `theme.toml` in the gonk folder. Colors are hex or one of the terminal's colors:
+//!
+//! ```toml
+//! title = "#8ec07c"
+//! album = "magenta"
+//! ```
+use crate::config::read_lines;
+use gonk_core::gonk_path;
+use std::{fs, sync::OnceLock};
+use winter::Color;
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub number: Color,
+    pub title: Color,
+    pub album: Color,
+    pub artist: Color,
+    pub seeker: Color,
+    ///The marker next to the playing song.
+    pub playing: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            number: Color::Green,
+            title: Color::Cyan,
+            album: Color::Magenta,
+            artist: Color::Blue,
+            seeker: Color::White,
+            playing: Color::White,
+        }
+    }
+}
+
+impl Theme {
+    ///Missing files use the defaults. Lines that can't be read are skipped and returned as errors.
+    pub fn load_or_default() -> (Self, Vec<String>) {
+        match fs::read_to_string(gonk_path().join("theme.toml")) {
+            Ok(file) => Self::parse(&file),
+            Err(_) => (Self::default(), Vec::new()),
+        }
+    }
+
+    pub fn parse(file: &str) -> (Self, Vec<String>) {
+        let mut theme = Self::default();
+        let errors = read_lines("theme.toml", file, |name, value| {
+            let color = parse_color(value)?;
+            match name {
+                "number" => theme.number = color,
+                "title" => theme.title = color,
+                "album" => theme.album = color,
+                "artist" => theme.artist = color,
+                "seeker" => theme.seeker = color,
+                "playing" => theme.playing = color,
+                _ => return Err(format!("Unknown color: {name}")),
+            }
+            Ok(())
+        });
+        (theme, errors)
+    }
+}
+
+///Set the theme once at startup.
+pub fn set(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+///The defaults are used if the theme wasn't loaded.
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    if let Some(hex) = value.strip_prefix('#') {
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+        };
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Color::Rgb(r, g, b)),
+            _ => Err(format!("Colors must be #RRGGBB: {value}")),
+        };
+    }
+    match value.to_ascii_lowercase().as_str() {
+        "black" => Ok(Color::Black),
+        "red" => Ok(Color::Red),
+        "green" => Ok(Color::Green),
+        "yellow" => Ok(Color::Yellow),
+        "blue" => Ok(Color::Blue),
+        "magenta" => Ok(Color::Magenta),
+        "cyan" => Ok(Color::Cyan),
+        "white" => Ok(Color::White),
+        _ => Err(format!("Unknown color: {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        let (theme, errors) = Theme::parse(
+            "title = \"#8EC07C\"\nalbum = 'yellow'\n# comment\nseeker = \"#fff\"\nnumber = \"#gg0000\"\nborder = \"red\"",
+        );
+        assert_eq!(theme.title, Color::Rgb(0x8e, 0xc0, 0x7c));
+        assert_eq!(theme.album, Color::Yellow);
+        //Bad colors keep their default.
+        assert_eq!(theme.seeker, Theme::default().seeker);
+        assert_eq!(theme.number, Theme::default().number);
+        assert_eq!(errors.len(), 3);
+        assert!(errors[2].starts_with("theme.toml line 6: Unknown color: border"));
+    }
+}
@@ -0,0 +1,88 @@
+//! Waveform of the playing song, drawn in place of the seek bar.
+//!
+//! Songs without a cached overview are decoded on a background thread.
+//! Only one song is decoded at a time, skipping through the queue doesn't start a thread per song.
+use gonk_core::waveform::{self, BUCKETS};
+use std::{
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Default)]
+pub struct Waveform {
+    ///The song the levels are for.
+    pub path: Option<String>,
+    ///Empty if the song couldn't be decoded.
+    pub levels: Option<Vec<u8>>,
+    ///The song being decoded.
+    pending: Option<(String, JoinHandle<Option<Vec<u8>>>)>,
+}
+
+///Load or start decoding the overview when the playing song changes.
+pub fn update(waveform: &mut Waveform, playing: Option<&str>) {
+    if let Some((path, handle)) = waveform.pending.take_if(|(_, handle)| handle.is_finished()) {
+        //An empty overview stops a song that can't be decoded from being tried again.
+        let levels = handle.join().ok().flatten().unwrap_or_default();
+        if waveform.path.as_deref() == Some(path.as_str()) {
+            waveform.levels = Some(levels);
+        }
+    }
+
+    if waveform.path.as_deref() != playing {
+        waveform.path = playing.map(str::to_string);
+        waveform.levels = playing.and_then(|path| waveform::load(Path::new(path)));
+    }
+
+    let Some(path) = &waveform.path else {
+        return;
+    };
+    if waveform.levels.is_none() && waveform.pending.is_none() {
+        let song = PathBuf::from(path);
+        let handle = thread::spawn(move || {
+            let levels = gonk_player::overview(&song, BUCKETS).ok()?;
+            let _ = waveform::save(&song, &levels);
+            Some(levels)
+        });
+        waveform.pending = Some((path.clone(), handle));
+    }
+}
+
+///One bar per column, each column shows the loudest part it covers.
+pub fn bars(levels: &[u8], width: usize) -> String {
+    if levels.is_empty() {
+        return " ".repeat(width);
+    }
+    (0..width)
+        .map(|column| {
+            let start = column * levels.len() / width;
+            let end = ((column + 1) * levels.len() / width).max(start + 1);
+            let level = levels[start..end.min(levels.len())]
+                .iter()
+                .max()
+                .copied()
+                .unwrap_or(0);
+            BARS[level as usize * (BARS.len() - 1) / 255]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns() {
+        assert_eq!(bars(&[0, 255], 2), " █");
+        assert_eq!(bars(&[0, 128, 255], 3), " ▄█");
+
+        //Squeezed columns keep the loudest level.
+        assert_eq!(bars(&[0, 255, 0, 0], 2), "█ ");
+
+        //Stretched levels repeat.
+        assert_eq!(bars(&[0, 255], 4), "  ██");
+        assert_eq!(bars(&[], 3), "   ");
+        assert_eq!(bars(&[255; BUCKETS], 0), "");
+    }
+}
@@ -63,6 +63,11 @@ fn custom_old(files: &[DirEntry]) -> Vec<Result<Song, String>> {
                     track_number,
                     path: file.path.clone(),
                     gain,
+                    album_gain: 0.0,
+                    genre: String::new(),
+                    size: 0,
+                    guessed: false,
+                    year: 0,
                 })
             }
             Err(err) => Err(format!("Error: ({err}) @ {}", file.path)),
@@ -167,6 +172,11 @@ fn symphonia(files: &[DirEntry]) -> Vec<Result<Song, String>> {
                 track_number,
                 path: entry.path.clone(),
                 gain,
+                album_gain: 0.0,
+                genre: String::new(),
+                size: 0,
+                guessed: false,
+                year: 0,
             })
         })
         .collect()
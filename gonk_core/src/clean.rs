@@ -0,0 +1,89 @@
+//! Clean up tag text
+//!
+//! Some taggers write a byte order mark or decomposed unicode (NFD).
+//! "Sigur Ro\u{301}s" looks the same as "Sigur Rós" but sorts and groups as a different artist.
+use crate::db::Song;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+///Characters that take up no space.
+///
+///Zero width joiners are kept, they change how emoji and some scripts are drawn.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{FEFF}' | '\u{200B}' | '\u{2060}' | '\u{00AD}')
+}
+
+///Returns the cleaned string or `None` if nothing changed.
+///
+///Strips byte order marks and zero width spaces, turns any other whitespace into a
+///normal space, trims the ends and normalizes to NFC.
+pub fn text(s: &str) -> Option<String> {
+    let dirty = |c: char| is_invisible(c) || (c.is_whitespace() && c != ' ');
+    if !s.contains(dirty) && s.trim() == s && is_nfc_quick(s.chars()) == IsNormalized::Yes {
+        return None;
+    }
+
+    let cleaned: String = s
+        .chars()
+        .filter(|c| !is_invisible(*c))
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .nfc()
+        .collect();
+    let cleaned = cleaned.trim();
+
+    if cleaned == s {
+        None
+    } else {
+        Some(cleaned.to_string())
+    }
+}
+
+///Clean the artist, album and title. Returns true if any of them changed.
+///
+///A tag that's only whitespace is left alone so it doesn't become empty.
+pub fn clean(song: &mut Song) -> bool {
+    let mut changed = false;
+    for tag in [&mut song.artist, &mut song.album, &mut song.title] {
+        if let Some(cleaned) = text(tag).filter(|cleaned| !cleaned.is_empty()) {
+            *tag = cleaned;
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdb::Database;
+
+    #[test]
+    fn strings() {
+        assert_eq!(text("Sigur Rós"), None);
+        assert_eq!(text("Sigur Ro\u{301}s").unwrap(), "Sigur Rós");
+        assert_eq!(text("\u{FEFF}Björk").unwrap(), "Björk");
+        assert_eq!(text("Zero\u{200B}Width").unwrap(), "ZeroWidth");
+        assert_eq!(text("No\u{A0}Break\u{3000}").unwrap(), "No Break");
+        assert_eq!(text("  padded\t").unwrap(), "padded");
+        assert_eq!(text("👨\u{200D}👩").map(|_| ()), None);
+    }
+
+    #[test]
+    fn merged() {
+        let mut songs = vec![
+            Song::test("Sigur Rós", "Ágætis byrjun", "1"),
+            Song::test("Sigur Ro\u{301}s", "A\u{301}gætis byrjun", "2"),
+            Song::test("\u{FEFF}Sigur Rós ", "\u{FEFF}Ágætis byrjun", "3"),
+        ];
+        assert_eq!(Database::from_songs(songs.clone()).artists().len(), 3);
+
+        let changed: Vec<bool> = songs.iter_mut().map(clean).collect();
+        assert_eq!(changed, [false, true, true]);
+
+        let db = Database::from_songs(songs);
+        assert_eq!(db.artists(), ["Sigur Rós"]);
+        assert_eq!(
+            db.album("Sigur Rós", "Ágætis byrjun").unwrap().songs.len(),
+            3
+        );
+    }
+}
@@ -1,11 +1,31 @@
 use crate::*;
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use regex::Regex;
 use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt,
     fs::File,
     io::{BufWriter, Write},
+    panic,
+    sync::atomic::{AtomicUsize, Ordering},
     thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
 };
 
+///Files read by the current scan.
+static SCANNED: AtomicUsize = AtomicUsize::new(0);
+///Files found by the current scan. Zero until the folder has been walked.
+static FOUND: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    ///Set while a file is being read by the scanner.
+    static READING: Cell<bool> = const { Cell::new(false) };
+}
+
+///Don't show an estimate until the scan has been running this long.
+const ETA_DELAY: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Song {
     pub title: String,
@@ -14,7 +34,48 @@ pub struct Song {
     pub disc_number: u8,
     pub track_number: u8,
     pub path: String,
+    ///Linear track gain, 0.0 if the song isn't tagged.
     pub gain: f32,
+    ///Linear album gain, 0.0 if the song isn't tagged.
+    pub album_gain: f32,
+    ///Empty if the song isn't tagged.
+    pub genre: String,
+    ///The file size in bytes when it was read, 0 in older databases.
+    pub size: u64,
+    ///Some of the tags were guessed from the path.
+    pub guessed: bool,
+    ///0 if the song isn't tagged.
+    pub year: u16,
+}
+
+///Read a replay gain tag like `-5.39 dB` as a linear factor.
+pub fn replay_gain(tag: &str) -> Option<f32> {
+    let tag = tag.trim();
+    let db = match tag.get(tag.len().saturating_sub(2)..) {
+        Some(unit) if unit.eq_ignore_ascii_case("db") => &tag[..tag.len() - 2],
+        _ => tag,
+    };
+    let db = db.trim().parse::<f32>().ok()?;
+    Some(10.0f32.powf(db / 20.0))
+}
+
+///The year at the start of a date tag like `1997-05-21`.
+pub fn year(tag: &str) -> Option<u16> {
+    let year = tag.trim().get(..4)?;
+    if year.bytes().all(|b| b.is_ascii_digit()) {
+        year.parse().ok().filter(|year| *year != 0)
+    } else {
+        None
+    }
+}
+
+///Read an Opus `R128_TRACK_GAIN` or `R128_ALBUM_GAIN` tag as a linear factor.
+///
+///They're stored in 1/256 dB relative to -23 LUFS, replay gain uses -18 LUFS.
+pub fn r128_gain(tag: &str) -> Option<f32> {
+    let q78 = tag.trim().parse::<i16>().ok()?;
+    let db = q78 as f32 / 256.0 + 5.0;
+    Some(10.0f32.powf(db / 20.0))
 }
 
 impl Serialize for Song {
@@ -22,22 +83,29 @@ impl Serialize for Song {
         use std::fmt::Write;
 
         let mut buffer = String::new();
-        let gain = if self.gain == 0.0 {
-            "0.0".to_string()
-        } else {
-            self.gain.to_string()
+        let gain = |gain: f32| {
+            if gain == 0.0 {
+                "0.0".to_string()
+            } else {
+                gain.to_string()
+            }
         };
 
         let result = writeln!(
             &mut buffer,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
             escape(&self.title),
             escape(&self.album),
             escape(&self.artist),
             self.disc_number,
             self.track_number,
             escape(&self.path),
-            gain,
+            gain(self.gain),
+            gain(self.album_gain),
+            escape(&self.genre),
+            self.size,
+            self.guessed,
+            self.year,
         );
 
         match result {
@@ -72,6 +140,14 @@ impl Deserialize for Song {
             track_number: parts.next().ok_or("Missing track_number")?.parse::<u8>()?,
             path: parts.next().ok_or("Missing path")?.to_string(),
             gain: parts.next().ok_or("Missing gain")?.parse::<f32>()?,
+            //Databases written before album gain was read don't have it.
+            album_gain: parts.next().map_or(Ok(0.0), str::parse::<f32>)?,
+            //Or genre.
+            genre: parts.next().unwrap_or_default().to_string(),
+            //Or size.
+            size: parts.next().map_or(Ok(0), str::parse::<u64>)?,
+            guessed: parts.next().map_or(Ok(false), str::parse::<bool>)?,
+            year: parts.next().map_or(Ok(0), str::parse::<u16>)?,
         })
     }
 }
@@ -90,7 +166,11 @@ impl Deserialize for Vec<Song> {
     type Error = Box<dyn std::error::Error>;
 
     fn deserialize(s: &str) -> Result<Self, Self::Error> {
-        s.trim().split('\n').map(Song::deserialize).collect()
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Vec::new());
+        }
+        s.split('\n').map(Song::deserialize).collect()
     }
 }
 
@@ -108,6 +188,11 @@ impl Song {
             track_number: 1,
             path: String::new(),
             gain: 0.0,
+            album_gain: 0.0,
+            genre: String::new(),
+            size: 0,
+            guessed: false,
+            year: 0,
         }
     }
     pub fn example() -> Self {
@@ -119,6 +204,21 @@ impl Song {
             track_number: 1,
             path: "path".to_string(),
             gain: 1.0,
+            album_gain: 1.0,
+            genre: "genre".to_string(),
+            size: 0,
+            guessed: false,
+            year: 2001,
+        }
+    }
+    ///An example song for tests in every crate, the path is `title.flac`.
+    pub fn test(artist: &str, album: &str, title: &str) -> Self {
+        Self {
+            artist: artist.to_string(),
+            album: album.to_string(),
+            title: title.to_string(),
+            path: format!("{title}.flac"),
+            ..Self::example()
         }
     }
 }
@@ -140,7 +240,7 @@ impl TryFrom<&Path> for Song {
     fn try_from(path: &Path) -> Result<Self, Self::Error> {
         let extension = path.extension().ok_or("Path is not audio")?;
 
-        if extension != "flac" {
+        let mut song = if extension != "flac" {
             use symphonia::{
                 core::{formats::FormatOptions, io::*, meta::*, probe::Hint},
                 default::get_probe,
@@ -172,6 +272,11 @@ impl TryFrom<&Path> for Song {
             let mut track_number = 1;
             let mut disc_number = 1;
             let mut gain = 0.0;
+            let mut album_gain = 0.0;
+            let mut genre = String::new();
+            let mut year = 0;
+            //Only used if there are no replay gain tags.
+            let mut r128 = (None, None);
 
             let mut metadata_revision = probe.format.metadata();
             let mut metadata = probe.metadata.get();
@@ -197,6 +302,16 @@ impl TryFrom<&Path> for Song {
                             }
                             StandardTagKey::Album => album = tag.value.to_string(),
                             StandardTagKey::TrackTitle => title = tag.value.to_string(),
+                            StandardTagKey::Genre if genre.is_empty() => {
+                                genre = tag.value.to_string()
+                            }
+                            StandardTagKey::Date
+                            | StandardTagKey::ReleaseDate
+                            | StandardTagKey::OriginalDate
+                                if year == 0 =>
+                            {
+                                year = self::year(&tag.value.to_string()).unwrap_or(0);
+                            }
                             StandardTagKey::TrackNumber => {
                                 let num = tag.value.to_string();
                                 if let Some((num, _)) = num.split_once('/') {
@@ -214,19 +329,29 @@ impl TryFrom<&Path> for Song {
                                 }
                             }
                             StandardTagKey::ReplayGainTrackGain => {
-                                let tag = tag.value.to_string();
-                                let (_, value) =
-                                    tag.split_once(' ').ok_or("Invalid replay gain.")?;
-                                let db = value.parse().unwrap_or(0.0);
-                                gain = 10.0f32.powf(db / 20.0);
+                                gain = replay_gain(&tag.value.to_string()).unwrap_or(0.0);
+                            }
+                            StandardTagKey::ReplayGainAlbumGain => {
+                                album_gain = replay_gain(&tag.value.to_string()).unwrap_or(0.0);
                             }
                             _ => (),
                         }
+                    } else if tag.key.eq_ignore_ascii_case("R128_TRACK_GAIN") {
+                        r128.0 = r128_gain(&tag.value.to_string());
+                    } else if tag.key.eq_ignore_ascii_case("R128_ALBUM_GAIN") {
+                        r128.1 = r128_gain(&tag.value.to_string());
                     }
                 }
             }
 
-            Ok(Song {
+            if gain == 0.0 {
+                gain = r128.0.unwrap_or(0.0);
+            }
+            if album_gain == 0.0 {
+                album_gain = r128.1.unwrap_or(0.0);
+            }
+
+            Song {
                 title,
                 album,
                 artist,
@@ -234,11 +359,20 @@ impl TryFrom<&Path> for Song {
                 track_number,
                 path: path.to_str().ok_or("Invalid UTF-8 in path.")?.to_string(),
                 gain,
-            })
+                album_gain,
+                genre,
+                size: 0,
+                guessed: false,
+                year,
+            }
         } else {
             read_metadata(path)
-                .map_err(|err| format!("Error: ({err}) @ {}", path.to_string_lossy()))
-        }
+                .map_err(|err| format!("Error: ({err}) @ {}", path.to_string_lossy()))?
+        };
+
+        song.size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        clean::clean(&mut song);
+        Ok(song)
     }
 }
 
@@ -247,6 +381,33 @@ pub enum ScanResult {
     Completed,
     CompletedWithErrors(Vec<String>),
     FileInUse,
+    ///The database could not be written.
+    Failed(String),
+}
+
+///Files that couldn't be read by the last scan, one per line.
+pub fn scan_errors_path() -> PathBuf {
+    gonk_path().join("gonk.log")
+}
+
+///Is the current thread reading a file for the scanner.
+///
+///Decoders can panic on broken files, the panic hook uses this to let the scan carry on.
+pub fn is_reading_file() -> bool {
+    READING.with(Cell::get)
+}
+
+///Read the tags of a single file. A broken file is an error instead of a panic.
+fn read_song(path: &Path) -> Result<Song, String> {
+    READING.with(|reading| reading.set(true));
+    let song = panic::catch_unwind(|| Song::try_from(path)).unwrap_or_else(|_| {
+        Err(format!(
+            "Error: (failed to read file) @ {}",
+            path.to_string_lossy()
+        ))
+    });
+    READING.with(|reading| reading.set(false));
+    song
 }
 
 pub fn reset() -> Result<(), Box<dyn Error>> {
@@ -257,8 +418,303 @@ pub fn reset() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn create(path: &str) -> JoinHandle<ScanResult> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SongField {
+    Artist,
+    Album,
+    Title,
+}
+
+impl TryFrom<&str> for SongField {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "artist" => Ok(SongField::Artist),
+            "album" => Ok(SongField::Album),
+            "title" => Ok(SongField::Title),
+            _ => Err(format!("Unknown field: {s}")),
+        }
+    }
+}
+
+///Replace every match of `regex` in the songs field. Returns the number of songs changed.
+fn replace_field(songs: &mut [Song], field: SongField, regex: &Regex, replacement: &str) -> usize {
+    let mut count = 0;
+    for song in songs {
+        let value = match field {
+            SongField::Artist => &mut song.artist,
+            SongField::Album => &mut song.album,
+            SongField::Title => &mut song.title,
+        };
+
+        if let Cow::Owned(new) = regex.replace_all(value, replacement) {
+            if new != *value {
+                *value = new;
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+///Find and replace a field in the physical database using a regular expression.
+///
+///Returns the number of songs that were changed.
+pub fn update_field_by_pattern(
+    field: SongField,
+    pattern: &str,
+    replacement: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let regex = Regex::new(pattern)?;
+    let string = fs::read_to_string(database_path())?;
+    let mut songs = if string.trim().is_empty() {
+        Vec::new()
+    } else {
+        Vec::<Song>::deserialize(&string)?
+    };
+
+    let count = replace_field(&mut songs, field, &regex, replacement);
+
+    if count != 0 {
+        fs::write(database_path(), songs.serialize())?;
+    }
+
+    Ok(count)
+}
+
+///Move a path from one directory to another.
+///
+///Returns `None` if the path is not inside `from`.
+pub fn migrate_path(path: &str, from: &str, to: &str) -> Option<String> {
+    //Paths added with `gonk add` start with the UNC prefix.
+    let (unc, path) = match path.strip_prefix(r"\\?\") {
+        Some(path) => (r"\\?\", path),
+        None => ("", path),
+    };
+    let rest = path.strip_prefix(from)?;
+
+    //Don't match `/music` with `/musicbox`.
+    if !rest.is_empty() && !rest.starts_with(['/', '\\']) && !from.ends_with(['/', '\\']) {
+        return None;
+    }
+
+    Some(format!("{unc}{to}{rest}"))
+}
+
+///Move every song from one directory to another. Returns the number of songs changed.
+pub fn migrate_songs(songs: &mut [Song], from: &str, to: &str) -> usize {
+    let mut count = 0;
+    for song in songs {
+        if let Some(path) = migrate_path(&song.path, from, to) {
+            song.path = path;
+            count += 1;
+        }
+    }
+    count
+}
+
+///Move the play counts along with the songs. Returns the number of songs changed.
+pub fn migrate_plays(plays: &mut HashMap<String, plays::Plays>, from: &str, to: &str) -> usize {
+    let moved: Vec<(String, String)> = plays
+        .keys()
+        .filter_map(|path| Some((path.clone(), migrate_path(path, from, to)?)))
+        .collect();
+    for (old, new) in &moved {
+        if let Some(old) = plays.remove(old) {
+            let new = plays.entry(new.clone()).or_default();
+            *new = new.add(old);
+        }
+    }
+    moved.len()
+}
+
+///Update the paths in the physical database after the music folder was moved.
+///
+///Nothing is changed if none of the new paths exist.
+pub fn migrate_paths(from: &str, to: &str) -> Result<usize, Box<dyn Error>> {
+    let string = fs::read_to_string(database_path())?;
+    let mut songs = if string.trim().is_empty() {
+        Vec::new()
+    } else {
+        Vec::<Song>::deserialize(&string)?
+    };
+
+    let count = migrate_songs(&mut songs, from, to);
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let exists = songs
+        .iter()
+        .any(|song| song.path.contains(to) && Path::new(&song.path).exists());
+
+    if !exists {
+        return Err("None of the new paths exist, nothing was changed.")?;
+    }
+
+    fs::write(database_path(), songs.serialize())?;
+    Ok(count)
+}
+
+///Songs in the database compared with the files on disk.
+#[derive(Debug, Default, PartialEq)]
+pub struct Check {
+    pub ok: usize,
+    ///Songs that were deleted or can't be read.
+    pub missing: Vec<String>,
+    ///Songs that were modified after the database was written.
+    pub changed: Vec<String>,
+}
+
+impl Check {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "OK: {} songs, MISSING: {} songs, CHANGED: {} songs (modified since the last scan)",
+            self.ok,
+            self.missing.len(),
+            self.changed.len()
+        )
+    }
+}
+
+fn check_songs(songs: &[Song], indexed: SystemTime) -> Check {
+    let mut check = Check::default();
+    for song in songs {
+        let Ok(metadata) = File::open(&song.path).and_then(|file| file.metadata()) else {
+            check.missing.push(song.path.clone());
+            continue;
+        };
+
+        //The database doesn't store a checksum, so the size and modified time are the best we have.
+        let resized = song.size != 0 && metadata.len() != song.size;
+        if resized || metadata.modified().is_ok_and(|modified| modified > indexed) {
+            check.changed.push(song.path.clone());
+        } else {
+            check.ok += 1;
+        }
+    }
+    check
+}
+
+fn read_database() -> Result<Vec<Song>, Box<dyn Error>> {
+    let string = fs::read_to_string(database_path())?;
+    if string.trim().is_empty() {
+        Ok(Vec::new())
+    } else {
+        Ok(Vec::<Song>::deserialize(&string)?)
+    }
+}
+
+///Check that every song in the database can still be read.
+pub fn check() -> Result<Check, Box<dyn Error>> {
+    let indexed = fs::metadata(database_path())?.modified()?;
+    Ok(check_songs(&read_database()?, indexed))
+}
+
+///Remove the missing songs and read the tags of the changed songs again.
+///
+///Changed songs that can no longer be read are removed.
+pub fn fix(check: &Check) -> Result<(), Box<dyn Error>> {
+    let mut songs = read_database()?;
+    fix_songs(&mut songs, check);
+    fs::write(database_path(), songs.serialize())?;
+    Ok(())
+}
+
+fn fix_songs(songs: &mut Vec<Song>, check: &Check) {
+    songs.retain_mut(|song| {
+        if check.missing.contains(&song.path) {
+            return false;
+        }
+        if !check.changed.contains(&song.path) {
+            return true;
+        }
+        match Song::try_from(Path::new(&song.path)) {
+            Ok(new) => {
+                *song = new;
+                true
+            }
+            Err(_) => false,
+        }
+    });
+}
+
+///Read the metadata of every audio file in a directory.
+///
+///With `guess` set, missing tags are guessed from the path.
+pub fn read_dir(path: &str, guess: bool) -> Vec<Result<Song, String>> {
+    let mut paths: Vec<winwalk::DirEntry> = winwalk::walkdir(path, 0)
+        .into_iter()
+        .flatten()
+        .filter(|entry| match entry.extension().and_then(|ex| ex.to_str()) {
+            //Files copied from a Mac are often `.AIF`.
+            Some(ex) => ["flac", "mp3", "ogg", "aiff", "aif"]
+                .iter()
+                .any(|audio| ex.eq_ignore_ascii_case(audio)),
+            None => false,
+        })
+        .collect();
+
+    FOUND.fetch_add(paths.len(), Ordering::Relaxed);
+
+    //The order files are listed in depends on the file system, keep the database the same between scans.
+    paths.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+
+    paths
+        .into_par_iter()
+        .map(|entry| {
+            let mut song = read_song(Path::new(&entry.path));
+            if let (Ok(song), true) = (&mut song, guess) {
+                guess::guess(song);
+            }
+            SCANNED.fetch_add(1, Ordering::Relaxed);
+            song
+        })
+        .collect()
+}
+
+///How many files the current scan has read and how many it found.
+pub fn progress() -> (usize, usize) {
+    (
+        SCANNED.load(Ordering::Relaxed),
+        FOUND.load(Ordering::Relaxed),
+    )
+}
+
+///Format the scan progress e.g. `Scanning: 1234/5678 (ETA: 2m 15s)`.
+///
+///Returns `None` while the estimate would be too noisy to be useful.
+pub fn progress_message(done: usize, total: usize, elapsed: Duration) -> Option<String> {
+    if elapsed < ETA_DELAY || done == 0 || total == 0 {
+        return None;
+    }
+
+    let remaining = elapsed.mul_f64(total.saturating_sub(done) as f64 / done as f64);
+    let secs = remaining.as_secs();
+    let eta = if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    };
+
+    Some(format!("Scanning: {done}/{total} (ETA: {eta})"))
+}
+
+pub fn create(path: &str, guess: bool) -> JoinHandle<ScanResult> {
     let path = path.to_string();
+    SCANNED.store(0, Ordering::Relaxed);
+    FOUND.store(0, Ordering::Relaxed);
+
     thread::spawn(move || {
         let mut db_path = database_path().to_path_buf();
         db_path.pop();
@@ -266,21 +722,7 @@ pub fn create(path: &str) -> JoinHandle<ScanResult> {
 
         match File::create(&db_path) {
             Ok(file) => {
-                let paths: Vec<winwalk::DirEntry> = winwalk::walkdir(path, 0)
-                    .into_iter()
-                    .flatten()
-                    .filter(|entry| match entry.extension() {
-                        Some(ex) => {
-                            matches!(ex.to_str(), Some("flac" | "mp3" | "ogg"))
-                        }
-                        None => false,
-                    })
-                    .collect();
-
-                let songs: Vec<_> = paths
-                    .into_par_iter()
-                    .map(|entry| Song::try_from(Path::new(&entry.path)))
-                    .collect();
+                let songs = read_dir(&path, guess);
 
                 let errors: Vec<String> = songs
                     .iter()
@@ -295,17 +737,26 @@ pub fn create(path: &str) -> JoinHandle<ScanResult> {
 
                 let songs: Vec<Song> = songs.into_iter().flatten().collect();
                 let mut writer = BufWriter::new(&file);
-                writer.write_all(&songs.serialize().into_bytes()).unwrap();
-                writer.flush().unwrap();
+                if let Err(err) = writer
+                    .write_all(songs.serialize().as_bytes())
+                    .and_then(|_| writer.flush())
+                {
+                    return ScanResult::Failed(err.to_string());
+                }
+                drop(writer);
 
                 //Remove old database and replace it with new.
-                fs::rename(db_path, database_path()).unwrap();
+                if let Err(err) = fs::rename(db_path, database_path()) {
+                    return ScanResult::Failed(err.to_string());
+                }
 
-                // let _db = vdb::create().unwrap();
+                //Errors from an older scan would be confusing.
+                let _ = fs::remove_file(scan_errors_path());
 
                 if errors.is_empty() {
                     ScanResult::Completed
                 } else {
+                    let _ = fs::write(scan_errors_path(), errors.join("\n"));
                     ScanResult::CompletedWithErrors(errors)
                 }
             }
@@ -327,6 +778,169 @@ mod tests {
         assert_eq!(Song::deserialize(&string).unwrap(), song);
     }
 
+    #[test]
+    fn gain() {
+        assert_eq!(replay_gain("0.00 dB"), Some(1.0));
+        assert_eq!(
+            replay_gain("+6.02 dB").map(|g| (g * 100.0).round()),
+            Some(200.0)
+        );
+        assert_eq!(
+            replay_gain("-6.02dB").map(|g| (g * 100.0).round()),
+            Some(50.0)
+        );
+        assert_eq!(replay_gain("-5.39"), replay_gain("-5.39 dB"));
+        assert_eq!(replay_gain("loud"), None);
+
+        //-10 dB from -23 LUFS is -5 dB from -18 LUFS.
+        assert_eq!(r128_gain("-2560"), replay_gain("-5 dB"));
+        assert_eq!(r128_gain("-1280"), Some(1.0));
+        assert_eq!(r128_gain("-5.0"), None);
+
+        //Songs from before album gain was stored.
+        let old = "title\talbum\tartist\t1\t1\tpath\t0.5";
+        let song = Song::deserialize(old).unwrap();
+        assert_eq!((song.gain, song.album_gain), (0.5, 0.0));
+        assert!(song.genre.is_empty());
+        assert_eq!(song.size, 0);
+        assert!(!song.guessed);
+        assert_eq!(song.year, 0);
+
+        assert_eq!(year("1997"), Some(1997));
+        assert_eq!(year(" 1997-05-21T00:00:00"), Some(1997));
+        assert_eq!(year("2003/2004"), Some(2003));
+        assert_eq!(year("97"), None);
+        assert_eq!(year("May 1997"), None);
+        assert_eq!(year("0000"), None);
+    }
+
+    #[test]
+    fn progress() {
+        let secs = Duration::from_secs;
+        assert_eq!(progress_message(10, 100, secs(4)), None);
+        assert_eq!(progress_message(0, 100, secs(10)), None);
+        assert_eq!(
+            progress_message(1234, 5678, secs(60)).unwrap(),
+            "Scanning: 1234/5678 (ETA: 3m 36s)"
+        );
+        assert_eq!(
+            progress_message(50, 100, secs(10)).unwrap(),
+            "Scanning: 50/100 (ETA: 10s)"
+        );
+        assert_eq!(
+            progress_message(1, 1000, secs(10)).unwrap(),
+            "Scanning: 1/1000 (ETA: 2h 46m)"
+        );
+    }
+
+    #[test]
+    fn check() {
+        let exists = std::env::temp_dir().join("gonk_check.flac");
+        fs::write(&exists, []).unwrap();
+        let song = |path: &Path| Song {
+            path: path.to_string_lossy().to_string(),
+            ..Song::example()
+        };
+        let mut songs = vec![song(&exists), song(Path::new("missing.flac"))];
+
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        let check = check_songs(&songs, later);
+        assert_eq!(check.ok, 1);
+        assert_eq!(check.missing, ["missing.flac"]);
+        assert!(check.changed.is_empty());
+        assert!(!check.is_ok());
+        assert_eq!(
+            check.to_string(),
+            "OK: 1 songs, MISSING: 1 songs, CHANGED: 0 songs (modified since the last scan)"
+        );
+
+        //The same age but a different size.
+        let resized = [Song {
+            size: 10,
+            ..song(&exists)
+        }];
+        assert_eq!(check_songs(&resized, later).changed.len(), 1);
+
+        //Written after the database, an empty file can't be read again so it's removed.
+        let check = check_songs(&songs, SystemTime::UNIX_EPOCH);
+        assert_eq!(check.changed.len(), 1);
+        fix_songs(&mut songs, &check);
+        assert!(songs.is_empty());
+
+        fs::remove_file(exists).unwrap();
+    }
+
+    #[test]
+    fn unreadable() {
+        let dir = std::env::temp_dir().join("gonk_unreadable");
+        fs::create_dir_all(&dir).unwrap();
+        //A truncated download and a file that isn't audio at all.
+        fs::write(dir.join("truncated.flac"), b"fLaC\0\0\0\x22").unwrap();
+        fs::write(dir.join("text.mp3"), "not an mp3").unwrap();
+
+        let songs = read_dir(&dir.to_string_lossy(), false);
+        assert_eq!(songs.len(), 2);
+        for song in songs {
+            assert!(song.unwrap_err().contains("gonk_unreadable"));
+        }
+        assert!(!is_reading_file());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replace() {
+        let mut songs = vec![Song::example(), Song::example()];
+        songs[0].artist = String::from("Artist feat. Someone");
+
+        let regex = Regex::new(r"feat\.").unwrap();
+        assert_eq!(
+            replace_field(&mut songs, SongField::Artist, &regex, "ft."),
+            1
+        );
+        assert_eq!(songs[0].artist, "Artist ft. Someone");
+        assert_eq!(songs[1].artist, "artist");
+
+        let regex = Regex::new(r"^(.*)$").unwrap();
+        assert_eq!(
+            replace_field(&mut songs, SongField::Title, &regex, "$1!"),
+            2
+        );
+        assert_eq!(songs[0].title, "title!");
+    }
+
+    #[test]
+    fn migrate() {
+        assert_eq!(
+            migrate_path(r"D:\Music\a.flac", r"D:\Music", r"E:\Music"),
+            Some(String::from(r"E:\Music\a.flac"))
+        );
+        assert_eq!(
+            migrate_path(r"\\?\D:\Music\a.flac", r"D:\Music", r"E:\Music"),
+            Some(String::from(r"\\?\E:\Music\a.flac"))
+        );
+        assert_eq!(
+            migrate_path("/home/user/music/a.flac", "/home/user/music", "/data/music"),
+            Some(String::from("/data/music/a.flac"))
+        );
+        assert_eq!(
+            migrate_path("/other/a.flac", "/home/user/music", "/data/music"),
+            None
+        );
+
+        let played = |count| plays::Plays {
+            count,
+            last_played: 10,
+        };
+        let mut plays = HashMap::new();
+        plays.insert(String::from("/music/a.flac"), played(2));
+        plays.insert(String::from("/other/b.flac"), played(1));
+        assert_eq!(migrate_plays(&mut plays, "/music", "/data/music"), 1);
+        assert_eq!(plays.len(), 2);
+        assert_eq!(plays["/data/music/a.flac"], played(2));
+        assert_eq!(plays["/other/b.flac"], played(1));
+    }
+
     #[test]
     fn path() {
         let path = PathBuf::from(
@@ -1,4 +1,7 @@
-use crate::{db::UNKNOWN_ARTIST, Song};
+use crate::{
+    db::{r128_gain, replay_gain, year, UNKNOWN_ARTIST},
+    Song,
+};
 use std::{
     collections::HashMap,
     error::Error,
@@ -95,6 +98,8 @@ pub fn read_metadata<P: AsRef<Path>>(path: P) -> Result<Song, Box<dyn Error>> {
 
     let mut song: Song = Song::default();
     song.path = path.as_ref().to_string_lossy().to_string();
+    //Only used if there are no replay gain tags.
+    let mut r128 = (None, None);
 
     let mut flag = [0; 1];
 
@@ -130,20 +135,35 @@ pub fn read_metadata<P: AsRef<Path>>(path: P) -> Result<Song, Box<dyn Error>> {
                     "artist" if song.artist == UNKNOWN_ARTIST => song.artist = v.to_string(),
                     "title" => song.title = v.to_string(),
                     "album" => song.album = v.to_string(),
+                    "genre" if song.genre.is_empty() => song.genre = v.to_string(),
                     "tracknumber" => song.track_number = v.parse().unwrap_or(1),
                     "discnumber" => song.disc_number = v.parse().unwrap_or(1),
+                    "date" | "year" | "originaldate" if song.year == 0 => {
+                        song.year = year(v).unwrap_or(0)
+                    }
                     "replaygain_track_gain" => {
-                        //Remove the trailing " dB" from "-5.39 dB".
-                        if let Some(slice) = v.get(..v.len() - 3) {
-                            if let Ok(db) = slice.parse::<f32>() {
-                                song.gain = 10.0f32.powf(db / 20.0);
-                            }
+                        if let Some(gain) = replay_gain(v) {
+                            song.gain = gain;
+                        }
+                    }
+                    "replaygain_album_gain" => {
+                        if let Some(gain) = replay_gain(v) {
+                            song.album_gain = gain;
                         }
                     }
+                    "r128_track_gain" => r128.0 = r128_gain(v),
+                    "r128_album_gain" => r128.1 = r128_gain(v),
                     _ => {}
                 }
             }
 
+            //Replay gain tags take priority.
+            if song.gain == 0.0 {
+                song.gain = r128.0.unwrap_or(0.0);
+            }
+            if song.album_gain == 0.0 {
+                song.album_gain = r128.1.unwrap_or(0.0);
+            }
             return Ok(song);
         }
 
@@ -0,0 +1,339 @@
+//! Guess missing tags from the path
+//!
+//! Untagged files are named after the folders they're in and their file name,
+//! e.g. `Artist/Album (2001)/03 - Title.mp3`.
+use crate::db::{Song, UNKNOWN_ALBUM, UNKNOWN_ARTIST, UNKNOWN_TITLE};
+use std::path::Path;
+
+///Folders that organize music rather than name it.
+const GENERIC: [&str; 20] = [
+    "music",
+    "my music",
+    "download",
+    "downloads",
+    "desktop",
+    "documents",
+    "unsorted",
+    "misc",
+    "various",
+    "various artists",
+    "new folder",
+    "songs",
+    "audio",
+    "mp3",
+    "flac",
+    "home",
+    "users",
+    "media",
+    "tmp",
+    "temp",
+];
+
+#[derive(Debug, Default, PartialEq)]
+struct FileName {
+    disc: Option<u8>,
+    track: Option<u8>,
+    artist: Option<String>,
+    title: String,
+}
+
+///Fill in the tags that are still unknown and flag the song. Returns true if anything was guessed.
+///
+///The track and disc number are only guessed when the title is unknown,
+///since an untagged number can't be told apart from track one.
+pub fn guess(song: &mut Song) -> bool {
+    let path = Path::new(&song.path);
+    let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        return false;
+    };
+    let file = file_name(stem);
+
+    let mut folders = path
+        .ancestors()
+        .skip(1)
+        .filter_map(|path| path.file_name()?.to_str());
+    let mut album_folder = folders.next();
+    let disc_folder = album_folder.and_then(disc);
+    if disc_folder.is_some() {
+        album_folder = folders.next();
+    }
+    let album_folder = album_folder.filter(|name| is_name(name));
+    //Loose files in a folder like `~/Downloads` aren't in an artist folder.
+    let artist_folder = album_folder
+        .and(folders.next())
+        .filter(|name| is_name(name));
+
+    //"Artist - Album" folders name both.
+    let (folder_artist, album) = match album_folder {
+        Some(name) => match name.split_once(" - ") {
+            Some((artist, album)) => (Some(artist.trim()), Some(strip_year(album))),
+            None => (None, Some(strip_year(name))),
+        },
+        None => (None, None),
+    };
+
+    let mut guessed = false;
+    if song.title == UNKNOWN_TITLE && !file.title.is_empty() {
+        song.title = file.title;
+        if let Some(track) = file.track {
+            song.track_number = track;
+        }
+        if let Some(disc) = file.disc.or(disc_folder) {
+            song.disc_number = disc;
+        }
+        guessed = true;
+    }
+
+    if song.album == UNKNOWN_ALBUM {
+        if let Some(album) = album.filter(|album| !album.is_empty()) {
+            song.album = album.to_string();
+            guessed = true;
+        }
+    }
+
+    if song.artist == UNKNOWN_ARTIST {
+        let artist = file.artist.as_deref().or(folder_artist).or(artist_folder);
+        if let Some(artist) = artist.filter(|artist| !artist.is_empty()) {
+            song.artist = artist.to_string();
+            guessed = true;
+        }
+    }
+
+    song.guessed |= guessed;
+    guessed
+}
+
+///Split a file name like `1-03 - Artist - Title` into its parts.
+fn file_name(stem: &str) -> FileName {
+    let name = stem.replace('_', " ");
+    let mut rest = name.trim();
+    let mut file = FileName::default();
+
+    //[03] Title
+    if let Some(inner) = rest.strip_prefix(['[', '(']) {
+        if let Some(end) = inner.find([']', ')']) {
+            if let Ok(track) = inner[..end].trim().parse() {
+                file.track = Some(track);
+                rest = inner[end + 1..].trim_start();
+            }
+        }
+    }
+
+    //Track 03
+    let track_prefix = rest
+        .get(..5)
+        .is_some_and(|s| s.eq_ignore_ascii_case("track"));
+    if file.track.is_none() && track_prefix {
+        let digits = digits(rest[5..].trim_start());
+        if let Ok(track) = digits.parse() {
+            file.track = Some(track);
+            let after = rest[5..].trim_start()[digits.len()..].trim_start();
+            rest = after.trim_start_matches(['-', '.', ' ']);
+        }
+    }
+
+    //03 Title, 03. Title, 03 - Title and 1-03 Title.
+    if file.track.is_none() {
+        let first = digits(rest);
+        let after = &rest[first.len()..];
+        let disc_track = after
+            .strip_prefix('-')
+            .map(digits)
+            .filter(|track| (1..=2).contains(&track.len()));
+
+        match disc_track {
+            Some(track) if first.len() == 1 => {
+                let after = &after[1 + track.len()..];
+                if after.starts_with([' ', '.']) {
+                    file.disc = first.parse().ok();
+                    file.track = track.parse().ok();
+                    rest = after.trim_start_matches(['-', '.', ' ']);
+                }
+            }
+            _ if (1..=2).contains(&first.len()) && after.starts_with([' ', '.', '-']) => {
+                let title = after.trim_start_matches(['-', '.', ' ']);
+                //A title that's only a number like "22" isn't a track.
+                if !title.is_empty() {
+                    file.track = first.parse().ok();
+                    rest = title;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //Artist - Title
+    match rest.rsplit_once(" - ") {
+        Some((artist, title)) => {
+            let artist = artist.split(" - ").next().unwrap_or(artist).trim();
+            if !artist.is_empty() {
+                file.artist = Some(artist.to_string());
+            }
+            file.title = title.trim().to_string();
+        }
+        None => file.title = rest.trim().to_string(),
+    }
+
+    //Nothing was left after the track number.
+    if file.title.is_empty() {
+        file.title = name.trim().to_string();
+    }
+
+    file
+}
+
+fn digits(s: &str) -> &str {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    &s[..end]
+}
+
+///The disc number of a folder like `CD1` or `Disc 2`.
+fn disc(name: &str) -> Option<u8> {
+    let lower = name.to_ascii_lowercase();
+    let rest = ["cd", "disc", "disk"]
+        .iter()
+        .find_map(|prefix| lower.strip_prefix(prefix))?;
+    rest.trim().parse().ok()
+}
+
+///Does a folder look like the name of an artist or album.
+fn is_name(name: &str) -> bool {
+    let lower = name.trim().to_lowercase();
+    !lower.is_empty()
+        && !GENERIC.contains(&lower.as_str())
+        && !lower.chars().all(|c| c.is_ascii_digit())
+        //Drive roots like `C:`.
+        && !lower.ends_with(':')
+        && disc(name).is_none()
+}
+
+///Remove a trailing year like "(2001)" or "[2001]".
+fn strip_year(album: &str) -> &str {
+    let album = album.trim();
+    for (open, close) in [('(', ')'), ('[', ']')] {
+        if let Some(start) = album.strip_suffix(close).and_then(|s| s.rfind(open)) {
+            let year = &album[start + 1..album.len() - 1];
+            if year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()) {
+                return album[..start].trim_end();
+            }
+        }
+    }
+    album
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guessed(path: &str) -> Song {
+        let mut song = Song {
+            path: path.to_string(),
+            ..Song::default()
+        };
+        guess(&mut song);
+        song
+    }
+
+    fn tags(song: &Song) -> (&str, &str, &str, u8, u8) {
+        (
+            &song.artist,
+            &song.album,
+            &song.title,
+            song.disc_number,
+            song.track_number,
+        )
+    }
+
+    #[test]
+    fn paths() {
+        let song = guessed("/home/bay/Music/Radiohead/OK Computer/01 - Airbag.mp3");
+        assert_eq!(tags(&song), ("Radiohead", "OK Computer", "Airbag", 1, 1));
+
+        let song = guessed("D:/Downloads/Daft Punk - Discovery (2001)/03. Digital Love.flac");
+        assert_eq!(
+            tags(&song),
+            ("Daft Punk", "Discovery", "Digital Love", 1, 3)
+        );
+
+        let song = guessed("/music/Boards of Canada/Geogaddi/Disc 2/05 Dawn Chorus.mp3");
+        assert_eq!(
+            tags(&song),
+            ("Boards of Canada", "Geogaddi", "Dawn Chorus", 2, 5)
+        );
+
+        let song = guessed("/music/Aphex Twin/Drukqs [2001]/2-11 Avril 14th.ogg");
+        assert_eq!(tags(&song), ("Aphex Twin", "Drukqs", "Avril 14th", 2, 11));
+
+        //Loose files only name the artist and title.
+        let song = guessed("/home/bay/Downloads/Artist Name - Song Title.mp3");
+        assert_eq!(
+            tags(&song),
+            ("Artist Name", UNKNOWN_ALBUM, "Song Title", 1, 1)
+        );
+
+        let song = guessed("/home/bay/Downloads/track_07_some_song.mp3");
+        assert_eq!(
+            tags(&song),
+            (UNKNOWN_ARTIST, UNKNOWN_ALBUM, "some song", 1, 7)
+        );
+
+        let song = guessed("C:/Music/[04] Intro.ogg");
+        assert_eq!(tags(&song), (UNKNOWN_ARTIST, UNKNOWN_ALBUM, "Intro", 1, 4));
+
+        //Existing tags are kept.
+        let mut song = Song {
+            path: String::from("/music/Folder Artist/Folder Album/02 - File Title.mp3"),
+            title: String::from("Tagged Title"),
+            ..Song::default()
+        };
+        assert!(guess(&mut song));
+        assert!(song.guessed);
+        assert_eq!(
+            tags(&song),
+            ("Folder Artist", "Folder Album", "Tagged Title", 1, 1)
+        );
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            file_name("01 - Artist - Title"),
+            FileName {
+                disc: None,
+                track: Some(1),
+                artist: Some(String::from("Artist")),
+                title: String::from("Title"),
+            }
+        );
+        assert_eq!(file_name("Artist - Album - 05 - Title").title, "Title");
+        assert_eq!(
+            file_name("Artist - Album - 05 - Title").artist.unwrap(),
+            "Artist"
+        );
+
+        //Titles that start with or are a number.
+        assert_eq!(file_name("22").title, "22");
+        assert_eq!(file_name("22").track, None);
+        assert_eq!(file_name("1999").track, None);
+        assert_eq!(file_name("100 Bad Days").track, None);
+        assert_eq!(file_name("7 Rings").track, Some(7));
+
+        assert_eq!(file_name("Track 03").title, "Track 03");
+        assert_eq!(file_name("Track 03").track, Some(3));
+        assert_eq!(file_name("  spaced   ").title, "spaced");
+        assert_eq!(file_name("Trééé").title, "Trééé");
+    }
+
+    #[test]
+    fn folders() {
+        assert_eq!(disc("CD1"), Some(1));
+        assert_eq!(disc("Disc 2"), Some(2));
+        assert_eq!(disc("Discovery"), None);
+        assert!(is_name("Radiohead"));
+        assert!(!is_name("Downloads"));
+        assert!(!is_name("C:"));
+        assert!(!is_name("2001"));
+        assert_eq!(strip_year("Album (2001)"), "Album");
+        assert_eq!(strip_year("Album (Deluxe)"), "Album (Deluxe)");
+    }
+}
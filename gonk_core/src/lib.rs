@@ -19,19 +19,28 @@ use std::{
 
 pub use crate::{
     db::{Album, Artist, Song},
+    normalize::Normalize,
     playlist::Playlist,
 };
 pub use flac_decoder::*;
 pub use index::*;
 
+pub mod clean;
 pub mod db;
 pub mod flac_decoder;
+pub mod guess;
 pub mod index;
 pub mod log;
+pub mod m3u;
+pub mod normalize;
 pub mod playlist;
+pub mod plays;
 pub mod settings;
+pub mod silence;
+pub mod snapshot;
 pub mod strsim;
 pub mod vdb;
+pub mod waveform;
 
 ///Escape potentially problematic strings.
 pub fn escape(input: &str) -> Cow<str> {
@@ -67,6 +67,21 @@ pub fn clear() {
     }
 }
 
+///Keep showing a message that's already been logged, other messages are still shown on top of it.
+///
+///Call this more often than `MESSAGE_COOLDOWN`.
+pub fn keep(message: &str) {
+    match unsafe { LOG.messages.iter_mut().find(|(m, _)| m == message) } {
+        Some((_, instant)) => *instant = Instant::now(),
+        None => crate::log!("{message}"),
+    }
+}
+
+///Stop showing a message without clearing the others.
+pub fn remove(message: &str) {
+    unsafe { LOG.messages.retain(|(m, _)| m != message) };
+}
+
 pub fn last_message() -> Option<&'static str> {
     if let Some((message, _)) = unsafe { LOG.messages.last() } {
         Some(message.as_str())
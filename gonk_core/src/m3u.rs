@@ -0,0 +1,172 @@
+//! Import and export M3U8 playlists
+//!
+//! Songs are only imported if they're in the database.
+//!
+use crate::{
+    gonk_path,
+    playlist::{file_name, write_atomic},
+    vdb::Database,
+    Song,
+};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+///Where a playlist is exported to.
+pub fn export_path(name: &str) -> PathBuf {
+    let file = file_name(name);
+    let stem = file.strip_suffix(".playlist").unwrap_or(&file);
+    gonk_path().join(format!("{stem}.m3u8"))
+}
+
+///Write an extended M3U8 file.
+///
+///Songs don't store their length so the duration is always -1.
+pub fn export(songs: &[Song], path: &Path) -> io::Result<()> {
+    write_atomic(path, serialize(songs).as_bytes())
+}
+
+///Songs in the file that are also in the database, in the same order.
+pub fn import(path: &Path, db: &Database) -> io::Result<Vec<Song>> {
+    let bytes = fs::read(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(parse(&String::from_utf8_lossy(&bytes), dir, db.songs()))
+}
+
+fn serialize(songs: &[Song]) -> String {
+    let mut buffer = String::from("#EXTM3U\n");
+    for song in songs {
+        //A comma in the title is fine, only the first one ends the duration.
+        let title = format!("{} - {}", song.artist, song.title).replace(['\r', '\n'], "");
+        buffer.push_str(&format!("#EXTINF:-1,{title}\n"));
+        buffer.push_str(&song.path);
+        buffer.push('\n');
+    }
+    buffer
+}
+
+fn parse<'a>(file: &str, dir: &Path, songs: impl Iterator<Item = &'a Song>) -> Vec<Song> {
+    let songs: HashMap<String, &Song> = songs
+        .map(|song| (key(Path::new(&song.path)), song))
+        .collect();
+    file.trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            //Relative paths are relative to the playlist.
+            let path = dir.join(file_uri(line).unwrap_or_else(|| line.to_string()));
+            songs.get(&key(&path)).map(|song| (*song).clone())
+        })
+        .collect()
+}
+
+///The path in a `file://` URI, `None` if the line is a plain path.
+fn file_uri(line: &str) -> Option<String> {
+    let path = line.strip_prefix("file://")?;
+    let path = decode(path.strip_prefix("localhost").unwrap_or(path));
+    //`file:///C:/Music` has a slash before the drive letter.
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => Some(path[1..].to_string()),
+        _ => Some(path),
+    }
+}
+
+///Undo the percent encoding, `%20` is a space. A `%` without two hex digits is kept.
+fn decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+///Compare paths by their components so `./` and `..` and mixed slashes still match.
+fn key(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = path.strip_prefix("//?/").unwrap_or(&path);
+    let mut parts: Vec<String> = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            component => parts.push(component.as_os_str().to_string_lossy().to_string()),
+        }
+    }
+    let key = parts.join("/");
+    //File names are case insensitive on Windows.
+    if cfg!(windows) {
+        key.to_lowercase()
+    } else {
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(path: &str, title: &str) -> Song {
+        Song {
+            path: path.to_string(),
+            title: title.to_string(),
+            ..Song::example()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let songs = vec![song("/music/a.flac", "A, B"), song("/music/b.flac", "B")];
+        let file = serialize(&songs);
+        assert_eq!(
+            file,
+            "#EXTM3U\n#EXTINF:-1,artist - A, B\n/music/a.flac\n#EXTINF:-1,artist - B\n/music/b.flac\n"
+        );
+        assert_eq!(parse(&file, Path::new("/"), songs.iter()), songs);
+    }
+
+    #[test]
+    fn paths() {
+        let songs = [
+            song("/music/album/a.flac", "a"),
+            song("/music/album/b.flac", "b"),
+        ];
+        let file = "\u{feff}#EXTM3U\r\n\r\nb.flac\r\n../album/./a.flac\n/music/missing.flac\nfile:///music/album/b.flac\n";
+        let imported = parse(file, Path::new("/music/album"), songs.iter());
+        let titles: Vec<&str> = imported.iter().map(|song| song.title.as_str()).collect();
+        assert_eq!(titles, ["b", "a", "b"]);
+    }
+
+    #[test]
+    fn uris() {
+        let songs = [song("/music/Björk/My Song.flac", "a")];
+        let file = "file:///music/Bj%C3%B6rk/My%20Song.flac\nfile://localhost/music/Bj%c3%b6rk/My%20Song.flac\n";
+        assert_eq!(parse(file, Path::new("/"), songs.iter()).len(), 2);
+
+        assert_eq!(file_uri("/music/a.flac"), None);
+        assert_eq!(
+            file_uri("file:///C:/Music/a%20b.flac").unwrap(),
+            "C:/Music/a b.flac"
+        );
+        assert_eq!(decode("100%25 %zz %4"), "100% %zz %4");
+    }
+}
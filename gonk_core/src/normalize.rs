@@ -0,0 +1,265 @@
+//! Song title normalization
+//!
+//! Titles are only changed for display, the database and search use the original strings.
+use std::borrow::Cow;
+
+const FEATURING: [&str; 3] = ["feat. ", "ft. ", "featuring "];
+const QUALIFIERS: [&str; 12] = [
+    "remaster",
+    "edit",
+    "version",
+    "mix",
+    "live",
+    "mono",
+    "stereo",
+    "demo",
+    "acoustic",
+    "instrumental",
+    "bonus",
+    "single",
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Normalize {
+    ///Move "feat. X" from the title to the artist.
+    pub featuring: bool,
+    ///Remove trailing qualifiers like "(2011 Remaster)" or "- Radio Edit".
+    pub strip: bool,
+    ///Maximum width of a column, zero is unlimited.
+    pub max_width: usize,
+}
+
+impl Normalize {
+    pub fn title<'a>(&self, title: &'a str) -> Cow<'a, str> {
+        let mut title = if self.featuring {
+            split_featuring(title).0
+        } else {
+            Cow::Borrowed(title)
+        };
+
+        if self.strip {
+            title = strip(title);
+        }
+
+        if self.max_width != 0 && title.chars().count() > self.max_width {
+            //Cut the qualifiers before cutting the title.
+            title = ellipsis(strip(title), self.max_width);
+        }
+
+        title
+    }
+
+    pub fn artist<'a>(&self, artist: &'a str, title: &str) -> Cow<'a, str> {
+        let artist = match split_featuring(title).1 {
+            Some(featuring) if self.featuring => Cow::Owned(format!("{artist} feat. {featuring}")),
+            _ => Cow::Borrowed(artist),
+        };
+        self.column(artist)
+    }
+
+    pub fn album<'a>(&self, album: &'a str) -> Cow<'a, str> {
+        self.column(Cow::Borrowed(album))
+    }
+
+    fn column<'a>(&self, s: Cow<'a, str>) -> Cow<'a, str> {
+        if self.max_width == 0 {
+            s
+        } else {
+            ellipsis(s, self.max_width)
+        }
+    }
+}
+
+fn strip(title: Cow<'_, str>) -> Cow<'_, str> {
+    match title {
+        Cow::Borrowed(title) => Cow::Borrowed(strip_qualifiers(title)),
+        Cow::Owned(title) => Cow::Owned(strip_qualifiers(&title).to_string()),
+    }
+}
+
+///Split the featured artists from a title.
+///
+///"Song (feat. Someone) [Live]" -> ("Song [Live]", Some("Someone"))
+pub fn split_featuring(title: &str) -> (Cow<'_, str>, Option<&str>) {
+    //Offsets are the same since only ascii is changed.
+    let lower = title.to_ascii_lowercase();
+
+    for marker in FEATURING {
+        let Some(i) = lower.find(marker) else {
+            continue;
+        };
+
+        //Don't match the middle of a word.
+        let before = &title[..i];
+        let start = match before.chars().last() {
+            Some('(' | '[') => i - 1,
+            Some(' ') | None => i,
+            _ => continue,
+        };
+        let open = title[start..].chars().next();
+        let featuring = &title[i + marker.len()..];
+
+        let (featuring, end) = match open {
+            Some('(') | Some('[') => {
+                let close = if open == Some('(') { ')' } else { ']' };
+                match featuring.find(close) {
+                    Some(j) => (&featuring[..j], i + marker.len() + j + 1),
+                    None => (featuring, title.len()),
+                }
+            }
+            //Unbracketed features run until the next qualifier.
+            _ => match [" (", " [", " - "]
+                .iter()
+                .filter_map(|s| featuring.find(s))
+                .min()
+            {
+                Some(j) => (&featuring[..j], i + marker.len() + j),
+                None => (featuring, title.len()),
+            },
+        };
+
+        let featuring = featuring.trim();
+        if featuring.is_empty() {
+            continue;
+        }
+
+        let mut new = title[..start].trim_end().to_string();
+        let rest = title[end..].trim();
+        if !rest.is_empty() {
+            new.push(' ');
+            new.push_str(rest);
+        }
+
+        return (Cow::Owned(new), Some(featuring));
+    }
+
+    (Cow::Borrowed(title), None)
+}
+
+///Remove trailing qualifiers from a title.
+///
+///"Song (2011 Remaster) - Radio Edit" -> "Song"
+pub fn strip_qualifiers(mut title: &str) -> &str {
+    loop {
+        let trimmed = title.trim_end();
+        let new = if let Some(open) = match trimmed.chars().last() {
+            Some(')') => trimmed.rfind('('),
+            Some(']') => trimmed.rfind('['),
+            _ => None,
+        } {
+            &trimmed[..open]
+        } else if let Some(i) = trimmed.rfind(" - ") {
+            let suffix = trimmed[i + 3..].to_ascii_lowercase();
+            if QUALIFIERS.iter().any(|q| suffix.contains(q)) {
+                &trimmed[..i]
+            } else {
+                trimmed
+            }
+        } else {
+            trimmed
+        };
+
+        let new = new.trim_end();
+
+        //Never remove the whole title.
+        if new.is_empty() || new.len() == title.len() {
+            return title.trim_end();
+        }
+
+        title = new;
+    }
+}
+
+///Cut a string to a maximum number of characters, ending with '…'.
+pub fn ellipsis(s: Cow<'_, str>, width: usize) -> Cow<'_, str> {
+    if s.chars().count() <= width {
+        return s;
+    }
+
+    let mut new: String = s.chars().take(width.saturating_sub(1)).collect();
+    new.truncate(new.trim_end().len());
+    new.push('…');
+    Cow::Owned(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn featuring() {
+        let cases = [
+            (
+                "Song Name (feat. Somebody) [2011 Remaster] - Radio Edit",
+                "Song Name [2011 Remaster] - Radio Edit",
+                Some("Somebody"),
+            ),
+            ("Song (ft. A & B)", "Song", Some("A & B")),
+            ("Song feat. X", "Song", Some("X")),
+            ("Song [feat. X] (Live)", "Song (Live)", Some("X")),
+            ("Song Feat. X - Live", "Song - Live", Some("X")),
+            (
+                "Song (Featuring Someone Else)",
+                "Song",
+                Some("Someone Else"),
+            ),
+            ("Song (feat. Unclosed", "Song", Some("Unclosed")),
+            ("Aloft. Song", "Aloft. Song", None),
+            ("Shaft. Theme", "Shaft. Theme", None),
+            ("Song", "Song", None),
+        ];
+
+        for (title, expected, featuring) in cases {
+            let (new, f) = split_featuring(title);
+            assert_eq!(new, expected, "{title}");
+            assert_eq!(f, featuring, "{title}");
+        }
+    }
+
+    #[test]
+    fn qualifiers() {
+        let cases = [
+            (
+                "Song Name (feat. Somebody) [2011 Remaster] - Radio Edit",
+                "Song Name",
+            ),
+            ("Let It Be - Remastered 2009", "Let It Be"),
+            ("Song (Live)", "Song"),
+            ("Song - Part 2", "Song - Part 2"),
+            ("(Intro)", "(Intro)"),
+            ("Song (Left", "Song (Left"),
+            ("Song ", "Song"),
+            ("Song [Bonus Track] (Mono Version)", "Song"),
+        ];
+
+        for (title, expected) in cases {
+            assert_eq!(strip_qualifiers(title), expected, "{title}");
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        let title = "Song Name (feat. Somebody) [2011 Remaster] - Radio Edit";
+        let off = Normalize::default();
+        assert_eq!(off.title(title), title);
+        assert_eq!(off.artist("Artist", title), "Artist");
+
+        let on = Normalize {
+            featuring: true,
+            strip: true,
+            max_width: 0,
+        };
+        assert_eq!(on.title(title), "Song Name");
+        assert_eq!(on.artist("Artist", title), "Artist feat. Somebody");
+
+        //Qualifiers are removed before the title is cut.
+        let width = Normalize {
+            featuring: false,
+            strip: false,
+            max_width: 12,
+        };
+        assert_eq!(width.title("Song (2011 Remaster)"), "Song");
+        assert_eq!(width.title("A Very Long Song Name"), "A Very Long…");
+        assert_eq!(width.album("Short"), "Short");
+    }
+}
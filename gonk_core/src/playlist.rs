@@ -4,10 +4,84 @@
 //!
 use crate::{escape, gonk_path, Deserialize, Index, Serialize, Song};
 use std::{
-    fs::{self},
-    path::PathBuf,
+    collections::HashSet,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    mem,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
 };
 
+///The built-in playlist for liked songs. It's always shown, even when empty.
+pub const LIKED: &str = "Liked Songs";
+
+///The longest playlist name in characters.
+pub const MAX_NAME: usize = 100;
+
+///Characters that can't be used in a file name on Windows.
+const RESERVED: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+///Names Windows uses for devices, even with an extension.
+const DEVICES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+///Trim the name the user typed and check it can be used for a playlist.
+pub fn validate_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.trim_end_matches('.').is_empty() {
+        return Err(String::from("Enter a playlist name."));
+    }
+    if name.chars().count() > MAX_NAME {
+        return Err(format!("Names can't be longer than {MAX_NAME} characters."));
+    }
+    if let Some(c) = name.chars().find(|c| RESERVED.contains(c)) {
+        return Err(format!("Names can't contain {c}"));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(String::from("Names can't contain control characters."));
+    }
+    Ok(name)
+}
+
+///The file a playlist is saved to. The name itself is stored inside the file.
+///
+///Characters Windows doesn't allow are replaced and trailing dots and spaces are removed.
+pub fn file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if RESERVED.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .take(MAX_NAME)
+        .collect();
+    let mut name = name.trim().trim_end_matches(['.', ' ']).to_string();
+    if name.is_empty() {
+        name.push('_');
+    }
+    if DEVICES
+        .iter()
+        .any(|device| device.eq_ignore_ascii_case(&name))
+    {
+        name.push('_');
+    }
+    format!("{name}.playlist")
+}
+
+///Names that would be saved to the same file.
+///
+///File names are case insensitive on Windows so case is ignored.
+pub fn same_name(a: &str, b: &str) -> bool {
+    file_name(a).to_lowercase() == file_name(b).to_lowercase()
+}
+
 #[derive(Debug, Default, PartialEq)]
 pub struct Playlist {
     name: String,
@@ -20,7 +94,7 @@ impl Playlist {
     pub fn new(name: &str, songs: Vec<Song>) -> Self {
         let name = escape(name);
         Self {
-            path: gonk_path().join(format!("{name}.playlist")),
+            path: gonk_path().join(file_name(&name)),
             name: String::from(name),
             songs: Index::from(songs),
         }
@@ -28,8 +102,14 @@ impl Playlist {
     pub fn name(&self) -> &str {
         &self.name
     }
+    pub fn is_liked(&self) -> bool {
+        self.name == LIKED
+    }
+    ///Write to `<name>.playlist.tmp` then rename it over the playlist.
+    ///
+    ///A crash while saving leaves the old playlist intact.
     pub fn save(&self) -> std::io::Result<()> {
-        fs::write(&self.path, self.serialize())
+        write_atomic(&self.path, self.serialize().as_bytes())
     }
     //TODO: This is super slow.
     pub fn delete(&self) {
@@ -64,7 +144,42 @@ impl Deserialize for Playlist {
     }
 }
 
-pub fn playlists() -> Vec<Playlist> {
+///Renaming can fail on Windows while another process has the file open.
+const RENAME_ATTEMPTS: usize = 5;
+
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let result = write_tmp(&tmp, contents).and_then(|_| rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write_tmp(tmp: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match fs::rename(from, to) {
+            Err(_) if cfg!(windows) && attempt < RENAME_ATTEMPTS => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            result => return result,
+        }
+    }
+}
+
+///Every playlist file in the gonk folder.
+fn files() -> Vec<PathBuf> {
     winwalk::walkdir(gonk_path().to_str().unwrap(), 0)
         .into_iter()
         .flatten()
@@ -74,11 +189,103 @@ pub fn playlists() -> Vec<Playlist> {
             }
             None => false,
         })
-        .flat_map(|entry| fs::read_to_string(entry.path))
-        .map(|string| Playlist::deserialize(&string).unwrap())
+        .map(|entry| PathBuf::from(entry.path))
         .collect()
 }
 
+///Loading the playlists never moves them, see `repair_names`.
+pub fn playlists() -> Vec<Playlist> {
+    let mut playlists: Vec<Playlist> = files().iter().flat_map(|path| read(path)).collect();
+
+    //Liked songs are always first.
+    let liked = match playlists.iter().position(Playlist::is_liked) {
+        Some(i) => playlists.remove(i),
+        None => Playlist::new(LIKED, Vec::new()),
+    };
+    playlists.insert(0, liked);
+    playlists
+}
+
+///Empty and corrupt files are skipped instead of stopping the other playlists from loading.
+fn read(path: &Path) -> Option<Playlist> {
+    let string = fs::read_to_string(path).ok()?;
+    let mut playlist = Playlist::deserialize(&string).ok()?;
+    playlist.path = path.to_path_buf();
+    Some(playlist)
+}
+
+///Move every playlist saved before names were sanitized, called once at startup.
+pub fn repair_names() {
+    for path in files() {
+        if let Some(playlist) = read(&path) {
+            repair(playlist, &path);
+        }
+    }
+}
+
+///Move playlists saved before names were sanitized to the file their name belongs in.
+///
+///The playlist is left where it is if another playlist already uses that file.
+fn repair(mut playlist: Playlist, found: &Path) -> Playlist {
+    playlist.path = found.to_path_buf();
+    let expected = found.with_file_name(file_name(&playlist.name));
+    let matches = found
+        .file_name()
+        .zip(expected.file_name())
+        .is_some_and(|(a, b)| a.eq_ignore_ascii_case(b));
+    if matches || expected.exists() {
+        return playlist;
+    }
+
+    let old = mem::replace(&mut playlist.path, expected);
+    match playlist.save() {
+        Ok(()) => {
+            let _ = fs::remove_file(old);
+        }
+        Err(_) => playlist.path = old,
+    }
+    playlist
+}
+
+///Paths of every liked song.
+pub fn liked() -> HashSet<String> {
+    let path = gonk_path().join(file_name(LIKED));
+    let Ok(string) = fs::read_to_string(path) else {
+        return HashSet::new();
+    };
+    match Playlist::deserialize(&string) {
+        Ok(playlist) => playlist
+            .songs
+            .iter()
+            .map(|song| song.path.clone())
+            .collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+///Add songs to the liked playlist, skipping any that are already liked.
+///
+///Returns how many songs were added.
+pub fn add_to_liked(liked: &mut Playlist, songs: Vec<Song>) -> std::io::Result<usize> {
+    let mut paths: HashSet<String> = liked.songs.iter().map(|song| song.path.clone()).collect();
+    let len = liked.songs.len();
+
+    for song in songs {
+        if paths.insert(song.path.clone()) {
+            liked.songs.push(song);
+        }
+    }
+
+    let added = liked.songs.len() - len;
+    if added > 0 {
+        if liked.songs.index().is_none() {
+            liked.songs.select(Some(0));
+        }
+        liked.save()?;
+    }
+    Ok(added)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,4 +320,144 @@ mod tests {
         assert!(!playlists.is_empty());
         playlist.delete();
     }
+
+    #[test]
+    fn liked() {
+        let mut liked = Playlist::new(LIKED, Vec::new());
+        liked.path = std::env::temp_dir().join("gonk_liked.playlist");
+
+        let song = |title: &str| Song::test("artist", "album", title);
+
+        let added = add_to_liked(&mut liked, vec![song("a"), song("b"), song("a")]).unwrap();
+        assert_eq!(added, 2);
+        assert_eq!(liked.songs.index(), Some(0));
+
+        let added = add_to_liked(&mut liked, vec![song("b"), song("c")]).unwrap();
+        assert_eq!(added, 1);
+        assert_eq!(liked.songs.len(), 3);
+
+        //An empty playlist can be read back.
+        let empty = Playlist::new(LIKED, Vec::new());
+        assert!(Playlist::deserialize(&empty.serialize())
+            .unwrap()
+            .is_liked());
+
+        fs::remove_file(&liked.path).unwrap();
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(validate_name("  Road trip \n").unwrap(), "Road trip");
+        assert_eq!(validate_name("Vol. 2.").unwrap(), "Vol. 2.");
+
+        //Empty
+        assert!(validate_name("").is_err());
+        assert!(validate_name("   ").is_err());
+        assert!(validate_name("...").is_err());
+
+        //Too long
+        assert!(validate_name(&"a".repeat(MAX_NAME)).is_ok());
+        assert!(validate_name(&"é".repeat(MAX_NAME + 1)).is_err());
+
+        //Reserved characters
+        for c in RESERVED {
+            let err = validate_name(&format!("AC{c}DC")).unwrap_err();
+            assert!(err.ends_with(c), "{err}");
+        }
+        assert!(validate_name("a\tb").is_err());
+
+        //Duplicates
+        assert!(same_name("Rock", "rock"));
+        assert!(same_name("Rock", "Rock. "));
+        assert!(!same_name("Rock", "Rock 2"));
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name("Road trip"), "Road trip.playlist");
+        assert_eq!(file_name("AC/DC: Live?"), "AC_DC_ Live_.playlist");
+        assert_eq!(file_name("Vol. 2. . "), "Vol. 2.playlist");
+        assert_eq!(file_name("..."), "_.playlist");
+        assert_eq!(file_name("con"), "con_.playlist");
+        assert_eq!(
+            file_name(&"a".repeat(200)).len(),
+            MAX_NAME + ".playlist".len()
+        );
+
+        //The name is kept as typed.
+        let name = "日本語のプレイリスト 🎵 Ñoño / Ελληνικά";
+        let playlist = Playlist::new(name, vec![Song::example()]);
+        assert_eq!(playlist.name(), name);
+        assert!(playlist
+            .path
+            .ends_with("日本語のプレイリスト 🎵 Ñoño _ Ελληνικά.playlist"));
+        let p = Playlist::deserialize(&playlist.serialize()).unwrap();
+        assert_eq!(p, playlist);
+    }
+
+    #[test]
+    fn migrate() {
+        let dir = std::env::temp_dir().join("gonk_playlist_migrate");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        //Saved with a trailing dot before names were sanitized.
+        let mut playlist = Playlist::new("Best of.", vec![Song::example()]);
+        let old = dir.join("Best of..playlist");
+        playlist.path = old.clone();
+        playlist.save().unwrap();
+
+        //Reading it doesn't move the file.
+        assert_eq!(read(&old).unwrap().path, old);
+        assert!(old.exists());
+
+        let repaired = repair(playlist, &old);
+        assert_eq!(repaired.name(), "Best of.");
+        assert_eq!(repaired.path, dir.join("Best of.playlist"));
+        assert!(!old.exists());
+        let string = fs::read_to_string(&repaired.path).unwrap();
+        assert_eq!(Playlist::deserialize(&string).unwrap(), repaired);
+
+        //Matching files aren't touched.
+        let path = repaired.path.clone();
+        assert_eq!(repair(repaired, &path).path, path);
+
+        //Another playlist already has the file.
+        let mut playlist = Playlist::new("Best of. ", vec![Song::example()]);
+        let other = dir.join("other.playlist");
+        playlist.path = other.clone();
+        playlist.save().unwrap();
+        assert_eq!(repair(playlist, &other).path, other);
+        assert!(path.exists());
+
+        //Empty and corrupt files are skipped.
+        let empty = dir.join("empty.playlist");
+        fs::write(&empty, "").unwrap();
+        assert!(read(&empty).is_none());
+        fs::write(&empty, "name\tpath").unwrap();
+        assert!(read(&empty).is_none());
+        assert!(read(&dir.join("missing.playlist")).is_none());
+        assert_eq!(read(&path).unwrap().name(), "Best of.");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_save() {
+        let mut playlist = Playlist::new("atomic", vec![Song::example()]);
+        playlist.path = std::env::temp_dir().join("gonk_atomic.playlist");
+        let tmp = std::env::temp_dir().join("gonk_atomic.playlist.tmp");
+
+        playlist.save().unwrap();
+        assert!(!tmp.exists());
+        let string = fs::read_to_string(&playlist.path).unwrap();
+        assert_eq!(Playlist::deserialize(&string).unwrap(), playlist);
+        fs::remove_file(&playlist.path).unwrap();
+
+        //A directory can't be replaced by a file so the rename fails.
+        fs::create_dir_all(playlist.path.join("child")).unwrap();
+        assert!(playlist.save().is_err());
+        assert!(!tmp.exists());
+        fs::remove_dir_all(&playlist.path).unwrap();
+    }
 }
@@ -0,0 +1,151 @@
+//! Play counts
+//!
+//! Stored in `plays.db` next to the database, one song per line: `path\tcount\tlast_played`.
+//! The database is rebuilt by every scan so the counts are kept separately.
+use crate::{escape, gonk_path, playlist::write_atomic};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+///A song has to be heard this long to count as played, or half of it if it's shorter.
+pub const MIN_LISTEN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Plays {
+    pub count: u32,
+    ///Seconds since the Unix epoch, zero if the song was never played.
+    pub last_played: u64,
+}
+
+impl Plays {
+    ///Add another play of the same song, or the plays of another song on the same album.
+    pub fn add(self, other: Plays) -> Plays {
+        Plays {
+            count: self.count.saturating_add(other.count),
+            last_played: self.last_played.max(other.last_played),
+        }
+    }
+}
+
+pub fn plays_path() -> PathBuf {
+    gonk_path().join("plays.db")
+}
+
+///Missing and broken files have no plays, a broken line only loses that song.
+pub fn load() -> HashMap<String, Plays> {
+    fs::read_to_string(plays_path())
+        .map(|file| deserialize(&file))
+        .unwrap_or_default()
+}
+
+pub fn save(plays: &HashMap<String, Plays>) -> io::Result<()> {
+    write_atomic(&plays_path(), serialize(plays).as_bytes())
+}
+
+///Skipped songs aren't counted. The duration is zero until the song is loaded.
+pub fn listened(heard: Duration, duration: Duration) -> bool {
+    !duration.is_zero() && heard >= MIN_LISTEN.min(duration / 2)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+fn serialize(plays: &HashMap<String, Plays>) -> String {
+    let mut paths: Vec<&String> = plays.keys().collect();
+    paths.sort_unstable();
+    let mut buffer = String::new();
+    for path in paths {
+        let plays = plays[path];
+        buffer.push_str(&format!(
+            "{}\t{}\t{}\n",
+            escape(path),
+            plays.count,
+            plays.last_played
+        ));
+    }
+    buffer
+}
+
+fn deserialize(file: &str) -> HashMap<String, Plays> {
+    file.lines()
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            let path = parts.next()?;
+            let count = parts.next()?.parse().ok()?;
+            let last_played = parts.next()?.parse().ok()?;
+            Some((path.to_string(), Plays { count, last_played }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut plays = HashMap::new();
+        plays.insert(
+            String::from("/music/b.flac"),
+            Plays {
+                count: 3,
+                last_played: 1700000000,
+            },
+        );
+        plays.insert(
+            String::from("/music/a.flac"),
+            Plays {
+                count: 1,
+                last_played: 1600000000,
+            },
+        );
+        let file = serialize(&plays);
+        assert_eq!(
+            file,
+            "/music/a.flac\t1\t1600000000\n/music/b.flac\t3\t1700000000\n"
+        );
+        assert_eq!(deserialize(&file), plays);
+
+        //Broken lines are skipped.
+        let plays = deserialize("a.flac\t1\t2\nb.flac\tmany\t2\nc.flac\n\nd.flac\t4\t5");
+        assert_eq!(plays.len(), 2);
+        assert_eq!(plays["d.flac"].count, 4);
+    }
+
+    #[test]
+    fn add() {
+        let a = Plays {
+            count: 2,
+            last_played: 10,
+        };
+        let b = Plays {
+            count: u32::MAX,
+            last_played: 5,
+        };
+        assert_eq!(
+            a.add(b),
+            Plays {
+                count: u32::MAX,
+                last_played: 10
+            }
+        );
+        assert_eq!(Plays::default().add(a), a);
+    }
+
+    #[test]
+    fn listen_time() {
+        let secs = Duration::from_secs;
+        assert!(!listened(secs(10), secs(240)));
+        assert!(listened(MIN_LISTEN, secs(240)));
+        //Short songs only need half.
+        assert!(!listened(secs(9), secs(20)));
+        assert!(listened(secs(10), secs(20)));
+        assert!(!listened(secs(10), Duration::ZERO));
+    }
+}
@@ -9,14 +9,557 @@ use std::{
     io::{BufWriter, Read, Seek, Write},
 };
 
+///Name of the preset used after the columns are moved by hand.
+pub const CUSTOM_PRESET: &str = "custom";
+///The default column widths.
+pub const BALANCED: [u16; 4] = [6, 37, 31, 26];
+
+///Percentage widths of the queue columns: number, title, album and artist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnPreset {
+    pub name: String,
+    pub widths: [u16; 4],
+}
+
+pub fn default_presets() -> Vec<ColumnPreset> {
+    [
+        ("balanced", BALANCED),
+        ("title-focus", [6, 54, 20, 20]),
+        ("no-album", [6, 54, 0, 40]),
+        ("numbers-only", [8, 92, 0, 0]),
+    ]
+    .into_iter()
+    .map(|(name, widths)| ColumnPreset {
+        name: name.to_string(),
+        widths,
+    })
+    .collect()
+}
+
+///Scale the widths so they add up to 100.
+pub fn normalize_widths(widths: [u16; 4]) -> [u16; 4] {
+    let widths = widths.map(|width| width.min(100));
+    let sum: u16 = widths.iter().sum();
+    if sum == 100 {
+        return widths;
+    }
+    if sum == 0 {
+        return BALANCED;
+    }
+
+    let mut normalized = widths.map(|width| width * 100 / sum);
+
+    //Give the rounding error to the widest column.
+    let remainder = 100 - normalized.iter().sum::<u16>();
+    if let Some(widest) = (0..4).max_by_key(|&i| normalized[i]) {
+        normalized[widest] += remainder;
+    }
+    normalized
+}
+
+fn serialize_widths(widths: &[u16; 4]) -> String {
+    widths.map(|width| width.to_string()).join(",")
+}
+
+fn deserialize_widths(s: &str) -> Result<[u16; 4], Box<dyn Error>> {
+    let widths: Vec<u16> = s
+        .split(',')
+        .map(|width| width.trim().parse::<u16>())
+        .collect::<Result<_, _>>()?;
+    let widths: [u16; 4] = widths
+        .try_into()
+        .map_err(|_| format!("Expected 4 column widths: {s}"))?;
+    Ok(normalize_widths(widths))
+}
+
+fn deserialize_presets(s: &str) -> Result<Vec<ColumnPreset>, Box<dyn Error>> {
+    let mut presets = Vec::new();
+    for preset in s.split(';') {
+        let (name, widths) = preset
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid column preset: {preset}"))?;
+        presets.push(ColumnPreset {
+            name: name.to_string(),
+            widths: deserialize_widths(widths)?,
+        });
+    }
+    Ok(presets)
+}
+
+fn serialize_equalizer(gains: &[f32; 10]) -> String {
+    gains.map(|gain| gain.to_string()).join(",")
+}
+
+fn deserialize_equalizer(s: &str) -> Result<[f32; 10], String> {
+    let gains: Vec<f32> = s
+        .split(',')
+        .map(|gain| gain.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid equalizer gains: {s}"))?;
+    let gains: [f32; 10] = gains
+        .try_into()
+        .map_err(|_| format!("Expected 10 equalizer gains: {s}"))?;
+    if gains.iter().any(|gain| gain.abs() > MAX_EQ_GAIN) {
+        return Err(format!(
+            "Equalizer gains must be between -{MAX_EQ_GAIN} and {MAX_EQ_GAIN} dB: {s}"
+        ));
+    }
+    Ok(gains)
+}
+
+///Largest boost or cut of an equalizer band in dB.
+pub const MAX_EQ_GAIN: f32 = 12.0;
+
+///Longest crossfade in seconds.
+pub const MAX_CROSSFADE: u8 = 12;
+
+///Longest pause fade in milliseconds.
+pub const MAX_FADE_MS: u32 = 1000;
+
+///Names used by `gonk config`.
+pub const KEYS: [&str; 25] = [
+    "player.volume",
+    "player.volume_curve",
+    "player.output_device",
+    "player.pause_on_device_change",
+    "player.startup",
+    "player.keep_pitch",
+    "player.remember_rate",
+    "player.seek_across",
+    "player.repeat",
+    "player.replay_gain",
+    "player.crossfade",
+    "player.equalizer",
+    "player.fade_ms",
+    "player.mono",
+    "library.music_folder",
+    "library.guess_tags",
+    "normalize.featuring",
+    "normalize.strip",
+    "normalize.max_width",
+    "browser.sort",
+    "browser.mode",
+    "queue.column_preset",
+    "queue.custom_columns",
+    "queue.layout",
+    "discord.enabled",
+];
+
+///What to do with the restored queue when gonk starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Startup {
+    #[default]
+    Paused,
+    Resume,
+    ///Resume if gonk was closed while playing.
+    ResumeIfPlaying,
+}
+
+impl Startup {
+    pub fn next(self) -> Self {
+        match self {
+            Startup::Paused => Startup::Resume,
+            Startup::Resume => Startup::ResumeIfPlaying,
+            Startup::ResumeIfPlaying => Startup::Paused,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Startup::Paused => "paused",
+            Startup::Resume => "resume",
+            Startup::ResumeIfPlaying => "resume-if-playing",
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            Startup::Paused => "Stay paused",
+            Startup::Resume => "Resume playback",
+            Startup::ResumeIfPlaying => "Resume if exited while playing",
+        }
+    }
+    ///Should playback resume given the state gonk was closed in.
+    pub fn resume(self, was_playing: bool) -> bool {
+        match self {
+            Startup::Paused => false,
+            Startup::Resume => true,
+            Startup::ResumeIfPlaying => was_playing,
+        }
+    }
+}
+
+impl TryFrom<&str> for Startup {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "paused" => Ok(Startup::Paused),
+            "resume" => Ok(Startup::Resume),
+            "resume-if-playing" => Ok(Startup::ResumeIfPlaying),
+            _ => Err(format!("Invalid startup option: {s}")),
+        }
+    }
+}
+
+///What happens when a song finishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatMode {
+    ///Stop after the last song in the queue.
+    Off,
+    ///Go back to the first song after the last one.
+    #[default]
+    Queue,
+    ///Play the same song again.
+    One,
+}
+
+impl RepeatMode {
+    pub fn next(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::Queue,
+            RepeatMode::Queue => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::Queue => "queue",
+            RepeatMode::One => "one",
+        }
+    }
+}
+
+impl TryFrom<&str> for RepeatMode {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "off" => Ok(RepeatMode::Off),
+            "queue" => Ok(RepeatMode::Queue),
+            "one" => Ok(RepeatMode::One),
+            _ => Err(format!("Invalid repeat mode: {s}")),
+        }
+    }
+}
+
+///Which replay gain tag is used to level songs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplayGainMode {
+    Off,
+    #[default]
+    Track,
+    ///Keeps the differences between songs on the same album, uses the track gain if it's missing.
+    Album,
+}
+
+impl ReplayGainMode {
+    pub fn next(self) -> Self {
+        match self {
+            ReplayGainMode::Off => ReplayGainMode::Track,
+            ReplayGainMode::Track => ReplayGainMode::Album,
+            ReplayGainMode::Album => ReplayGainMode::Off,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            ReplayGainMode::Off => "off",
+            ReplayGainMode::Track => "track",
+            ReplayGainMode::Album => "album",
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            ReplayGainMode::Off => "Off",
+            ReplayGainMode::Track => "Track",
+            ReplayGainMode::Album => "Album",
+        }
+    }
+}
+
+impl TryFrom<&str> for ReplayGainMode {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "off" => Ok(ReplayGainMode::Off),
+            "track" => Ok(ReplayGainMode::Track),
+            "album" => Ok(ReplayGainMode::Album),
+            _ => Err(format!("Invalid replay gain mode: {s}")),
+        }
+    }
+}
+
+///How the volume percentage is turned into a gain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VolumeCurve {
+    #[default]
+    Linear,
+    ///Even steps in decibels, which sound more even than the linear steps.
+    Logarithmic,
+}
+
+impl VolumeCurve {
+    pub fn next(self) -> Self {
+        match self {
+            VolumeCurve::Linear => VolumeCurve::Logarithmic,
+            VolumeCurve::Logarithmic => VolumeCurve::Linear,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            VolumeCurve::Linear => "linear",
+            VolumeCurve::Logarithmic => "logarithmic",
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            VolumeCurve::Linear => "Linear",
+            VolumeCurve::Logarithmic => "Logarithmic",
+        }
+    }
+}
+
+impl TryFrom<&str> for VolumeCurve {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "linear" => Ok(VolumeCurve::Linear),
+            "logarithmic" => Ok(VolumeCurve::Logarithmic),
+            _ => Err(format!("Invalid volume curve: {s}")),
+        }
+    }
+}
+
+///How songs are laid out in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueLayout {
+    ///Two lines per song on narrow terminals.
+    #[default]
+    Auto,
+    Columns,
+    TwoLine,
+}
+
+impl QueueLayout {
+    pub fn next(self) -> Self {
+        match self {
+            QueueLayout::Auto => QueueLayout::Columns,
+            QueueLayout::Columns => QueueLayout::TwoLine,
+            QueueLayout::TwoLine => QueueLayout::Auto,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueLayout::Auto => "auto",
+            QueueLayout::Columns => "columns",
+            QueueLayout::TwoLine => "two-line",
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            QueueLayout::Auto => "Two lines on narrow terminals",
+            QueueLayout::Columns => "Columns",
+            QueueLayout::TwoLine => "Two lines",
+        }
+    }
+}
+
+impl TryFrom<&str> for QueueLayout {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "auto" => Ok(QueueLayout::Auto),
+            "columns" => Ok(QueueLayout::Columns),
+            "two-line" => Ok(QueueLayout::TwoLine),
+            _ => Err(format!("Invalid queue layout: {s}")),
+        }
+    }
+}
+
+///The order of the artists and albums in the browser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrowserSort {
+    #[default]
+    Name,
+    MostPlayed,
+    RecentlyPlayed,
+}
+
+impl BrowserSort {
+    pub fn next(self) -> Self {
+        match self {
+            BrowserSort::Name => BrowserSort::MostPlayed,
+            BrowserSort::MostPlayed => BrowserSort::RecentlyPlayed,
+            BrowserSort::RecentlyPlayed => BrowserSort::Name,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            BrowserSort::Name => "name",
+            BrowserSort::MostPlayed => "most-played",
+            BrowserSort::RecentlyPlayed => "recently-played",
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            BrowserSort::Name => "Name",
+            BrowserSort::MostPlayed => "Most played",
+            BrowserSort::RecentlyPlayed => "Recently played",
+        }
+    }
+}
+
+impl TryFrom<&str> for BrowserSort {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "name" => Ok(BrowserSort::Name),
+            "most-played" => Ok(BrowserSort::MostPlayed),
+            "recently-played" => Ok(BrowserSort::RecentlyPlayed),
+            _ => Err(format!("Invalid browser sort: {s}")),
+        }
+    }
+}
+
+///The panes shown in the browser, from left to right. They all end with the songs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrowserMode {
+    ArtistAlbumSong,
+    #[default]
+    GenreArtistAlbum,
+    YearArtistAlbum,
+    DecadeAlbum,
+}
+
+impl BrowserMode {
+    pub fn next(self) -> Self {
+        match self {
+            BrowserMode::ArtistAlbumSong => BrowserMode::GenreArtistAlbum,
+            BrowserMode::GenreArtistAlbum => BrowserMode::YearArtistAlbum,
+            BrowserMode::YearArtistAlbum => BrowserMode::DecadeAlbum,
+            BrowserMode::DecadeAlbum => BrowserMode::ArtistAlbumSong,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            BrowserMode::ArtistAlbumSong => "artist",
+            BrowserMode::GenreArtistAlbum => "genre",
+            BrowserMode::YearArtistAlbum => "year",
+            BrowserMode::DecadeAlbum => "decade",
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            BrowserMode::ArtistAlbumSong => "Artist, album",
+            BrowserMode::GenreArtistAlbum => "Genre, artist, album",
+            BrowserMode::YearArtistAlbum => "Year, artist, album",
+            BrowserMode::DecadeAlbum => "Decade, album",
+        }
+    }
+}
+
+impl TryFrom<&str> for BrowserMode {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "artist" => Ok(BrowserMode::ArtistAlbumSong),
+            "genre" => Ok(BrowserMode::GenreArtistAlbum),
+            "year" => Ok(BrowserMode::YearArtistAlbum),
+            "decade" => Ok(BrowserMode::DecadeAlbum),
+            _ => Err(format!("Invalid browser mode: {s}")),
+        }
+    }
+}
+
+///A value in the settings file that couldn't be used. The default is used instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub key: &'static str,
+    ///The value as it was written in the file.
+    pub value: String,
+    pub message: String,
+}
+
+///Settings stored in each column of the settings file, other columns hold the playback state.
+const COLUMNS: [(usize, &str); 25] = [
+    (0, "player.volume"),
+    (3, "player.output_device"),
+    (4, "library.music_folder"),
+    (5, "player.pause_on_device_change"),
+    (6, "normalize.featuring"),
+    (7, "normalize.strip"),
+    (8, "normalize.max_width"),
+    (9, "discord.enabled"),
+    (11, "queue.column_preset"),
+    (12, "queue.custom_columns"),
+    (13, "player.startup"),
+    (15, "queue.layout"),
+    (16, "library.guess_tags"),
+    (17, "player.keep_pitch"),
+    (18, "player.remember_rate"),
+    (19, "player.seek_across"),
+    (20, "player.repeat"),
+    (21, "player.replay_gain"),
+    (22, "player.crossfade"),
+    (23, "player.equalizer"),
+    (24, "player.fade_ms"),
+    (25, "player.mono"),
+    (26, "browser.sort"),
+    (27, "player.volume_curve"),
+    (28, "browser.mode"),
+];
+
 #[derive(Debug)]
 pub struct Settings {
     pub volume: u8,
-    pub index: u16,
+    pub index: usize,
     pub elapsed: f32,
     pub output_device: String,
     pub music_folder: String,
+    pub pause_on_device_change: bool,
+    pub normalize: Normalize,
+    pub discord: bool,
+    ///Column presets that can be edited in the settings file.
+    ///
+    ///Stored as `name=6,37,31,26;name=...`.
+    pub column_presets: Vec<ColumnPreset>,
+    ///Name of the active preset or [`CUSTOM_PRESET`].
+    pub column_preset: String,
+    pub custom_columns: [u16; 4],
+    pub startup: Startup,
+    ///Was a song playing when gonk was closed.
+    pub playing: bool,
+    pub queue_layout: QueueLayout,
+    ///Guess missing tags from the path when scanning.
+    pub guess_tags: bool,
+    ///Time-stretch instead of resampling when the playback rate changes.
+    pub keep_pitch: bool,
+    ///Keep the playback rate for songs in the same folder.
+    pub remember_rate: bool,
+    ///Seeking past the end of a song carries on into the next one.
+    pub seek_across: bool,
+    pub repeat: RepeatMode,
+    pub replay_gain: ReplayGainMode,
+    ///Seconds of overlap between songs, zero plays them back to back.
+    pub crossfade: u8,
+    ///Gain of each equalizer band in dB, from 31 Hz to 16 kHz.
+    pub equalizer: [f32; 10],
+    ///How long pausing and resuming fade for in milliseconds.
+    pub fade_ms: u32,
+    ///Play both channels in both ears.
+    pub mono: bool,
+    pub browser_sort: BrowserSort,
+    pub volume_curve: VolumeCurve,
+    pub browser_mode: BrowserMode,
     pub queue: Vec<Song>,
+    ///Values that couldn't be read when the settings were loaded.
+    pub errors: Vec<ConfigError>,
+    ///Why the saved queue or the whole file couldn't be read.
+    pub load_error: Option<String>,
     pub file: Option<File>,
 }
 
@@ -32,6 +575,59 @@ impl Serialize for Settings {
         buffer.push_str(&escape(&self.output_device));
         buffer.push('\t');
         buffer.push_str(&escape(&self.music_folder));
+        buffer.push('\t');
+        buffer.push_str(&self.pause_on_device_change.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.normalize.featuring.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.normalize.strip.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.normalize.max_width.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.discord.to_string());
+        buffer.push('\t');
+        let presets: Vec<String> = self
+            .column_presets
+            .iter()
+            .map(|preset| format!("{}={}", preset.name, serialize_widths(&preset.widths)))
+            .collect();
+        buffer.push_str(&presets.join(";"));
+        buffer.push('\t');
+        buffer.push_str(&self.column_preset);
+        buffer.push('\t');
+        buffer.push_str(&serialize_widths(&self.custom_columns));
+        buffer.push('\t');
+        buffer.push_str(self.startup.name());
+        buffer.push('\t');
+        buffer.push_str(&self.playing.to_string());
+        buffer.push('\t');
+        buffer.push_str(self.queue_layout.name());
+        buffer.push('\t');
+        buffer.push_str(&self.guess_tags.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.keep_pitch.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.remember_rate.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.seek_across.to_string());
+        buffer.push('\t');
+        buffer.push_str(self.repeat.name());
+        buffer.push('\t');
+        buffer.push_str(self.replay_gain.name());
+        buffer.push('\t');
+        buffer.push_str(&self.crossfade.to_string());
+        buffer.push('\t');
+        buffer.push_str(&serialize_equalizer(&self.equalizer));
+        buffer.push('\t');
+        buffer.push_str(&self.fade_ms.to_string());
+        buffer.push('\t');
+        buffer.push_str(&self.mono.to_string());
+        buffer.push('\t');
+        buffer.push_str(self.browser_sort.name());
+        buffer.push('\t');
+        buffer.push_str(self.volume_curve.name());
+        buffer.push('\t');
+        buffer.push_str(self.browser_mode.name());
         buffer.push('\n');
         buffer.push_str(&self.queue.serialize());
         buffer
@@ -44,27 +640,69 @@ impl Deserialize for Settings {
     fn deserialize(s: &str) -> Result<Self, Self::Error> {
         let (start, end) = s.split_once('\n').ok_or("Invalid settings")?;
         let split: Vec<&str> = start.split('\t').collect();
-        let music_folder = if split.len() == 4 {
-            String::new()
-        } else {
-            split[4].to_string()
+        let mut errors = Vec::new();
+        //The presets can be edited by hand, a typo keeps the defaults instead of the whole file.
+        let column_presets = match split.get(10).map(|presets| deserialize_presets(presets)) {
+            Some(Ok(presets)) => presets,
+            Some(Err(err)) => {
+                errors.push(ConfigError {
+                    key: "queue.column_presets",
+                    value: split[10].to_string(),
+                    message: err.to_string(),
+                });
+                default_presets()
+            }
+            None => default_presets(),
+        };
+        let playing = match split.get(14).map(|playing| playing.parse::<bool>()) {
+            Some(Ok(playing)) => playing,
+            Some(Err(err)) => {
+                errors.push(ConfigError {
+                    key: "player.playing",
+                    value: split[14].to_string(),
+                    message: format!("{err}: {}", split[14]),
+                });
+                false
+            }
+            None => false,
         };
 
-        let queue = if end.is_empty() {
-            Vec::new()
-        } else {
-            Vec::<Song>::deserialize(end)?
+        //A truncated queue shouldn't lose the settings.
+        let (queue, load_error) = match Vec::<Song>::deserialize(end) {
+            Ok(queue) => (queue, None),
+            Err(err) => (
+                Vec::new(),
+                Some(format!("The saved queue is corrupt: {err}")),
+            ),
         };
 
-        Ok(Self {
-            volume: split[0].parse::<u8>()?,
-            index: split[1].parse::<u16>()?,
-            elapsed: split[2].parse::<f32>()?,
-            output_device: split[3].to_string(),
-            music_folder,
+        let mut settings = Self {
+            index: split.get(1).ok_or("Missing index")?.parse::<usize>()?,
+            elapsed: split.get(2).ok_or("Missing elapsed")?.parse::<f32>()?,
+            column_preset: column_presets[0].name.clone(),
+            column_presets,
+            playing,
             queue,
-            file: None,
-        })
+            load_error,
+            errors,
+            ..Default::default()
+        };
+
+        //A typo in one setting shouldn't reset the others.
+        for (i, key) in COLUMNS {
+            let Some(value) = split.get(i) else {
+                continue;
+            };
+            if let Err(message) = settings.set(key, value) {
+                settings.errors.push(ConfigError {
+                    key,
+                    value: value.to_string(),
+                    message,
+                });
+            }
+        }
+
+        Ok(settings)
     }
 }
 
@@ -76,7 +714,31 @@ impl Default for Settings {
             elapsed: Default::default(),
             output_device: Default::default(),
             music_folder: Default::default(),
+            pause_on_device_change: true,
+            normalize: Normalize::default(),
+            discord: false,
+            column_presets: default_presets(),
+            column_preset: String::from("balanced"),
+            custom_columns: BALANCED,
+            startup: Startup::default(),
+            playing: false,
+            queue_layout: QueueLayout::default(),
+            guess_tags: true,
+            keep_pitch: true,
+            remember_rate: false,
+            seek_across: true,
+            repeat: RepeatMode::default(),
+            replay_gain: ReplayGainMode::default(),
+            crossfade: 0,
+            equalizer: [0.0; 10],
+            fade_ms: 20,
+            mono: false,
+            browser_sort: BrowserSort::default(),
+            volume_curve: VolumeCurve::default(),
+            browser_mode: BrowserMode::default(),
             queue: Default::default(),
+            errors: Vec::new(),
+            load_error: None,
             file: None,
         }
     }
@@ -91,13 +753,200 @@ impl Settings {
             .create(true)
             .open(settings_path())
             .unwrap();
-        let mut string = String::new();
-        file.read_to_string(&mut string)?;
-        let mut settings = Settings::deserialize(&string).unwrap_or_default();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let string = String::from_utf8_lossy(&bytes);
+        let mut settings = match Settings::deserialize(&string) {
+            Ok(settings) => settings,
+            Err(_) if string.is_empty() => Settings::default(),
+            Err(err) => Settings {
+                load_error: Some(format!("Could not read the settings: {err}")),
+                ..Default::default()
+            },
+        };
         settings.file = Some(file);
         Ok(settings)
     }
 
+    ///Take songs that were moved or deleted out of the queue. Returns how many were removed.
+    ///
+    ///If the playing song is gone the next one is played from the start.
+    pub fn remove_missing(&mut self) -> usize {
+        let before = self.queue.len();
+        let mut index = self.index;
+        let mut queue = Vec::with_capacity(before);
+        for (i, song) in std::mem::take(&mut self.queue).into_iter().enumerate() {
+            if Path::new(&song.path).exists() {
+                queue.push(song);
+            } else if i < self.index {
+                index -= 1;
+            } else if i == self.index {
+                self.elapsed = 0.0;
+            }
+        }
+
+        if index >= queue.len() {
+            index = 0;
+            self.elapsed = 0.0;
+        }
+        self.index = index;
+        self.queue = queue;
+        before - self.queue.len()
+    }
+
+    ///Widths of the active column preset.
+    pub fn columns(&self) -> [u16; 4] {
+        self.column_presets
+            .iter()
+            .find(|preset| preset.name == self.column_preset)
+            .map(|preset| preset.widths)
+            .unwrap_or(self.custom_columns)
+    }
+
+    ///Forget the columns that were moved by hand and go back to the default preset.
+    pub fn reset_columns(&mut self) {
+        self.column_preset = String::from("balanced");
+        self.custom_columns = BALANCED;
+    }
+
+    ///Switch to the next column preset and return its name.
+    pub fn next_column_preset(&mut self) -> &str {
+        let next = match self
+            .column_presets
+            .iter()
+            .position(|preset| preset.name == self.column_preset)
+        {
+            Some(i) => (i + 1) % self.column_presets.len(),
+            None => 0,
+        };
+        if let Some(preset) = self.column_presets.get(next) {
+            self.column_preset = preset.name.clone();
+        }
+        &self.column_preset
+    }
+
+    ///Get a setting by name e.g. `player.volume`.
+    pub fn get(&self, key: &str) -> Result<String, String> {
+        Ok(match key {
+            "player.volume" => self.volume.to_string(),
+            "player.output_device" => self.output_device.clone(),
+            "player.pause_on_device_change" => self.pause_on_device_change.to_string(),
+            "player.startup" => self.startup.name().to_string(),
+            "player.keep_pitch" => self.keep_pitch.to_string(),
+            "player.remember_rate" => self.remember_rate.to_string(),
+            "player.seek_across" => self.seek_across.to_string(),
+            "player.repeat" => self.repeat.name().to_string(),
+            "player.replay_gain" => self.replay_gain.name().to_string(),
+            "player.crossfade" => self.crossfade.to_string(),
+            "player.equalizer" => serialize_equalizer(&self.equalizer),
+            "player.fade_ms" => self.fade_ms.to_string(),
+            "player.mono" => self.mono.to_string(),
+            "library.music_folder" => self.music_folder.clone(),
+            "library.guess_tags" => self.guess_tags.to_string(),
+            "normalize.featuring" => self.normalize.featuring.to_string(),
+            "normalize.strip" => self.normalize.strip.to_string(),
+            "normalize.max_width" => self.normalize.max_width.to_string(),
+            "queue.column_preset" => self.column_preset.clone(),
+            "queue.custom_columns" => serialize_widths(&self.custom_columns),
+            "queue.layout" => self.queue_layout.name().to_string(),
+            "browser.sort" => self.browser_sort.name().to_string(),
+            "player.volume_curve" => self.volume_curve.name().to_string(),
+            "browser.mode" => self.browser_mode.name().to_string(),
+            "discord.enabled" => self.discord.to_string(),
+            _ => return Err(format!("Unknown setting: {key}")),
+        })
+    }
+
+    ///Change a setting by name. The value is checked before anything is changed.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+            value
+                .parse::<T>()
+                .map_err(|_| format!("Invalid value: {value}"))
+        }
+
+        match key {
+            "player.volume" => match parse::<u8>(value)? {
+                volume @ 0..=100 => self.volume = volume,
+                volume => return Err(format!("The volume must be between 0 and 100: {volume}")),
+            },
+            "player.output_device" => self.output_device = escape(value).to_string(),
+            "player.pause_on_device_change" => self.pause_on_device_change = parse(value)?,
+            "player.startup" => self.startup = Startup::try_from(value)?,
+            "player.keep_pitch" => self.keep_pitch = parse(value)?,
+            "player.remember_rate" => self.remember_rate = parse(value)?,
+            "player.seek_across" => self.seek_across = parse(value)?,
+            "player.repeat" => self.repeat = RepeatMode::try_from(value)?,
+            "player.replay_gain" => self.replay_gain = ReplayGainMode::try_from(value)?,
+            "player.equalizer" => self.equalizer = deserialize_equalizer(value)?,
+            "player.mono" => self.mono = parse(value)?,
+            "player.fade_ms" => match parse::<u32>(value)? {
+                ms @ 0..=MAX_FADE_MS => self.fade_ms = ms,
+                ms => {
+                    return Err(format!(
+                        "The fade must be between 0 and {MAX_FADE_MS} ms: {ms}"
+                    ))
+                }
+            },
+            "player.crossfade" => match parse::<u8>(value)? {
+                secs @ 0..=MAX_CROSSFADE => self.crossfade = secs,
+                secs => {
+                    return Err(format!(
+                        "The crossfade must be between 0 and {MAX_CROSSFADE} seconds: {secs}"
+                    ))
+                }
+            },
+            "library.music_folder" => self.music_folder = escape(value).to_string(),
+            "library.guess_tags" => self.guess_tags = parse(value)?,
+            "normalize.featuring" => self.normalize.featuring = parse(value)?,
+            "normalize.strip" => self.normalize.strip = parse(value)?,
+            "normalize.max_width" => self.normalize.max_width = parse(value)?,
+            "queue.column_preset" => {
+                if value != CUSTOM_PRESET
+                    && !self
+                        .column_presets
+                        .iter()
+                        .any(|preset| preset.name == value)
+                {
+                    return Err(format!("Unknown column preset: {value}"));
+                }
+                self.column_preset = value.to_string();
+            }
+            "queue.custom_columns" => {
+                self.custom_columns = deserialize_widths(value)
+                    .map_err(|_| format!("Invalid column widths: {value}"))?
+            }
+            "queue.layout" => self.queue_layout = QueueLayout::try_from(value)?,
+            "browser.sort" => self.browser_sort = BrowserSort::try_from(value)?,
+            "player.volume_curve" => self.volume_curve = VolumeCurve::try_from(value)?,
+            "browser.mode" => self.browser_mode = BrowserMode::try_from(value)?,
+            "discord.enabled" => self.discord = parse(value)?,
+            _ => return Err(format!("Unknown setting: {key}")),
+        }
+        self.errors.retain(|error| error.key != key);
+        Ok(())
+    }
+
+    ///Forget the errors of settings that changed since `before` was listed.
+    pub fn clear_changed(&mut self, before: &[(&'static str, String)]) {
+        let after = self.list();
+        //Errors for columns that can't be set stay until the file is saved.
+        self.errors.retain(|error| {
+            before
+                .iter()
+                .zip(&after)
+                .find(|((key, _), _)| *key == error.key)
+                .is_none_or(|((_, old), (_, new))| old == new)
+        });
+    }
+
+    ///Every setting that can be changed with [`Settings::set`].
+    pub fn list(&self) -> Vec<(&'static str, String)> {
+        KEYS.iter()
+            .map(|key| (*key, self.get(key).unwrap()))
+            .collect()
+    }
+
     pub fn save(&self) -> std::io::Result<()> {
         let mut file = self.file.as_ref().unwrap();
         file.set_len(0)?;
@@ -116,4 +965,262 @@ mod tests {
     fn settings() {
         Settings::new().unwrap();
     }
+
+    #[test]
+    fn columns() {
+        assert_eq!(normalize_widths([6, 37, 31, 26]), [6, 37, 31, 26]);
+        assert_eq!(normalize_widths([0, 0, 0, 0]), BALANCED);
+        assert_eq!(normalize_widths([10, 10, 10, 20]), [20, 20, 20, 40]);
+        assert_eq!(normalize_widths([1, 1, 1, 0]), [33, 33, 34, 0]);
+        assert_eq!(normalize_widths([500, 0, 0, 0]), [100, 0, 0, 0]);
+        assert!(deserialize_widths("1,2,3").is_err());
+
+        let mut settings = Settings::default();
+        settings.column_presets[1].widths = [10, 60, 20, 10];
+        settings.custom_columns = [6, 40, 30, 24];
+        settings.column_preset = CUSTOM_PRESET.to_string();
+        assert_eq!(settings.columns(), [6, 40, 30, 24]);
+
+        let mut settings = Settings::deserialize(&settings.serialize()).unwrap();
+        assert_eq!(settings.columns(), [6, 40, 30, 24]);
+        assert_eq!(settings.next_column_preset(), "balanced");
+        assert_eq!(settings.next_column_preset(), "title-focus");
+        assert_eq!(settings.columns(), [10, 60, 20, 10]);
+
+        //Presets edited by hand are scaled to 100.
+        let s = "15\t0\t0\t\t\ttrue\tfalse\tfalse\t0\tfalse\twide=0,50,50,50\twide\t6,37,31,26\n";
+        let settings = Settings::deserialize(s).unwrap();
+        assert_eq!(settings.columns(), [0, 33, 33, 34]);
+    }
+
+    #[test]
+    fn startup() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.startup, Startup::Paused);
+        settings.startup = Startup::ResumeIfPlaying;
+        settings.playing = true;
+
+        let settings = Settings::deserialize(&settings.serialize()).unwrap();
+        assert_eq!(settings.startup, Startup::ResumeIfPlaying);
+        assert!(settings.playing);
+
+        assert!(!Startup::Paused.resume(true));
+        assert!(Startup::Resume.resume(false));
+        assert!(Startup::ResumeIfPlaying.resume(true));
+        assert!(!Startup::ResumeIfPlaying.resume(false));
+        assert_eq!(Startup::ResumeIfPlaying.next(), Startup::Paused);
+
+        //Older settings files don't have a queue layout.
+        let s = "15\t0\t0\t\t\ttrue\tfalse\tfalse\t0\tfalse\tbalanced=6,37,31,26\tbalanced\t6,37,31,26\tpaused\tfalse\n";
+        assert_eq!(
+            Settings::deserialize(s).unwrap().queue_layout,
+            QueueLayout::Auto
+        );
+    }
+
+    #[test]
+    fn config() {
+        let mut settings = Settings::default();
+        settings.set("player.volume", "70").unwrap();
+        assert_eq!(settings.volume, 70);
+        assert_eq!(settings.get("player.volume").unwrap(), "70");
+
+        assert!(settings.set("player.volume", "101").is_err());
+        assert!(settings.set("player.volume", "loud").is_err());
+        assert!(settings.set("player.loudness", "1").is_err());
+        assert!(settings.set("queue.column_preset", "missing").is_err());
+        assert_eq!(settings.volume, 70);
+
+        settings.set("player.startup", "resume").unwrap();
+        settings.set("queue.custom_columns", "10,10,10,20").unwrap();
+        settings.set("queue.column_preset", CUSTOM_PRESET).unwrap();
+        settings.set("queue.layout", "two-line").unwrap();
+        assert!(settings.set("queue.layout", "three-line").is_err());
+        settings.set("library.guess_tags", "false").unwrap();
+        settings.set("player.remember_rate", "true").unwrap();
+        settings.set("player.seek_across", "false").unwrap();
+        settings.set("player.repeat", "one").unwrap();
+        assert!(settings.set("player.repeat", "all").is_err());
+        settings.set("player.replay_gain", "album").unwrap();
+        settings.set("player.crossfade", "4").unwrap();
+        settings.set("player.fade_ms", "50").unwrap();
+        settings.set("player.mono", "true").unwrap();
+        settings.set("browser.sort", "most-played").unwrap();
+        assert!(settings.set("browser.sort", "random").is_err());
+        settings.set("browser.mode", "decade").unwrap();
+        assert!(settings.set("browser.mode", "album").is_err());
+        settings.set("player.volume_curve", "logarithmic").unwrap();
+        assert!(settings.set("player.volume_curve", "log").is_err());
+        assert!(settings.set("player.mono", "left").is_err());
+        assert!(settings.set("player.fade_ms", "5000").is_err());
+        assert!(settings.set("player.crossfade", "13").is_err());
+        settings
+            .set("player.equalizer", "3,2,0,0,0,0,0,0,-1.5,-2")
+            .unwrap();
+        assert!(settings.set("player.equalizer", "3,2").is_err());
+        assert!(settings
+            .set("player.equalizer", "20,0,0,0,0,0,0,0,0,0")
+            .is_err());
+        assert_eq!(settings.startup, Startup::Resume);
+        assert_eq!(settings.columns(), [20, 20, 20, 40]);
+        let settings = Settings::deserialize(&settings.serialize()).unwrap();
+        assert_eq!(settings.queue_layout, QueueLayout::TwoLine);
+        assert!(!settings.guess_tags);
+        assert!(settings.keep_pitch && settings.remember_rate);
+        assert!(!settings.seek_across);
+        assert_eq!(settings.repeat, RepeatMode::One);
+        assert_eq!(settings.replay_gain, ReplayGainMode::Album);
+        assert_eq!(settings.crossfade, 4);
+        assert_eq!(settings.equalizer[8], -1.5);
+        assert_eq!(settings.fade_ms, 50);
+        assert!(settings.mono);
+        assert_eq!(settings.browser_sort, BrowserSort::MostPlayed);
+        assert_eq!(settings.browser_mode, BrowserMode::DecadeAlbum);
+        assert_eq!(settings.volume_curve, VolumeCurve::Logarithmic);
+
+        let list = settings.list();
+        assert_eq!(list.len(), KEYS.len());
+        assert_eq!(list[0], ("player.volume", String::from("70")));
+    }
+
+    #[test]
+    fn validation() {
+        let load = |volume: &str, max_width: &str, preset: &str, startup: &str, layout: &str| {
+            let s = format!("{volume}\t0\t0\tSpeakers\t\tyes\tfalse\tfalse\t{max_width}\tfalse\tbalanced=6,37,31,26\t{preset}\t6,37\t{startup}\tfalse\t{layout}\n");
+            Settings::deserialize(&s).unwrap()
+        };
+        let keys = |settings: &Settings| -> Vec<&str> {
+            settings.errors.iter().map(|error| error.key).collect()
+        };
+
+        //Bad booleans and column widths are reported in every file.
+        let settings = load("70", "10", "balanced", "resume", "auto");
+        assert_eq!(
+            keys(&settings),
+            ["player.pause_on_device_change", "queue.custom_columns"]
+        );
+        assert_eq!(settings.errors[0].value, "yes");
+        assert!(settings.errors[1].message.contains("6,37"));
+
+        //Everything else is still read.
+        assert_eq!(settings.volume, 70);
+        assert_eq!(settings.output_device, "Speakers");
+        assert_eq!(settings.normalize.max_width, 10);
+        assert_eq!(settings.startup, Startup::Resume);
+        assert!(settings.pause_on_device_change);
+        assert_eq!(settings.custom_columns, BALANCED);
+
+        //Numbers out of range or that aren't numbers.
+        let settings = load("150", "-1", "balanced", "resume", "auto");
+        assert_eq!(keys(&settings)[0], "player.volume");
+        assert_eq!(keys(&settings)[2], "normalize.max_width");
+        assert!(settings.errors[0].message.contains("150"));
+        assert_eq!(settings.volume, 15);
+        assert_eq!(
+            load("loud", "0", "balanced", "paused", "auto").errors[0].value,
+            "loud"
+        );
+
+        //Unknown names.
+        let settings = load("15", "0", "wide", "later", "three-line");
+        assert_eq!(
+            keys(&settings)[1..],
+            [
+                "queue.column_preset",
+                "queue.custom_columns",
+                "player.startup",
+                "queue.layout"
+            ]
+        );
+        assert_eq!(settings.column_preset, "balanced");
+        assert_eq!(settings.startup, Startup::Paused);
+        assert_eq!(settings.queue_layout, QueueLayout::Auto);
+
+        //Setting a valid value clears the error.
+        let mut settings = load("150", "0", "balanced", "paused", "auto");
+        settings.set("player.volume", "50").unwrap();
+        assert!(!keys(&settings).contains(&"player.volume"));
+
+        //So does changing it in the app.
+        let before = settings.list();
+        settings.pause_on_device_change = false;
+        settings.clear_changed(&before);
+        assert_eq!(keys(&settings), ["queue.custom_columns"]);
+
+        //Saving writes the fixed values and the defaults of the broken ones.
+        let settings = Settings::deserialize(&settings.serialize()).unwrap();
+        assert!(settings.errors.is_empty());
+        assert!(!settings.pause_on_device_change);
+        assert_eq!(settings.volume, 50);
+
+        //A broken preset or playing state only loses that column.
+        let s = "70\t0\t0\tSpeakers\t\ttrue\tfalse\tfalse\t0\tfalse\tbalanced=6,37,31;wide\tbalanced\t6,37,31,26\tresume\tmaybe\tauto\n";
+        let mut settings = Settings::deserialize(s).unwrap();
+        assert_eq!(keys(&settings), ["queue.column_presets", "player.playing"]);
+        assert!(settings.errors[0].message.contains("column widths"));
+        assert_eq!(settings.errors[1].value, "maybe");
+        assert_eq!(settings.column_presets, default_presets());
+        assert!(!settings.playing);
+        assert_eq!(settings.volume, 70);
+        assert_eq!(settings.output_device, "Speakers");
+        let before = settings.list();
+        settings.volume = 40;
+        settings.clear_changed(&before);
+        assert_eq!(settings.errors.len(), 2);
+    }
+
+    #[test]
+    fn corrupt_queue() {
+        let mut settings = Settings {
+            volume: 40,
+            queue: vec![Song::example()],
+            ..Default::default()
+        };
+        let s = settings.serialize();
+
+        //Cut off in the middle of a song.
+        let truncated = &s[..s.len() - 6];
+        let loaded = Settings::deserialize(truncated).unwrap();
+        assert_eq!(loaded.volume, 40);
+        assert!(loaded.queue.is_empty());
+        assert!(loaded.load_error.is_some());
+
+        settings.queue.clear();
+        let loaded = Settings::deserialize(&settings.serialize()).unwrap();
+        assert!(loaded.load_error.is_none());
+    }
+
+    #[test]
+    fn missing_songs() {
+        let exists = std::env::temp_dir().join("gonk_queue.flac");
+        fs::write(&exists, []).unwrap();
+        let song = |path: &Path| Song {
+            path: path.to_string_lossy().to_string(),
+            ..Song::example()
+        };
+        let missing = Path::new("missing.flac");
+
+        let mut settings = Settings {
+            queue: vec![song(missing), song(&exists), song(missing), song(&exists)],
+            index: 3,
+            elapsed: 12.0,
+            ..Default::default()
+        };
+        assert_eq!(settings.remove_missing(), 2);
+        assert_eq!((settings.queue.len(), settings.index), (2, 1));
+        assert_eq!(settings.elapsed, 12.0);
+
+        //The playing song is gone, play the next one from the start.
+        settings.queue.insert(1, song(missing));
+        settings.index = 1;
+        assert_eq!(settings.remove_missing(), 1);
+        assert_eq!((settings.index, settings.elapsed), (1, 0.0));
+
+        //Nothing after it.
+        settings.queue.push(song(missing));
+        settings.index = 2;
+        settings.elapsed = 5.0;
+        settings.remove_missing();
+        assert_eq!((settings.index, settings.elapsed), (0, 0.0));
+    }
 }
@@ -0,0 +1,61 @@
+//! Songs that end in silence
+//!
+//! Stored in `silence.db` next to the database, one song per line: `path\tsilent`.
+//! A song is only checked the first time it runs into the next track of its album,
+//! scanning would take too long otherwise.
+use crate::{escape, gonk_path, playlist::write_atomic};
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+pub fn silence_path() -> PathBuf {
+    gonk_path().join("silence.db")
+}
+
+///Missing and broken files haven't been checked, a broken line only loses that song.
+pub fn load() -> HashMap<String, bool> {
+    fs::read_to_string(silence_path())
+        .map(|file| deserialize(&file))
+        .unwrap_or_default()
+}
+
+pub fn save(endings: &HashMap<String, bool>) -> io::Result<()> {
+    write_atomic(&silence_path(), serialize(endings).as_bytes())
+}
+
+fn serialize(endings: &HashMap<String, bool>) -> String {
+    let mut paths: Vec<&String> = endings.keys().collect();
+    paths.sort_unstable();
+    let mut buffer = String::new();
+    for path in paths {
+        buffer.push_str(&format!("{}\t{}\n", escape(path), endings[path]));
+    }
+    buffer
+}
+
+fn deserialize(file: &str) -> HashMap<String, bool> {
+    file.lines()
+        .filter_map(|line| {
+            let (path, silent) = line.split_once('\t')?;
+            Some((path.to_string(), silent.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut endings = HashMap::new();
+        endings.insert(String::from("/music/b.flac"), false);
+        endings.insert(String::from("/music/a.flac"), true);
+        let file = serialize(&endings);
+        assert_eq!(file, "/music/a.flac\ttrue\n/music/b.flac\tfalse\n");
+        assert_eq!(deserialize(&file), endings);
+
+        //Broken lines are skipped.
+        let endings = deserialize("a.flac\ttrue\nb.flac\tmaybe\nc.flac\n\nd.flac\tfalse");
+        assert_eq!(endings.len(), 2);
+        assert!(!endings["d.flac"]);
+    }
+}
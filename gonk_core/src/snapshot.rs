@@ -0,0 +1,236 @@
+//! Queue snapshots
+//!
+//! The queue is saved before it's cleared or replaced so it can be restored later.
+//! Only the most recent snapshots are kept.
+use crate::{gonk_path, Deserialize, Serialize, Song};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MAX_SNAPSHOTS: usize = 5;
+
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    ///Milliseconds since the unix epoch.
+    pub time: u64,
+    ///The song that was playing.
+    pub index: Option<usize>,
+    ///Seconds into the song that was playing, zero in older snapshots.
+    pub elapsed: f32,
+    pub songs: Vec<Song>,
+}
+
+impl Serialize for Snapshot {
+    fn serialize(&self) -> String {
+        let mut buffer = String::new();
+        buffer.push_str(&self.time.to_string());
+        buffer.push('\t');
+        if let Some(index) = self.index {
+            buffer.push_str(&index.to_string());
+        }
+        buffer.push('\t');
+        buffer.push_str(&self.elapsed.to_string());
+        buffer.push('\n');
+        buffer.push_str(&self.songs.serialize());
+        buffer
+    }
+}
+
+impl Deserialize for Snapshot {
+    type Error = Box<dyn std::error::Error>;
+
+    fn deserialize(s: &str) -> Result<Self, Self::Error> {
+        let (start, end) = s.split_once('\n').ok_or("Invalid snapshot")?;
+        let mut parts = start.split('\t');
+        let time = parts.next().ok_or("Invalid snapshot")?;
+        let index = parts.next().ok_or("Invalid snapshot")?;
+        let elapsed = parts.next().unwrap_or("0");
+
+        Ok(Self {
+            time: time.parse()?,
+            index: if index.is_empty() {
+                None
+            } else {
+                Some(index.parse()?)
+            },
+            elapsed: elapsed.parse()?,
+            songs: Vec::<Song>::deserialize(end)?,
+        })
+    }
+}
+
+impl Snapshot {
+    ///Remove songs that are no longer on disk.
+    ///
+    ///Returns the songs, the new index of the playing song, where to seek to in it
+    ///and how many were skipped.
+    pub fn resolve(self) -> (Vec<Song>, Option<usize>, f32, usize) {
+        let len = self.songs.len();
+        let mut index = None;
+        let mut songs = Vec::with_capacity(len);
+
+        for (i, song) in self.songs.into_iter().enumerate() {
+            if !Path::new(&song.path).exists() {
+                continue;
+            }
+            if Some(i) == self.index {
+                index = Some(songs.len());
+            }
+            songs.push(song);
+        }
+
+        //The playing song is gone, start from the top.
+        let elapsed = if index.is_some() { self.elapsed } else { 0.0 };
+        if index.is_none() && !songs.is_empty() {
+            index = Some(0);
+        }
+
+        let skipped = len - songs.len();
+        (songs, index, elapsed, skipped)
+    }
+}
+
+fn dir() -> PathBuf {
+    gonk_path().join("snapshots")
+}
+
+///Save the queue and remove the oldest snapshots.
+pub fn save(songs: &[Song], index: Option<usize>, elapsed: f32) -> std::io::Result<()> {
+    save_in(&dir(), songs, index, elapsed)
+}
+
+///Every snapshot, newest first.
+pub fn snapshots() -> Vec<Snapshot> {
+    snapshots_in(&dir())
+}
+
+fn save_in(dir: &Path, songs: &[Song], index: Option<usize>, elapsed: f32) -> std::io::Result<()> {
+    if songs.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(dir)?;
+
+    let mut time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    //Don't overwrite a snapshot taken in the same millisecond.
+    while dir.join(format!("{time}.snapshot")).exists() {
+        time += 1;
+    }
+
+    let snapshot = Snapshot {
+        time,
+        index,
+        elapsed,
+        songs: songs.to_vec(),
+    };
+    fs::write(dir.join(format!("{time}.snapshot")), snapshot.serialize())?;
+
+    for old in files(dir).into_iter().skip(MAX_SNAPSHOTS) {
+        fs::remove_file(old)?;
+    }
+
+    Ok(())
+}
+
+fn snapshots_in(dir: &Path) -> Vec<Snapshot> {
+    files(dir)
+        .into_iter()
+        .flat_map(fs::read_to_string)
+        .flat_map(|string| Snapshot::deserialize(&string))
+        .collect()
+}
+
+///Snapshot files sorted from newest to oldest.
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ex| ex == "snapshot"))
+        .filter_map(|path| {
+            let time = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((time, path))
+        })
+        .collect();
+
+    files.sort_by(|(a, _), (b, _)| b.cmp(a));
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join("gonk_snapshots");
+        let _ = fs::remove_dir_all(&dir);
+
+        for i in 0..7 {
+            let songs = vec![Song::example(); i + 1];
+            save_in(&dir, &songs, Some(i), i as f32).unwrap();
+        }
+        save_in(&dir, &[], None, 0.0).unwrap();
+
+        let snapshots = snapshots_in(&dir);
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+        //Newest first.
+        assert_eq!(snapshots[0].songs.len(), 7);
+        assert_eq!(snapshots[0].index, Some(6));
+        assert_eq!(snapshots[0].elapsed, 6.0);
+        assert_eq!(snapshots[4].songs.len(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve() {
+        let exists = std::env::temp_dir().join("gonk_snapshot_song.flac");
+        fs::write(&exists, []).unwrap();
+
+        let song = |path: &Path| Song {
+            path: path.to_string_lossy().to_string(),
+            ..Song::example()
+        };
+        let missing = Path::new("missing.flac");
+
+        let snapshot = Snapshot {
+            time: 0,
+            index: Some(2),
+            elapsed: 12.5,
+            songs: vec![song(missing), song(&exists), song(&exists), song(missing)],
+        };
+        let string = snapshot.serialize();
+        assert_eq!(Snapshot::deserialize(&string).unwrap(), snapshot);
+
+        //Older snapshots don't have the elapsed time.
+        let old = string.replacen("\t12.5", "", 1);
+        assert_eq!(Snapshot::deserialize(&old).unwrap().elapsed, 0.0);
+
+        let (songs, index, elapsed, skipped) = snapshot.resolve();
+        assert_eq!(songs.len(), 2);
+        assert_eq!(index, Some(1));
+        assert_eq!(elapsed, 12.5);
+        assert_eq!(skipped, 2);
+
+        //The playing song is gone so it starts from the top.
+        let snapshot = Snapshot {
+            time: 0,
+            index: Some(0),
+            elapsed: 12.5,
+            songs: vec![song(missing), song(&exists)],
+        };
+        assert_eq!(snapshot.resolve().2, 0.0);
+
+        fs::remove_file(exists).unwrap();
+    }
+}
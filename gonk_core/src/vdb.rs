@@ -4,10 +4,54 @@
 //!
 //! Also contains code for querying artists, albums and songs.
 //!
-use crate::db::{Album, Song};
-use crate::{database_path, strsim, Deserialize};
-use std::collections::BTreeMap;
-use std::{cmp::Ordering, fs, str::from_utf8_unchecked};
+use crate::db::{Album, Song, UNKNOWN_ALBUM};
+use crate::plays::{self, Plays};
+use crate::{clean, database_path, playlist, strsim, Deserialize, Playlist, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{cmp::Ordering, fs, io, path::Path, str::from_utf8_unchecked};
+
+///Bump when every database needs to be rewritten by `migrate`.
+const VERSION: u32 = 1;
+
+fn read() -> Vec<Song> {
+    let bytes = match fs::read(database_path()) {
+        Ok(bytes) => bytes,
+        Err(error) => match error.kind() {
+            std::io::ErrorKind::NotFound => Vec::new(),
+            _ => panic!("{error}"),
+        },
+    };
+    unsafe { from_utf8_unchecked(&bytes) }
+        .lines()
+        .flat_map(Song::deserialize)
+        .collect()
+}
+
+///Rewrite databases from older versions so the tags match what a new scan would find.
+///
+///The version is stored next to the database so this only runs once.
+pub fn migrate() -> io::Result<()> {
+    let version_path = database_path().with_extension("version");
+    let version = fs::read_to_string(&version_path)
+        .ok()
+        .and_then(|version| version.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+    if version >= VERSION {
+        return Ok(());
+    }
+
+    //Older versions didn't clean the tags when they were scanned.
+    let mut songs = read();
+    let mut cleaned = false;
+    for song in songs.iter_mut() {
+        cleaned |= clean::clean(song);
+    }
+    if cleaned {
+        playlist::write_atomic(database_path(), songs.serialize().as_bytes())?;
+    }
+
+    playlist::write_atomic(&version_path, VERSION.to_string().as_bytes())
+}
 
 #[cfg(test)]
 mod tests {
@@ -19,14 +63,136 @@ mod tests {
         dbg!(db.artists());
         dbg!(db.search("test"));
     }
+
+    #[test]
+    fn stable_order() {
+        let song = |title: &str| Song::test("artist", "album", title);
+        let a = Database::from_songs(vec![song("b"), song("a"), song("c")]);
+        let b = Database::from_songs(vec![song("c"), song("a"), song("b")]);
+
+        let album = a.album("artist", "album").unwrap();
+        assert_eq!(album.songs, b.album("artist", "album").unwrap().songs);
+        assert_eq!(album.songs[0].path, "a.flac");
+
+        //Songs with the same track number are told apart by path.
+        assert_eq!(a.song("artist", "album", "c.flac").unwrap().path, "c.flac");
+        assert!(a.song("artist", "album", "missing.flac").is_none());
+    }
+
+    #[test]
+    fn genres() {
+        let song = |artist: &str, genre: &str| Song {
+            genre: genre.to_string(),
+            ..Song::test(artist, "album", &format!("{artist}{genre}"))
+        };
+        let db = Database::from_songs(vec![
+            song("b", "Rock"),
+            song("a", "jazz"),
+            song("c", "Rock"),
+            song("c", ""),
+            song("d", ""),
+        ]);
+        assert_eq!(db.genres(), ["jazz", "Rock"]);
+        let genre = |genre: &str| Group::Genre(genre.to_string());
+        assert_eq!(db.artists_in(&genre("Rock")), ["b", "c"]);
+        assert_eq!(db.artists_in(&genre("jazz")), ["a"]);
+        assert!(db.artists_in(&genre("missing")).is_empty());
+    }
+
+    #[test]
+    fn years() {
+        let song = |artist: &str, album: &str, year: u16| Song {
+            year,
+            ..Song::test(artist, album, album)
+        };
+        let db = Database::from_songs(vec![
+            song("b", "Later", 1999),
+            song("a", "Earlier", 1991),
+            song("a", "Untagged", 0),
+            song("c", "Next", 2004),
+        ]);
+        assert_eq!(db.years(), [1991, 1999, 2004]);
+        assert_eq!(db.decades(), [1990, 2000]);
+        assert_eq!(db.artists_in(&Group::Year(1999)), ["b"]);
+        assert_eq!(db.artists_in(&Group::Decade(1990)), ["a", "b"]);
+        assert_eq!(Group::Decade(1990).name(), "1990s");
+
+        //Untagged songs aren't in the first decade.
+        assert!(db.artists_in(&Group::Decade(0)).is_empty());
+
+        let albums: Vec<_> = db
+            .albums()
+            .into_iter()
+            .map(|(artist, album)| (artist.as_str(), album.title.as_str()))
+            .collect();
+        assert_eq!(
+            albums,
+            [
+                ("a", "Earlier"),
+                ("b", "Later"),
+                ("c", "Next"),
+                ("a", "Untagged")
+            ]
+        );
+    }
+
+    #[test]
+    fn plays() {
+        let mut db = Database::from_songs(vec![
+            Song::test("a", "album", "1"),
+            Song::test("a", "album", "2"),
+            Song::test("b", "album", "3"),
+        ]);
+        let played = |count, last_played| Plays { count, last_played };
+        db.plays.insert(String::from("1.flac"), played(2, 100));
+        db.plays.insert(String::from("2.flac"), played(1, 300));
+        db.plays.insert(String::from("3.flac"), played(5, 200));
+        //Not in the database any more.
+        db.plays.insert(String::from("4.flac"), played(9, 400));
+
+        assert_eq!(db.artist_plays("a"), played(3, 300));
+        assert_eq!(db.artist_plays("missing"), Plays::default());
+        let recent: Vec<_> = db
+            .recently_played(2)
+            .into_iter()
+            .map(|song| song.path)
+            .collect();
+        assert_eq!(recent, ["2.flac", "3.flac"]);
+        assert_eq!(db.recently_played(10).len(), 3);
+    }
+
+    #[test]
+    fn unknown_albums() {
+        let song = |path: &str| Song {
+            path: path.to_string(),
+            ..Song::default()
+        };
+        let db = Database::from_songs(vec![
+            song("/downloads/a.mp3"),
+            song("/music/loose/b.mp3"),
+            song("/music/loose/c.mp3"),
+        ]);
+        let albums = db.albums_by_artist(crate::db::UNKNOWN_ARTIST);
+        assert_eq!(albums.len(), 2);
+        assert_eq!(albums[0].title, "Unknown Album (downloads)");
+        assert_eq!(albums[1].title, "Unknown Album (loose)");
+        assert_eq!(albums[1].songs.len(), 2);
+
+        //Missing artists and albums are empty instead of panicking.
+        assert!(db.albums_by_artist("missing").is_empty());
+        assert!(db.album(crate::db::UNKNOWN_ARTIST, "missing").is_none());
+        assert_eq!(db.artist_count, 1);
+        assert_eq!(db.album_count, 2);
+        assert_eq!(db.len, 3);
+    }
 }
 
 const MIN_ACCURACY: f64 = 0.70;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Item {
-    ///(Artist, Album, Name, Disc Number, Track Number)
-    Song((String, String, String, u8, u8)),
+    ///(Artist, Album, Name, Disc Number, Track Number, Path)
+    Song((String, String, String, u8, u8, String)),
     ///(Artist, Album)
     Album((String, String)),
     ///(Artist)
@@ -38,7 +204,7 @@ fn jaro(query: &str, input: Item) -> Result<(Item, f64), (Item, f64)> {
     let str = match input {
         Item::Artist(ref artist) => artist,
         Item::Album((_, ref album)) => album,
-        Item::Song((_, _, ref song, _, _)) => song,
+        Item::Song((_, _, ref song, _, _, _)) => song,
     };
     let acc = strsim::jaro_winkler(query, &str.to_lowercase());
     if acc > MIN_ACCURACY {
@@ -48,48 +214,88 @@ fn jaro(query: &str, input: Item) -> Result<(Item, f64), (Item, f64)> {
     }
 }
 
+///Songs that share a tag, the browser can be narrowed down to one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Group {
+    Genre(String),
+    Year(u16),
+    ///Starts from the first year of the decade.
+    Decade(u16),
+}
+
+impl Group {
+    pub fn contains(&self, song: &Song) -> bool {
+        match self {
+            Group::Genre(genre) => song.genre == *genre,
+            Group::Year(year) => song.year == *year,
+            Group::Decade(decade) => song.year != 0 && song.year / 10 * 10 == *decade,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Group::Genre(genre) => genre.clone(),
+            Group::Year(year) => year.to_string(),
+            Group::Decade(decade) => format!("{decade}s"),
+        }
+    }
+}
+
 //I feel like Box<[String, Box<Album>]> might have been a better choice.
 pub struct Database {
     btree: BTreeMap<String, Vec<Album>>,
+    ///Paths of the songs in the liked playlist.
+    liked: HashSet<String>,
+    ///Play counts by path, songs that were never played aren't in here.
+    plays: HashMap<String, Plays>,
     pub len: usize,
+    ///Counted when the database is built so they aren't recounted every frame.
+    pub artist_count: usize,
+    pub album_count: usize,
 }
 
 impl Database {
     ///Read the database from disk and load it into memory.
     pub fn new() -> Self {
         mini::profile!();
-        let bytes = match fs::read(database_path()) {
-            Ok(bytes) => bytes,
-            Err(error) => match error.kind() {
-                std::io::ErrorKind::NotFound => Vec::new(),
-                _ => panic!("{error}"),
-            },
-        };
-        let songs: Vec<Song> = unsafe { from_utf8_unchecked(&bytes) }
-            .lines()
-            .flat_map(Song::deserialize)
-            .collect();
+        let mut db = Self::from_songs(read());
+        db.liked = playlist::liked();
+        db.plays = plays::load();
+        db
+    }
 
+    ///Build the database from songs that are already in memory.
+    pub fn from_songs(songs: Vec<Song>) -> Self {
         let len = songs.len();
         let mut btree: BTreeMap<String, Vec<Album>> = BTreeMap::new();
         let mut albums: BTreeMap<(String, String), Vec<Song>> = BTreeMap::new();
 
         //Add songs to albums.
-        for song in songs.into_iter() {
+        for mut song in songs.into_iter() {
+            //Untagged songs are split by folder instead of being one album.
+            if song.album == UNKNOWN_ALBUM {
+                let folder = Path::new(&song.path)
+                    .parent()
+                    .and_then(|path| path.file_name()?.to_str());
+                if let Some(folder) = folder {
+                    song.album = format!("{UNKNOWN_ALBUM} ({folder})");
+                }
+            }
+
             albums
                 .entry((song.artist.clone(), song.album.clone()))
                 .or_default()
                 .push(song);
         }
 
-        //Sort songs.
+        //Sort songs. Songs with the same track number are ordered by path so rescans don't shuffle them.
         albums.iter_mut().for_each(|(_, album)| {
             album.sort_unstable_by(|a, b| {
-                if a.disc_number == b.disc_number {
-                    a.track_number.cmp(&b.track_number)
-                } else {
-                    a.disc_number.cmp(&b.disc_number)
-                }
+                (a.disc_number, a.track_number, &a.path).cmp(&(
+                    b.disc_number,
+                    b.track_number,
+                    &b.path,
+                ))
             });
         });
 
@@ -106,7 +312,79 @@ impl Database {
             albums.sort_unstable_by_key(|album| album.title.to_ascii_lowercase());
         });
 
-        Self { btree, len }
+        Self {
+            artist_count: btree.len(),
+            album_count: btree.values().map(Vec::len).sum(),
+            btree,
+            liked: HashSet::new(),
+            plays: HashMap::new(),
+            len,
+        }
+    }
+
+    ///Every song, sorted by artist then album.
+    pub fn songs(&self) -> impl Iterator<Item = &Song> {
+        self.btree
+            .values()
+            .flatten()
+            .flat_map(|album| album.songs.iter())
+    }
+
+    pub fn is_liked(&self, path: &str) -> bool {
+        self.liked.contains(path)
+    }
+
+    ///Update the liked songs after the playlist changed.
+    pub fn set_liked(&mut self, liked: &Playlist) {
+        self.liked = liked.songs.iter().map(|song| song.path.clone()).collect();
+    }
+
+    ///Count a play of the song and save the counts straight away.
+    pub fn increment_play_count(&mut self, path: &Path) -> io::Result<()> {
+        let entry = self
+            .plays
+            .entry(path.to_string_lossy().to_string())
+            .or_default();
+        *entry = entry.add(Plays {
+            count: 1,
+            last_played: plays::now(),
+        });
+        plays::save(&self.plays)
+    }
+
+    pub fn plays(&self, path: &str) -> Plays {
+        self.plays.get(path).copied().unwrap_or_default()
+    }
+
+    ///Plays of every song by the artist.
+    pub fn artist_plays(&self, artist: &str) -> Plays {
+        self.albums_by_artist(artist)
+            .iter()
+            .map(|album| self.album_plays(album))
+            .fold(Plays::default(), Plays::add)
+    }
+
+    pub fn album_plays(&self, album: &Album) -> Plays {
+        album
+            .songs
+            .iter()
+            .map(|song| self.plays(&song.path))
+            .fold(Plays::default(), Plays::add)
+    }
+
+    ///The songs played most recently, newest first.
+    pub fn recently_played(&self, limit: usize) -> Vec<Song> {
+        let mut songs: Vec<(&Song, u64)> = self
+            .songs()
+            .map(|song| (song, self.plays(&song.path).last_played))
+            .filter(|(_, last_played)| *last_played > 0)
+            .collect();
+        songs.sort_by(|(_, a), (_, b)| b.cmp(a));
+        songs
+            .into_iter()
+            .take(limit)
+            .map(|(song, _)| song.clone())
+            .collect()
     }
 
     ///Get all artist names.
@@ -116,35 +394,76 @@ impl Database {
         v
     }
 
+    ///Every genre that's tagged, sorted by name.
+    pub fn genres(&self) -> Vec<&String> {
+        let mut v: Vec<_> = self.songs().map(|song| &song.genre).collect();
+        v.retain(|genre| !genre.is_empty());
+        v.sort_unstable_by(|a, b| (a.to_ascii_lowercase(), a).cmp(&(b.to_ascii_lowercase(), b)));
+        v.dedup();
+        v
+    }
+
+    ///Every year that's tagged, oldest first.
+    pub fn years(&self) -> Vec<u16> {
+        let mut v: Vec<_> = self.songs().map(|song| song.year).collect();
+        v.retain(|year| *year != 0);
+        v.sort_unstable();
+        v.dedup();
+        v
+    }
+
+    ///Every decade with a tagged year, oldest first.
+    pub fn decades(&self) -> Vec<u16> {
+        let mut v: Vec<_> = self.years().iter().map(|year| year / 10 * 10).collect();
+        v.dedup();
+        v
+    }
+
+    ///Artists with at least one song in the group.
+    pub fn artists_in(&self, group: &Group) -> Vec<&String> {
+        self.artists()
+            .into_iter()
+            .filter(|artist| {
+                self.btree[*artist]
+                    .iter()
+                    .any(|album| album.songs.iter().any(|song| group.contains(song)))
+            })
+            .collect()
+    }
+
+    ///Every album and its artist, sorted by title.
+    pub fn albums(&self) -> Vec<(&String, &Album)> {
+        let mut albums: Vec<_> = self
+            .btree
+            .iter()
+            .flat_map(|(artist, albums)| albums.iter().map(move |album| (artist, album)))
+            .collect();
+        albums.sort_by_key(|(_, album)| album.title.to_ascii_lowercase());
+        albums
+    }
+
     ///Get all albums by an artist.
+    ///
+    ///Empty if the artist was removed since the list was built.
     pub fn albums_by_artist(&self, artist: &str) -> &[Album] {
-        self.btree.get(artist).unwrap()
+        self.btree.get(artist).map_or(&[], Vec::as_slice)
     }
 
     ///Get an album by artist and album name.
-    pub fn album(&self, artist: &str, album: &str) -> &Album {
-        if let Some(albums) = self.btree.get(artist) {
-            for al in albums {
-                if album == al.title {
-                    return al;
-                }
-            }
-        }
-        panic!("Could not find album {} {}", artist, album);
+    pub fn album(&self, artist: &str, album: &str) -> Option<&Album> {
+        self.btree.get(artist)?.iter().find(|al| al.title == album)
     }
 
-    ///Get an individual song in the database.
-    pub fn song(&self, artist: &str, album: &str, disc: u8, number: u8) -> &Song {
-        for al in self.btree.get(artist).unwrap() {
-            if al.title == album {
-                for song in &al.songs {
-                    if song.disc_number == disc && song.track_number == number {
-                        return song;
-                    }
-                }
-            }
-        }
-        unreachable!();
+    ///Find a song by its path, which is what identifies a song across rescans.
+    ///
+    ///Returns `None` if the song was removed since the item was created.
+    pub fn song(&self, artist: &str, album: &str, path: &str) -> Option<&Song> {
+        self.btree
+            .get(artist)?
+            .iter()
+            .filter(|al| al.title == album)
+            .flat_map(|al| al.songs.iter())
+            .find(|song| song.path == path)
     }
 
     ///Search the database and return the 25 most accurate matches.
@@ -165,6 +484,7 @@ impl Database {
                             song.title.clone(),
                             song.disc_number,
                             song.track_number,
+                            song.path.clone(),
                         )),
                     ));
                 }
@@ -211,8 +531,8 @@ impl Database {
                         Item::Album(_) => Ordering::Equal,
                         Item::Artist(_) => Ordering::Greater,
                     },
-                    Item::Song((_, _, _, disc_a, number_a)) => match item_2 {
-                        Item::Song((_, _, _, disc_b, number_b)) => match disc_a.cmp(disc_b) {
+                    Item::Song((_, _, _, disc_a, number_a, _)) => match item_2 {
+                        Item::Song((_, _, _, disc_b, number_b, _)) => match disc_a.cmp(disc_b) {
                             Ordering::Less => Ordering::Less,
                             Ordering::Equal => number_a.cmp(number_b),
                             Ordering::Greater => Ordering::Greater,
@@ -0,0 +1,85 @@
+//! Waveform cache
+//!
+//! Overviews are saved in the gonk folder so each song is only decoded once.
+//! A song is identified by its path and modification time, so changed files are decoded again.
+use crate::gonk_path;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+///Number of levels in an overview.
+pub const BUCKETS: usize = 200;
+
+fn dir() -> PathBuf {
+    gonk_path().join("waveforms")
+}
+
+///FNV-1a, the name of a cached file can't change between versions.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn key(song: &Path) -> Option<String> {
+    let modified = fs::metadata(song)
+        .ok()?
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    let mut bytes = song.to_string_lossy().into_owned().into_bytes();
+    bytes.extend_from_slice(&modified.to_le_bytes());
+    Some(format!("{:016x}", hash(&bytes)))
+}
+
+pub fn load(song: &Path) -> Option<Vec<u8>> {
+    load_in(&dir(), song)
+}
+
+pub fn save(song: &Path, levels: &[u8]) -> io::Result<()> {
+    save_in(&dir(), song, levels)
+}
+
+fn load_in(dir: &Path, song: &Path) -> Option<Vec<u8>> {
+    let levels = fs::read(dir.join(key(song)?)).ok()?;
+    (levels.len() == BUCKETS).then_some(levels)
+}
+
+fn save_in(dir: &Path, song: &Path, levels: &[u8]) -> io::Result<()> {
+    let key = key(song).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(key), levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache() {
+        let dir = std::env::temp_dir().join("gonk_waveform_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let song = dir.join("song.flac");
+        fs::write(&song, "").unwrap();
+
+        assert_eq!(load_in(&dir, &song), None);
+        let levels: Vec<u8> = (0..BUCKETS).map(|i| i as u8).collect();
+        save_in(&dir, &song, &levels).unwrap();
+        assert_eq!(load_in(&dir, &song).unwrap(), levels);
+
+        //Different songs don't share an overview.
+        let other = dir.join("other.flac");
+        fs::write(&other, "").unwrap();
+        assert_eq!(load_in(&dir, &other), None);
+        assert!(save_in(&dir, &dir.join("missing.flac"), &levels).is_err());
+
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+//! Convert between channel counts.
+//!
+//! Songs are converted to stereo as they're decoded, then stereo is mapped onto the device.
+//! Channels are in the WAVE order: front left, front right, centre, LFE, back left, back right...
+use std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelConverter {
+    from: usize,
+    to: usize,
+    ///Keeps a downmix from clipping when every channel is loud.
+    norm: f32,
+}
+
+impl ChannelConverter {
+    pub fn new(from: usize, to: usize) -> Self {
+        let from = from.max(1);
+        let (left, right) = (0..from)
+            .map(|i| weights(from, i))
+            .fold((0.0, 0.0), |(l, r), (a, b)| (l + a, r + b));
+        Self {
+            from,
+            to: to.max(1),
+            norm: left.max(right).max(1.0),
+        }
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.from == self.to
+    }
+
+    ///Append the converted frames of `input` to `output`.
+    pub fn convert(&self, input: &[f32], output: &mut Vec<f32>) {
+        for frame in input.chunks_exact(self.from) {
+            let start = output.len();
+            output.resize(start + self.to, 0.0);
+            self.frame(frame, &mut output[start..]);
+        }
+    }
+
+    ///Convert a single frame, extra output channels are silent.
+    pub fn frame(&self, input: &[f32], output: &mut [f32]) {
+        output.fill(0.0);
+        match (self.from, self.to) {
+            (from, to) if from == to => output.copy_from_slice(input),
+            (1, _) => {
+                output[0] = input[0];
+                output[1] = input[0];
+            }
+            (_, 1) => {
+                let (l, r) = self.downmix(input);
+                output[0] = (l + r) / 2.0;
+            }
+            (from, to) if from < to => output[..from].copy_from_slice(input),
+            _ => (output[0], output[1]) = self.downmix(input),
+        }
+    }
+
+    fn downmix(&self, input: &[f32]) -> (f32, f32) {
+        let (l, r) = input
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                let (l, r) = weights(self.from, i);
+                (sample * l, sample * r)
+            })
+            .fold((0.0, 0.0), |(l, r), (a, b)| (l + a, r + b));
+        (l / self.norm, r / self.norm)
+    }
+}
+
+///How much of a channel goes to the left and right when downmixing to stereo.
+fn weights(channels: usize, index: usize) -> (f32, f32) {
+    match (channels, index) {
+        (1, _) => (1.0, 1.0),
+        (_, 0) => (1.0, 0.0),
+        (_, 1) => (0.0, 1.0),
+        //Quadraphonic doesn't have a centre or LFE.
+        (4, 2) => (FRAC_1_SQRT_2, 0.0),
+        (4, 3) => (0.0, FRAC_1_SQRT_2),
+        (_, 2) => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+        //The LFE is left out, speakers play the bass anyway.
+        (_, 3) => (0.0, 0.0),
+        (_, i) if i % 2 == 0 => (FRAC_1_SQRT_2, 0.0),
+        _ => (0.0, FRAC_1_SQRT_2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(from: usize, to: usize, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        ChannelConverter::new(from, to).convert(input, &mut output);
+        output
+    }
+
+    #[test]
+    fn mono() {
+        assert_eq!(convert(1, 2, &[0.5, -0.25]), [0.5, 0.5, -0.25, -0.25]);
+        assert_eq!(convert(1, 4, &[0.5]), [0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(convert(2, 1, &[0.5, 0.25]), [0.375]);
+    }
+
+    #[test]
+    fn extra_channels() {
+        assert_eq!(
+            convert(2, 4, &[0.1, 0.2, 0.3, 0.4]),
+            [0.1, 0.2, 0.0, 0.0, 0.3, 0.4, 0.0, 0.0]
+        );
+        assert!(ChannelConverter::new(2, 2).is_passthrough());
+        assert_eq!(convert(2, 2, &[0.1, 0.2]), [0.1, 0.2]);
+        //A partial frame is dropped.
+        assert_eq!(convert(2, 4, &[0.1, 0.2, 0.3]).len(), 4);
+    }
+
+    #[test]
+    fn downmix() {
+        //Front left only.
+        let left = convert(6, 2, &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert!(left[0] > 0.0 && left[1] == 0.0);
+
+        //The centre is split evenly and the LFE is dropped.
+        let centre = convert(6, 2, &[0.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(centre[0], centre[1]);
+
+        //Every channel at full scale doesn't clip.
+        let loud = convert(6, 2, &[1.0; 6]);
+        assert!((loud[0] - 1.0).abs() < 1e-6, "{loud:?}");
+        assert!((loud[1] - 1.0).abs() < 1e-6, "{loud:?}");
+
+        //Frames are converted separately.
+        assert_eq!(convert(6, 2, &[0.0; 12]), [0.0; 4]);
+    }
+}
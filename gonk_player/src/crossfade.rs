@@ -0,0 +1,165 @@
+//! Mix the end of a song with the start of the next one.
+//!
+//! The outgoing song is faded out while the incoming one is faded in, both ramps are linear
+//! and add up to one so the level stays about the same.
+use crate::{channels::ChannelConverter, decoder::Symphonia};
+use std::{path::Path, time::Duration};
+
+///Quieter than this counts as silence, about -50 dBFS.
+const SILENCE: f32 = 0.003;
+///How far from the end the silence check starts.
+///
+///The decoder stops 250ms before the end, so this leaves about 250ms to check.
+const TAIL: Duration = Duration::from_millis(500);
+
+pub struct Crossfade {
+    ///The incoming song.
+    pub next: Symphonia,
+    ///Decoded samples of the incoming song that haven't been mixed yet.
+    incoming: Vec<f32>,
+    ///The incoming song is converted to stereo before it's mixed.
+    converter: ChannelConverter,
+    ///Frames mixed so far.
+    frame: usize,
+    ///Length of the ramp in frames.
+    total: usize,
+    ///Gain of the incoming song relative to the outgoing one.
+    ///
+    ///The output thread only applies one gain at a time.
+    ratio: f32,
+}
+
+impl Crossfade {
+    ///Fade over what's left of the current song.
+    pub fn new(next: Symphonia, remaining: Duration, ratio: f32) -> Self {
+        let total = (remaining.as_secs_f64() * next.sample_rate() as f64) as usize;
+        Self {
+            converter: ChannelConverter::new(next.channels(), 2),
+            next,
+            incoming: Vec::new(),
+            frame: 0,
+            total,
+            ratio,
+        }
+    }
+
+    ///Mix a packet of the outgoing song with the same amount of the incoming one.
+    pub fn mix(&mut self, outgoing: &[f32]) -> Vec<f32> {
+        while self.incoming.len() < outgoing.len() {
+            let Some(packet) = self.next.next_packet() else {
+                break;
+            };
+            let start = self.incoming.len();
+            self.converter.convert(packet.samples(), &mut self.incoming);
+            for sample in &mut self.incoming[start..] {
+                *sample *= self.ratio;
+            }
+        }
+
+        let mixed = mix(outgoing, &self.incoming, self.frame, self.total);
+        let used = outgoing.len().min(self.incoming.len());
+        self.incoming.drain(..used);
+        //Assumes the decoded samples are stereo.
+        self.frame += outgoing.len() / 2;
+        mixed
+    }
+
+    ///The incoming song and the samples that were decoded but not mixed.
+    pub fn finish(self) -> (Symphonia, Vec<f32>) {
+        (self.next, self.incoming)
+    }
+}
+
+///How long the fade is for a song of `duration`.
+///
+///Short songs are only faded for half their length so they can still be heard.
+pub fn window(crossfade: f32, duration: Duration) -> Duration {
+    Duration::from_secs_f32(crossfade.max(0.0)).min(duration / 2)
+}
+
+///Decode the end of a song and check if it fades out.
+///
+///Albums mastered to play without a gap, like live albums and mixes, run straight into the next track.
+pub fn ends_in_silence(path: &Path) -> Option<bool> {
+    let mut sym = Symphonia::new(path).ok()?;
+    sym.seek(sym.duration().saturating_sub(TAIL).as_secs_f32());
+    let mut samples = Vec::new();
+    while let Some(packet) = sym.next_packet() {
+        samples.extend_from_slice(packet.samples());
+    }
+    Some(is_silent(&samples))
+}
+
+fn is_silent(samples: &[f32]) -> bool {
+    if samples.is_empty() {
+        return true;
+    }
+    let square: f32 = samples.iter().map(|sample| sample * sample).sum();
+    (square / samples.len() as f32).sqrt() < SILENCE
+}
+
+///Gain of the outgoing and incoming song at `frame`.
+fn ramp(frame: usize, total: usize) -> (f32, f32) {
+    if total == 0 {
+        return (0.0, 1.0);
+    }
+    let t = (frame as f32 / total as f32).min(1.0);
+    (1.0 - t, t)
+}
+
+fn mix(outgoing: &[f32], incoming: &[f32], frame: usize, total: usize) -> Vec<f32> {
+    outgoing
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            //Assumes the decoded samples are stereo.
+            let (fade_out, fade_in) = ramp(frame + i / 2, total);
+            let incoming = incoming.get(i).copied().unwrap_or_default();
+            sample * fade_out + incoming * fade_in
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps() {
+        assert_eq!(ramp(0, 4), (1.0, 0.0));
+        assert_eq!(ramp(2, 4), (0.5, 0.5));
+        assert_eq!(ramp(4, 4), (0.0, 1.0));
+        //The outgoing song ran longer than its duration said.
+        assert_eq!(ramp(9, 4), (0.0, 1.0));
+        assert_eq!(ramp(0, 0), (0.0, 1.0));
+
+        //Left and right are faded together.
+        let mixed = mix(&[1.0; 8], &[0.0; 8], 0, 4);
+        assert_eq!(mixed, [1.0, 1.0, 0.75, 0.75, 0.5, 0.5, 0.25, 0.25]);
+
+        //The incoming song is silent past its end.
+        let mixed = mix(&[0.0; 4], &[1.0; 2], 2, 4);
+        assert_eq!(mixed, [0.5, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn silence() {
+        assert!(is_silent(&[]));
+        assert!(is_silent(&[0.0; 64]));
+        assert!(is_silent(&[0.001, -0.002, 0.001, 0.0]));
+        assert!(!is_silent(&[0.5, -0.5, 0.25, -0.25]));
+        //A single click in a quiet ending isn't enough.
+        let mut ending = [0.0; 1000];
+        ending[500] = 0.05;
+        assert!(is_silent(&ending));
+    }
+
+    #[test]
+    fn windows() {
+        let secs = Duration::from_secs;
+        assert_eq!(window(5.0, secs(200)), secs(5));
+        assert_eq!(window(0.0, secs(200)), secs(0));
+        //Clamped to half of a short song.
+        assert_eq!(window(5.0, secs(6)), secs(3));
+    }
+}
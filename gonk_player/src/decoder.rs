@@ -1,9 +1,13 @@
 //! Decoder for audio files.
+//!
+//! Packets are read ahead of the decoder on a background thread so slow storage doesn't stall playback.
+use std::collections::VecDeque;
 use std::io::ErrorKind;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::time::Duration;
-use std::{fs::File, path::Path};
+use std::{fs::File, path::Path, thread};
 use symphonia::core::errors::Error;
-use symphonia::core::formats::{FormatReader, Track};
+use symphonia::core::formats::{FormatReader, Packet, Track};
 use symphonia::{
     core::{
         audio::SampleBuffer,
@@ -17,8 +21,31 @@ use symphonia::{
     default::get_probe,
 };
 
+///Number of packets read ahead of the decoder, about 1.5 MB for FLAC.
+pub const PREBUFFER: usize = 32;
+
+///Packets shared between the decoder and the reader thread.
+struct Buffer {
+    packets: VecDeque<Result<Packet, Error>>,
+    ///Seek requested by the decoder, packets read before it are discarded.
+    seek: Option<SeekTo>,
+    ///The decoder was dropped.
+    closed: bool,
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    condvar: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
 pub struct Symphonia {
-    pub format_reader: Box<dyn FormatReader>,
+    shared: Arc<Shared>,
     pub decoder: Box<dyn codecs::Decoder>,
     pub track: Track,
     pub elapsed: u64,
@@ -29,6 +56,13 @@ pub struct Symphonia {
 
 impl Symphonia {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_prebuffer(path, PREBUFFER)
+    }
+    ///Read up to `packets` packets ahead of the decoder.
+    pub fn new_with_prebuffer<P: AsRef<Path>>(
+        path: P,
+        packets: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let file = File::open(path)?;
         let mss = MediaSourceStream::new(Box::new(file), Default::default());
         let probed = get_probe().format(
@@ -48,8 +82,20 @@ impl Symphonia {
         let decoder = symphonia::default::get_codecs()
             .make(&track.codec_params, &codecs::DecoderOptions::default())?;
 
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer {
+                packets: VecDeque::with_capacity(packets.max(1)),
+                seek: None,
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+        });
+        let reader = shared.clone();
+        let format_reader = probed.format;
+        thread::spawn(move || read_ahead(format_reader, &reader, packets.max(1)));
+
         Ok(Self {
-            format_reader: probed.format,
+            shared,
             decoder,
             track,
             duration,
@@ -71,20 +117,42 @@ impl Symphonia {
     pub fn sample_rate(&self) -> u32 {
         self.track.codec_params.sample_rate.unwrap()
     }
+    pub fn channels(&self) -> usize {
+        self.track
+            .codec_params
+            .channels
+            .map_or(2, |channels| channels.count())
+    }
     //TODO: I would like seeking out of bounds to play the next song.
     //I can't trust symphonia to provide accurate errors so it's not worth the hassle.
     //I could use pos + elapsed > duration but the duration isn't accurate.
     pub fn seek(&mut self, pos: f32) {
         let pos = Duration::from_secs_f32(pos);
 
-        //Ignore errors.
-        let _ = self.format_reader.seek(
-            SeekMode::Coarse,
-            SeekTo::Time {
-                time: Time::new(pos.as_secs(), pos.subsec_nanos() as f64 / 1_000_000_000.0),
-                track_id: None,
-            },
-        );
+        //The reader thread seeks and throws away anything it read before.
+        let mut buffer = self.shared.lock();
+        buffer.packets.clear();
+        buffer.seek = Some(SeekTo::Time {
+            time: Time::new(pos.as_secs(), pos.subsec_nanos() as f64 / 1_000_000_000.0),
+            track_id: None,
+        });
+        self.shared.condvar.notify_all();
+    }
+
+    ///Take the next packet from the buffer, blocking until one has been read.
+    fn read_packet(&mut self) -> Result<Packet, Error> {
+        let mut buffer = self.shared.lock();
+        loop {
+            if let Some(packet) = buffer.packets.pop_front() {
+                self.shared.condvar.notify_all();
+                return packet;
+            }
+            buffer = self
+                .shared
+                .condvar
+                .wait(buffer)
+                .unwrap_or_else(|err| err.into_inner());
+        }
     }
 
     pub fn next_packet(&mut self) -> Option<SampleBuffer<f32>> {
@@ -92,7 +160,7 @@ impl Symphonia {
             return None;
         }
 
-        let next_packet = match self.format_reader.next_packet() {
+        let next_packet = match self.read_packet() {
             Ok(next_packet) => {
                 self.error_count = 0;
                 next_packet
@@ -141,3 +209,50 @@ impl Symphonia {
         }
     }
 }
+
+impl Drop for Symphonia {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.condvar.notify_all();
+    }
+}
+
+///Keep the buffer full until the decoder is dropped.
+fn read_ahead(mut format_reader: Box<dyn FormatReader>, shared: &Shared, capacity: usize) {
+    let mut buffer = shared.lock();
+    loop {
+        if buffer.closed {
+            return;
+        }
+
+        if let Some(seek) = buffer.seek.take() {
+            drop(buffer);
+            //Ignore errors.
+            let _ = format_reader.seek(SeekMode::Coarse, seek);
+            buffer = shared.lock();
+            buffer.packets.clear();
+            continue;
+        }
+
+        if buffer.packets.len() >= capacity {
+            buffer = shared
+                .condvar
+                .wait(buffer)
+                .unwrap_or_else(|err| err.into_inner());
+            continue;
+        }
+
+        //Don't hold the lock while waiting on the disk.
+        drop(buffer);
+        let packet = format_reader.next_packet();
+        buffer = shared.lock();
+
+        //A seek came in while reading, this packet is from the old position.
+        if buffer.seek.is_some() {
+            continue;
+        }
+
+        buffer.packets.push_back(packet);
+        shared.condvar.notify_all();
+    }
+}
@@ -0,0 +1,177 @@
+//! Ten band graphic equalizer.
+//!
+//! Each band is a peaking biquad filter from the Audio EQ Cookbook.
+//! Bands at 0 dB are skipped so a flat equalizer doesn't touch the samples.
+
+///Centre frequencies in Hz, an octave apart.
+pub const BANDS: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+///Largest boost or cut in dB.
+pub use gonk_core::settings::MAX_EQ_GAIN as MAX_GAIN;
+
+///About one octave wide.
+const Q: f32 = 1.41;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    ///None if the band is flat or too close to the Nyquist frequency to be filtered.
+    fn peaking(frequency: f32, gain: f32, sample_rate: u32) -> Option<Self> {
+        if gain == 0.0 || frequency >= sample_rate as f32 * 0.45 {
+            return None;
+        }
+        let a = 10.0f32.powf(gain / 40.0);
+        let w0 = std::f32::consts::TAU * frequency / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * Q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha / a;
+        Some(Self {
+            b0: (1.0 + alpha * a) / a0,
+            b1: -2.0 * cos / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha / a) / a0,
+        })
+    }
+}
+
+struct Band {
+    gain: f32,
+    coefficients: Option<Coefficients>,
+    ///Two delays for each channel.
+    state: Vec<[f32; 2]>,
+}
+
+pub struct Equalizer {
+    bands: Vec<Band>,
+    channels: usize,
+    sample_rate: u32,
+}
+
+impl Equalizer {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        Self {
+            bands: BANDS
+                .iter()
+                .map(|_| Band {
+                    gain: 0.0,
+                    coefficients: None,
+                    state: vec![[0.0; 2]; channels],
+                })
+                .collect(),
+            channels,
+            sample_rate,
+        }
+    }
+
+    ///Only the bands that changed are recalculated, this is cheap to call for every packet.
+    pub fn set_gains(&mut self, gains: &[f32; 10]) {
+        for ((band, &gain), frequency) in self.bands.iter_mut().zip(gains).zip(BANDS) {
+            let gain = gain.clamp(-MAX_GAIN, MAX_GAIN);
+            if band.gain != gain {
+                band.gain = gain;
+                band.coefficients = Coefficients::peaking(frequency, gain, self.sample_rate);
+            }
+        }
+    }
+
+    ///Filter interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for band in &mut self.bands {
+            let Some(c) = band.coefficients else {
+                continue;
+            };
+            for frame in samples.chunks_mut(self.channels) {
+                for (sample, [s1, s2]) in frame.iter_mut().zip(&mut band.state) {
+                    //Transposed direct form II.
+                    let x = *sample;
+                    let y = c.b0 * x + *s1;
+                    *s1 = c.b1 * x - c.a1 * y + *s2;
+                    *s2 = c.b2 * x - c.a2 * y;
+                    *sample = y;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let s = (std::f32::consts::TAU * frequency * i as f32 / 44100.0).sin() * 0.25;
+                [s, s]
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        //Skip the filter settling in.
+        let samples = &samples[samples.len() / 2..];
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn flat() {
+        let mut eq = Equalizer::new(2, 44100);
+        eq.set_gains(&[0.0; 10]);
+        let input = sine(440.0, 4096);
+        let mut output = input.clone();
+        eq.process(&mut output);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn boost() {
+        let mut eq = Equalizer::new(2, 44100);
+        let mut gains = [0.0; 10];
+        gains[5] = 6.0;
+        eq.set_gains(&gains);
+
+        let input = sine(1000.0, 44100);
+        let mut output = input.clone();
+        eq.process(&mut output);
+        let db = 20.0 * (rms(&output) / rms(&input)).log10();
+        assert!((db - 6.0).abs() < 0.2, "{db}");
+
+        //Three octaves away it's barely changed.
+        let mut eq = Equalizer::new(2, 44100);
+        eq.set_gains(&gains);
+        let input = sine(125.0, 44100);
+        let mut output = input.clone();
+        eq.process(&mut output);
+        let db = 20.0 * (rms(&output) / rms(&input)).log10();
+        assert!(db.abs() < 0.5, "{db}");
+
+        //Out of range gains are clamped and bands above Nyquist are skipped.
+        let mut eq = Equalizer::new(1, 22050);
+        eq.set_gains(&[40.0; 10]);
+        assert_eq!(eq.bands[0].gain, MAX_GAIN);
+        assert!(eq.bands[9].coefficients.is_none());
+    }
+
+    #[test]
+    fn channels() {
+        let mut eq = Equalizer::new(2, 44100);
+        eq.set_gains(&[-12.0; 10]);
+
+        //A click on the left doesn't leak into the right.
+        let mut samples = vec![0.0; 512];
+        samples[0] = 1.0;
+        eq.process(&mut samples);
+        assert!(samples.iter().skip(1).step_by(2).all(|s| *s == 0.0));
+        assert!(samples.iter().step_by(2).any(|s| *s != 0.0));
+    }
+}
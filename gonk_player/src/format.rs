@@ -0,0 +1,211 @@
+//! Convert samples to the output device's format.
+//!
+//! Samples are `f32` until they are written to the device.
+//! Most devices mix in 32-bit float but some use 16, 24 or 32-bit integers.
+use wasapi::WAVEFORMATEXTENSIBLE;
+
+pub const WAVE_FORMAT_PCM: u16 = 1;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+///Writes one sample into the bytes of a single channel.
+pub type Write = fn(f32, &mut [u8]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    F32,
+    I16,
+    ///Packed into three bytes.
+    I24,
+    I32,
+}
+
+impl SampleFormat {
+    ///Returns `None` if the format isn't supported.
+    pub fn new(format: &WAVEFORMATEXTENSIBLE) -> Option<Self> {
+        let tag = match format.Format.wFormatTag {
+            //The first part of the sub-format GUID is the format tag.
+            WAVE_FORMAT_EXTENSIBLE => unsafe {
+                std::ptr::addr_of!(format.SubFormat)
+                    .cast::<u32>()
+                    .read_unaligned() as u16
+            },
+            tag => tag,
+        };
+        Self::from_tag(tag, format.Format.wBitsPerSample)
+    }
+
+    pub fn from_tag(tag: u16, bits: u16) -> Option<Self> {
+        match (tag, bits) {
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => Some(SampleFormat::F32),
+            (WAVE_FORMAT_PCM, 16) => Some(SampleFormat::I16),
+            (WAVE_FORMAT_PCM, 24) => Some(SampleFormat::I24),
+            (WAVE_FORMAT_PCM, 32) => Some(SampleFormat::I32),
+            _ => None,
+        }
+    }
+
+    ///Bytes per sample.
+    pub fn size(self) -> usize {
+        match self {
+            SampleFormat::I16 => 2,
+            SampleFormat::I24 => 3,
+            SampleFormat::F32 | SampleFormat::I32 => 4,
+        }
+    }
+
+    ///Picked once per buffer so there's no branching for each sample.
+    pub fn write(self) -> Write {
+        match self {
+            SampleFormat::F32 => write_f32,
+            SampleFormat::I16 => write_i16,
+            SampleFormat::I24 => write_i24,
+            SampleFormat::I32 => write_i32,
+        }
+    }
+}
+
+///The sample rate, bit depth and channels of a device, shown in the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceFormat {
+    pub sample_rate: u32,
+    pub bits: u16,
+    pub channels: u16,
+    pub float: bool,
+}
+
+impl DeviceFormat {
+    pub fn new(format: &WAVEFORMATEXTENSIBLE) -> Self {
+        Self {
+            sample_rate: format.Format.nSamplesPerSec,
+            bits: format.Format.wBitsPerSample,
+            channels: format.Format.nChannels,
+            float: SampleFormat::new(format) == Some(SampleFormat::F32),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let khz = self.sample_rate as f32 / 1000.0;
+        let kind = if self.float { " float" } else { "" };
+        write!(f, "{khz} kHz {}-bit{kind} {}ch", self.bits, self.channels)
+    }
+}
+
+///Change an unsupported format to 32-bit float, keeping the channels and sample rate.
+pub fn use_float(format: &mut WAVEFORMATEXTENSIBLE) {
+    let block_align = format.Format.nChannels * 4;
+    format.Format.wBitsPerSample = 32;
+    format.Format.nBlockAlign = block_align;
+    format.Format.nAvgBytesPerSec = format.Format.nSamplesPerSec * block_align as u32;
+
+    if format.Format.wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+        //PCM and float sub-formats only differ in the first part.
+        unsafe {
+            std::ptr::addr_of_mut!(format.SubFormat)
+                .cast::<u32>()
+                .write_unaligned(WAVE_FORMAT_IEEE_FLOAT as u32)
+        };
+    } else {
+        format.Format.wFormatTag = WAVE_FORMAT_IEEE_FLOAT;
+    }
+}
+
+fn write_f32(sample: f32, bytes: &mut [u8]) {
+    bytes[..4].copy_from_slice(&sample.to_le_bytes());
+}
+
+fn write_i16(sample: f32, bytes: &mut [u8]) {
+    let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+    bytes[..2].copy_from_slice(&sample.to_le_bytes());
+}
+
+fn write_i24(sample: f32, bytes: &mut [u8]) {
+    const MAX: f32 = 8_388_607.0;
+    let sample = (sample.clamp(-1.0, 1.0) * MAX).round() as i32;
+    bytes[..3].copy_from_slice(&sample.to_le_bytes()[..3]);
+}
+
+fn write_i32(sample: f32, bytes: &mut [u8]) {
+    //`i32::MAX` can't be stored exactly in an `f32`.
+    let sample = (sample.clamp(-1.0, 1.0) as f64 * i32::MAX as f64).round() as i32;
+    bytes[..4].copy_from_slice(&sample.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(format: SampleFormat, sample: f32) -> i64 {
+        let mut bytes = [0; 4];
+        format.write()(sample, &mut bytes[..format.size()]);
+        match format {
+            SampleFormat::F32 => unreachable!(),
+            SampleFormat::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            //Shift the sign bit into place.
+            SampleFormat::I24 => {
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as i64
+            }
+            SampleFormat::I32 => i32::from_le_bytes(bytes) as i64,
+        }
+    }
+
+    #[test]
+    fn integers() {
+        for (format, max) in [
+            (SampleFormat::I16, i16::MAX as i64),
+            (SampleFormat::I24, 8_388_607),
+            (SampleFormat::I32, i32::MAX as i64),
+        ] {
+            assert_eq!(convert(format, 0.0), 0, "{format:?}");
+            assert_eq!(convert(format, 1.0), max, "{format:?}");
+            assert_eq!(convert(format, -1.0), -max, "{format:?}");
+            assert_eq!(convert(format, 0.5), (max as f64 / 2.0).round() as i64);
+
+            //Clipped instead of wrapping around.
+            assert_eq!(convert(format, 2.0), max, "{format:?}");
+            assert_eq!(convert(format, -2.0), -max, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn float() {
+        let mut bytes = [0; 4];
+        for sample in [0.0, 1.0, -1.0, 1.5] {
+            SampleFormat::F32.write()(sample, &mut bytes);
+            assert_eq!(f32::from_le_bytes(bytes), sample);
+        }
+    }
+
+    #[test]
+    fn tags() {
+        use SampleFormat::*;
+        assert_eq!(
+            SampleFormat::from_tag(WAVE_FORMAT_IEEE_FLOAT, 32),
+            Some(F32)
+        );
+        assert_eq!(SampleFormat::from_tag(WAVE_FORMAT_PCM, 16), Some(I16));
+        assert_eq!(SampleFormat::from_tag(WAVE_FORMAT_PCM, 24), Some(I24));
+        assert_eq!(SampleFormat::from_tag(WAVE_FORMAT_PCM, 32), Some(I32));
+        assert_eq!(SampleFormat::from_tag(WAVE_FORMAT_IEEE_FLOAT, 64), None);
+        assert_eq!(SampleFormat::from_tag(WAVE_FORMAT_PCM, 8), None);
+        assert_eq!(I24.size(), 3);
+    }
+
+    #[test]
+    fn display() {
+        let format = |sample_rate, bits, float| DeviceFormat {
+            sample_rate,
+            bits,
+            channels: 2,
+            float,
+        };
+        assert_eq!(
+            format(48000, 32, true).to_string(),
+            "48 kHz 32-bit float 2ch"
+        );
+        assert_eq!(format(44100, 16, false).to_string(), "44.1 kHz 16-bit 2ch");
+        assert_eq!(format(192000, 24, false).to_string(), "192 kHz 24-bit 2ch");
+    }
+}
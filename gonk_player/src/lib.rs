@@ -1,41 +1,152 @@
 #![allow(static_mut_refs)]
 //! TODO: Describe the audio backend
+use channels::ChannelConverter;
 use crossbeam_queue::SegQueue;
+use crossfade::Crossfade;
 use decoder::Symphonia;
+use equalizer::Equalizer;
+pub use format::DeviceFormat;
+use format::SampleFormat;
 use gonk_core::{Index, Song};
 use mini::*;
 use ringbuf::traits::{Consumer, Observer, Producer, Split};
 use ringbuf::HeapRb;
-use std::mem::MaybeUninit;
+use shuffle::Shuffle;
+use std::collections::BTreeMap;
+use std::mem::{self, MaybeUninit};
 use std::{
     path::{Path, PathBuf},
     sync::Once,
     thread,
     time::Duration,
 };
-use symphonia::core::audio::SampleBuffer;
+use stretch::{Stretcher, MAX_RATE, MIN_RATE};
 use wasapi::*;
 
+mod channels;
+mod crossfade;
 mod decoder;
+mod equalizer;
+mod format;
+mod overview;
+mod shuffle;
+mod stretch;
+
+pub use equalizer::{BANDS, MAX_GAIN};
+pub use gonk_core::settings::{RepeatMode, ReplayGainMode, VolumeCurve};
+pub use overview::overview;
 
 //TODO: These should be configurable.
 const VOLUME_REDUCTION: f32 = 75.0;
 
+///Used for songs without a replay gain tag.
+const DEFAULT_GAIN: f32 = 0.5;
+
+///Range of the logarithmic volume curve, anything quieter is silent.
+const VOLUME_RANGE_DB: f32 = 40.0;
+
+///How much `w` and `s` change the volume.
+const VOLUME_STEP: u8 = 5;
+
+///How much `<` and `>` change the playback rate.
+const RATE_STEP: f32 = 0.25;
+
+///How far `q` and `e` seek in seconds.
+pub const SEEK_STEP: f32 = 10.0;
+
+const TONE_SAMPLE_RATE: u32 = 44100;
+const TONE_FREQUENCY: f32 = 440.0;
+const TONE_DURATION_MS: usize = 1000;
+///Quiet enough to not hurt with the system volume turned up.
+const TONE_VOLUME: f32 = 0.1;
+
 //Foobar uses a buffer size of 1000ms by default.
-pub static mut RB_SIZE: usize = 4096 * 4;
-// const RB_SIZE: usize = 4096 * 4;
+///How much decoded audio is kept ready to play.
+pub static mut BUFFER_DURATION: Duration = Duration::from_millis(200);
 
 const COMMON_SAMPLE_RATES: [u32; 13] = [
     5512, 8000, 11025, 16000, 22050, 32000, 44100, 48000, 64000, 88200, 96000, 176400, 192000,
 ];
 
+///The ring buffer is sized so the buffer duration fits at this rate.
+const MAX_SAMPLE_RATE: u32 = 192000;
+
+///Milliseconds without a request for samples before the device is checked.
+const DEVICE_TIMEOUT: u32 = 500;
+
 static mut EVENTS: SegQueue<Event> = SegQueue::new();
+//Position of the decoder, this is ahead of what is being played.
 static mut ELAPSED: Duration = Duration::from_secs(0);
+//Time until a decoded sample is heard.
+//The device period plus the samples in the device buffer and ring buffer.
+static mut LATENCY: Duration = Duration::from_secs(0);
+//Decoded audio in the ring buffer.
+static mut BUFFERED: Duration = Duration::from_secs(0);
 static mut DURATION: Duration = Duration::from_secs(0);
+///Volume from 0 to 100, `VOLUME` is worked out from this so the steps don't drift.
+static mut PERCENT: u8 = 15;
 static mut VOLUME: f32 = 15.0 / VOLUME_REDUCTION;
+static mut VOLUME_CURVE: VolumeCurve = VolumeCurve::Linear;
 static mut GAIN: Option<f32> = None;
+//Ignore the replay gain of every song, this isn't saved.
+static mut BYPASS_GAIN: bool = false;
+static mut REPLAY_GAIN: ReplayGainMode = ReplayGainMode::Track;
 static mut OUTPUT_DEVICE: Option<Device> = None;
 static mut PAUSED: bool = false;
+///Paused by `pause_for_device_change` and not resumed since.
+static mut DEVICE_LOST: bool = false;
+static mut FADE: Fade = Fade::Off;
+///Play the average of both channels in both ears.
+static mut MONO: bool = false;
+///How long pausing and resuming take to ramp the volume, zero is instant.
+static mut PAUSE_FADE_MS: u32 = 20;
+static mut RATE: f32 = 1.0;
+static mut KEEP_PITCH: bool = true;
+static mut REMEMBER_RATE: bool = false;
+//Rates of the folders played since gonk was started.
+static mut FOLDER_RATES: BTreeMap<PathBuf, f32> = BTreeMap::new();
+static mut FOLDER: Option<PathBuf> = None;
+//Seeking past the start or end of a song moves into the previous or next song.
+static mut SEEK_ACROSS: bool = true;
+static mut REPEAT: RepeatMode = RepeatMode::Queue;
+///The last song couldn't be opened, repeating it would try again forever.
+static mut FAILED: bool = false;
+///Songs that failed to open one after another, playback stops once every song in the queue has.
+static mut FAILURES: usize = 0;
+///A song is playing or waiting to be opened, there's nothing to seek in otherwise.
+static mut LOADED: bool = false;
+///How long before the end of a song the next one is opened.
+const PRELOAD: Duration = Duration::from_secs(1);
+///The next song sent to the decoder and whether it follows on from the current one,
+///only used by the UI thread.
+static mut UP_NEXT: Option<(PathBuf, bool)> = None;
+///The decoder moved on to this song without being told to.
+static mut GAPLESS: Option<PathBuf> = None;
+///Goes up every time a song starts, including a song that starts again.
+static mut STARTS: u64 = 0;
+///How the last song led into the next one.
+static mut TRANSITION: Option<Transition> = None;
+///The gain of the next song and how many frames of the current one are left in the ring buffer.
+static mut NEXT_GAIN: Option<(f32, usize)> = None;
+///Seconds to jump back to and from, the loop is off until both are set.
+static mut LOOP_A: Option<f32> = None;
+static mut LOOP_B: Option<f32> = None;
+///The device playback moved to after the last one was unplugged.
+static mut DEVICE_SWITCH: Option<String> = None;
+///Updated every time the output stream is opened.
+static mut DEVICE_FORMAT: Option<DeviceFormat> = None;
+///A new gain for the playing song, unlike `GAIN` the buffered samples are kept.
+static mut LIVE_GAIN: Option<f32> = None;
+///Seconds the end of a song is mixed with the start of the next, zero cuts straight over.
+static mut CROSSFADE: f32 = 0.0;
+static mut SHUFFLE: Shuffle = Shuffle::new();
+///Gain of each equalizer band in dB.
+static mut EQ_GAINS: [f32; 10] = [0.0; 10];
+static mut EQ_BYPASS: bool = false;
+//How far a seek went past the song, negative when it went before the start.
+static mut OVERFLOW: Option<f32> = None;
+//Mix formats by device name, see `cached_mix_format`.
+static mut MIX_FORMATS: BTreeMap<String, Option<DeviceFormat>> = BTreeMap::new();
 
 //Safety: Only written on decoder thread.
 static mut NEXT: bool = false;
@@ -57,14 +168,65 @@ enum Event {
     //Path, Gain
     Song(PathBuf, f32),
     Seek(f32),
+    ///Seek to a fraction of the song's duration.
+    SeekRatio(f32),
     SeekBackward,
     SeekForward,
+    ///Seek from the start, or from the end when negative. Used when seeking across songs.
+    Skim(f32),
+    ///Unpause once the events before it have been handled.
+    Play,
+    ///The song to open just before the current one ends.
+    UpNext(Option<UpNext>),
+    ///The replay gain mode changed while a song was playing.
+    Gain(f32),
+}
+
+#[derive(Debug, PartialEq)]
+struct UpNext {
+    path: PathBuf,
+    gain: f32,
+    ///The next track on the same album and disc.
+    album: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Gapless,
+    Crossfade,
+    ///Crossfading was skipped because the album runs from one track into the next.
+    Album,
+}
+
+impl Transition {
+    pub fn description(self) -> &'static str {
+        match self {
+            Transition::Gapless => "gapless",
+            Transition::Crossfade => "crossfade",
+            Transition::Album => "gapless album",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Fade {
+    Off,
+    ///Set by the UI, the output thread knows the sample rate.
+    Requested(Duration),
+    Fading {
+        remaining: u32,
+        total: u32,
+    },
+    ///Silent until the next song is played.
+    Stopped,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Device {
     pub inner: IMMDevice,
     pub name: String,
+    ///The format the device mixes at, songs at other rates are converted to it.
+    pub format: Option<DeviceFormat>,
 }
 
 unsafe impl Send for Device {}
@@ -82,59 +244,132 @@ pub fn devices() -> Vec<Device> {
             .EnumAudioEndpoints(DataFlow::Render, DeviceState::Active)
             .unwrap();
 
-        (0..collection.GetCount().unwrap())
+        let devices: Vec<Device> = (0..collection.GetCount().unwrap())
             .map(|i| {
                 let device = collection.Item(i).unwrap();
+                let name = device.name();
                 Device {
-                    name: device.name(),
+                    format: cached_mix_format(&name, &device),
+                    name,
                     inner: device,
                 }
             })
-            .collect()
+            .collect();
+
+        //Unplugged devices are asked again when they come back.
+        MIX_FORMATS.retain(|name, _| devices.iter().any(|device| &device.name == name));
+        devices
     }
 }
 
 ///Get the default output device.
 pub fn default_device() -> Device {
+    try_default_device().unwrap()
+}
+
+///There isn't a default device when nothing is plugged in.
+fn try_default_device() -> Option<Device> {
     profile!();
     unsafe {
         init_com();
         let device = ENUMERATOR
             .assume_init_mut()
             .GetDefaultAudioEndpoint(DataFlow::Render, Role::Console)
-            .unwrap();
-        Device {
-            name: device.name(),
+            .ok()?;
+        let name = device.name();
+        Some(Device {
+            format: cached_mix_format(&name, &device),
+            name,
             inner: device,
-        }
+        })
+    }
+}
+
+///Ask the device what format it mixes at in shared mode.
+unsafe fn mix_format(device: &IMMDevice) -> Option<DeviceFormat> {
+    let client: IAudioClient = device.Activate(ExecutionContext::All).ok()?;
+    let format = read_mix_format(&client).ok()?;
+    Some(DeviceFormat::new(&format))
+}
+
+///Same as `mix_format`, but each device is only asked once while it's plugged in.
+///
+///The device list is updated every tick and activating a client for each one is slow.
+unsafe fn cached_mix_format(name: &str, device: &IMMDevice) -> Option<DeviceFormat> {
+    if let Some(format) = MIX_FORMATS.get(name) {
+        return *format;
+    }
+    let format = mix_format(device);
+    MIX_FORMATS.insert(name.to_string(), format);
+    format
+}
+
+///Copy the mix format out of the memory WASAPI allocated for it.
+///
+///Only `WAVE_FORMAT_EXTENSIBLE` formats have the extra fields, for anything else they're zeroed.
+unsafe fn read_mix_format(client: &IAudioClient) -> Result<WAVEFORMATEXTENSIBLE, String> {
+    let ptr = client.GetMixFormat().map_err(backend_error)? as *const _ as *const WAVEFORMATEX;
+    let base = ptr.read_unaligned();
+    let extra = mem::size_of::<WAVEFORMATEXTENSIBLE>() - mem::size_of::<WAVEFORMATEX>();
+
+    let mut format: WAVEFORMATEXTENSIBLE = mem::zeroed();
+    if base.wFormatTag == format::WAVE_FORMAT_EXTENSIBLE && base.cbSize as usize >= extra {
+        format = ptr.cast::<WAVEFORMATEXTENSIBLE>().read_unaligned();
+    } else {
+        format.Format = base;
     }
+
+    CoTaskMemFree(ptr.cast());
+    Ok(format)
+}
+
+///The format playback was opened with, this follows the sample rate of the song.
+pub fn device_info() -> Option<DeviceFormat> {
+    unsafe { DEVICE_FORMAT }
 }
 
-pub unsafe fn create_wasapi(
-    device: &Device,
-    sample_rate: Option<u32>,
-) -> (
+type Wasapi = (
     IAudioClient,
     IAudioRenderClient,
     WAVEFORMATEXTENSIBLE,
     *mut c_void,
-) {
-    let client: IAudioClient = device.inner.Activate(ExecutionContext::All).unwrap();
-    let mut format =
-        (client.GetMixFormat().unwrap() as *const _ as *const WAVEFORMATEXTENSIBLE).read();
+);
+
+pub unsafe fn create_wasapi(device: &Device, sample_rate: Option<u32>) -> Wasapi {
+    try_create_wasapi(device, sample_rate).unwrap()
+}
+
+///Same as `create_wasapi` but the error is returned instead of panicking.
+unsafe fn try_create_wasapi(device: &Device, sample_rate: Option<u32>) -> Result<Wasapi, String> {
+    let client: IAudioClient = device
+        .inner
+        .Activate(ExecutionContext::All)
+        .map_err(backend_error)?;
+    let mut format = read_mix_format(&client)?;
 
     if format.Format.nChannels < 2 {
-        todo!("Support mono devices.");
+        return Err(String::from("Mono devices aren't supported"));
     }
 
+    //Ask for float samples if the mix format can't be written to.
+    let unsupported = SampleFormat::new(&format).is_none().then(|| {
+        let tag = format.Format.wFormatTag;
+        let bits = format.Format.wBitsPerSample;
+        warn!("Unsupported sample format: tag {tag:#x}, {bits}-bit. Trying 32-bit float.");
+        format::use_float(&mut format);
+        format!("tag {tag:#x}, {bits}-bit")
+    });
+
     //Update format to desired sample rate.
     if let Some(sample_rate) = sample_rate {
-        assert!(COMMON_SAMPLE_RATES.contains(&sample_rate));
+        if !COMMON_SAMPLE_RATES.contains(&sample_rate) {
+            return Err(format!("Unsupported sample rate: {sample_rate}"));
+        }
         format.Format.nSamplesPerSec = sample_rate;
         format.Format.nAvgBytesPerSec = sample_rate * format.Format.nBlockAlign as u32;
     }
 
-    let (default, _min) = client.GetDevicePeriod().unwrap();
+    let (default, _min) = client.GetDevicePeriod().map_err(backend_error)?;
 
     client
         .Initialize(
@@ -147,17 +382,121 @@ pub unsafe fn create_wasapi(
             &format as *const _ as *const WAVEFORMATEX,
             None,
         )
-        .unwrap();
+        .map_err(|e| match &unsupported {
+            Some(original) => format!(
+                "Unsupported sample format ({original}) and 32-bit float was rejected: {e:?}"
+            ),
+            None => backend_error(e),
+        })?;
 
     //This must be set for some reason.
     let event = CreateEventA(core::ptr::null_mut(), 0, 0, core::ptr::null_mut());
-    assert!(!event.is_null());
-    client.SetEventHandle(event as isize).unwrap();
+    if event.is_null() {
+        return Err(String::from("Failed to create the device event"));
+    }
+    let start = || -> Result<IAudioRenderClient, String> {
+        client
+            .SetEventHandle(event as isize)
+            .map_err(backend_error)?;
+        let render_client: IAudioRenderClient = client.GetService().map_err(backend_error)?;
+        client.Start().map_err(backend_error)?;
+        Ok(render_client)
+    };
+
+    match start() {
+        Ok(render_client) => Ok((client, render_client, format, event)),
+        Err(e) => {
+            CloseHandle(event);
+            Err(e)
+        }
+    }
+}
+
+fn backend_error(e: impl std::fmt::Debug) -> String {
+    format!("{e:?}")
+}
 
-    let render_client: IAudioRenderClient = client.GetService().unwrap();
-    client.Start().unwrap();
+#[link(name = "kernel32")]
+extern "system" {
+    fn CloseHandle(handle: *mut c_void) -> i32;
+}
 
-    (client, render_client, format, event)
+#[link(name = "ole32")]
+extern "system" {
+    fn CoTaskMemFree(pv: *const c_void);
+}
+
+///Play a short sine wave on a device without touching the playback state.
+///
+///The client is only kept for the length of the tone.
+pub fn test_tone(device: Device) -> thread::JoinHandle<Result<(), String>> {
+    thread::spawn(move || unsafe {
+        init_com();
+        //Songs are usually 44.1 kHz, so this gets converted the same way.
+        let (audio, render, format, event) = try_create_wasapi(&device, Some(TONE_SAMPLE_RATE))?;
+        let result = play_tone(&audio, &render, &format, event);
+        let _ = audio.Stop();
+        drop((render, audio));
+        CloseHandle(event);
+        result
+    })
+}
+
+unsafe fn play_tone(
+    audio: &IAudioClient,
+    render: &IAudioRenderClient,
+    format: &WAVEFORMATEXTENSIBLE,
+    event: *mut c_void,
+) -> Result<(), String> {
+    let block_align = format.Format.nBlockAlign as u32;
+    let sample_format = SampleFormat::new(format).unwrap_or(SampleFormat::F32);
+    let (size, write) = (sample_format.size(), sample_format.write());
+    let converter = ChannelConverter::new(1, format.Format.nChannels as usize);
+    let mut frame = vec![0.0; format.Format.nChannels as usize];
+    let buffer_size = audio.GetBufferSize().map_err(backend_error)?;
+    let total = TONE_SAMPLE_RATE as usize * TONE_DURATION_MS / 1000;
+    let mut written = 0;
+
+    while written < total {
+        if WaitForSingleObject(event, 2000) != WAIT_OBJECT_0 {
+            return Err(String::from("The device stopped responding"));
+        }
+
+        let padding = audio.GetCurrentPadding().map_err(backend_error)?;
+        let n_frames = (buffer_size - padding).min((total - written) as u32);
+        if n_frames == 0 {
+            continue;
+        }
+
+        let b = render.GetBuffer(n_frames).map_err(backend_error)?;
+        let output = std::slice::from_raw_parts_mut(b, (n_frames * block_align) as usize);
+
+        //Follow the volume like songs do, it can be changed while the tone plays.
+        let gain = TONE_VOLUME * VOLUME;
+        for bytes in output.chunks_mut(block_align as usize) {
+            converter.frame(&[tone_sample(written) * gain], &mut frame);
+            for (sample, bytes) in frame.iter().zip(bytes.chunks_mut(size)) {
+                write(*sample, bytes);
+            }
+            written += 1;
+        }
+
+        render.ReleaseBuffer(n_frames, 0).map_err(backend_error)?;
+    }
+
+    //Let the device play what's left in its buffer.
+    let padding = audio.GetCurrentPadding().map_err(backend_error)?;
+    thread::sleep(frames_to_duration(padding, TONE_SAMPLE_RATE));
+    Ok(())
+}
+
+///A 440 Hz sine wave that fades in and out to avoid clicks.
+fn tone_sample(frame: usize) -> f32 {
+    let total = TONE_SAMPLE_RATE as usize * TONE_DURATION_MS / 1000;
+    let fade = TONE_SAMPLE_RATE as usize / 100;
+    let t = frame as f32 / TONE_SAMPLE_RATE as f32;
+    let envelope = (frame.min(total.saturating_sub(frame)) as f32 / fade as f32).min(1.0);
+    (t * TONE_FREQUENCY * std::f32::consts::TAU).sin() * envelope
 }
 
 //0.016384MB, no stack overflow here.
@@ -167,17 +506,36 @@ pub unsafe fn create_wasapi(
 
 pub fn spawn_audio_threads(device: Device) {
     unsafe {
-        let rb: HeapRb<f32> = HeapRb::new(RB_SIZE);
-        // let rb = StaticRb::<f32, RB_SIZE>::default();
+        let (_, capacity) = watermarks(BUFFER_DURATION, MAX_SAMPLE_RATE);
+        let rb: HeapRb<f32> = HeapRb::new(capacity.max(1));
         let (mut prod, mut cons) = rb.split();
 
         thread::spawn(move || {
             info!("Spawned decoder thread!");
 
             let mut sym: Option<Symphonia> = None;
-            let mut leftover_packet: Option<SampleBuffer<f32>> = None;
+            let mut up_next: Option<UpNext> = None;
+            //Opened near the end of the current song so the two play without a gap.
+            let mut staged: Option<Symphonia> = None;
+            let mut crossfade: Option<Crossfade> = None;
+            let mut current_gain = DEFAULT_GAIN;
+            //The song being decoded, its ending is checked before crossfading into the next track.
+            let mut current: Option<PathBuf> = None;
+            let mut endings = gonk_core::silence::load();
+            //Decoding the end of a song takes too long for this thread.
+            let mut checking: Option<thread::JoinHandle<(PathBuf, Option<bool>)>> = None;
+            let mut equalizer = Equalizer::new(2, 44100);
+            let mut stretcher = Stretcher::new(2, 44100);
+            //Everything after the decoder is stereo.
+            let mut converter = ChannelConverter::new(2, 2);
+            let mut stereo: Vec<f32> = Vec::new();
+            //Samples that haven't fit in the ring buffer yet.
+            let mut samples: Vec<f32> = Vec::new();
             let mut i = 0;
             let mut finished = true;
+            //Depends on the sample rate of the song.
+            let mut marks = watermarks(BUFFER_DURATION, 44100);
+            let mut filling = true;
 
             loop {
                 std::thread::sleep(std::time::Duration::from_millis(8));
@@ -194,6 +552,7 @@ pub fn spawn_audio_threads(device: Device) {
                                     new_path.to_string_lossy()
                                 );
                                 warn!("Failed to play: {}, Error: {e}", new_path.to_string_lossy(),);
+                                FAILED = true;
                                 NEXT = true;
                                 continue;
                             }
@@ -202,23 +561,37 @@ pub fn spawn_audio_threads(device: Device) {
                         //We don't set the playback state here because it might be delayed.
                         SAMPLE_RATE = Some(s.sample_rate());
                         DURATION = s.duration();
+                        stretcher = Stretcher::new(2, s.sample_rate());
+                        equalizer = Equalizer::new(2, s.sample_rate());
+                        converter = ChannelConverter::new(s.channels(), 2);
+                        marks = watermarks(BUFFER_DURATION, s.sample_rate());
 
                         //Set the decoder for the new song.
                         sym = Some(s);
+                        crossfade = None;
 
                         //Remove the leftovers.
-                        leftover_packet = None;
+                        samples.clear();
+                        i = 0;
                         //Start the playback
                         finished = false;
 
                         //Set the gain
                         GAIN = Some(gain);
+                        current_gain = gain;
+                        current = Some(new_path);
                     }
                     Some(Event::Stop) => {
                         info!("Stopping playback.");
                         //Stop the decoder and remove the extra packet.
                         sym = None;
-                        leftover_packet = None;
+                        staged = None;
+                        crossfade = None;
+                        current = None;
+                        samples.clear();
+                        i = 0;
+                        stretcher.clear();
+                        filling = true;
 
                         //Remove any excess packets from the queue.
                         //If this isn't done, the user can clear the queue
@@ -226,6 +599,8 @@ pub fn spawn_audio_threads(device: Device) {
                         prod.advance_write_index(prod.occupied_len());
                     }
                     Some(Event::Seek(pos)) => {
+                        stretcher.clear();
+                        crossfade = None;
                         if let Some(sym) = &mut sym {
                             info!(
                                 "Seeking {} / {} paused: {}",
@@ -233,29 +608,58 @@ pub fn spawn_audio_threads(device: Device) {
                                 DURATION.as_secs_f32() as u32,
                                 PAUSED
                             );
-                            sym.seek(pos);
+                            sym.seek(clamp_seek(pos, sym.duration()));
+                        }
+                    }
+                    Some(Event::SeekRatio(ratio)) => {
+                        stretcher.clear();
+                        crossfade = None;
+                        if let Some(sym) = &mut sym {
+                            sym.seek(sym.duration().as_secs_f32() * ratio.clamp(0.0, 1.0));
                         }
                     }
                     Some(Event::SeekForward) => {
+                        stretcher.clear();
+                        crossfade = None;
                         if let Some(sym) = &mut sym {
-                            info!(
-                                "Seeking {} / {}",
-                                sym.elapsed().as_secs_f32() + 10.0,
-                                sym.duration().as_secs_f32()
-                            );
-                            sym.seek((sym.elapsed().as_secs_f32() + 10.0).clamp(0.0, f32::MAX))
+                            let target = sym.elapsed().as_secs_f32() + SEEK_STEP;
+                            seek_within(sym, target, SEEK_ACROSS);
                         }
                     }
                     Some(Event::SeekBackward) => {
+                        stretcher.clear();
+                        crossfade = None;
                         if let Some(sym) = &mut sym {
-                            info!(
-                                "Seeking {} / {}",
-                                sym.elapsed().as_secs_f32() - 10.0,
-                                sym.duration().as_secs_f32()
-                            );
-                            sym.seek((sym.elapsed().as_secs_f32() - 10.0).clamp(0.0, f32::MAX))
+                            let target = sym.elapsed().as_secs_f32() - SEEK_STEP;
+                            seek_within(sym, target, SEEK_ACROSS);
                         }
                     }
+                    Some(Event::Skim(pos)) => {
+                        stretcher.clear();
+                        crossfade = None;
+                        if let Some(sym) = &mut sym {
+                            let target = if pos < 0.0 {
+                                sym.duration().as_secs_f32() + pos
+                            } else {
+                                pos
+                            };
+                            seek_within(sym, target, true);
+                        }
+                    }
+                    Some(Event::Play) => {
+                        info!("Resuming playback.");
+                        PAUSED = false;
+                        DEVICE_LOST = false;
+                    }
+                    Some(Event::UpNext(next)) => {
+                        up_next = next;
+                        staged = None;
+                        crossfade = None;
+                    }
+                    Some(Event::Gain(gain)) => {
+                        current_gain = gain;
+                        LIVE_GAIN = Some(gain);
+                    }
                     None => {}
                 }
 
@@ -267,35 +671,170 @@ pub fn spawn_audio_threads(device: Device) {
                     continue;
                 };
 
-                if let Some(p) = &mut leftover_packet {
-                    //Note: this has caused a crash before.
-                    //This may not work as intended.
-                    //Really need to write some unit tests for song playback.
-                    //Stability has taken a huge hit since I stopped using it as my primary music player.
+                let buffered = prod.occupied_len() + samples.len() - i;
+                filling = keep_filling(filling, buffered, marks);
 
+                if i < samples.len() {
                     //Push as many samples as will fit.
-                    if let Some(samples) = p.samples().get(i..) {
-                        i += prod.push_slice(&samples);
-                    } else {
-                        i = 0;
+                    i += prod.push_slice(&samples[i..]);
+                } else if filling {
+                    i = 0;
+                    samples.clear();
+                    stretcher.set_rate(RATE);
+                    stretcher.set_keep_pitch(KEEP_PITCH);
+
+                    let packet = sym.next_packet();
+                    let mut ended = packet.is_none();
+                    let input = match &packet {
+                        Some(packet) if !converter.is_passthrough() => {
+                            stereo.clear();
+                            converter.convert(packet.samples(), &mut stereo);
+                            Some(stereo.as_slice())
+                        }
+                        packet => packet.as_ref().map(|p| p.samples()),
+                    };
+                    let mixed = match (input, &mut crossfade) {
+                        (Some(input), Some(crossfade)) => Some(crossfade.mix(input)),
+                        _ => None,
+                    };
+                    match mixed.as_deref().or(input) {
+                        //Play what's left in the stretcher before going back to normal speed.
+                        Some(input) if RATE == 1.0 => {
+                            stretcher.flush(&mut samples);
+                            samples.extend_from_slice(input);
+                        }
+                        Some(input) => stretcher.process(input, &mut samples),
+                        None => stretcher.flush(&mut samples),
+                    }
+                    ELAPSED = sym.elapsed().saturating_sub(stretcher.buffered());
+
+                    //B can be past the last packet so the end of the song counts as well.
+                    if let Some((a, b)) = ordered(LOOP_A, LOOP_B) {
+                        if ended || sym.elapsed().as_secs_f32() >= b {
+                            sym.seek(a);
+                            crossfade = None;
+                            ended = false;
+                        }
                     }
 
-                    //Did we push all the samples?
-                    if i == p.len() {
-                        i = 0;
-                        leftover_packet = None;
+                    let window = crossfade::window(CROSSFADE, sym.duration());
+                    let remaining = sym.duration().saturating_sub(sym.elapsed());
+
+                    if let (None, None, Some(next)) = (&staged, &crossfade, &up_next) {
+                        if remaining <= PRELOAD + window {
+                            //Only the endings of albums that could run on are checked.
+                            let unchecked = current.as_ref().filter(|path| {
+                                next.album
+                                    && !window.is_zero()
+                                    && checking.is_none()
+                                    && !endings.contains_key(&*path.to_string_lossy())
+                            });
+                            if let Some(path) = unchecked {
+                                let path = path.clone();
+                                checking = Some(thread::spawn(move || {
+                                    let silent = crossfade::ends_in_silence(&path);
+                                    (path, silent)
+                                }));
+                            }
+
+                            match Symphonia::new(&next.path) {
+                                Ok(s) => staged = Some(s),
+                                Err(e) => {
+                                    warn!("Failed to open: {}, Error: {e}", next.path.display());
+                                    up_next = None;
+                                }
+                            }
+                        }
+                    }
+
+                    if let Some(check) = checking.take_if(|check| check.is_finished()) {
+                        if let Ok((path, Some(silent))) = check.join() {
+                            endings.insert(path.to_string_lossy().into_owned(), silent);
+                            if let Err(err) = gonk_core::silence::save(&endings) {
+                                warn!("Failed to save the song endings: {err}");
+                            }
+                        }
+                    }
+
+                    //The tracks of a live album or a mix are played without a gap instead.
+                    let runs_on = !window.is_zero()
+                        && up_next.as_ref().is_some_and(|up| up.album)
+                        && current.as_ref().is_some_and(|path| {
+                            endings.get(&*path.to_string_lossy()) == Some(&false)
+                        });
+
+                    //Fade into the next song, this needs the same sample rate as the swap below.
+                    if !window.is_zero() && remaining <= window && crossfade.is_none() && !runs_on {
+                        let rate = sym.sample_rate();
+                        if let (Some(next), Some(up)) =
+                            (staged.take_if(|next| next.sample_rate() == rate), &up_next)
+                        {
+                            info!("Crossfading into {}.", up.path.display());
+                            let ratio = output_gain(up.gain, BYPASS_GAIN)
+                                / output_gain(current_gain, BYPASS_GAIN);
+                            crossfade = Some(Crossfade::new(next, remaining, ratio));
+                        }
                     }
-                } else {
-                    leftover_packet = sym.next_packet();
-                    ELAPSED = sym.elapsed();
 
                     //It's important that finished is used as a guard.
                     //If next is used it can be changed by a different thread.
                     //This may be an excessive amount of conditions :/
-                    if leftover_packet.is_none() && !PAUSED && !finished && !NEXT {
-                        finished = true;
+                    if ended && samples.is_empty() && !PAUSED && !finished && !NEXT {
+                        let transition = if crossfade.is_some() {
+                            Transition::Crossfade
+                        } else if runs_on {
+                            Transition::Album
+                        } else {
+                            Transition::Gapless
+                        };
+                        let next = match crossfade.take() {
+                            Some(crossfade) => Some(crossfade.finish()),
+                            None => staged
+                                .take()
+                                .filter(|next| next.sample_rate() == sym.sample_rate())
+                                .map(|next| (next, Vec::new())),
+                        };
+                        match (next, up_next.take()) {
+                            //Carry on without touching the ring buffer or the output stream.
+                            (Some((next, leftover)), Some(up)) => {
+                                info!(
+                                    "Playing {} without a gap ({}).",
+                                    up.path.display(),
+                                    transition.description()
+                                );
+                                TRANSITION = Some(transition);
+                                DURATION = next.duration();
+                                ELAPSED = Duration::from_secs(0);
+                                converter = ChannelConverter::new(next.channels(), 2);
+                                *sym = next;
+
+                                //The part of the next song that was decoded during the crossfade.
+                                if RATE == 1.0 {
+                                    samples.extend_from_slice(&leftover);
+                                } else {
+                                    stretcher.process(&leftover, &mut samples);
+                                }
+
+                                let frames = (prod.occupied_len() + samples.len()) / 2;
+                                NEXT_GAIN = Some((up.gain, frames));
+                                current_gain = up.gain;
+                                current = Some(up.path.clone());
+                                GAPLESS = Some(up.path);
+                                LOOP_A = None;
+                                LOOP_B = None;
+                            }
+                            _ => {
+                                finished = true;
+                                info!("Playback ended.");
+                            }
+                        }
                         NEXT = true;
-                        info!("Playback ended.");
+                    }
+
+                    if !EQ_BYPASS {
+                        let gains = EQ_GAINS;
+                        equalizer.set_gains(&gains);
+                        equalizer.process(&mut samples);
                     }
                 }
             }
@@ -305,49 +844,111 @@ pub fn spawn_audio_threads(device: Device) {
             info!("Spawned WASAPI thread!");
             init_com();
 
+            //The device being used, this changes when the user picks a new one.
+            let mut device = device;
             let (mut audio, mut render, mut format, mut event) = create_wasapi(&device, None);
+            DEVICE_FORMAT = Some(DeviceFormat::new(&format));
             let mut block_align = format.Format.nBlockAlign as u32;
             let mut sample_rate = format.Format.nSamplesPerSec;
-            let mut gain = 0.5;
+            let mut period = device_period(&audio);
+            let mut gain = DEFAULT_GAIN;
+            //Switched to once the end of the last song has been played.
+            let mut next_gain: Option<(f32, usize)> = None;
+            //Ramps towards zero while paused and one while playing.
+            let mut level = 0.0;
+            //The device was unplugged or stopped working.
+            let mut lost = false;
+            //One stereo frame mapped onto the device's channels.
+            let mut frame: Vec<f32> = Vec::new();
 
             loop {
                 //Block until the output device is ready for new samples.
-                if WaitForSingleObject(event, u32::MAX) != WAIT_OBJECT_0 {
-                    unreachable!()
+                //The event stops firing when the device is unplugged.
+                let ready = WaitForSingleObject(event, DEVICE_TIMEOUT) == WAIT_OBJECT_0;
+                if !ready && audio.GetCurrentPadding().is_err() {
+                    lost = true;
                 }
 
-                if PAUSED {
+                //Carry on from the same position on the default device.
+                //The ring buffer is kept so nothing is skipped.
+                if lost {
+                    let _ = audio.Stop();
+                    let reopened = try_default_device().and_then(|new| {
+                        try_create_wasapi(&new, Some(sample_rate))
+                            .map_err(|e| warn!("Could not open {}: {e}", new.name))
+                            .ok()
+                            .map(|wasapi| (new, wasapi))
+                    });
+                    //Nothing to switch to yet, try again after the next timeout.
+                    let Some((new, wasapi)) = reopened else {
+                        continue;
+                    };
+                    warn!(
+                        "{} was disconnected, switching to {}.",
+                        device.name, new.name
+                    );
+                    (audio, render, format, event) = wasapi;
+                    DEVICE_FORMAT = Some(DeviceFormat::new(&format));
+                    DEVICE_SWITCH = Some(new.name.clone());
+                    device = new;
+                    block_align = format.Format.nBlockAlign as u32;
+                    period = device_period(&audio);
+                    lost = false;
                     continue;
                 }
 
-                if let Some(device) = OUTPUT_DEVICE.take() {
-                    info!("Changing output device to: {}", device.name);
+                if !ready {
+                    continue;
+                }
+
+                //Keep going until the fade out has finished.
+                if PAUSED && level == 0.0 {
+                    continue;
+                }
+
+                //The decoder and ring buffer are left alone so playback
+                //carries on from the same position on the new device.
+                if let Some(new_device) = OUTPUT_DEVICE.take() {
+                    info!("Changing output device to: {}", new_device.name);
+                    device = new_device;
                     //Set the new audio device.
                     audio.Stop().unwrap();
                     (audio, render, format, event) = create_wasapi(&device, Some(sample_rate));
+                    DEVICE_FORMAT = Some(DeviceFormat::new(&format));
                     //Different devices have different block alignments.
                     block_align = format.Format.nBlockAlign as u32;
+                    period = device_period(&audio);
                 }
 
                 if let Some(sr) = SAMPLE_RATE {
                     if sr != sample_rate {
                         info!("Changing sample rate to {}", sr);
-                        let device = OUTPUT_DEVICE.as_ref().unwrap_or(&device);
                         sample_rate = sr;
 
                         //Set the new sample rate.
                         audio.Stop().unwrap();
-                        (audio, render, format, event) = create_wasapi(device, Some(sample_rate));
+                        (audio, render, format, event) = create_wasapi(&device, Some(sample_rate));
+                        DEVICE_FORMAT = Some(DeviceFormat::new(&format));
                         //Doesn't need to be set since it's the same device.
                         //I just did this to avoid any issues.
                         block_align = format.Format.nBlockAlign as u32;
+                        period = device_period(&audio);
                     }
                 }
 
+                //Taken first so a new song's gain isn't overwritten by the last one.
+                if let Some(g) = LIVE_GAIN.take() {
+                    gain = g;
+                }
+
                 if let Some(g) = GAIN.take() {
                     if gain != g {
                         gain = g;
                     }
+                    next_gain = None;
+                    NEXT_GAIN = None;
+                    //New songs fade in.
+                    level = 0.0;
                     //Make sure there are no old samples before dramatically increasing the volume.
                     //Without this there were some serious jumps in volume when skipping songs.
                     cons.clear();
@@ -355,8 +956,12 @@ pub fn spawn_audio_threads(device: Device) {
                 }
 
                 //Sample-rate probably changed if this fails.
-                let padding = audio.GetCurrentPadding().unwrap();
-                let buffer_size = audio.GetBufferSize().unwrap();
+                let (Ok(padding), Ok(buffer_size)) =
+                    (audio.GetCurrentPadding(), audio.GetBufferSize())
+                else {
+                    lost = true;
+                    continue;
+                };
 
                 let n_frames = buffer_size - 1 - padding;
                 debug_assert!(n_frames < buffer_size - padding);
@@ -367,67 +972,298 @@ pub fn spawn_audio_threads(device: Device) {
                     continue;
                 }
 
-                let b = render.GetBuffer(n_frames).unwrap();
+                let Ok(b) = render.GetBuffer(n_frames) else {
+                    lost = true;
+                    continue;
+                };
                 let output = std::slice::from_raw_parts_mut(b, size);
                 let channels = format.Format.nChannels as usize;
-                let volume = VOLUME * gain;
+                let converter = ChannelConverter::new(2, channels);
+                frame.resize(channels, 0.0);
+                //Chosen once here instead of checking the format for every sample.
+                let sample_format = SampleFormat::new(&format).unwrap_or(SampleFormat::F32);
+                let (sample_size, write) = (sample_format.size(), sample_format.write());
+
+                if let Fade::Requested(duration) = FADE {
+                    let total = (duration.as_secs_f64() * sample_rate as f64) as u32;
+                    FADE = Fade::Fading {
+                        remaining: total,
+                        total,
+                    };
+                }
 
+                if let Some(next) = NEXT_GAIN.take() {
+                    next_gain = Some(next);
+                }
+
+                //Checked once per buffer so toggling the bypass is heard straight away.
+                let mut applied = output_gain(gain, BYPASS_GAIN);
+                let step = level_step(PAUSE_FADE_MS, sample_rate);
                 let mut iter = cons.pop_iter();
 
-                for bytes in output.chunks_mut(std::mem::size_of::<f32>() * channels) {
-                    let sample = iter.next().unwrap_or_default();
-                    bytes[0..4].copy_from_slice(&(sample * volume).to_le_bytes());
+                for bytes in output.chunks_mut(block_align as usize) {
+                    //The next song was decoded straight after the last one.
+                    //It can start up to one period late since the frames were counted by the decoder.
+                    if let Some(g) = count_down(&mut next_gain) {
+                        gain = g;
+                        applied = output_gain(gain, BYPASS_GAIN);
+                    }
+                    let fade = match &mut FADE {
+                        Fade::Fading { remaining, total } => {
+                            let fade = fade_gain(*remaining, *total);
+                            *remaining = remaining.saturating_sub(1);
+                            fade
+                        }
+                        Fade::Stopped => 0.0,
+                        Fade::Off | Fade::Requested(_) => 1.0,
+                    };
+                    //Read every frame so pausing halfway through a fade in turns around smoothly.
+                    level = approach(level, if PAUSED { 0.0 } else { 1.0 }, step);
+                    let volume = VOLUME * applied * fade * level;
+
+                    //The rest of the song is kept for when playback resumes.
+                    if PAUSED && level == 0.0 {
+                        bytes.fill(0);
+                        continue;
+                    }
 
-                    if channels > 1 {
-                        let sample = iter.next().unwrap_or_default();
-                        bytes[4..8].copy_from_slice(&(sample * volume).to_le_bytes());
+                    let left = iter.next().unwrap_or_default();
+                    let right = iter.next().unwrap_or_default();
+                    let stereo = if MONO {
+                        mono(left, right)
+                    } else {
+                        [left, right]
+                    };
+                    converter.frame(&stereo, &mut frame);
+                    for (sample, bytes) in frame.iter().zip(bytes.chunks_mut(sample_size)) {
+                        write(sample * volume, bytes);
                     }
                 }
 
-                render.ReleaseBuffer(n_frames, 0).unwrap();
+                if let Fade::Fading { remaining: 0, .. } = FADE {
+                    FADE = Fade::Stopped;
+                    LOADED = false;
+                    EVENTS.push(Event::Stop);
+                }
+
+                drop(iter);
+                LATENCY =
+                    output_latency(period, padding + n_frames, cons.occupied_len(), sample_rate);
+                BUFFERED = frames_to_duration((cons.occupied_len() / 2) as u32, sample_rate);
+
+                if render.ReleaseBuffer(n_frames, 0).is_err() {
+                    lost = true;
+                }
             }
         });
     }
 }
 
+///How long pausing, resuming and starting a song fade for. Zero doesn't fade.
+pub fn set_fade_ms(ms: u32) {
+    unsafe { PAUSE_FADE_MS = ms };
+}
+
+///How much the pause fade moves each frame.
+fn level_step(fade_ms: u32, sample_rate: u32) -> f32 {
+    if fade_ms == 0 || sample_rate == 0 {
+        1.0
+    } else {
+        1000.0 / (fade_ms as f32 * sample_rate as f32)
+    }
+}
+
+fn approach(level: f32, target: f32, step: f32) -> f32 {
+    if level < target {
+        (level + step).min(target)
+    } else {
+        (level - step).max(target)
+    }
+}
+
+pub fn set_mono(mono: bool) {
+    unsafe { MONO = mono };
+}
+
+///Halved so two channels at full scale don't clip.
+fn mono(left: f32, right: f32) -> [f32; 2] {
+    let sample = (left + right) / 2.0;
+    [sample, sample]
+}
+
 pub fn toggle_playback() {
-    unsafe { PAUSED = !PAUSED };
+    unsafe {
+        PAUSED = !PAUSED;
+        DEVICE_LOST &= PAUSED;
+    }
 }
 
 pub fn play() {
-    unsafe { PAUSED = false };
+    unsafe {
+        PAUSED = false;
+        DEVICE_LOST = false;
+    }
 }
 
 pub fn pause() {
     unsafe { PAUSED = true };
 }
 
+///Pause because the output device went away.
+///
+///Anything that resumes playback clears this, so the device is only switched on the first resume.
+pub fn pause_for_device_change() {
+    unsafe {
+        PAUSED = true;
+        DEVICE_LOST = true;
+    }
+}
+
+///Still paused by `pause_for_device_change`.
+pub fn device_lost() -> bool {
+    unsafe { DEVICE_LOST }
+}
+
+///Resume after the decoder has loaded the song and finished seeking.
+///
+///`play` takes effect straight away and would output the start of the song.
+pub fn play_when_ready() {
+    unsafe { EVENTS.push(Event::Play) };
+}
+
+///The volume from 0 to 100 whatever the curve is.
 pub fn get_volume() -> u8 {
-    unsafe { (VOLUME * VOLUME_REDUCTION) as u8 }
+    unsafe { PERCENT }
 }
 
+///Set the volume from 0 to 100, larger values are clamped.
 pub fn set_volume(volume: u8) {
     unsafe {
-        VOLUME = volume as f32 / VOLUME_REDUCTION;
+        PERCENT = volume.min(100);
+        VOLUME = volume_gain(PERCENT, VOLUME_CURVE);
     }
 }
 
-pub fn volume_up() {
+pub fn set_volume_curve(curve: VolumeCurve) {
     unsafe {
-        VOLUME = (VOLUME * VOLUME_REDUCTION + 5.0).clamp(0.0, 100.0) / VOLUME_REDUCTION;
+        VOLUME_CURVE = curve;
+        VOLUME = volume_gain(PERCENT, VOLUME_CURVE);
     }
 }
 
+///Both curves are silent at 0 and just as loud at 100.
+fn volume_gain(percent: u8, curve: VolumeCurve) -> f32 {
+    let max = 100.0 / VOLUME_REDUCTION;
+    match (curve, percent.min(100)) {
+        (_, 0) => 0.0,
+        (VolumeCurve::Linear, percent) => percent as f32 / VOLUME_REDUCTION,
+        (VolumeCurve::Logarithmic, percent) => {
+            let db = (percent as f32 / 100.0 - 1.0) * VOLUME_RANGE_DB;
+            max * 10.0f32.powf(db / 20.0)
+        }
+    }
+}
+
+pub fn volume_up() {
+    set_volume(step_up(get_volume()));
+}
+
 pub fn volume_down() {
+    set_volume(step_down(get_volume()));
+}
+
+///The next multiple of the step, a volume between steps is rounded up to the next one.
+fn step_up(percent: u8) -> u8 {
+    (percent / VOLUME_STEP + 1)
+        .saturating_mul(VOLUME_STEP)
+        .min(100)
+}
+
+fn step_down(percent: u8) -> u8 {
+    percent.min(100).div_ceil(VOLUME_STEP).saturating_sub(1) * VOLUME_STEP
+}
+
+///Speed up or slow down playback, between 0.5x and 3x.
+pub fn set_rate(rate: f32) {
     unsafe {
-        VOLUME = (VOLUME * VOLUME_REDUCTION - 5.0).clamp(0.0, 100.0) / VOLUME_REDUCTION;
+        RATE = rate.clamp(MIN_RATE, MAX_RATE);
+        if REMEMBER_RATE {
+            if let Some(folder) = &FOLDER {
+                FOLDER_RATES.insert(folder.clone(), RATE);
+            }
+        }
     }
 }
 
+pub fn rate() -> f32 {
+    unsafe { RATE }
+}
+
+pub fn rate_up() {
+    set_rate(rate() + RATE_STEP);
+}
+
+pub fn rate_down() {
+    set_rate(rate() - RATE_STEP);
+}
+
+///Keep the pitch when the rate changes, otherwise the audio is resampled.
+///
+///Resampling is cheaper but it sounds like a chipmunk.
+pub fn set_keep_pitch(keep_pitch: bool) {
+    unsafe { KEEP_PITCH = keep_pitch };
+}
+
+///Keep the rate for songs in the same folder, like the chapters of an audiobook.
+pub fn set_remember_rate(remember: bool) {
+    unsafe { REMEMBER_RATE = remember };
+}
+
+///The rate for a new song, normal speed unless the rate was remembered for its folder.
+unsafe fn song_rate(path: &Path) -> f32 {
+    FOLDER = path.parent().map(Path::to_path_buf);
+    match &FOLDER {
+        Some(folder) if REMEMBER_RATE => FOLDER_RATES.get(folder).copied().unwrap_or(1.0),
+        _ => 1.0,
+    }
+}
+
+///Seek to `pos` seconds, it's clamped to the song once it's loaded.
+///
+///Does nothing if no song is playing. The new position is shown straight away, even when paused.
 pub fn seek(pos: f32) {
     unsafe {
+        if !LOADED {
+            return;
+        }
         EVENTS.push(Event::Seek(pos));
-        ELAPSED = Duration::from_secs_f32(pos);
+        ELAPSED = Duration::from_secs_f32(clamp_seek(pos, DURATION));
+    }
+}
+
+///Seek to a fraction of the duration, clamped between zero and one.
+///
+///Like `seek` it does nothing until a song is loaded. The decoder applies the ratio, so it works before the duration is known.
+pub fn seek_ratio(ratio: f32) {
+    let ratio = ratio.clamp(0.0, 1.0);
+    unsafe {
+        if !LOADED {
+            return;
+        }
+        EVENTS.push(Event::SeekRatio(ratio));
+        //The duration isn't known until the song is loaded.
+        if !DURATION.is_zero() {
+            ELAPSED = DURATION.mul_f32(ratio);
+        }
+    }
+}
+
+///Seeking past the end would leave the elapsed time longer than the song.
+fn clamp_seek(pos: f32, duration: Duration) -> f32 {
+    if duration.is_zero() {
+        pos.max(0.0)
+    } else {
+        pos.clamp(0.0, duration.as_secs_f32())
     }
 }
 
@@ -439,26 +1275,195 @@ pub fn seek_backward() {
     unsafe { EVENTS.push(Event::SeekBackward) };
 }
 
+///Seek into a song that was just played, negative positions are from the end.
+///
+///If the position is outside the song it overflows into the next or previous song.
+pub fn skim(pos: f32) {
+    unsafe {
+        if pos > 0.0 {
+            ELAPSED = Duration::from_secs_f32(pos);
+        }
+        EVENTS.push(Event::Skim(pos));
+    }
+}
+
+///Let seeking carry on into the next or previous song instead of stopping at the ends.
+pub fn set_seek_across(across: bool) {
+    unsafe { SEEK_ACROSS = across };
+}
+
+///How far the last seek went past the end of the song, negative if it went before the start.
+///
+///The UI should play the next or previous song and seek into it by this much.
+pub fn take_overflow() -> Option<f32> {
+    unsafe { OVERFLOW.take() }
+}
+
+///Seek the decoder or record how far the target is outside of the song.
+unsafe fn seek_within(sym: &mut Symphonia, target: f32, across: bool) {
+    let duration = sym.duration().as_secs_f32();
+    info!("Seeking {} / {}", target, duration);
+    match seek_target(target, duration, across) {
+        Ok(pos) => sym.seek(pos),
+        Err(overflow) => OVERFLOW = Some(overflow),
+    }
+}
+
+///The position to seek to, or how far past the song it is.
+fn seek_target(target: f32, duration: f32, across: bool) -> Result<f32, f32> {
+    if !across {
+        return Ok(target.max(0.0));
+    }
+    if target < 0.0 {
+        Err(target)
+    } else if duration > 0.0 && target > duration {
+        Err(target - duration)
+    } else {
+        Ok(target)
+    }
+}
+
 //This is mainly for testing.
 pub fn play_path<P: AsRef<Path>>(path: P) {
     unsafe {
         PAUSED = false;
+        LOADED = true;
+        FADE = Fade::Off;
         ELAPSED = Duration::from_secs(0);
-        EVENTS.push(Event::Song(path.as_ref().to_path_buf(), 0.5));
+        RATE = song_rate(path.as_ref());
+        EVENTS.push(Event::Song(path.as_ref().to_path_buf(), DEFAULT_GAIN));
     }
 }
 
 pub fn play_song(song: &Song) {
     unsafe {
         PAUSED = false;
+        LOADED = true;
+        DEVICE_LOST = false;
+        LOOP_A = None;
+        LOOP_B = None;
+        FADE = Fade::Off;
+        STARTS += 1;
         ELAPSED = Duration::from_secs(0);
+        //Unknown until the decoder has opened it.
+        DURATION = Duration::from_secs(0);
+        RATE = song_rate(Path::new(&song.path));
         EVENTS.push(Event::Song(
             PathBuf::from(&song.path),
-            if song.gain == 0.0 { 0.5 } else { song.gain },
+            song_gain(song, REPLAY_GAIN),
         ));
     }
 }
 
+fn song_gain(song: &Song, mode: ReplayGainMode) -> f32 {
+    let gain = match mode {
+        ReplayGainMode::Off => 0.0,
+        ReplayGainMode::Track => song.gain,
+        ReplayGainMode::Album if song.album_gain == 0.0 => song.gain,
+        ReplayGainMode::Album => song.album_gain,
+    };
+    if gain == 0.0 {
+        DEFAULT_GAIN
+    } else {
+        gain
+    }
+}
+
+///Boost or cut one of the equalizer [`BANDS`], clamped to [`MAX_GAIN`] dB.
+pub fn set_band_gain(index: usize, db: f32) {
+    unsafe {
+        if let Some(gain) = EQ_GAINS.get_mut(index) {
+            *gain = db.clamp(-MAX_GAIN, MAX_GAIN);
+        }
+    }
+}
+
+pub fn band_gains() -> [f32; 10] {
+    unsafe { EQ_GAINS }
+}
+
+///Turn the equalizer off and on to compare them. Returns true if it's now bypassed.
+pub fn toggle_eq_bypass() -> bool {
+    unsafe {
+        EQ_BYPASS = !EQ_BYPASS;
+        EQ_BYPASS
+    }
+}
+
+///Mix the end of each song with the start of the next for `secs`, zero turns it off.
+pub fn set_crossfade(secs: f32) {
+    unsafe { CROSSFADE = secs.max(0.0) };
+}
+
+///Set where the A-B loop jumps back to.
+pub fn set_loop_a(pos: f32) {
+    unsafe { LOOP_A = Some(clamp_seek(pos, DURATION)) };
+}
+
+///Set where the A-B loop jumps back from. It's fine to set it before A.
+pub fn set_loop_b(pos: f32) {
+    unsafe { LOOP_B = Some(clamp_seek(pos, DURATION)) };
+}
+
+pub fn clear_loop() {
+    unsafe {
+        LOOP_A = None;
+        LOOP_B = None;
+    }
+}
+
+///The points that were set, in the order they were given.
+pub fn loop_points() -> (Option<f32>, Option<f32>) {
+    unsafe { (LOOP_A, LOOP_B) }
+}
+
+///Start and end of the loop, swapped if B is before A.
+fn ordered(a: Option<f32>, b: Option<f32>) -> Option<(f32, f32)> {
+    match (a?, b?) {
+        (a, b) if a == b => None,
+        (a, b) if b < a => Some((b, a)),
+        range => Some(range),
+    }
+}
+
+///The playing song switches to the new gain straight away.
+pub fn set_replay_gain(mode: ReplayGainMode, playing: Option<&Song>) {
+    unsafe {
+        REPLAY_GAIN = mode;
+        if let Some(song) = playing {
+            EVENTS.push(Event::Gain(song_gain(song, mode)));
+            //The next song was opened with the old gain.
+            UP_NEXT = None;
+        }
+    }
+}
+
+///Bypassing plays every song as if it wasn't tagged.
+fn output_gain(song_gain: f32, bypass: bool) -> f32 {
+    if bypass {
+        DEFAULT_GAIN
+    } else {
+        song_gain
+    }
+}
+
+///Turn replay gain off and on to compare them. Returns true if it's now bypassed.
+pub fn toggle_gain_bypass() -> bool {
+    unsafe {
+        BYPASS_GAIN = !BYPASS_GAIN;
+        BYPASS_GAIN
+    }
+}
+
+pub fn gain_bypassed() -> bool {
+    unsafe { BYPASS_GAIN }
+}
+
+///The name of the device playback switched to after the one in use was unplugged.
+pub fn take_device_switch() -> Option<String> {
+    unsafe { DEVICE_SWITCH.take() }
+}
+
 pub fn set_output_device(device: &str) {
     let d = devices();
     unsafe {
@@ -480,6 +1485,62 @@ pub fn play_index(songs: &mut Index<Song>, i: usize) {
     }
 }
 
+fn same_album(a: &Song, b: &Song) -> bool {
+    a.artist == b.artist && a.album == b.album
+}
+
+///The first song of the next album in the queue.
+fn next_album(songs: &[Song], index: usize) -> Option<usize> {
+    let current = songs.get(index)?;
+    songs[index..]
+        .iter()
+        .position(|song| !same_album(song, current))
+        .map(|i| index + i)
+}
+
+///The first song of the album before the current one.
+fn prev_album(songs: &[Song], index: usize) -> Option<usize> {
+    let current = songs.get(index)?;
+    let start = songs[..index]
+        .iter()
+        .rposition(|song| !same_album(song, current))?;
+    let prev = &songs[start];
+    let first = songs[..start]
+        .iter()
+        .rposition(|song| !same_album(song, prev))
+        .map_or(0, |i| i + 1);
+    Some(first)
+}
+
+///Skip the rest of the album. Does nothing if there are no more albums.
+pub fn jump_to_next_album(songs: &mut Index<Song>) {
+    if let Some(i) = songs.index().and_then(|index| next_album(songs, index)) {
+        play_index(songs, i);
+    }
+}
+
+///Go back to the start of the previous album.
+pub fn jump_to_prev_album(songs: &mut Index<Song>) {
+    if let Some(i) = songs.index().and_then(|index| prev_album(songs, index)) {
+        play_index(songs, i);
+    }
+}
+
+///Insert songs before `position`, which is clamped to the end of the queue.
+///
+///The playing song stays selected when songs are inserted before it.
+pub fn add_songs_at(songs: &mut Index<Song>, new: Vec<Song>, position: usize) {
+    let position = position.min(songs.len());
+    let len = new.len();
+    songs.splice(position..position, new);
+
+    if let Some(playing) = songs.index() {
+        if position <= playing {
+            songs.select(Some(playing + len));
+        }
+    }
+}
+
 pub fn delete(songs: &mut Index<Song>, index: usize) {
     if songs.is_empty() {
         return;
@@ -491,7 +1552,7 @@ pub fn delete(songs: &mut Index<Song>, index: usize) {
         let len = songs.len();
         if len == 0 {
             *songs = Index::default();
-            unsafe { EVENTS.push(Event::Stop) };
+            stop();
         } else if index == playing && index == 0 {
             songs.select(Some(0));
             if let Some(song) = songs.selected() {
@@ -508,18 +1569,266 @@ pub fn delete(songs: &mut Index<Song>, index: usize) {
     };
 }
 
+pub fn stop() {
+    unsafe {
+        LOADED = false;
+        EVENTS.push(Event::Stop);
+    }
+}
+
+///Lower the volume to zero over `duration` and then stop.
+///
+///Avoids the pop from cutting the audio off mid-sample.
+pub fn fade_out_and_stop(duration: Duration) {
+    unsafe {
+        //Nothing is being output so there is nothing to fade.
+        if PAUSED {
+            FADE = Fade::Off;
+            return stop();
+        }
+        FADE = Fade::Requested(duration);
+    }
+}
+
 pub fn clear(songs: &mut Index<Song>) {
-    unsafe { EVENTS.push(Event::Stop) };
+    stop();
     songs.clear();
 }
 
 pub fn clear_except_playing(songs: &mut Index<Song>) {
-    if let Some(index) = songs.index() {
-        let playing = songs.remove(index);
+    //The index is left behind when the queue is cleared.
+    if let Some(playing) = songs.selected().cloned() {
         *songs = Index::new(vec![playing], Some(0));
     }
 }
 
+pub fn set_repeat(mode: RepeatMode) {
+    unsafe { REPEAT = mode };
+}
+
+///Off, queue, one and back to off. Returns the new mode.
+pub fn toggle_repeat() -> RepeatMode {
+    unsafe {
+        REPEAT = REPEAT.next();
+        REPEAT
+    }
+}
+
+pub fn repeat() -> RepeatMode {
+    unsafe { REPEAT }
+}
+
+///Play the queue in a random order. Returns true if it's now shuffled.
+pub fn toggle_shuffle(songs: &Index<Song>) -> bool {
+    unsafe {
+        SHUFFLE.on = !SHUFFLE.on;
+        SHUFFLE.reset(songs.len(), songs.index(), shuffle::seed());
+        SHUFFLE.on
+    }
+}
+
+pub fn shuffled() -> bool {
+    unsafe { SHUFFLE.on }
+}
+
+///Skip to the next song, the queue wraps around whatever the repeat mode is.
+pub fn next_song(songs: &mut Index<Song>) {
+    let next = songs
+        .index()
+        .and_then(|index| following(songs.len(), index, RepeatMode::Queue));
+    match next {
+        Some(i) => advance(songs, i),
+        None => {
+            songs.down();
+            if let Some(song) = songs.selected() {
+                play_song(song);
+            }
+        }
+    }
+}
+
+///Go back to the last song, when shuffled this is the one that was played before.
+pub fn prev_song(songs: &mut Index<Song>) {
+    if shuffled() {
+        if let Some(i) = unsafe { SHUFFLE.back(songs.len()) } {
+            return play_index(songs, i);
+        }
+    }
+    songs.up();
+    if let Some(song) = songs.selected() {
+        play_song(song);
+    }
+}
+
+///The songs before and after the current one, following the repeat mode and shuffle order.
+///
+///Seeking carries on into these, `None` when the queue doesn't repeat past that end.
+pub fn neighbours(songs: &Index<Song>) -> (Option<usize>, Option<usize>) {
+    let (len, mode) = (songs.len(), repeat());
+    let Some(index) = songs.index().filter(|&index| index < len) else {
+        return (None, None);
+    };
+    //Like `prev_song`, the queue order is used once the shuffle history runs out.
+    let last = if shuffled() {
+        unsafe { SHUFFLE.last(len) }
+    } else {
+        None
+    };
+    let previous = match mode {
+        RepeatMode::One => Some(index),
+        _ if last.is_some() => last,
+        RepeatMode::Queue => Some(index.checked_sub(1).unwrap_or(len - 1)),
+        RepeatMode::Off => index.checked_sub(1),
+    };
+    (previous, following(len, index, mode))
+}
+
+///Play the song at `i` from `pos`, a neighbour of the current song.
+///
+///Negative positions are from the end of the previous song, which keeps the shuffle history in order.
+pub fn skim_into(songs: &mut Index<Song>, i: usize, pos: f32) {
+    if let (true, Some(current)) = (shuffled(), songs.index()) {
+        if current != i {
+            unsafe {
+                if pos < 0.0 {
+                    SHUFFLE.back(songs.len());
+                } else {
+                    SHUFFLE.advance(current, i);
+                }
+            }
+        }
+    }
+    play_index(songs, i);
+    skim(pos);
+}
+
+///The song that will be played once the current one finishes.
+pub fn up_next(songs: &Index<Song>) -> Option<&Song> {
+    let index = songs.index()?;
+    songs.get(following(songs.len(), index, repeat())?)
+}
+
+///The song after the one at `index`, picked at random when shuffled.
+fn following(len: usize, index: usize, mode: RepeatMode) -> Option<usize> {
+    unsafe {
+        if SHUFFLE.on {
+            SHUFFLE.peek(len, index, mode)
+        } else {
+            next_index(len, index, mode)
+        }
+    }
+}
+
+///Play the song at `i` and remember where it came from.
+fn advance(songs: &mut Index<Song>, i: usize) {
+    if let (true, Some(current)) = (shuffled(), songs.index()) {
+        unsafe { SHUFFLE.advance(current, i) };
+    }
+    play_index(songs, i);
+}
+
+///Let the decoder open the next song before the current one ends.
+///
+///Call this whenever the queue changes, it does nothing if the song is the same.
+pub fn prepare_next(current: Option<&Song>, song: Option<&Song>) {
+    unsafe {
+        let album = current.zip(song).is_some_and(|(a, b)| follows(a, b));
+        let path = song.map(|song| (PathBuf::from(&song.path), album));
+        if UP_NEXT == path {
+            return;
+        }
+        UP_NEXT = path;
+        let next = song.map(|song| UpNext {
+            path: PathBuf::from(&song.path),
+            gain: song_gain(song, REPLAY_GAIN),
+            album,
+        });
+        EVENTS.push(Event::UpNext(next));
+    }
+}
+
+///`next` is the following track on the same album and disc.
+fn follows(current: &Song, next: &Song) -> bool {
+    current.artist == next.artist
+        && current.album == next.album
+        && current.disc_number == next.disc_number
+        && current.track_number.checked_add(1) == Some(next.track_number)
+}
+
+///How the last song led into the next one, if it didn't stop in between.
+pub fn transition() -> Option<Transition> {
+    unsafe { TRANSITION }
+}
+
+///The song to play after the one at `index` finishes.
+fn next_index(len: usize, index: usize, mode: RepeatMode) -> Option<usize> {
+    if index >= len {
+        return None;
+    }
+    match mode {
+        RepeatMode::One => Some(index),
+        RepeatMode::Queue => Some((index + 1) % len),
+        RepeatMode::Off if index + 1 < len => Some(index + 1),
+        RepeatMode::Off => None,
+    }
+}
+
+///Call once `play_next` returns true.
+///
+///Returns false if the queue is over and nothing was played.
+pub fn finished(songs: &mut Index<Song>) -> bool {
+    let failed = unsafe { std::mem::take(&mut FAILED) };
+    let failures = unsafe {
+        FAILURES = if failed { FAILURES + 1 } else { 0 };
+        FAILURES
+    };
+    //Repeating the queue or shuffling would try the same songs forever.
+    if failures >= songs.len() {
+        unsafe { FAILURES = 0 };
+        gonk_core::log!("None of the songs in the queue could be played.");
+        stop();
+        return false;
+    }
+
+    //The decoder already went on to the next song.
+    if let Some(path) = unsafe { GAPLESS.take() } {
+        let next = songs
+            .index()
+            .and_then(|index| following(songs.len(), index, repeat()));
+        if let Some(i) = next.filter(|&i| Path::new(&songs[i].path) == path) {
+            if let (true, Some(current)) = (shuffled(), songs.index()) {
+                unsafe { SHUFFLE.advance(current, i) };
+            }
+            songs.select(Some(i));
+            unsafe {
+                RATE = song_rate(&path);
+                STARTS += 1;
+            }
+            return true;
+        }
+    }
+
+    let mode = match repeat() {
+        RepeatMode::One if failed => RepeatMode::Off,
+        mode => mode,
+    };
+    match songs
+        .index()
+        .and_then(|index| following(songs.len(), index, mode))
+    {
+        //Playing the song again goes through the decoder from the start,
+        //`NEXT` was already taken so it won't finish twice.
+        Some(i) => {
+            advance(songs, i);
+            true
+        }
+        None => {
+            stop();
+            false
+        }
+    }
+}
+
 pub fn is_paused() -> bool {
     unsafe { PAUSED }
 }
@@ -536,10 +1845,558 @@ pub fn play_next() -> bool {
     }
 }
 
+unsafe fn device_period(audio: &IAudioClient) -> Duration {
+    let (default, _min) = audio.GetDevicePeriod().unwrap();
+    //Reference time is in 100 nanosecond units.
+    Duration::from_nanos(default as u64 * 100)
+}
+
+fn frames_to_duration(frames: u32, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64(frames as f64 / sample_rate as f64)
+}
+
+///Low and high water marks in samples.
+fn watermarks(duration: Duration, sample_rate: u32) -> (usize, usize) {
+    //Assumes the decoded samples are stereo.
+    let high = (duration.as_secs_f64() * sample_rate as f64) as usize * 2;
+    (high / 2, high)
+}
+
+///Count down one frame, returns the gain once the frames run out.
+fn count_down(next_gain: &mut Option<(f32, usize)>) -> Option<f32> {
+    match *next_gain {
+        Some((gain, 0)) => {
+            *next_gain = None;
+            Some(gain)
+        }
+        Some((gain, frames)) => {
+            *next_gain = Some((gain, frames - 1));
+            None
+        }
+        None => None,
+    }
+}
+
+///Decoding stops at the high water mark and doesn't start again until
+///the buffer drops below the low water mark.
+fn keep_filling(filling: bool, buffered: usize, (low, high): (usize, usize)) -> bool {
+    if buffered >= high {
+        false
+    } else if buffered < low {
+        true
+    } else {
+        filling
+    }
+}
+
+fn output_latency(
+    period: Duration,
+    device_frames: u32,
+    ring_samples: usize,
+    sample_rate: u32,
+) -> Duration {
+    //Assumes the decoded samples are stereo.
+    let ring_frames = (ring_samples / 2) as u32;
+    period + frames_to_duration(device_frames + ring_frames, sample_rate)
+}
+
+///Linear volume ramp from 1.0 down to 0.0.
+fn fade_gain(remaining: u32, total: u32) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        remaining as f32 / total as f32
+    }
+}
+
+fn playback_elapsed(decoder: Duration, latency: Duration) -> Duration {
+    decoder.saturating_sub(latency)
+}
+
+///The position of the audio that is currently coming out of the speakers.
+///
+///This is the decoder position minus the output latency.
+///The latency is scaled by the rate so the position stays in media time.
 pub fn elapsed() -> Duration {
-    unsafe { ELAPSED }
+    unsafe { playback_elapsed(ELAPSED, LATENCY.mul_f32(RATE)) }
+}
+
+///How long it takes for decoded audio to reach the output device.
+///
+///Updated every time the device asks for samples.
+pub fn latency() -> Duration {
+    unsafe { LATENCY }
+}
+
+///How much decoded audio is waiting to be played.
+pub fn buffered() -> Duration {
+    unsafe { BUFFERED }
 }
 
 pub fn duration() -> Duration {
     unsafe { DURATION }
 }
+
+///Changes whenever a song starts playing, from the start or straight after the last one.
+pub fn starts() -> u64 {
+    unsafe { STARTS }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    ///An ID3v2.3 tag with a title and artist.
+    fn id3(title: &str, artist: &str) -> Vec<u8> {
+        let mut frames = Vec::new();
+        for (id, text) in [(b"TIT2", title), (b"TPE1", artist)] {
+            frames.extend(id);
+            //The encoding byte comes before the text.
+            frames.extend((text.len() as u32 + 1).to_be_bytes());
+            frames.extend([0, 0, 0]);
+            frames.extend(text.as_bytes());
+        }
+
+        let mut tag = Vec::from(*b"ID3\x03\x00\x00");
+        //The size is stored seven bits per byte.
+        let len = frames.len() as u32;
+        tag.extend([len >> 21, len >> 14, len >> 7, len].map(|b| (b & 0x7F) as u8));
+        tag.extend(frames);
+        tag
+    }
+
+    ///Write one second of 44100 Hz stereo silence to an AIFF file, with `tag` in an `ID3 ` chunk.
+    fn aiff(path: &Path, tag: &[u8]) {
+        const FRAMES: u32 = 44100;
+        let data_len = FRAMES * 2 * 2;
+        //Chunks are padded to an even length.
+        let tag_len = if tag.is_empty() {
+            0
+        } else {
+            8 + tag.len().next_multiple_of(2) as u32
+        };
+
+        let mut bytes = Vec::from(*b"FORM");
+        bytes.extend((4 + 8 + 18 + 8 + 8 + data_len + tag_len).to_be_bytes());
+        bytes.extend(b"AIFF");
+
+        bytes.extend(b"COMM");
+        bytes.extend(18u32.to_be_bytes());
+        bytes.extend(2u16.to_be_bytes());
+        bytes.extend(FRAMES.to_be_bytes());
+        bytes.extend(16u16.to_be_bytes());
+        //44100 as an 80-bit extended float.
+        bytes.extend([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+
+        bytes.extend(b"SSND");
+        bytes.extend((8 + data_len).to_be_bytes());
+        bytes.extend(0u32.to_be_bytes());
+        bytes.extend(0u32.to_be_bytes());
+        bytes.extend(vec![0; data_len as usize]);
+
+        if !tag.is_empty() {
+            bytes.extend(b"ID3 ");
+            bytes.extend((tag.len() as u32).to_be_bytes());
+            bytes.extend(tag);
+            if tag.len() % 2 == 1 {
+                bytes.push(0);
+            }
+        }
+
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn tone() {
+        let total = TONE_SAMPLE_RATE as usize * TONE_DURATION_MS / 1000;
+        //Fades in and out so it doesn't click.
+        assert_eq!(tone_sample(0), 0.0);
+        assert!(tone_sample(total - 1).abs() < 0.01);
+        assert!((0..total).all(|i| tone_sample(i).abs() <= 1.0));
+        assert!((0..total).any(|i| tone_sample(i) > 0.99));
+    }
+
+    #[test]
+    fn aiff_decode() {
+        let path = std::env::temp_dir().join("gonk_test.aiff");
+        aiff(&path, &id3("Silence", "Nobody"));
+
+        let mut sym = Symphonia::new(&path).unwrap();
+        assert_eq!(sym.sample_rate(), 44100);
+        assert_eq!(sym.duration(), Duration::from_secs(1));
+
+        let packet = sym.next_packet().unwrap();
+        assert!(!packet.samples().is_empty());
+
+        let song = Song::try_from(path.as_path()).unwrap();
+        assert_eq!(song.path, path.to_string_lossy());
+        assert_eq!(song.title, "Silence");
+        assert_eq!(song.artist, "Nobody");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn aiff_scan() {
+        let dir = std::env::temp_dir().join("gonk_aiff_scan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        aiff(&dir.join("Silence.AIF"), &id3("Silence", "Nobody"));
+
+        let songs: Vec<Song> = gonk_core::db::read_dir(&dir.to_string_lossy(), false)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(songs.len(), 1);
+
+        let db = gonk_core::vdb::Database::from_songs(songs);
+        assert_eq!(db.artists(), ["Nobody"]);
+        let album = &db.albums_by_artist("Nobody")[0];
+        assert_eq!(album.songs[0].title, "Silence");
+        assert!(db
+            .song("Nobody", &album.title, &album.songs[0].path)
+            .is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prebuffer() {
+        let path = std::env::temp_dir().join("gonk_prebuffer.aiff");
+        aiff(&path, &[]);
+
+        //A single packet of read ahead still decodes the whole file.
+        let mut sym = Symphonia::new_with_prebuffer(&path, 1).unwrap();
+        assert!(sym.next_packet().is_some());
+        assert!(sym.next_packet().is_some());
+        let elapsed = sym.elapsed();
+        assert!(elapsed > Duration::ZERO);
+
+        //Packets read before the seek are thrown away.
+        sym.seek(0.0);
+        assert!(sym.next_packet().is_some());
+        assert!(sym.elapsed() < elapsed);
+
+        drop(sym);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn elapsed() {
+        let decoder = Duration::from_secs(10);
+        let buffered = frames_to_duration(22050, 44100);
+        assert_eq!(buffered, Duration::from_millis(500));
+        assert_eq!(
+            decoder - playback_elapsed(decoder, buffered),
+            Duration::from_millis(500)
+        );
+
+        //Can't go below zero when a song starts.
+        assert_eq!(
+            playback_elapsed(Duration::from_millis(100), buffered),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn latency() {
+        let period = Duration::from_millis(10);
+
+        //Nothing buffered is just the device period.
+        assert_eq!(output_latency(period, 0, 0, 44100), period);
+
+        for (sample_rate, device_frames, ring_samples, expected) in [
+            (44100, 441, 882, 30),
+            (48000, 480, 960, 30),
+            (96000, 960, 1920, 30),
+            (192000, 0, 4096 * 4, 52),
+            (48000, 4800, 0, 110),
+        ] {
+            let latency = output_latency(period, device_frames, ring_samples, sample_rate);
+            assert_eq!(latency.as_millis(), expected, "{sample_rate} Hz");
+        }
+    }
+
+    #[test]
+    fn albums() {
+        let song = |artist: &str, album: &str| Song::test(artist, album, "title");
+        let songs = [
+            song("a", "x"),
+            song("a", "x"),
+            song("a", "y"),
+            song("b", "x"),
+            song("b", "x"),
+            song("b", "x"),
+        ];
+
+        assert_eq!(next_album(&songs, 0), Some(2));
+        assert_eq!(next_album(&songs, 2), Some(3));
+        //The last album has nothing after it.
+        assert_eq!(next_album(&songs, 4), None);
+
+        assert_eq!(prev_album(&songs, 4), Some(2));
+        assert_eq!(prev_album(&songs, 2), Some(0));
+        assert_eq!(prev_album(&songs, 1), None);
+        assert_eq!(next_album(&songs, 6), None);
+        assert_eq!(prev_album(&songs, 6), None);
+    }
+
+    #[test]
+    fn insert() {
+        let song = |title: &str| Song::test("artist", "album", title);
+        let titles = |songs: &Index<Song>| -> Vec<String> {
+            songs.iter().map(|song| song.title.clone()).collect()
+        };
+        let mut songs = Index::new(vec![song("a"), song("b"), song("c")], Some(1));
+
+        //After the playing song.
+        add_songs_at(&mut songs, vec![song("d")], 2);
+        assert_eq!(titles(&songs), ["a", "b", "d", "c"]);
+        assert_eq!(songs.selected().unwrap().title, "b");
+
+        //At the playing song, it moves down.
+        add_songs_at(&mut songs, vec![song("e"), song("f")], 1);
+        assert_eq!(titles(&songs), ["a", "e", "f", "b", "d", "c"]);
+        assert_eq!(songs.selected().unwrap().title, "b");
+
+        add_songs_at(&mut songs, vec![song("g")], 0);
+        assert_eq!(songs.index(), Some(4));
+
+        add_songs_at(&mut songs, vec![song("h")], usize::MAX);
+        assert_eq!(titles(&songs).last().unwrap(), "h");
+        assert_eq!(songs.selected().unwrap().title, "b");
+
+        //Nothing is selected in an empty queue.
+        let mut songs = Index::default();
+        add_songs_at(&mut songs, vec![song("a")], 3);
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs.index(), None);
+    }
+
+    #[test]
+    fn gain() {
+        let tagged = Song {
+            gain: 0.8,
+            album_gain: 0.6,
+            ..Song::example()
+        };
+        let untagged = Song {
+            gain: 0.0,
+            album_gain: 0.0,
+            ..Song::example()
+        };
+        let track = |song| song_gain(song, ReplayGainMode::Track);
+
+        assert_eq!(output_gain(track(&tagged), false), 0.8);
+        assert_eq!(output_gain(track(&untagged), false), DEFAULT_GAIN);
+
+        //Tagged songs are played at the same level as untagged ones.
+        assert_eq!(output_gain(track(&tagged), true), DEFAULT_GAIN);
+        assert_eq!(output_gain(track(&untagged), true), DEFAULT_GAIN);
+
+        assert_eq!(song_gain(&tagged, ReplayGainMode::Album), 0.6);
+        assert_eq!(song_gain(&tagged, ReplayGainMode::Off), DEFAULT_GAIN);
+        assert_eq!(song_gain(&untagged, ReplayGainMode::Album), DEFAULT_GAIN);
+
+        //Only the track was tagged.
+        let track_only = Song {
+            album_gain: 0.0,
+            ..tagged
+        };
+        assert_eq!(song_gain(&track_only, ReplayGainMode::Album), 0.8);
+    }
+
+    #[test]
+    fn seeking() {
+        assert_eq!(seek_target(15.0, 60.0, true), Ok(15.0));
+        assert_eq!(seek_target(60.0, 60.0, true), Ok(60.0));
+        assert_eq!(seek_target(65.0, 60.0, true), Err(5.0));
+        assert_eq!(seek_target(-4.0, 60.0, true), Err(-4.0));
+
+        //The duration isn't known yet.
+        assert_eq!(seek_target(65.0, 0.0, true), Ok(65.0));
+
+        //The old behaviour.
+        assert_eq!(seek_target(65.0, 60.0, false), Ok(65.0));
+        assert_eq!(seek_target(-4.0, 60.0, false), Ok(0.0));
+    }
+
+    #[test]
+    fn clamped_seek() {
+        let duration = Duration::from_secs(30);
+        assert_eq!(clamp_seek(12.0, duration), 12.0);
+        assert_eq!(clamp_seek(45.0, duration), 30.0);
+        assert_eq!(clamp_seek(-3.0, duration), 0.0);
+
+        //The song hasn't been loaded yet.
+        assert_eq!(clamp_seek(45.0, Duration::ZERO), 45.0);
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(ordered(Some(10.0), Some(20.0)), Some((10.0, 20.0)));
+        //B was set before A.
+        assert_eq!(ordered(Some(20.0), Some(10.0)), Some((10.0, 20.0)));
+        assert_eq!(ordered(Some(10.0), None), None);
+        assert_eq!(ordered(None, Some(10.0)), None);
+        assert_eq!(ordered(Some(10.0), Some(10.0)), None);
+    }
+
+    #[test]
+    fn mono_mix() {
+        assert_eq!(mono(1.0, 0.0), [0.5, 0.5]);
+        assert_eq!(mono(1.0, 1.0), [1.0, 1.0]);
+        assert_eq!(mono(-1.0, -1.0), [-1.0, -1.0]);
+        assert_eq!(mono(0.5, -0.5), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn volume_curves() {
+        for curve in [VolumeCurve::Linear, VolumeCurve::Logarithmic] {
+            assert_eq!(volume_gain(0, curve), 0.0);
+            assert_eq!(volume_gain(100, curve), 100.0 / VOLUME_REDUCTION);
+            assert_eq!(volume_gain(255, curve), volume_gain(100, curve));
+            for percent in 0..100 {
+                assert!(volume_gain(percent, curve) < volume_gain(percent + 1, curve));
+            }
+        }
+        //Half way is 20 dB down instead of 6 dB.
+        let half =
+            volume_gain(50, VolumeCurve::Logarithmic) / volume_gain(100, VolumeCurve::Logarithmic);
+        assert!((half - 0.1).abs() < 1e-6, "{half}");
+        assert!(volume_gain(50, VolumeCurve::Logarithmic) < volume_gain(50, VolumeCurve::Linear));
+    }
+
+    #[test]
+    fn volume_steps() {
+        let mut percent = 0;
+        for expected in (5..=100).step_by(5) {
+            percent = step_up(percent);
+            assert_eq!(percent, expected);
+        }
+        assert_eq!(step_up(100), 100);
+        for expected in (0..=95).rev().step_by(5) {
+            percent = step_down(percent);
+            assert_eq!(percent, expected);
+        }
+        assert_eq!(step_down(0), 0);
+
+        //Set by the user or an old config.
+        assert_eq!(step_up(13), 15);
+        assert_eq!(step_down(13), 10);
+        assert_eq!(step_up(98), 100);
+        assert_eq!(step_down(255), 95);
+    }
+
+    #[test]
+    fn repeat_modes() {
+        assert_eq!(next_index(3, 1, RepeatMode::Off), Some(2));
+        assert_eq!(next_index(3, 2, RepeatMode::Off), None);
+        assert_eq!(next_index(3, 2, RepeatMode::Queue), Some(0));
+        assert_eq!(next_index(3, 2, RepeatMode::One), Some(2));
+        assert_eq!(next_index(1, 0, RepeatMode::Queue), Some(0));
+
+        //The queue was cleared while the song was playing.
+        assert_eq!(next_index(0, 0, RepeatMode::One), None);
+
+        let mut mode = RepeatMode::Off;
+        for expected in [RepeatMode::Queue, RepeatMode::One, RepeatMode::Off] {
+            mode = mode.next();
+            assert_eq!(mode, expected);
+        }
+    }
+
+    #[test]
+    fn gapless_gain() {
+        //Two frames of the last song are still in the ring buffer.
+        let mut next_gain = Some((0.5, 2));
+        assert_eq!(count_down(&mut next_gain), None);
+        assert_eq!(count_down(&mut next_gain), None);
+        assert_eq!(count_down(&mut next_gain), Some(0.5));
+        assert_eq!(next_gain, None);
+        assert_eq!(count_down(&mut next_gain), None);
+    }
+
+    #[test]
+    fn follows_album() {
+        let track = |album: &str, disc: u8, track: u8| Song {
+            disc_number: disc,
+            track_number: track,
+            ..Song::test("artist", album, "title")
+        };
+        assert!(follows(&track("x", 1, 1), &track("x", 1, 2)));
+        assert!(!follows(&track("x", 1, 2), &track("x", 1, 1)));
+        assert!(!follows(&track("x", 1, 1), &track("x", 1, 3)));
+        //The next disc starts again from track one.
+        assert!(!follows(&track("x", 1, 9), &track("x", 2, 1)));
+        assert!(!follows(&track("x", 1, 1), &track("y", 1, 2)));
+        assert!(!follows(&track("x", 1, 255), &track("x", 1, 0)));
+    }
+
+    #[test]
+    fn backpressure() {
+        for sample_rate in [44100, 192000] {
+            let (low, high) = watermarks(Duration::from_millis(500), sample_rate);
+            assert_eq!(high, sample_rate as usize);
+            assert_eq!(low, high / 2);
+
+            //A fake decoder with fixed size packets and a device that
+            //takes a bit less than a packet each time.
+            let packet = 2304;
+            let mut buffered = 0;
+            let mut filling = true;
+            let mut decoded = 0;
+            let mut restarts = 0;
+
+            for _ in 0..100_000 {
+                let was_filling = filling;
+                filling = keep_filling(filling, buffered, (low, high));
+                if filling {
+                    //Only restarts once drained below the low mark.
+                    if !was_filling {
+                        assert!(buffered < low);
+                        restarts += 1;
+                    }
+                    buffered += packet;
+                    decoded += 1;
+                }
+                assert!(buffered < high + packet);
+                buffered = buffered.saturating_sub(2000);
+            }
+
+            assert!(restarts > 1);
+            //Nothing is decoded that isn't played.
+            assert!(decoded * packet <= 100_000 * 2000 + high + packet);
+        }
+    }
+
+    #[test]
+    fn pause_fade() {
+        let step = level_step(10, 48000);
+        assert_eq!(step, 1.0 / 480.0);
+        assert_eq!(level_step(0, 48000), 1.0);
+
+        //Fading in for half the time then pausing ramps back down from the same level.
+        let mut level = 0.0;
+        for _ in 0..240 {
+            level = approach(level, 1.0, step);
+        }
+        assert!((level - 0.5).abs() < 0.001, "{level}");
+        level = approach(level, 0.0, step);
+        assert!(level < 0.5 && level > 0.49);
+        for _ in 0..1000 {
+            level = approach(level, 0.0, step);
+        }
+        assert_eq!(level, 0.0);
+        assert_eq!(approach(0.0, 1.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn fade() {
+        assert_eq!(fade_gain(100, 100), 1.0);
+        assert_eq!(fade_gain(50, 100), 0.5);
+        assert_eq!(fade_gain(0, 100), 0.0);
+        assert_eq!(fade_gain(0, 0), 0.0);
+    }
+}
@@ -0,0 +1,94 @@
+//! Loudness of each part of a song for the waveform behind the seek bar.
+use crate::decoder::Symphonia;
+use std::{path::Path, thread};
+
+///Only a few packets are read ahead, the song is read once from start to finish.
+const PREBUFFER: usize = 4;
+
+///Decode the whole song and return the level of each bucket from 0 to 255.
+///
+///Meant to run on a background thread, it yields after every packet so playback isn't starved.
+pub fn overview(path: &Path, buckets: usize) -> Result<Vec<u8>, String> {
+    let mut sym = Symphonia::new_with_prebuffer(path, PREBUFFER).map_err(|err| err.to_string())?;
+    if sym.duration == 0 {
+        return Err(String::from("The song has no duration"));
+    }
+
+    let mut levels = Levels::new(buckets);
+    while let Some(packet) = sym.next_packet() {
+        levels.add(sym.elapsed as f64 / sym.duration as f64, packet.samples());
+        thread::yield_now();
+    }
+    Ok(levels.finish())
+}
+
+///The sum of squares of the samples in each bucket.
+struct Levels {
+    sums: Vec<f64>,
+    counts: Vec<usize>,
+}
+
+impl Levels {
+    fn new(buckets: usize) -> Self {
+        Self {
+            sums: vec![0.0; buckets],
+            counts: vec![0; buckets],
+        }
+    }
+
+    ///`position` is how far through the song the samples start, from 0 to 1.
+    fn add(&mut self, position: f64, samples: &[f32]) {
+        let len = self.sums.len();
+        if len == 0 {
+            return;
+        }
+        let i = ((position.clamp(0.0, 1.0) * len as f64) as usize).min(len - 1);
+        self.sums[i] += samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+        self.counts[i] += samples.len();
+    }
+
+    ///RMS of each bucket scaled so the loudest bucket is 255.
+    fn finish(self) -> Vec<u8> {
+        let rms: Vec<f64> = self
+            .sums
+            .iter()
+            .zip(&self.counts)
+            .map(|(sum, count)| {
+                if *count == 0 {
+                    0.0
+                } else {
+                    (sum / *count as f64).sqrt()
+                }
+            })
+            .collect();
+        let max = rms.iter().cloned().fold(0.0, f64::max);
+        if max == 0.0 {
+            return vec![0; rms.len()];
+        }
+        rms.iter()
+            .map(|level| (level / max * 255.0).round() as u8)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let mut levels = Levels::new(4);
+        levels.add(0.0, &[0.0; 64]);
+        levels.add(0.5, &[0.5, -0.5, 0.5, -0.5]);
+        levels.add(0.8, &[0.25; 8]);
+        //Past the end goes in the last bucket.
+        levels.add(1.5, &[0.25; 8]);
+        assert_eq!(levels.finish(), [0, 0, 255, 128]);
+
+        //Nothing was heard.
+        let mut levels = Levels::new(3);
+        levels.add(0.0, &[0.0; 8]);
+        assert_eq!(levels.finish(), [0, 0, 0]);
+        assert!(Levels::new(0).finish().is_empty());
+    }
+}
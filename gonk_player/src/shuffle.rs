@@ -0,0 +1,190 @@
+//! Play the queue in a random order.
+//!
+//! Every song is played once before any of them are played again.
+//! The songs that were played are kept so previous goes back through them.
+use gonk_core::settings::RepeatMode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+///Songs remembered for going back.
+pub const HISTORY: usize = 50;
+
+pub struct Shuffle {
+    pub on: bool,
+    ///Songs played since the last time every song was played.
+    played: Vec<bool>,
+    ///Indices of the songs played before the current one, the last is the most recent.
+    history: Vec<usize>,
+    ///Picked ahead of time so the player can open it before the current song ends.
+    upcoming: Option<usize>,
+    rng: u64,
+}
+
+impl Shuffle {
+    pub const fn new() -> Self {
+        Self {
+            on: false,
+            played: Vec::new(),
+            history: Vec::new(),
+            upcoming: None,
+            rng: 0,
+        }
+    }
+
+    ///Start again from `current`, nothing has been played yet.
+    pub fn reset(&mut self, len: usize, current: Option<usize>, seed: u64) {
+        self.played = vec![false; len];
+        if let Some(played) = current.and_then(|i| self.played.get_mut(i)) {
+            *played = true;
+        }
+        self.history.clear();
+        self.upcoming = None;
+        //Xorshift gets stuck on zero.
+        self.rng = seed | 1;
+    }
+
+    ///The song after `current`, the same one is returned until `advance` is called.
+    pub fn peek(&mut self, len: usize, current: usize, mode: RepeatMode) -> Option<usize> {
+        if current >= len {
+            return None;
+        }
+        if mode == RepeatMode::One {
+            return Some(current);
+        }
+
+        //Songs were added or removed.
+        self.played.resize(len, false);
+        self.played[current] = true;
+
+        if let Some(upcoming) = self.upcoming.filter(|&i| i < len && i != current) {
+            return Some(upcoming);
+        }
+
+        let mut left: Vec<usize> = (0..len).filter(|&i| !self.played[i]).collect();
+        if left.is_empty() {
+            if mode == RepeatMode::Off {
+                return None;
+            }
+            //Every song was played, go around again without repeating the current one.
+            self.played.fill(false);
+            self.played[current] = true;
+            left = (0..len).filter(|&i| i != current).collect();
+        }
+
+        let next = match left.len() {
+            0 => current,
+            n => left[self.random(n)],
+        };
+        self.upcoming = Some(next);
+        Some(next)
+    }
+
+    ///Move from `current` to `next`.
+    pub fn advance(&mut self, current: usize, next: usize) {
+        if current != next {
+            if self.history.len() == HISTORY {
+                self.history.remove(0);
+            }
+            self.history.push(current);
+        }
+        if let Some(played) = self.played.get_mut(next) {
+            *played = true;
+        }
+        self.upcoming = None;
+    }
+
+    ///The song `back` would return, without going back.
+    pub fn last(&self, len: usize) -> Option<usize> {
+        self.history
+            .iter()
+            .rev()
+            .copied()
+            .find(|&index| index < len)
+    }
+
+    ///The song played before the current one.
+    pub fn back(&mut self, len: usize) -> Option<usize> {
+        self.upcoming = None;
+        //Songs at the end of the queue may have been removed.
+        while let Some(index) = self.history.pop() {
+            if index < len {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn random(&mut self, n: usize) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % n as u64) as usize
+    }
+}
+
+pub fn seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_replacement() {
+        let mut shuffle = Shuffle::new();
+        shuffle.reset(10, Some(0), 7);
+
+        let mut current = 0;
+        let mut seen = vec![current];
+        for _ in 0..9 {
+            let next = shuffle.peek(10, current, RepeatMode::Off).unwrap();
+            //Asking again doesn't pick a different song.
+            assert_eq!(shuffle.peek(10, current, RepeatMode::Off), Some(next));
+            shuffle.advance(current, next);
+            current = next;
+            seen.push(current);
+        }
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+
+        //Every song was played.
+        assert_eq!(shuffle.peek(10, current, RepeatMode::Off), None);
+        let next = shuffle.peek(10, current, RepeatMode::Queue).unwrap();
+        assert_ne!(next, current);
+        assert_eq!(shuffle.peek(10, current, RepeatMode::One), Some(current));
+    }
+
+    #[test]
+    fn history() {
+        let mut shuffle = Shuffle::new();
+        shuffle.reset(100, Some(0), 3);
+
+        let mut order = vec![0];
+        for _ in 0..60 {
+            let current = *order.last().unwrap();
+            let next = shuffle.peek(100, current, RepeatMode::Queue).unwrap();
+            shuffle.advance(current, next);
+            order.push(next);
+        }
+
+        //Back goes through the songs in the order they were played.
+        order.pop();
+        for _ in 0..HISTORY {
+            assert_eq!(shuffle.last(100), order.last().copied());
+            assert_eq!(shuffle.back(100), order.pop());
+        }
+        assert_eq!(shuffle.back(100), None);
+
+        //Removed songs are skipped.
+        shuffle.advance(5, 6);
+        shuffle.advance(90, 7);
+        assert_eq!(shuffle.last(50), Some(5));
+        assert_eq!(shuffle.back(50), Some(5));
+
+        //A queue of one song.
+        shuffle.reset(1, Some(0), 3);
+        assert_eq!(shuffle.peek(1, 0, RepeatMode::Queue), Some(0));
+    }
+}
@@ -0,0 +1,309 @@
+//! Change the playback rate.
+//!
+//! Stretching keeps the pitch by overlap-adding short windows of the input (WSOLA).
+//! Each window is moved a little so its waveform lines up with the end of the last one.
+//! Resampling is cheaper but the pitch changes with the speed.
+use std::time::Duration;
+
+pub const MIN_RATE: f32 = 0.5;
+pub const MAX_RATE: f32 = 3.0;
+
+///Length of the crossfade between windows.
+const OVERLAP_MS: usize = 20;
+///How far a window can be moved to line up with the last one.
+const SEARCH_MS: usize = 8;
+///Only every nth frame is compared when lining up windows.
+const SEARCH_STEP: usize = 4;
+
+pub struct Stretcher {
+    channels: usize,
+    sample_rate: u32,
+    ///Frames crossfaded between windows, this is also how many frames each window outputs.
+    overlap: usize,
+    search: usize,
+    rate: f32,
+    keep_pitch: bool,
+    ///Interleaved input that may still be used.
+    input: Vec<f32>,
+    ///Where the next window is read from in frames.
+    position: f64,
+    ///The input following the last window, faded out under the next one.
+    tail: Vec<f32>,
+    ///The frame after the tail.
+    next: usize,
+}
+
+impl Stretcher {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let ms = |ms: usize| (sample_rate as usize * ms / 1000).max(1);
+        Self {
+            channels: channels.max(1),
+            sample_rate,
+            overlap: ms(OVERLAP_MS),
+            search: ms(SEARCH_MS),
+            rate: 1.0,
+            keep_pitch: true,
+            input: Vec::new(),
+            position: 0.0,
+            tail: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.clamp(MIN_RATE, MAX_RATE);
+    }
+
+    pub fn set_keep_pitch(&mut self, keep_pitch: bool) {
+        if self.keep_pitch != keep_pitch {
+            self.keep_pitch = keep_pitch;
+            self.tail.clear();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty() && self.tail.is_empty()
+    }
+
+    ///Throw away the buffered input after a seek.
+    pub fn clear(&mut self) {
+        self.input.clear();
+        self.tail.clear();
+        self.position = 0.0;
+        self.next = 0;
+    }
+
+    ///How much of the input hasn't been output yet.
+    pub fn buffered(&self) -> Duration {
+        let frames = (self.frames() as f64 - self.position).max(0.0);
+        Duration::from_secs_f64(frames / self.sample_rate.max(1) as f64)
+    }
+
+    fn frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    ///Add interleaved samples and write as much output as possible.
+    pub fn process(&mut self, samples: &[f32], output: &mut Vec<f32>) {
+        self.input.extend_from_slice(samples);
+        if self.keep_pitch {
+            self.stretch(output);
+        } else {
+            self.resample(output);
+        }
+        self.drain();
+    }
+
+    ///Output what's left at the end of a song.
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let ch = self.channels;
+        let frames = self.frames();
+        let remaining = (frames as f64 - self.position).max(0.0);
+        let len = (remaining / self.rate as f64).round() as usize;
+
+        if self.keep_pitch {
+            let start = if self.tail.is_empty() {
+                (self.position as usize).min(frames)
+            } else {
+                self.next.min(frames)
+            };
+            let rest = self.tail.iter().chain(&self.input[start * ch..]);
+            output.extend(rest.take(len * ch));
+        } else {
+            while self.position < frames as f64 {
+                let i = self.position as usize;
+                let t = (self.position - i as f64) as f32;
+                let next = (i + 1).min(frames - 1);
+                for c in 0..ch {
+                    let a = self.input[i * ch + c];
+                    let b = self.input[next * ch + c];
+                    output.push(a + (b - a) * t);
+                }
+                self.position += self.rate as f64;
+            }
+        }
+
+        self.clear();
+    }
+
+    fn resample(&mut self, output: &mut Vec<f32>) {
+        let ch = self.channels;
+        while self.position + 1.0 < self.frames() as f64 {
+            let i = self.position as usize;
+            let t = (self.position - i as f64) as f32;
+            for c in 0..ch {
+                let a = self.input[i * ch + c];
+                let b = self.input[(i + 1) * ch + c];
+                output.push(a + (b - a) * t);
+            }
+            self.position += self.rate as f64;
+        }
+    }
+
+    fn stretch(&mut self, output: &mut Vec<f32>) {
+        let ch = self.channels;
+        let overlap = self.overlap;
+
+        loop {
+            let nominal = self.position.round() as usize;
+            let (from, to) = if self.tail.is_empty() {
+                (nominal, nominal)
+            } else {
+                (nominal.saturating_sub(self.search), nominal + self.search)
+            };
+
+            //Wait for enough input to read the window and the tail after it.
+            if to + 2 * overlap > self.frames() {
+                return;
+            }
+
+            let start = if self.tail.is_empty() {
+                nominal
+            } else {
+                self.best_start(from, to)
+            };
+
+            for i in 0..overlap {
+                let fade = (i as f32 + 0.5) / overlap as f32;
+                for c in 0..ch {
+                    let sample = self.input[(start + i) * ch + c];
+                    output.push(match self.tail.get(i * ch + c) {
+                        Some(tail) => tail * (1.0 - fade) + sample * fade,
+                        None => sample,
+                    });
+                }
+            }
+
+            self.tail.clear();
+            self.tail
+                .extend_from_slice(&self.input[(start + overlap) * ch..(start + 2 * overlap) * ch]);
+            self.next = start + 2 * overlap;
+            self.position += overlap as f64 * self.rate as f64;
+        }
+    }
+
+    ///The start of the window that lines up best with the tail.
+    fn best_start(&self, from: usize, to: usize) -> usize {
+        let ch = self.channels;
+        let mut best = (f32::MIN, from);
+
+        for start in from..=to {
+            let mut correlation = 0.0;
+            let mut energy = 0.0;
+            for i in (0..self.overlap).step_by(SEARCH_STEP) {
+                for c in 0..ch {
+                    let sample = self.input[(start + i) * ch + c];
+                    correlation += self.tail[i * ch + c] * sample;
+                    energy += sample * sample;
+                }
+            }
+
+            let score = correlation / (energy + f32::EPSILON).sqrt();
+            if score > best.0 {
+                best = (score, start);
+            }
+        }
+
+        best.1
+    }
+
+    ///Remove input that will never be read again.
+    fn drain(&mut self) {
+        let mut used = (self.position as usize).saturating_sub(self.search);
+        if !self.tail.is_empty() {
+            used = used.min(self.next);
+        }
+        let used = used.min(self.frames());
+
+        if used > 0 {
+            self.input.drain(..used * self.channels);
+            self.position -= used as f64;
+            self.next = self.next.saturating_sub(used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    ///Five seconds of a stereo 440 Hz sine wave.
+    fn sine() -> Vec<f32> {
+        (0..SAMPLE_RATE as usize * 5)
+            .flat_map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let sample = (t * 440.0 * std::f32::consts::TAU).sin() * 0.5;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn run(input: &[f32], rate: f32, keep_pitch: bool) -> Vec<f32> {
+        let mut stretcher = Stretcher::new(2, SAMPLE_RATE);
+        stretcher.set_rate(rate);
+        stretcher.set_keep_pitch(keep_pitch);
+
+        //Roughly the size of an mp3 packet.
+        let mut output = Vec::new();
+        for packet in input.chunks(1152 * 2) {
+            stretcher.process(packet, &mut output);
+        }
+        stretcher.flush(&mut output);
+        assert!(stretcher.is_empty());
+        output
+    }
+
+    ///The frequency of the left channel.
+    fn frequency(samples: &[f32]) -> f32 {
+        let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let crossings = left
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        crossings as f32 / 2.0 / (left.len() as f32 / SAMPLE_RATE as f32)
+    }
+
+    #[test]
+    fn length() {
+        let input = sine();
+        for keep_pitch in [true, false] {
+            for rate in [0.5, 0.75, 1.25, 1.5, 2.0, 3.0] {
+                let output = run(&input, rate, keep_pitch);
+                assert_eq!(output.len() % 2, 0);
+
+                let expected = input.len() as f32 / rate;
+                let error = (output.len() as f32 - expected).abs() / expected;
+                assert!(error < 0.01, "{rate}x: {} != {expected}", output.len());
+            }
+        }
+    }
+
+    #[test]
+    fn pitch() {
+        let input = sine();
+
+        let stretched = frequency(&run(&input, 1.5, true));
+        assert!((stretched - 440.0).abs() < 440.0 * 0.05, "{stretched} Hz");
+
+        //Resampling plays the wave faster.
+        let resampled = frequency(&run(&input, 1.5, false));
+        assert!((resampled - 660.0).abs() < 660.0 * 0.05, "{resampled} Hz");
+    }
+
+    #[test]
+    fn clear() {
+        let mut stretcher = Stretcher::new(2, SAMPLE_RATE);
+        stretcher.set_rate(9.0);
+        assert_eq!(stretcher.rate, MAX_RATE);
+
+        let mut output = Vec::new();
+        stretcher.process(&sine()[..4410 * 2], &mut output);
+        assert!(stretcher.buffered() > Duration::ZERO);
+
+        stretcher.clear();
+        assert!(stretcher.is_empty());
+        assert_eq!(stretcher.buffered(), Duration::ZERO);
+    }
+}